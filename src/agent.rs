@@ -0,0 +1,910 @@
+//! Run the collection jobs of a config file until asked to stop, following changes to the config and the catalog.
+//!
+//! Everything the agent needs from the machine it runs on is behind [`Host`], [`Source`] and [`Clock`], so that it
+//! can be soak-tested against a simulated provider.
+
+use crate::clock::Clock;
+use crate::config::{self, Config, Job, Selection, Sink};
+use crate::error::Result;
+use crate::export::{Collector, Exporter, Metrics};
+use crate::source::Source;
+use crate::store::{Ring, Store};
+use crate::types::{CounterSet, CounterType, Provider};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use windows::core::GUID;
+
+/// The longest the agent sleeps for, so that it notices when it's asked to reload or shut down.
+const POLL: Duration = Duration::from_secs(1);
+
+/// The most stored samples a sink which is behind is sent with each new sample, so that backfilling a long outage
+/// doesn't hold up sampling.
+const BACKFILL: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    Reload,
+    Shutdown,
+}
+
+/// What the agent needs from the machine it's running on, besides counter values and the time.
+pub trait Host {
+    /// Load the registered countersets.
+    fn catalog(&mut self) -> Result<Vec<Provider>>;
+
+    /// Load the config.
+    fn config(&mut self) -> Result<Config>;
+
+    /// Whether the agent has been asked to do something since it last checked, e.g. by a signal.
+    fn request(&mut self) -> Option<Request>;
+
+    /// Get ready to send to a sink.
+    fn open(&mut self, sink: &Sink) -> Result<Box<dyn Exporter>> {
+        sink.open()
+    }
+}
+
+/// The sinks of each job.
+type Exporters = Vec<Vec<Box<dyn Exporter>>>;
+
+/// Why collection from one catalog with one config stopped.
+enum Next {
+    Shutdown,
+    Catalog(Vec<Provider>),
+    Config(Config, Exporters),
+}
+
+/// Collection from one counterset for a job.
+struct Task<'a> {
+    selection: Selection<'a>,
+    collector: Collector<'a>,
+    /// Where samples are kept if the config has a store, to backfill sinks from once they can be reached again.
+    ring: Option<Ring>,
+    /// Whether the last sample failed, so that an outage is only logged when it starts and ends.
+    failing: bool,
+}
+
+/// A sink of a job which couldn't be sent a counterset's samples, by the job's name, the counterset and the sink.
+type Behind = (String, GUID, String);
+
+/// The countersets and counters each job collects from a catalog, to tell whether a new catalog changes anything.
+type Fingerprint = Vec<Vec<(GUID, Vec<(u32, CounterType)>)>>;
+
+struct Agent<'e> {
+    host: &'e mut dyn Host,
+    source: &'e mut dyn Source,
+    clock: &'e mut dyn Clock,
+    refresh: Duration,
+    /// When each job is next due to sample.
+    due: Vec<SystemTime>,
+    /// When the catalog is next due to be refreshed.
+    refresh_due: SystemTime,
+    /// The time of the first sample each sink which is behind hasn't been sent yet, kept across reloads.
+    behind: HashMap<Behind, SystemTime>,
+}
+
+/// Run the jobs of the host's config until it asks for a shutdown, then flush their sinks.
+///
+/// The catalog is loaded again every `refresh`, and if that changes what any job collects, e.g. because a provider
+/// was installed, collection starts afresh with the new one. Sinks are kept, so nothing they buffer is lost.
+///
+/// If the config has a store, every sample is kept in it, and a sink which can't be sent one is backfilled from it
+/// once it can be reached again, a batch at a time, before it's sent new samples.
+pub fn run(
+    host: &mut dyn Host,
+    source: &mut dyn Source,
+    clock: &mut dyn Clock,
+    refresh: Duration,
+) -> Result<()> {
+    let mut config = host.config()?;
+    let mut exporters = open(host, &config)?;
+    let mut all = host.catalog()?;
+    let now = clock.now();
+    let mut agent = Agent {
+        host,
+        source,
+        clock,
+        refresh,
+        due: vec![now; config.jobs.len()],
+        refresh_due: now + refresh,
+        behind: HashMap::new(),
+    };
+
+    loop {
+        match agent.collect(&config, &all, &mut exporters)? {
+            Next::Shutdown => {
+                log::info!("Shutting down");
+                flush(&mut exporters);
+                return Ok(());
+            }
+            Next::Catalog(new) => {
+                log::info!("The catalog changed, starting collection again");
+                all = new;
+            }
+            Next::Config(new, new_exporters) => {
+                log::info!("Reloaded the config");
+                flush(&mut exporters);
+                agent.due = vec![agent.clock.now(); new.jobs.len()];
+                config = new;
+                exporters = new_exporters;
+            }
+        }
+    }
+}
+
+fn open(host: &mut dyn Host, config: &Config) -> Result<Exporters> {
+    config
+        .jobs
+        .iter()
+        .map(|job| {
+            job.sinks
+                .iter()
+                .map(|sink| {
+                    host.open(sink)
+                        .map_err(|e| format!("job \"{}\": {}: {}", job.name, sink, e).into())
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn flush(exporters: &mut Exporters) {
+    for exporter in exporters.iter_mut().flatten() {
+        if let Err(e) = exporter.flush() {
+            log::warn!("Failed to flush: {}", e);
+        }
+    }
+}
+
+fn fingerprint(config: &Config, all: &[Provider]) -> Fingerprint {
+    config
+        .jobs
+        .iter()
+        .map(|job| {
+            job.select(all)
+                .0
+                .iter()
+                .map(|s| {
+                    let counters = s.counters.iter().map(|c| (c.id, c.counter_type));
+                    (s.counterset.id, counters.collect())
+                })
+                .collect()
+        })
+        .collect()
+}
+
+impl Agent<'_> {
+    fn collect(
+        &mut self,
+        config: &Config,
+        all: &[Provider],
+        exporters: &mut Exporters,
+    ) -> Result<Next> {
+        let mut tasks = config
+            .jobs
+            .iter()
+            .map(|job| {
+                let (selections, errors) = job.select(all);
+                for error in errors {
+                    log::warn!("{}, will look again when the catalog is refreshed", error);
+                }
+                selections
+                    .into_iter()
+                    .map(|selection| Task {
+                        collector: Collector::new(&selection.provider.name, selection.counterset)
+                            .with_labels(&job.labels),
+                        ring: config
+                            .store
+                            .as_ref()
+                            .and_then(|store| ring(store, job, selection.counterset)),
+                        selection,
+                        failing: false,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let current = fingerprint(config, all);
+
+        loop {
+            match self.host.request() {
+                Some(Request::Shutdown) => return Ok(Next::Shutdown),
+                Some(Request::Reload) => {
+                    if let Some(next) = self.reload() {
+                        return Ok(next);
+                    }
+                }
+                None => {}
+            }
+
+            let now = self.clock.now();
+            if now >= self.refresh_due {
+                self.refresh_due = now + self.refresh;
+                match self.host.catalog() {
+                    Ok(new) if fingerprint(config, &new) != current => {
+                        return Ok(Next::Catalog(new))
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Failed to refresh the catalog: {}", e),
+                }
+            }
+
+            for (index, job) in config.jobs.iter().enumerate() {
+                let due = &mut self.due[index];
+                // The clock went back, so start counting again from now.
+                if *due > now + job.interval {
+                    *due = now;
+                }
+                if *due > now {
+                    continue;
+                }
+                // Skip samples which were missed, rather than catching up with a burst of them.
+                while *due <= now {
+                    *due += job.interval;
+                }
+                for task in &mut tasks[index] {
+                    sample(
+                        self.source,
+                        &*self.clock,
+                        job,
+                        task,
+                        &mut exporters[index],
+                        &mut self.behind,
+                    );
+                }
+            }
+
+            let now = self.clock.now();
+            let wake = self
+                .due
+                .iter()
+                .copied()
+                .chain([self.refresh_due, now + POLL])
+                .min()
+                .unwrap();
+            self.clock
+                .sleep(wake.duration_since(now).unwrap_or_default());
+        }
+    }
+
+    /// Load the config and open its sinks, or keep the current ones if that fails.
+    fn reload(&mut self) -> Option<Next> {
+        let result = self
+            .host
+            .config()
+            .and_then(|config| Ok((open(self.host, &config)?, config)));
+        match result {
+            Ok((exporters, config)) => Some(Next::Config(config, exporters)),
+            Err(e) => {
+                log::error!(
+                    "Failed to reload the config, keeping the current one: {}",
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Open the ring a job keeps a counterset's samples in, or carry on without it if that fails.
+fn ring(store: &Store, job: &Job, counterset: &CounterSet) -> Option<Ring> {
+    let store = Store {
+        path: store.path.join(&job.name),
+        ..store.clone()
+    };
+    match store.ring(counterset, job.interval) {
+        Ok(ring) => Some(ring),
+        Err(e) => {
+            log::error!(
+                "job \"{}\": failed to open the store for {}, its samples won't be kept: {}",
+                job.name,
+                counterset.name,
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Keep only the points of the counters and instances a job selected.
+fn select(job: &Job, selection: &Selection, metrics: &mut Metrics) {
+    metrics.points.retain(|point| {
+        selection.counters.iter().any(|c| c.id == point.counter.id)
+            && selection.includes(&job.instances, point.instance.as_deref())
+    });
+}
+
+fn sample(
+    source: &mut dyn Source,
+    clock: &dyn Clock,
+    job: &Job,
+    task: &mut Task,
+    exporters: &mut [Box<dyn Exporter>],
+    behind: &mut HashMap<Behind, SystemTime>,
+) {
+    let counterset = task.selection.counterset;
+    let sample = match source.sample(&counterset.id) {
+        Ok(sample) => sample,
+        Err(e) => {
+            if !task.failing {
+                log::warn!(
+                    "job \"{}\": failed to sample {}, will keep trying: {}",
+                    job.name,
+                    counterset.name,
+                    e
+                );
+                task.failing = true;
+            }
+            // Its provider may have gone away, in which case it has to be opened again once it's back.
+            source.reopen(&counterset.id);
+            return;
+        }
+    };
+    if task.failing {
+        log::info!("job \"{}\": sampling {} again", job.name, counterset.name);
+        task.failing = false;
+    }
+
+    let now = clock.now();
+    let Some(ring) = &mut task.ring else {
+        let mut metrics = task.collector.update(sample, now);
+        select(job, &task.selection, &mut metrics);
+        for exporter in exporters {
+            if let Err(e) = exporter.export(&metrics) {
+                log::warn!("job \"{}\": failed to export: {}", job.name, e);
+            }
+        }
+        return;
+    };
+
+    if let Err(e) = ring.append(counterset, now, &sample) {
+        log::warn!(
+            "job \"{}\": failed to store a sample of {}: {}",
+            job.name,
+            counterset.name,
+            e
+        );
+    }
+    let mut metrics = task.collector.update(sample, now);
+    select(job, &task.selection, &mut metrics);
+    for (sink, exporter) in job.sinks.iter().zip(exporters) {
+        let key = (job.name.clone(), counterset.id, sink.to_string());
+        // New samples wait until the older ones have been sent, so that they arrive in order.
+        if let Some(from) = behind.get_mut(&key) {
+            match backfill(job, &task.selection, ring, exporter.as_mut(), from) {
+                Ok(true) => {
+                    log::info!(
+                        "job \"{}\": {} has caught up with {}",
+                        job.name,
+                        sink,
+                        counterset.name
+                    );
+                    behind.remove(&key);
+                }
+                Ok(false) => {}
+                Err(e) => log::debug!("job \"{}\": {} is still behind: {}", job.name, sink, e),
+            }
+        } else if let Err(e) = exporter.deliver(&metrics) {
+            log::warn!(
+                "job \"{}\": failed to send {} to {}, will send it from the store: {}",
+                job.name,
+                counterset.name,
+                sink,
+                e
+            );
+            // As it was stored, to the millisecond.
+            let millis = now
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            behind.insert(
+                key,
+                SystemTime::UNIX_EPOCH + Duration::from_millis(millis as u64),
+            );
+        }
+    }
+}
+
+/// Send a sink a batch of the stored samples it's behind on, returning whether it has caught up.
+fn backfill(
+    job: &Job,
+    selection: &Selection,
+    ring: &Ring,
+    exporter: &mut dyn Exporter,
+    from: &mut SystemTime,
+) -> Result<bool> {
+    let mut records = ring.records(*from)?;
+    // Starts with the samples before `from`, for the first ones after it to be cooked against.
+    let mut collector =
+        Collector::new(&selection.provider.name, selection.counterset).with_labels(&job.labels);
+    let mut sent = 0;
+    while let Some(record) = records.next_record()? {
+        let time = record.time;
+        let mut metrics = collector.update(record.sample, time);
+        if time < *from {
+            continue;
+        }
+        if sent == BACKFILL {
+            return Ok(false);
+        }
+        select(job, selection, &mut metrics);
+        exporter.deliver(&metrics)?;
+        sent += 1;
+        *from = time + Duration::from_nanos(1);
+    }
+    Ok(true)
+}
+
+/// The machine the agent's running on, with its config in a file.
+///
+/// The config is reloaded when the file changes, or on SIGHUP or Ctrl+Break. SIGINT, SIGTERM or Ctrl+C shut down.
+pub struct Machine<F> {
+    path: PathBuf,
+    /// When the config file was last modified, as of the last time it was loaded.
+    modified: Option<SystemTime>,
+    catalog: F,
+    shutdown: Arc<AtomicBool>,
+    reload: Arc<AtomicBool>,
+}
+
+impl<F: FnMut() -> Result<Vec<Provider>>> Machine<F> {
+    pub fn new(path: &Path, catalog: F) -> Result<Self> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let reload = Arc::new(AtomicBool::new(false));
+        handle_signals(&shutdown, &reload)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            modified: None,
+            catalog,
+            shutdown,
+            reload,
+        })
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+}
+
+impl<F: FnMut() -> Result<Vec<Provider>>> Host for Machine<F> {
+    fn catalog(&mut self) -> Result<Vec<Provider>> {
+        (self.catalog)()
+    }
+
+    fn config(&mut self) -> Result<Config> {
+        // Before reading it, so that a change while it's being read isn't missed.
+        self.modified = self.modified();
+        config::load(&self.path)
+    }
+
+    fn request(&mut self) -> Option<Request> {
+        if self.shutdown.load(Ordering::Relaxed) {
+            Some(Request::Shutdown)
+        } else if self.reload.swap(false, Ordering::Relaxed) || self.modified() != self.modified {
+            Some(Request::Reload)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(windows)]
+fn handle_signals(shutdown: &Arc<AtomicBool>, reload: &Arc<AtomicBool>) -> Result<()> {
+    use std::sync::OnceLock;
+    use windows::Win32::Foundation::{BOOL, TRUE};
+    use windows::Win32::System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT};
+
+    static FLAGS: OnceLock<(Arc<AtomicBool>, Arc<AtomicBool>)> = OnceLock::new();
+
+    unsafe extern "system" fn handler(ctrl_type: u32) -> BOOL {
+        if let Some((shutdown, reload)) = FLAGS.get() {
+            match ctrl_type {
+                CTRL_BREAK_EVENT => reload.store(true, Ordering::Relaxed),
+                _ => shutdown.store(true, Ordering::Relaxed),
+            }
+        }
+        TRUE
+    }
+
+    FLAGS
+        .set((Arc::clone(shutdown), Arc::clone(reload)))
+        .map_err(|_| "Console control handler is already set")?;
+    unsafe { SetConsoleCtrlHandler(Some(handler), TRUE)? };
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn handle_signals(shutdown: &Arc<AtomicBool>, reload: &Arc<AtomicBool>) -> Result<()> {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+    use signal_hook::flag;
+
+    for signal in [SIGINT, SIGTERM] {
+        // A second signal while shutting down exits immediately.
+        flag::register_conditional_shutdown(signal, 1, Arc::clone(shutdown))?;
+        flag::register(signal, Arc::clone(shutdown))?;
+    }
+    flag::register(SIGHUP, Arc::clone(reload))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{Metrics, Value};
+    use crate::testing::{self, FakeClock};
+    use crate::types::Sample;
+    use std::cell::{Cell, RefCell};
+    use std::fmt::Write as _;
+    use std::rc::Rc;
+
+    /// Seconds since the start of the test, shared by the fake clock, host and provider.
+    type Time = Rc<Cell<u64>>;
+
+    /// Contoso Disk with two instances, and Contoso Network Adapter with one, counting up steadily from when their
+    /// provider starts.
+    struct Simulated {
+        time: Time,
+        /// The provider of Contoso Disk is stopped during these seconds, and starts afresh afterwards.
+        outages: Vec<(u64, u64)>,
+        reopened: usize,
+    }
+
+    impl Simulated {
+        fn uptime(&self) -> Option<u64> {
+            let now = self.time.get();
+            let mut started = 0;
+            for &(start, end) in &self.outages {
+                if (start..end).contains(&now) {
+                    return None;
+                }
+                if now >= end {
+                    started = end;
+                }
+            }
+            Some(now - started)
+        }
+    }
+
+    impl Source for Simulated {
+        fn sample(&mut self, counterset_id: &GUID) -> Result<Sample> {
+            let now = self.time.get() as i64;
+            let (counter_ids, instances) = match counterset_id.to_u128() {
+                0x8F1E2D3C_4B5A_4968_8776_A5B4C3D2E1F0 => {
+                    let uptime = self.uptime().ok_or("The provider isn't running")?;
+                    let values = |rate| vec![rate * uptime, uptime % 4];
+                    (vec![0, 5], vec![("C:", values(1000)), ("D:", values(10))])
+                }
+                0x0A1B2C3D_4E5F_4061_8293_A4B5C6D7E8F9 => {
+                    (vec![0], vec![("Ethernet 1", vec![50 * now as u64])])
+                }
+                _ => return Err("No such counterset".into()),
+            };
+            Ok(testing::sample(
+                *counterset_id,
+                now,
+                &counter_ids,
+                testing::named(instances),
+            ))
+        }
+
+        fn reopen(&mut self, _: &GUID) {
+            self.reopened += 1;
+        }
+    }
+
+    /// Writes a line for each sample a sink is sent, and when it's flushed.
+    struct Recorder {
+        name: String,
+        time: Time,
+        /// Samples can't be delivered during these seconds.
+        unreachable: Vec<(u64, u64)>,
+        out: Rc<RefCell<String>>,
+    }
+
+    impl Exporter for Recorder {
+        fn export(&mut self, metrics: &Metrics) -> Result<()> {
+            let mut out = self.out.borrow_mut();
+            let time = metrics.time.duration_since(testing::at(0))?.as_secs();
+            write!(out, "{:>3} {} {}", time, self.name, metrics.counterset.name)?;
+            for (name, value) in metrics.labels {
+                write!(out, " {}={}", name, value)?;
+            }
+            write!(out, ":")?;
+            for point in &metrics.points {
+                let value = match point.value {
+                    Value::Gauge(value) => value.to_string(),
+                    Value::Sum { total, start } => {
+                        let start = start.duration_since(testing::at(0))?.as_secs();
+                        format!("{} since {}", total, start)
+                    }
+                };
+                let instance = point.instance.as_deref().unwrap_or_default();
+                write!(out, " {}({})={}", point.counter.name, instance, value)?;
+            }
+            writeln!(out)?;
+            Ok(())
+        }
+
+        fn deliver(&mut self, metrics: &Metrics) -> Result<()> {
+            let now = self.time.get();
+            if self
+                .unreachable
+                .iter()
+                .any(|(start, end)| (start..end).contains(&&now))
+            {
+                writeln!(
+                    self.out.borrow_mut(),
+                    "{:>3} {} unreachable",
+                    now,
+                    self.name
+                )?;
+                return Err("Connection refused".into());
+            }
+            self.export(metrics)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            writeln!(self.out.borrow_mut(), "    {} flushed", self.name)?;
+            Ok(())
+        }
+    }
+
+    /// Serves configs and catalogs which change at given times.
+    struct FakeHost {
+        time: Time,
+        configs: Vec<(u64, String)>,
+        /// When to ask for the config to be reloaded.
+        reloads: Vec<u64>,
+        /// When Contoso Network is installed.
+        installed: u64,
+        /// When sinks can't be reached.
+        unreachable: Vec<(u64, u64)>,
+        shutdown: u64,
+        out: Rc<RefCell<String>>,
+    }
+
+    impl Host for FakeHost {
+        fn catalog(&mut self) -> Result<Vec<Provider>> {
+            let mut all = testing::catalog();
+            if self.time.get() < self.installed {
+                all.retain(|p| p.name != "Contoso-Network");
+            }
+            Ok(all)
+        }
+
+        fn config(&mut self) -> Result<Config> {
+            let now = self.time.get();
+            let (_, text) = self
+                .configs
+                .iter()
+                .rev()
+                .find(|(at, _)| *at <= now)
+                .unwrap();
+            config::parse_toml(text)
+        }
+
+        fn request(&mut self) -> Option<Request> {
+            let now = self.time.get();
+            if now >= self.shutdown {
+                Some(Request::Shutdown)
+            } else if self.reloads.first() == Some(&now) {
+                self.reloads.remove(0);
+                Some(Request::Reload)
+            } else {
+                None
+            }
+        }
+
+        fn open(&mut self, sink: &Sink) -> Result<Box<dyn Exporter>> {
+            let name = sink.to_string();
+            Ok(Box::new(Recorder {
+                name: name.split(" as ").next().unwrap().to_string(),
+                time: Rc::clone(&self.time),
+                unreachable: self.unreachable.clone(),
+                out: Rc::clone(&self.out),
+            }))
+        }
+    }
+
+    const DISKS_AND_NETWORK: &str = r#"
+        [[job]]
+        name = "disks"
+        countersets = ["Contoso Disk"]
+        instances = { exclude = ["D:"] }
+        counters = { include = ["Bytes Read", "Queue Length"] }
+        interval = "20s"
+        labels = { env = "test" }
+        [[job.sink]]
+        type = "graphite"
+        address = "disks:2003"
+
+        [[job]]
+        name = "network"
+        countersets = ['\Contoso Network Adapter(*)\Bytes Sent/sec']
+        interval = "20s"
+        [[job.sink]]
+        type = "statsd"
+        address = "network:8125"
+    "#;
+
+    const DISKS: &str = r#"
+        [[job]]
+        name = "disks"
+        countersets = ['\Contoso Disk(C:)\Queue Length']
+        interval = "30s"
+        labels = { env = "prod" }
+        [[job.sink]]
+        type = "graphite"
+        address = "disks:2003"
+    "#;
+
+    #[test]
+    fn follows_the_provider_catalog_and_config() {
+        let time = Time::default();
+        let out = Rc::new(RefCell::new(String::new()));
+        let mut host = FakeHost {
+            time: Rc::clone(&time),
+            // A config which fails to load is ignored.
+            configs: vec![
+                (0, DISKS_AND_NETWORK.into()),
+                (105, "[[job]]".into()),
+                (110, DISKS.into()),
+            ],
+            reloads: vec![105, 110],
+            installed: 45,
+            unreachable: Vec::new(),
+            shutdown: 150,
+            out: Rc::clone(&out),
+        };
+        let mut source = Simulated {
+            time: Rc::clone(&time),
+            outages: vec![(50, 90)],
+            reopened: 0,
+        };
+        let mut clock = FakeClock::sharing(Rc::clone(&time));
+
+        run(&mut host, &mut source, &mut clock, Duration::from_secs(60)).unwrap();
+        assert!(clock
+            .slept
+            .iter()
+            .all(|&slept| slept > Duration::ZERO && slept <= POLL));
+
+        // The network provider was found by the refresh at 60, while the disk provider was away between 50 and 90.
+        assert_eq!(
+            out.borrow().as_str(),
+            "  0 graphite disks:2003 Contoso Disk env=test: Bytes Read(C:)=0 Queue Length(C:)=0
+ 20 graphite disks:2003 Contoso Disk env=test: Bytes Read(C:)=20000 Queue Length(C:)=0
+ 40 graphite disks:2003 Contoso Disk env=test: Bytes Read(C:)=40000 Queue Length(C:)=0
+ 60 statsd network:8125 Contoso Network Adapter: Bytes Sent/sec(Ethernet 1)=0 since 60
+ 80 statsd network:8125 Contoso Network Adapter: Bytes Sent/sec(Ethernet 1)=1000 since 60
+100 graphite disks:2003 Contoso Disk env=test: Bytes Read(C:)=10000 Queue Length(C:)=2
+100 statsd network:8125 Contoso Network Adapter: Bytes Sent/sec(Ethernet 1)=2000 since 60
+    graphite disks:2003 flushed
+    statsd network:8125 flushed
+110 graphite disks:2003 Contoso Disk env=prod: Queue Length(C:)=0
+140 graphite disks:2003 Contoso Disk env=prod: Queue Length(C:)=2
+    graphite disks:2003 flushed
+"
+        );
+        // Once for each failed sample, at 60 and 80.
+        assert_eq!(source.reopened, 2);
+    }
+
+    #[test]
+    fn backfills_sinks_from_the_store() {
+        let store = crate::store::tests::store("agent", 1 << 20, None);
+        let config = format!(
+            "[store]\npath = '{}'\n{}",
+            store.path.display(),
+            DISKS.replace("30s", "20s")
+        );
+        let time = Time::default();
+        let out = Rc::new(RefCell::new(String::new()));
+        let mut host = FakeHost {
+            time: Rc::clone(&time),
+            configs: vec![(0, config.clone()), (50, config)],
+            reloads: vec![50],
+            installed: 0,
+            unreachable: vec![(30, 70)],
+            shutdown: 100,
+            out: Rc::clone(&out),
+        };
+        let mut source = Simulated {
+            time: Rc::clone(&time),
+            outages: Vec::new(),
+            reopened: 0,
+        };
+        let mut clock = FakeClock::sharing(Rc::clone(&time));
+
+        run(&mut host, &mut source, &mut clock, Duration::from_secs(60)).unwrap();
+        assert!(clock
+            .slept
+            .iter()
+            .all(|&slept| slept > Duration::ZERO && slept <= POLL));
+
+        // Samples from while the sink couldn't be reached are sent in order once it can, even after a reload.
+        assert_eq!(
+            out.borrow().as_str(),
+            "  0 graphite disks:2003 Contoso Disk env=prod: Queue Length(C:)=0
+ 20 graphite disks:2003 Contoso Disk env=prod: Queue Length(C:)=0
+ 40 graphite disks:2003 unreachable
+    graphite disks:2003 flushed
+ 50 graphite disks:2003 unreachable
+ 40 graphite disks:2003 Contoso Disk env=prod: Queue Length(C:)=0
+ 50 graphite disks:2003 Contoso Disk env=prod: Queue Length(C:)=2
+ 70 graphite disks:2003 Contoso Disk env=prod: Queue Length(C:)=2
+ 90 graphite disks:2003 Contoso Disk env=prod: Queue Length(C:)=2
+    graphite disks:2003 flushed
+"
+        );
+        let job = crate::store::Store {
+            path: store.path.join("disks"),
+            ..store.clone()
+        };
+        let mut records = job
+            .records(&testing::contoso_disk().id, SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let mut stored = 0;
+        while records.next_record().unwrap().is_some() {
+            stored += 1;
+        }
+        assert_eq!(stored, 6);
+
+        fs::remove_dir_all(&store.path).unwrap();
+    }
+
+    #[test]
+    fn keeps_collecting_for_a_simulated_week() {
+        const WEEK: u64 = 7 * 24 * 60 * 60;
+        let time = Time::default();
+        let out = Rc::new(RefCell::new(String::new()));
+        let mut host = FakeHost {
+            time: Rc::clone(&time),
+            configs: vec![(0, DISKS_AND_NETWORK.into())],
+            reloads: (0..WEEK).step_by(6 * 60 * 60).skip(1).collect(),
+            installed: 0,
+            unreachable: Vec::new(),
+            shutdown: WEEK,
+            out: Rc::clone(&out),
+        };
+        // The disk provider goes away for ten minutes every few hours.
+        let mut source = Simulated {
+            time: Rc::clone(&time),
+            outages: (0..WEEK)
+                .step_by(5 * 60 * 60)
+                .skip(1)
+                .map(|at| (at, at + 600))
+                .collect(),
+            reopened: 0,
+        };
+        let mut clock = FakeClock::sharing(Rc::clone(&time));
+
+        run(&mut host, &mut source, &mut clock, Duration::from_secs(300)).unwrap();
+        assert!(clock
+            .slept
+            .iter()
+            .all(|&slept| slept > Duration::ZERO && slept <= POLL));
+
+        let out = out.borrow();
+        let exported = |sink: &str| {
+            out.lines()
+                .filter(|line| !line.ends_with("flushed") && line.contains(sink))
+                .count()
+        };
+        let samples = (WEEK / 20) as usize;
+        // The network is sampled every time, and the disks except for the samples missed by outages.
+        assert_eq!(exported("network"), samples);
+        assert_eq!(exported("disks"), samples - source.outages.len() * 30);
+        assert_eq!(source.reopened, source.outages.len() * 30);
+        // Once for each reload, and both sinks at shutdown.
+        assert_eq!(
+            out.lines().filter(|line| line.ends_with("flushed")).count(),
+            2 * (WEEK / (6 * 60 * 60)) as usize
+        );
+        // Right up to the end.
+        assert!(out.contains(&format!("{} statsd network:8125", WEEK - 20)));
+    }
+}
@@ -0,0 +1,367 @@
+//! Synthetic instances which combine the raw values of several instances of a counterset, like the `_Total`
+//! instance which consumers see for MultiAggregate countersets.
+
+use crate::error::Result;
+use crate::source::Source;
+use crate::types::counter_type::Kind;
+use crate::types::{AggregateFunc, CounterSet, Instance, InstanceSample, Sample};
+use regex::Regex;
+use std::collections::HashMap;
+use std::str::FromStr;
+use windows::core::GUID;
+
+/// Which instances to combine into each synthetic instance.
+#[derive(Debug, Clone)]
+pub enum Grouping {
+    /// Every instance, as `_Total`.
+    Total,
+    /// Instances whose names are the same up to a separator, e.g. `#` to combine `chrome#1` and `chrome#2` into
+    /// `chrome`.
+    Prefix(String),
+    /// Instances whose names match a regex, grouped by its first capture group (or the whole match, if it has
+    /// none). Instances which don't match are left out.
+    Regex(Regex),
+}
+
+impl Grouping {
+    /// The name of the synthetic instance an instance belongs to, if any.
+    pub fn group(&self, name: &str) -> Option<String> {
+        match self {
+            Grouping::Total => Some(TOTAL.to_string()),
+            Grouping::Prefix(separator) => Some(match name.split_once(separator.as_str()) {
+                Some((prefix, _)) => prefix.to_string(),
+                None => name.to_string(),
+            }),
+            Grouping::Regex(regex) => {
+                let captures = regex.captures(name)?;
+                let group = captures.get(1).or_else(|| captures.get(0))?;
+                Some(group.as_str().to_string())
+            }
+        }
+    }
+}
+
+impl FromStr for Grouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("total") {
+            Ok(Grouping::Total)
+        } else if let Some(separator) = s.strip_prefix("prefix:").filter(|s| !s.is_empty()) {
+            Ok(Grouping::Prefix(separator.to_string()))
+        } else if let Some(pattern) = s.strip_prefix("regex:") {
+            Regex::new(pattern)
+                .map(Grouping::Regex)
+                .map_err(|e| e.to_string())
+        } else {
+            Err(format!(
+                "expected total, prefix:<separator> or regex:<pattern>, got `{}`",
+                s
+            ))
+        }
+    }
+}
+
+const TOTAL: &str = "_Total";
+
+/// How each counter's values are combined, by counter id.
+///
+/// Counters without an aggregate function are totalled, except elapsed time counters, whose raw value is a start
+/// time, so the earliest is kept. Base counters are combined the same way as the counters that are divided by them,
+/// so that the ratio is still meaningful, and an object's own time and frequency are taken from the latest instance.
+pub fn functions(counterset: &CounterSet) -> HashMap<u32, AggregateFunc> {
+    let mut functions = HashMap::new();
+    for counter in &counterset.counters {
+        let func = match counter.aggregate_func {
+            AggregateFunc::Undefined if counter.counter_type.kind() == Kind::Elapsed => {
+                AggregateFunc::Min
+            }
+            AggregateFunc::Undefined => AggregateFunc::Total,
+            func => func,
+        };
+        functions.entry(counter.id).or_insert(func);
+    }
+    for counter in &counterset.counters {
+        let func = functions[&counter.id];
+        for base in [counter.base_counter_id, counter.multi_counter_id]
+            .into_iter()
+            .flatten()
+        {
+            functions.insert(base.get(), func);
+        }
+        for time in [counter.perf_time_id, counter.perf_freq_id]
+            .into_iter()
+            .flatten()
+        {
+            functions.insert(time.get(), AggregateFunc::Max);
+        }
+    }
+    functions
+}
+
+fn combine(func: AggregateFunc, values: &[u64]) -> u64 {
+    match func {
+        AggregateFunc::Undefined | AggregateFunc::Total => {
+            values.iter().fold(0, |sum, v| sum.saturating_add(*v))
+        }
+        AggregateFunc::Avg => {
+            let sum = values.iter().map(|&v| v as u128).sum::<u128>();
+            (sum / values.len() as u128) as u64
+        }
+        AggregateFunc::Min => values.iter().copied().min().unwrap_or(0),
+        AggregateFunc::Max => values.iter().copied().max().unwrap_or(0),
+    }
+}
+
+/// Append a synthetic instance to the sample for each group of its instances.
+///
+/// Instances which are already totals, and groups with the same name as a real instance, are left out, since the
+/// provider already combines those itself.
+///
+/// Each group keeps the id it's given in `ids` the first time it's seen, counting down from the top to stay clear of
+/// the ids providers assign, so that its id doesn't change as other groups come and go.
+pub fn apply(
+    counterset: &CounterSet,
+    grouping: &Grouping,
+    ids: &mut HashMap<String, u32>,
+    sample: &mut Sample,
+) {
+    let functions = functions(counterset);
+
+    let mut groups: Vec<(String, Vec<&InstanceSample>)> = Vec::new();
+    for instance in &sample.instances {
+        let Some(name) = instance.instance.as_ref().map(|i| &i.name) else {
+            continue;
+        };
+        if name == TOTAL {
+            continue;
+        }
+        let Some(group) = grouping.group(name) else {
+            continue;
+        };
+        match groups.iter_mut().find(|(g, _)| *g == group) {
+            Some((_, members)) => members.push(instance),
+            None => groups.push((group, vec![instance])),
+        }
+    }
+
+    let mut synthetic = Vec::new();
+    for (name, members) in groups {
+        if sample
+            .instances
+            .iter()
+            .any(|i| i.instance.as_ref().is_some_and(|i| i.name == name))
+        {
+            log::debug!("Not aggregating {}, since the provider reports it", name);
+            continue;
+        }
+
+        let values = sample
+            .counter_ids
+            .iter()
+            .enumerate()
+            .map(|(column, id)| {
+                let func = functions.get(id).copied().unwrap_or(AggregateFunc::Total);
+                let values = members
+                    .iter()
+                    .filter_map(|m| m.values.get(column).copied())
+                    .collect::<Vec<_>>();
+                combine(func, &values)
+            })
+            .collect();
+
+        let next = u32::MAX - ids.len() as u32;
+        let id = *ids.entry(name.clone()).or_insert(next);
+        synthetic.push(InstanceSample {
+            instance: Some(Instance { id, name }),
+            values,
+        });
+    }
+
+    sample.instances.extend(synthetic);
+}
+
+/// Adds synthetic instances to the samples of a counterset from another source.
+pub struct Aggregating<'a> {
+    source: Box<dyn Source + 'a>,
+    counterset: &'a CounterSet,
+    grouping: Grouping,
+    /// The id of each group, as of when it was first seen.
+    ids: HashMap<String, u32>,
+}
+
+impl<'a> Aggregating<'a> {
+    /// Wrap a source, if there's a grouping to aggregate by.
+    pub fn wrap(
+        source: Box<dyn Source + 'a>,
+        counterset: &'a CounterSet,
+        grouping: Option<Grouping>,
+    ) -> Box<dyn Source + 'a> {
+        match grouping {
+            Some(grouping) => Box::new(Self {
+                source,
+                counterset,
+                grouping,
+                ids: HashMap::new(),
+            }),
+            None => source,
+        }
+    }
+}
+
+impl Source for Aggregating<'_> {
+    fn sample(&mut self, counterset_id: &GUID) -> Result<Sample> {
+        let mut sample = self.source.sample(counterset_id)?;
+        if *counterset_id == self.counterset.id {
+            apply(self.counterset, &self.grouping, &mut self.ids, &mut sample);
+        }
+        Ok(sample)
+    }
+
+    fn reopen(&mut self, counterset_id: &GUID) {
+        self.source.reopen(counterset_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, contoso_disk, named, CONTOSO_DISK};
+
+    /// Contoso Disk, where Read Latency is averaged and Queue Length is the maximum.
+    fn counterset() -> CounterSet {
+        let mut counterset = contoso_disk();
+        counterset.counters[1].aggregate_func = AggregateFunc::Avg;
+        counterset.counters[5].aggregate_func = AggregateFunc::Max;
+        counterset
+    }
+
+    /// A sample of bytes read, read latency and its base, and queue length, for each named instance.
+    fn sample(instances: &[(&str, [u64; 4])]) -> Sample {
+        let instances = named(instances.iter().copied());
+        testing::sample(CONTOSO_DISK, 0, &[0, 1, 2, 5], instances)
+    }
+
+    fn synthetic(sample: &Sample, real: usize) -> Vec<(String, Vec<u64>)> {
+        sample.instances[real..]
+            .iter()
+            .map(|i| (i.instance.clone().unwrap().name, i.values.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn functions_follow_counters() {
+        let functions = functions(&counterset());
+        assert_eq!(functions[&0], AggregateFunc::Total);
+        assert_eq!(functions[&1], AggregateFunc::Avg);
+        // The base of an averaged counter is averaged too.
+        assert_eq!(functions[&2], AggregateFunc::Avg);
+        assert_eq!(functions[&5], AggregateFunc::Max);
+    }
+
+    #[test]
+    fn totals_every_instance() {
+        let counterset = counterset();
+        let mut sample = sample(&[
+            ("disk0", [100, 10, 2, 1]),
+            ("disk1", [200, 30, 4, 7]),
+            ("_Total", [999, 999, 999, 999]),
+        ]);
+        apply(
+            &counterset,
+            &Grouping::Total,
+            &mut HashMap::new(),
+            &mut sample,
+        );
+        // The provider's own _Total is left alone.
+        assert_eq!(sample.instances.len(), 3);
+
+        sample.instances.pop();
+        apply(
+            &counterset,
+            &Grouping::Total,
+            &mut HashMap::new(),
+            &mut sample,
+        );
+        assert_eq!(
+            synthetic(&sample, 2),
+            [("_Total".to_string(), vec![300, 20, 3, 7])]
+        );
+        assert_eq!(sample.instances[2].instance.as_ref().unwrap().id, u32::MAX);
+    }
+
+    #[test]
+    fn groups_by_prefix_and_regex() {
+        let counterset = counterset();
+        let instances = [
+            ("svc#1", [1, 0, 0, 1]),
+            ("svc#2", [2, 0, 0, 5]),
+            ("app", [4, 0, 0, 3]),
+        ];
+
+        let mut by_prefix = sample(&instances);
+        apply(
+            &counterset,
+            &"prefix:#".parse().unwrap(),
+            &mut HashMap::new(),
+            &mut by_prefix,
+        );
+        assert_eq!(
+            synthetic(&by_prefix, 3),
+            [
+                ("svc".to_string(), vec![3, 0, 0, 5]),
+                // app is already an instance.
+            ]
+        );
+
+        let mut by_regex = sample(&instances);
+        apply(
+            &counterset,
+            &r"regex:^svc#(\d)".parse().unwrap(),
+            &mut HashMap::new(),
+            &mut by_regex,
+        );
+        assert_eq!(
+            synthetic(&by_regex, 3),
+            [
+                ("1".to_string(), vec![1, 0, 0, 1]),
+                ("2".to_string(), vec![2, 0, 0, 5]),
+            ]
+        );
+
+        assert!("regex:(".parse::<Grouping>().is_err());
+        assert!("prefix:".parse::<Grouping>().is_err());
+    }
+
+    #[test]
+    fn keeps_ids_as_groups_come_and_go() {
+        let counterset = counterset();
+        let grouping = "prefix:#".parse().unwrap();
+        let mut ids = HashMap::new();
+        let mut aggregate = |instances: &[(&str, [u64; 4])]| {
+            let mut sample = sample(instances);
+            apply(&counterset, &grouping, &mut ids, &mut sample);
+            sample.instances[instances.len()..]
+                .iter()
+                .map(|i| {
+                    let instance = i.instance.as_ref().unwrap();
+                    (instance.name.clone(), instance.id)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let both = [("a#1", [0; 4]), ("b#1", [0; 4])];
+        assert_eq!(
+            aggregate(&both),
+            [("a".to_string(), u32::MAX), ("b".to_string(), u32::MAX - 1)]
+        );
+        assert_eq!(
+            aggregate(&[("b#1", [0; 4])]),
+            [("b".to_string(), u32::MAX - 1)]
+        );
+        assert_eq!(
+            aggregate(&[("c#1", [0; 4]), ("a#1", [0; 4])]),
+            [("c".to_string(), u32::MAX - 2), ("a".to_string(), u32::MAX)]
+        );
+    }
+}
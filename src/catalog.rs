@@ -0,0 +1,149 @@
+use crate::error::Result;
+use crate::glob::Glob;
+use crate::types::{parse_guid, CounterSet, Provider};
+use std::convert::Infallible;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use windows::core::GUID;
+
+/// A counterset, identified by its GUID or by its name.
+#[derive(Debug, Clone)]
+pub enum CounterSetRef {
+    Id(GUID),
+    Name(String),
+}
+
+impl CounterSetRef {
+    pub fn find<'a>(&self, all: &'a [Provider]) -> Result<&'a CounterSet> {
+        self.find_with_provider(all).map(|(_, cs)| cs)
+    }
+
+    /// Find the counterset, along with the provider it belongs to.
+    pub fn find_with_provider<'a>(
+        &self,
+        all: &'a [Provider],
+    ) -> Result<(&'a Provider, &'a CounterSet)> {
+        let mut matches = all
+            .iter()
+            .flat_map(|p| p.countersets.iter().map(move |cs| (p, cs)))
+            .filter(|(_, cs)| match self {
+                CounterSetRef::Id(id) => cs.id == *id,
+                CounterSetRef::Name(name) => cs.name.eq_ignore_ascii_case(name),
+            });
+
+        let Some(first) = matches.next() else {
+            return Err(format!("Counterset {} not found", self).into());
+        };
+
+        // Names aren't guaranteed to be unique across providers.
+        let others = matches
+            .map(|(_, cs)| format!("{:?}", cs.id))
+            .collect::<Vec<_>>();
+        if !others.is_empty() {
+            return Err(format!(
+                "Counterset {} is ambiguous, use one of its GUIDs instead: {:?}, {}",
+                self,
+                first.1.id,
+                others.join(", ")
+            )
+            .into());
+        }
+
+        Ok(first)
+    }
+}
+
+impl FromStr for CounterSetRef {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match parse_guid(s) {
+            Some(id) => CounterSetRef::Id(id),
+            None => CounterSetRef::Name(s.to_string()),
+        })
+    }
+}
+
+impl Display for CounterSetRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CounterSetRef::Id(id) => write!(f, "{:?}", id),
+            CounterSetRef::Name(name) => write!(f, "\"{}\"", name),
+        }
+    }
+}
+
+/// A PDH-style path to a counter, e.g. `\LogicalDisk(*)\% Free Space`.
+///
+/// The counterset can be given by its GUID and the counter by its id instead of their names, e.g.
+/// `\8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0(C:)\3`.
+#[derive(Debug, Clone)]
+pub struct CounterPath {
+    pub counterset: CounterSetRef,
+    /// Which instances to evaluate; all of them if None.
+    pub instance: Option<Glob>,
+    pub counter: CounterRef,
+    /// The path as written, for messages.
+    text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CounterRef {
+    Id(u32),
+    Name(String),
+}
+
+impl FromStr for CounterPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let malformed = || {
+            format!(
+                "expected a path like `\\Counterset(instance)\\Counter`, got `{}`",
+                s
+            )
+        };
+        let (object, counter) = s
+            .strip_prefix('\\')
+            .and_then(|s| s.rsplit_once('\\'))
+            .ok_or_else(malformed)?;
+
+        let (counterset, instance) = match object.strip_suffix(')') {
+            Some(object) => {
+                let (counterset, instance) = object.rsplit_once('(').ok_or_else(malformed)?;
+                (counterset, Some(Glob::new(instance)))
+            }
+            None => (object, None),
+        };
+        if counterset.is_empty() || counter.is_empty() {
+            return Err(malformed());
+        }
+
+        let counter = match counter.parse() {
+            Ok(id) => CounterRef::Id(id),
+            Err(_) => CounterRef::Name(counter.to_string()),
+        };
+
+        Ok(Self {
+            counterset: counterset.parse().unwrap(),
+            instance,
+            counter,
+            text: s.to_string(),
+        })
+    }
+}
+
+impl Display for CounterPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Display for CounterRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CounterRef::Id(id) => write!(f, "{}", id),
+            CounterRef::Name(name) => write!(f, "\"{}\"", name),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The passage of time, abstracted so that periodic sampling can be driven by tests.
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+
+    fn sleep(&mut self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
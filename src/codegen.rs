@@ -0,0 +1,374 @@
+//! Generate code for reading a counterset from other programs.
+
+use crate::types::counter_type::Size;
+use crate::types::{Counter, CounterSet, InstanceType};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Language {
+    Rust,
+}
+
+pub fn generate(language: Language, counterset: &CounterSet) -> String {
+    match language {
+        Language::Rust => rust(counterset),
+    }
+}
+
+/// Code shared by every generated Rust module, which queries perflib and parses the result.
+const RUST_READER: &str = include_str!("codegen/reader.rs.in");
+
+/// Generate a self-contained Rust module which reads a counterset using the `windows` crate.
+pub fn rust(counterset: &CounterSet) -> String {
+    let mut out = String::new();
+
+    let struct_name = pascal_case(&counterset.name);
+    let fields = fields(counterset);
+
+    writeln!(
+        out,
+        "//! Bindings for the \"{}\" counterset, generated by perflib-explorer.",
+        counterset.name
+    )
+    .unwrap();
+    writeln!(out, "//!").unwrap();
+    writeln!(
+        out,
+        "//! Requires the `windows` crate with the `Win32_Foundation` and `Win32_System_Performance` features."
+    )
+    .unwrap();
+    out.push_str(
+        "
+#![allow(dead_code)]
+
+use std::mem;
+use windows::core::{Error, Result, GUID, PCWSTR};
+use windows::Win32::Foundation::{
+    ERROR_INVALID_DATA, ERROR_NOT_ENOUGH_MEMORY, ERROR_SUCCESS, HANDLE, WIN32_ERROR,
+};
+use windows::Win32::System::Performance::{
+    PerfAddCounters, PerfCloseQueryHandle, PerfOpenQueryHandle, PerfQueryCounterData,
+    PERF_COUNTERSET, PERF_COUNTER_DATA, PERF_COUNTER_HEADER, PERF_COUNTER_IDENTIFIER,
+    PERF_DATA_HEADER, PERF_ERROR_RETURN, PERF_INSTANCE_HEADER, PERF_MULTIPLE_COUNTERS,
+    PERF_MULTI_COUNTERS, PERF_MULTI_INSTANCES, PERF_WILDCARD_COUNTER,
+};
+
+",
+    );
+
+    write_doc(&mut out, "", &counterset.help, &counterset.name);
+    writeln!(
+        out,
+        "pub const COUNTERSET_ID: GUID = GUID::from_u128(0x{});",
+        format!("{:?}", counterset.id).replace('-', "_")
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "/// Ids of the counters in the counterset.").unwrap();
+    writeln!(out, "pub mod counter_ids {{").unwrap();
+    for field in &fields {
+        writeln!(out, "    /// {}", field.counter.name).unwrap();
+        writeln!(
+            out,
+            "    pub const {}: u32 = {};",
+            field.name.trim_start_matches("r#").to_uppercase(),
+            field.counter.id
+        )
+        .unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "/// Raw values of the counters for one instance.").unwrap();
+    writeln!(out, "#[derive(Debug, Clone, Default, PartialEq, Eq)]").unwrap();
+    writeln!(out, "pub struct {} {{", struct_name).unwrap();
+    for field in &fields {
+        let Some(ty) = field.ty else {
+            writeln!(
+                out,
+                "    // {} ({:?}) has no numeric value.",
+                field.counter.name, field.counter.counter_type
+            )
+            .unwrap();
+            continue;
+        };
+        if !field.counter.help.trim().is_empty() {
+            write_doc(&mut out, "    ", &field.counter.help, &field.counter.name);
+            writeln!(out, "    ///").unwrap();
+        }
+        writeln!(
+            out,
+            "    /// {}: `{:?}`",
+            field.counter.name, field.counter.counter_type
+        )
+        .unwrap();
+        writeln!(out, "    pub {}: {},", field.name, ty).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "impl {} {{", struct_name).unwrap();
+    writeln!(out, "    fn set(&mut self, counter_id: u32, value: u64) {{").unwrap();
+    writeln!(out, "        match counter_id {{").unwrap();
+    for field in &fields {
+        let conversion = match field.ty {
+            Some("u32") => " as u32",
+            Some(_) => "",
+            None => continue,
+        };
+        writeln!(
+            out,
+            "            counter_ids::{} => self.{} = value{},",
+            field.name.trim_start_matches("r#").to_uppercase(),
+            field.name,
+            conversion
+        )
+        .unwrap();
+    }
+    writeln!(out, "            _ => {{}}").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+
+    match counterset.instance_type {
+        InstanceType::SingleInstance | InstanceType::SingleAggregate => {
+            write!(
+                out,
+                "\
+impl Reader {{
+    /// Read the current values of the counters.
+    pub fn read(&mut self) -> Result<{struct_name}> {{
+        let mut values = {struct_name}::default();
+        for instance in self.query()? {{
+            for (id, value) in instance.values {{
+                values.set(id, value);
+            }}
+        }}
+        Ok(values)
+    }}
+}}
+"
+            )
+            .unwrap();
+        }
+        InstanceType::MultiInstances | InstanceType::MultiAggregate => {
+            write!(
+                out,
+                "\
+/// An instance of the counterset, and the values of its counters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {{
+    pub id: u32,
+    pub name: String,
+    pub values: {struct_name},
+}}
+
+impl Reader {{
+    /// Read the current values of the counters for every instance.
+    pub fn read(&mut self) -> Result<Vec<Instance>> {{
+        let instances = self.query()?;
+        let instances = instances
+            .into_iter()
+            .map(|instance| {{
+                let mut values = {struct_name}::default();
+                for (id, value) in instance.values {{
+                    values.set(id, value);
+                }}
+                Instance {{
+                    id: instance.id,
+                    name: instance.name,
+                    values,
+                }}
+            }})
+            .collect();
+        Ok(instances)
+    }}
+}}
+"
+            )
+            .unwrap();
+        }
+    }
+    writeln!(out).unwrap();
+
+    out.push_str(RUST_READER);
+
+    out
+}
+
+struct Field<'a> {
+    counter: &'a Counter,
+    /// A unique snake_case identifier for the counter.
+    name: String,
+    /// The Rust type of the raw value, or None if it doesn't have a numeric value.
+    ty: Option<&'static str>,
+}
+
+fn fields(counterset: &CounterSet) -> Vec<Field<'_>> {
+    let mut names = HashSet::new();
+    counterset
+        .counters
+        .iter()
+        .map(|counter| {
+            let mut base = snake_case(&counter.name);
+            if base.is_empty() {
+                base = format!("counter_{}", counter.id);
+            }
+            // These can't be raw identifiers.
+            if matches!(base.as_str(), "self" | "super" | "crate") {
+                base.push('_');
+            }
+            // The id alone isn't always enough, e.g. "Foo 3" (id 1), "Foo" (id 2) and "Foo" (id 3).
+            let mut name = base.clone();
+            let mut attempt = 1;
+            while !names.insert(name.clone()) {
+                name = match attempt {
+                    1 => format!("{}_{}", base, counter.id),
+                    _ => format!("{}_{}_{}", base, counter.id, attempt),
+                };
+                attempt += 1;
+            }
+            if KEYWORDS.contains(&name.as_str()) {
+                name = format!("r#{}", name);
+            }
+
+            let ty = match counter.counter_type.size() {
+                Size::Dword => Some("u32"),
+                Size::Large => Some("u64"),
+                Size::Zero | Size::Variable => None,
+            };
+
+            Field { counter, name, ty }
+        })
+        .collect()
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "abstract", "become", "box", "do", "final", "gen", "macro", "override",
+    "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// The words of a display name, e.g. "% Idle Time/sec" -> ["percent", "idle", "time", "per", "sec"].
+fn words(name: &str) -> Vec<String> {
+    let name = name.replace('%', " percent ").replace('/', " per ");
+    let mut words = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    // Identifiers can't start with a digit.
+    if words
+        .first()
+        .is_some_and(|w| w.starts_with(|c: char| c.is_ascii_digit()))
+    {
+        words.insert(0, "n".to_string());
+    }
+    words
+}
+
+fn snake_case(name: &str) -> String {
+    words(name).join("_")
+}
+
+fn pascal_case(name: &str) -> String {
+    let name = words(name)
+        .iter()
+        .map(|w| w[..1].to_ascii_uppercase() + &w[1..])
+        .collect::<String>();
+    match name.as_str() {
+        "" => "Values".to_string(),
+        // Can't be a raw identifier.
+        "Self" => "Self_".to_string(),
+        _ => name,
+    }
+}
+
+/// Write a doc comment, falling back to a placeholder if there's no help text.
+fn write_doc(out: &mut String, indent: &str, help: &str, fallback: &str) {
+    let text = match help.trim() {
+        "" => fallback,
+        help => help,
+    };
+    for line in text.lines() {
+        writeln!(out, "{}/// {}", indent, line.trim_end()).unwrap();
+    }
+}
+
+// The expected output of the tests is compiled too, to check that the generated code is valid.
+#[cfg(all(test, windows))]
+#[allow(clippy::all)]
+#[path = "../testdata/codegen/contoso_cache.rs"]
+mod contoso_cache;
+#[cfg(all(test, windows))]
+#[allow(clippy::all)]
+#[path = "../testdata/codegen/contoso_disk.rs"]
+mod contoso_disk;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::CounterSetRef;
+    use crate::testing::{catalog, contoso_disk};
+    use std::fs;
+    use std::path::Path;
+
+    fn check(counterset: &str, expected: &str) {
+        let all = catalog();
+        let counterset = counterset.parse::<CounterSetRef>().unwrap();
+        let generated = rust(counterset.find(&all).unwrap());
+
+        let path = Path::new("testdata/codegen").join(expected);
+        if std::env::var_os("UPDATE_EXPECT").is_some() {
+            fs::write(&path, &generated).unwrap();
+        }
+        assert_eq!(generated, fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn multi_instance() {
+        check("Contoso Disk", "contoso_disk.rs");
+    }
+
+    #[test]
+    fn single_instance() {
+        check("Contoso Cache", "contoso_cache.rs");
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(snake_case("% Idle Time"), "percent_idle_time");
+        assert_eq!(snake_case("Bytes Sent/sec"), "bytes_sent_per_sec");
+        assert_eq!(snake_case("4K Reads"), "n_4k_reads");
+        assert_eq!(
+            pascal_case("Contoso Network Adapter"),
+            "ContosoNetworkAdapter"
+        );
+        assert_eq!(pascal_case("???"), "Values");
+        assert_eq!(pascal_case("self"), "Self_");
+    }
+
+    #[test]
+    fn field_names_are_unique_identifiers() {
+        let mut counterset = contoso_disk();
+        let names = ["Foo 3", "Foo", "Foo", "Super", "Self", "Type"];
+        for (counter, name) in counterset.counters.iter_mut().zip(names) {
+            counter.name = name.to_string();
+        }
+        counterset.counters[2].id = 3;
+
+        let names = fields(&counterset)
+            .into_iter()
+            .map(|f| f.name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["foo_3", "foo", "foo_3_2", "super_", "self_", "r#type"]
+        );
+    }
+}
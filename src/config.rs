@@ -0,0 +1,549 @@
+//! Collection jobs described in a TOML or YAML file, e.g.
+//!
+//! ```toml
+//! [[job]]
+//! name = "disks"
+//! countersets = ["LogicalDisk", '\Processor(*)\% Processor Time']
+//! instances = { include = ["C:", "D:"], exclude = ["_Total"] }
+//! counters = { include = ["*Bytes*", "% *"] }
+//! interval = "10s"
+//! labels = { env = "prod" }
+//!
+//! [[job.sink]]
+//! type = "otlp"
+//! endpoint = "http://localhost:4318/v1/metrics"
+//!
+//! [store]
+//! path = "samples"
+//! size = "64MiB"
+//! retention = "7d"
+//! tiers = "1m,1h"
+//! ```
+//!
+//! or the same in YAML, with `job` and `sink` as lists.
+
+use crate::catalog::{CounterPath, CounterRef, CounterSetRef};
+use crate::cook;
+use crate::error::Result;
+use crate::export::{Encoding, Exporter, Graphite, Otlp, Statsd, Template};
+use crate::glob::Glob;
+use crate::http::Endpoint;
+use crate::store::Store;
+use crate::types::{Counter, CounterSet, Provider};
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "job")]
+    pub jobs: Vec<Job>,
+    /// Where to keep samples, to read back with `query` and to spool to while sinks can't be reached. Each job's
+    /// are kept in a directory of their own, named after it.
+    #[serde(default)]
+    pub store: Option<Store>,
+}
+
+/// Countersets to sample together, and where to send their values.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub name: String,
+    pub countersets: Vec<Selector>,
+    #[serde(default)]
+    pub instances: Filter,
+    #[serde(default)]
+    pub counters: Filter,
+    #[serde(default = "default_interval", deserialize_with = "duration")]
+    pub interval: Duration,
+    /// Added to every value the job sends.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(rename = "sink")]
+    pub sinks: Vec<Sink>,
+}
+
+/// A counterset by GUID or name, or a path like `\Processor(*)\% Processor Time` which also selects instances and
+/// counters.
+#[derive(Debug, Clone)]
+pub enum Selector {
+    Counterset(CounterSetRef),
+    Path(CounterPath),
+}
+
+/// Names to include, unless they're excluded. With no includes, everything is included.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    #[serde(default)]
+    pub include: Vec<Glob>,
+    #[serde(default)]
+    pub exclude: Vec<Glob>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Sink {
+    Otlp {
+        #[serde(deserialize_with = "parsed")]
+        endpoint: Endpoint,
+        #[serde(default = "default_encoding")]
+        encoding: Encoding,
+    },
+    Statsd {
+        address: String,
+        #[serde(default, deserialize_with = "parsed")]
+        template: Template,
+    },
+    Graphite {
+        address: String,
+        #[serde(default, deserialize_with = "parsed")]
+        template: Template,
+    },
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_encoding() -> Encoding {
+    Encoding::Protobuf
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Duration, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
+fn parsed<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+impl<'de> Deserialize<'de> for Selector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        parsed(deserializer)
+    }
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.starts_with('\\') {
+            true => s.parse().map(Selector::Path),
+            false => Ok(Selector::Counterset(s.parse().unwrap())),
+        }
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::Counterset(counterset) => write!(f, "{}", counterset),
+            Selector::Path(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.matches(name)))
+            && !self.exclude.iter().any(|glob| glob.matches(name))
+    }
+}
+
+impl Sink {
+    /// Get ready to send to the sink.
+    pub fn open(&self) -> Result<Box<dyn Exporter>> {
+        Ok(match self {
+            Sink::Otlp { endpoint, encoding } => Box::new(Otlp::new(endpoint.clone(), *encoding)),
+            Sink::Statsd { address, template } => Box::new(Statsd::new(address, template.clone())?),
+            Sink::Graphite { address, template } => {
+                Box::new(Graphite::new(address, template.clone()))
+            }
+        })
+    }
+}
+
+impl Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sink::Otlp { endpoint, encoding } => {
+                let encoding = encoding.to_possible_value().unwrap();
+                write!(f, "otlp {} as {}", endpoint, encoding.get_name())
+            }
+            Sink::Statsd { address, template } => write!(f, "statsd {} as {}", address, template),
+            Sink::Graphite { address, template } => {
+                write!(f, "graphite {} as {}", address, template)
+            }
+        }
+    }
+}
+
+/// Read a config file, as YAML if its extension says so and TOML otherwise, and check that it makes sense by
+/// itself.
+pub fn load(path: &Path) -> Result<Config> {
+    let text = fs::read_to_string(path)?;
+    let yaml = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml"));
+    let config = match yaml {
+        true => parse_yaml(&text),
+        false => parse_toml(&text),
+    };
+    config.map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+pub fn parse_toml(text: &str) -> Result<Config> {
+    let config: Config = toml::from_str(text)?;
+    config.check()?;
+    Ok(config)
+}
+
+pub fn parse_yaml(text: &str) -> Result<Config> {
+    let config: Config = serde_yaml::from_str(text)?;
+    config.check()?;
+    Ok(config)
+}
+
+/// The countersets, instances and counters one selector of a job picks out of the catalog.
+#[derive(Debug)]
+pub struct Selection<'a> {
+    pub provider: &'a Provider,
+    pub counterset: &'a CounterSet,
+    /// Instances the selector's path is limited to, besides the job's filter.
+    pub instance: Option<Glob>,
+    pub counters: Vec<&'a Counter>,
+}
+
+impl Config {
+    fn check(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        let mut names = HashSet::new();
+        if self.jobs.is_empty() {
+            errors.push("no jobs are configured".to_string());
+        }
+        for job in &self.jobs {
+            if !names.insert(job.name.as_str()) {
+                errors.push(format!("job \"{}\" is configured more than once", job.name));
+            }
+            if job.countersets.is_empty() {
+                errors.push(format!("job \"{}\" has no countersets", job.name));
+            }
+            if job.sinks.is_empty() {
+                errors.push(format!("job \"{}\" has no sinks", job.name));
+            }
+            if job.interval.is_zero() {
+                errors.push(format!("job \"{}\" has a zero interval", job.name));
+            }
+            let reserved = |c: char| c.is_control() || "/\\:*?\"<>|".contains(c);
+            if self.store.is_some()
+                && (job.name.is_empty() || job.name.starts_with('.') || job.name.contains(reserved))
+            {
+                errors.push(format!(
+                    "job \"{}\" can't be stored, as its name isn't a valid directory name",
+                    job.name
+                ));
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("\n").into()),
+        }
+    }
+
+    /// Find what every job selects in the catalog, reporting every counterset or counter that can't be found.
+    pub fn resolve<'a>(&self, all: &'a [Provider]) -> Result<Vec<Vec<Selection<'a>>>> {
+        let mut errors = Vec::new();
+        let mut resolved = Vec::new();
+        for job in &self.jobs {
+            match job.resolve(all) {
+                Ok(selections) => resolved.push(selections),
+                Err(job_errors) => errors.extend(job_errors),
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(resolved),
+            false => Err(errors.join("\n").into()),
+        }
+    }
+}
+
+impl Job {
+    pub fn resolve<'a>(
+        &self,
+        all: &'a [Provider],
+    ) -> std::result::Result<Vec<Selection<'a>>, Vec<String>> {
+        let (selections, errors) = self.select(all);
+        match errors.is_empty() {
+            true => Ok(selections),
+            false => Err(errors),
+        }
+    }
+
+    /// Find as much of what the job selects as the catalog has, along with what it doesn't.
+    pub fn select<'a>(&self, all: &'a [Provider]) -> (Vec<Selection<'a>>, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut selections = Vec::new();
+        for selector in &self.countersets {
+            let counterset = match selector {
+                Selector::Counterset(counterset) => counterset,
+                Selector::Path(path) => &path.counterset,
+            };
+            let (provider, counterset) = match counterset.find_with_provider(all) {
+                Ok(found) => found,
+                Err(e) => {
+                    errors.push(format!("job \"{}\": {}", self.name, e));
+                    continue;
+                }
+            };
+
+            let (instance, named) = match selector {
+                Selector::Counterset(_) => (None, None),
+                Selector::Path(path) => (path.instance.clone(), Some(&path.counter)),
+            };
+            let named_matches = |counter: &Counter| match named {
+                None => true,
+                Some(CounterRef::Id(id)) => counter.id == *id,
+                Some(CounterRef::Name(name)) => counter.name.eq_ignore_ascii_case(name),
+            };
+            if let Some(named) = named {
+                if !counterset.counters.iter().any(named_matches) {
+                    errors.push(format!(
+                        "job \"{}\": counterset {} has no counter {}",
+                        self.name, counterset.name, named
+                    ));
+                    continue;
+                }
+            }
+
+            let counters = counterset
+                .counters
+                .iter()
+                .filter(|c| cook::is_displayable(c.counter_type))
+                .filter(|c| named_matches(c) && self.counters.matches(&c.name))
+                .collect::<Vec<_>>();
+            if counters.is_empty() {
+                errors.push(format!(
+                    "job \"{}\": no counters of {} with a value are selected by {}",
+                    self.name, counterset.name, selector
+                ));
+                continue;
+            }
+            selections.push(Selection {
+                provider,
+                counterset,
+                instance,
+                counters,
+            });
+        }
+
+        // A counter pattern which matches nothing is probably a typo.
+        for glob in &self.counters.include {
+            let matched = selections
+                .iter()
+                .flat_map(|s| &s.counterset.counters)
+                .any(|c| glob.matches(&c.name));
+            if !matched && errors.is_empty() {
+                errors.push(format!(
+                    "job \"{}\": counter pattern `{}` doesn't match any counter",
+                    self.name, glob
+                ));
+            }
+        }
+
+        (selections, errors)
+    }
+}
+
+impl Selection<'_> {
+    /// Whether the job sends values for an instance, by name; single-instance countersets have none.
+    pub fn includes(&self, filter: &Filter, instance: Option<&str>) -> bool {
+        let Some(instance) = instance else {
+            return true;
+        };
+        self.instance
+            .as_ref()
+            .is_none_or(|glob| glob.matches(instance))
+            && filter.matches(instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::catalog;
+
+    const TOML: &str = r#"
+        [[job]]
+        name = "storage"
+        countersets = ["Contoso Cache", '\Contoso Disk(*)\Queue Length']
+        instances = { exclude = ["_Total"] }
+        interval = "30s"
+        labels = { env = "prod" }
+
+        [[job.sink]]
+        type = "otlp"
+        endpoint = "http://localhost:4318/v1/metrics"
+        encoding = "json"
+
+        [[job]]
+        name = "network"
+        countersets = ["0A1B2C3D-4E5F-4061-8293-A4B5C6D7E8F9"]
+        counters = { include = ["Bytes*"] }
+
+        [[job.sink]]
+        type = "statsd"
+        address = "localhost:8125"
+    "#;
+
+    const YAML: &str = r#"
+        job:
+          - name: storage
+            countersets: ["Contoso Cache", '\Contoso Disk(*)\Queue Length']
+            instances: { exclude: [_Total] }
+            interval: 30s
+            labels: { env: prod }
+            sink:
+              - type: otlp
+                endpoint: http://localhost:4318/v1/metrics
+                encoding: json
+          - name: network
+            countersets: [0A1B2C3D-4E5F-4061-8293-A4B5C6D7E8F9]
+            counters: { include: [Bytes*] }
+            sink:
+              - type: statsd
+                address: localhost:8125
+    "#;
+
+    fn describe(config: &Config, all: &[Provider]) -> Vec<String> {
+        let resolved = config.resolve(all).unwrap();
+        config
+            .jobs
+            .iter()
+            .zip(resolved)
+            .flat_map(|(job, selections)| {
+                selections.into_iter().map(move |s| {
+                    format!(
+                        "{} every {}: {}/{}({}) {:?} to {}",
+                        job.name,
+                        humantime::format_duration(job.interval),
+                        s.provider.name,
+                        s.counterset.name,
+                        s.instance.map_or("*".to_string(), |g| g.to_string()),
+                        s.counters.iter().map(|c| &c.name).collect::<Vec<_>>(),
+                        job.sinks[0]
+                    )
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_toml_and_yaml_alike() {
+        let all = catalog();
+        let toml = parse_toml(TOML).unwrap();
+        let yaml = parse_yaml(YAML).unwrap();
+        let expected = [
+            "storage every 30s: Contoso-Storage/Contoso Cache(*) [\"Cache Hits\", \"Cache Misses\", \"Cache Hit Ratio\"] to otlp http://localhost:4318/v1/metrics as json",
+            "storage every 30s: Contoso-Storage/Contoso Disk(*) [\"Queue Length\"] to otlp http://localhost:4318/v1/metrics as json",
+            "network every 10s: Contoso-Network/Contoso Network Adapter(*) [\"Bytes Sent/sec\", \"Bytes Received/sec\"] to statsd localhost:8125 as perflib.{counterset}.{instance}.{counter}",
+        ];
+        assert_eq!(describe(&toml, &all), expected);
+        assert_eq!(describe(&yaml, &all), expected);
+
+        let storage = &toml.jobs[0];
+        assert_eq!(storage.labels["env"], "prod");
+        assert!(matches!(
+            storage.sinks[0],
+            Sink::Otlp {
+                encoding: Encoding::Json,
+                ..
+            }
+        ));
+        assert!(storage.instances.matches("C:"));
+        assert!(!storage.instances.matches("_total"));
+    }
+
+    #[test]
+    fn rejects_malformed_configs() {
+        for (toml, expected) in [
+            ("", "no jobs are configured"),
+            (
+                "[[job]]\nname = \"a\"\ncountersets = [\"x\"]\nsink = []\n[[job]]\nname = \"a\"\ncountersets = []\nsink = []\ninterval = \"0s\"",
+                "job \"a\" has no sinks\njob \"a\" is configured more than once\njob \"a\" has no countersets\njob \"a\" has no sinks\njob \"a\" has a zero interval",
+            ),
+            (
+                "[[job]]\nname = \"a\"\ncountersets = [\"x\"]\nintervall = \"1s\"\nsink = []",
+                "unknown field `intervall`",
+            ),
+            (
+                "[[job]]\nname = \"a\"\ncountersets = [\"x\"]\n[[job.sink]]\ntype = \"kafka\"",
+                "unknown variant `kafka`",
+            ),
+            (
+                "[[job]]\nname = \"a\"\ncountersets = [\"x\"]\n[[job.sink]]\ntype = \"otlp\"\nendpoint = \"https://collector\"",
+                "only http:// URLs are supported",
+            ),
+            (
+                "[[job]]\nname = \"a\"\ncountersets = ['\\x']\n[[job.sink]]\ntype = \"statsd\"\naddress = \"localhost:8125\"",
+                "expected a path like",
+            ),
+            (
+                "[store]\npath = \"spool\"\nsize = \"1KB\"\n[[job]]\nname = \"a\"\ncountersets = [\"x\"]\nsink = []",
+                "a store needs at least 65536 bytes",
+            ),
+            (
+                "[store]\npath = \"spool\"\n[[job]]\nname = \"a/b\"\ncountersets = [\"x\"]\n[[job.sink]]\ntype = \"statsd\"\naddress = \"localhost:8125\"",
+                "job \"a/b\" can't be stored, as its name isn't a valid directory name",
+            ),
+        ] {
+            let error = parse_toml(toml).unwrap_err().to_string();
+            assert!(error.contains(expected), "{:?}: {}", toml, error);
+        }
+    }
+
+    #[test]
+    fn reports_everything_missing_from_the_catalog() {
+        let config = parse_toml(
+            r#"
+            [[job]]
+            name = "typos"
+            countersets = ["Contoso Dsk", '\Contoso Disk(*)\Bytes Wrote', '\Contoso Cache\Cache Hit Ratio Base']
+            [[job.sink]]
+            type = "graphite"
+            address = "localhost:2003"
+
+            [[job]]
+            name = "patterns"
+            countersets = ["Contoso Disk"]
+            counters = { include = ["Bytes*", "Writes*"] }
+            [[job.sink]]
+            type = "graphite"
+            address = "localhost:2003"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.resolve(&catalog()).unwrap_err().to_string(),
+            "\
+job \"typos\": Counterset \"Contoso Dsk\" not found
+job \"typos\": counterset Contoso Disk has no counter \"Bytes Wrote\"
+job \"typos\": no counters of Contoso Cache with a value are selected by \\Contoso Cache\\Cache Hit Ratio Base
+job \"patterns\": counter pattern `writes*` doesn't match any counter"
+        );
+    }
+}
@@ -0,0 +1,478 @@
+use crate::types::counter_type::{Display, Kind, Size, Timer};
+use crate::types::{Counter, CounterType, InstanceSample, Sample};
+
+/// 100ns units per second.
+const FREQUENCY_100NS: i64 = 10_000_000;
+
+/// A counter's raw value from one sample, together with everything needed to cook it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Raw {
+    pub value: u64,
+    /// The value of the counter's base counter (or multi counter), if it has one.
+    pub base: Option<u64>,
+    /// When the sample was taken, in the counter's time base.
+    pub time: i64,
+    /// Ticks per second of `time`.
+    pub frequency: i64,
+}
+
+impl Raw {
+    /// Extract the raw value of a counter for one instance of a sample.
+    ///
+    /// Returns None if the sample doesn't contain the counter.
+    pub fn from_sample(
+        counter: &Counter,
+        sample: &Sample,
+        instance: &InstanceSample,
+    ) -> Option<Self> {
+        let value_of = |id: u32| {
+            let index = sample.counter_ids.iter().position(|&c| c == id)?;
+            instance.values.get(index).copied()
+        };
+
+        let value = value_of(counter.id)?;
+
+        let base_id = match counter.counter_type.is_multi() {
+            true => counter.multi_counter_id.or(counter.base_counter_id),
+            false => counter.base_counter_id,
+        };
+        let base = base_id.and_then(|id| value_of(id.get()));
+
+        let (time, frequency) = match counter.counter_type.timer() {
+            Timer::Tick => (sample.timestamp, sample.frequency),
+            Timer::Ns100 => (sample.time_100ns, FREQUENCY_100NS),
+            Timer::Object => {
+                let time = counter.perf_time_id.and_then(|id| value_of(id.get()));
+                let frequency = counter.perf_freq_id.and_then(|id| value_of(id.get()));
+                match (time, frequency) {
+                    (Some(time), Some(frequency)) => (time as i64, frequency as i64),
+                    // Without its own clock, assume the object's time is in 100ns units like most providers use.
+                    _ => (sample.time_100ns, FREQUENCY_100NS),
+                }
+            }
+        };
+
+        Some(Self {
+            value,
+            base,
+            time,
+            frequency,
+        })
+    }
+}
+
+/// Compute the displayable value of a counter for one instance of a sample,
+/// using the same instance from the previous sample if the counter type needs it.
+pub fn cook_instance(
+    counter: &Counter,
+    previous: Option<(&Sample, &InstanceSample)>,
+    sample: &Sample,
+    instance: &InstanceSample,
+) -> Option<f64> {
+    let current = Raw::from_sample(counter, sample, instance)?;
+    let previous = previous.and_then(|(previous, previous_instance)| {
+        Raw::from_sample(counter, previous, previous_instance)
+    });
+    cook(counter.counter_type, previous.as_ref(), &current)
+}
+
+/// Whether a counter of this type has a value worth displaying by itself.
+pub fn is_displayable(counter_type: CounterType) -> bool {
+    // PERF_AVERAGE_BULK is a fraction marked NoShow, but it's the average count per operation, just without a suffix.
+    let hidden = counter_type.display() == Display::NoShow && counter_type.kind() != Kind::Fraction;
+    if hidden || counter_type.size() == Size::Zero {
+        return false;
+    }
+    match counter_type.kind() {
+        Kind::Base | Kind::Histogram | Kind::Text | Kind::Zero => false,
+        Kind::Number
+        | Kind::Value
+        | Kind::Rate
+        | Kind::Fraction
+        | Kind::Elapsed
+        | Kind::QueueLength
+        | Kind::Precision => true,
+    }
+}
+
+/// Whether a counter of this type is cooked from the change since the previous sample, rather than from one sample.
+///
+/// These are rolled up by adding up the changes over a longer interval, and the others by averaging their values.
+pub fn is_cumulative(counter_type: CounterType) -> bool {
+    match counter_type.kind() {
+        Kind::Value => counter_type.is_delta(),
+        Kind::Rate | Kind::QueueLength | Kind::Precision => true,
+        Kind::Fraction => {
+            counter_type.is_delta_base() || counter_type.display() != Display::Percent
+        }
+        Kind::Number | Kind::Elapsed | Kind::Base | Kind::Histogram | Kind::Text | Kind::Zero => {
+            false
+        }
+    }
+}
+
+/// The changes in a cumulative counter's raw values over several successive intervals, to cook it over all of
+/// them at once, e.g. the rate over a minute from a sample every second.
+///
+/// An interval over which the counter was reset is left out, rather than spoiling the rest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Deltas {
+    value: u64,
+    base: u64,
+    time: i64,
+    /// The end of the last interval added, for the frequency and the multi counter, which aren't added up.
+    last: Option<Raw>,
+}
+
+impl Deltas {
+    /// Add the change from one raw value to the next.
+    pub fn add(
+        &mut self,
+        counter_type: CounterType,
+        previous: &Raw,
+        current: &Raw,
+    ) -> Result<(), NoValue> {
+        let value = delta(counter_type.size(), previous.value, current.value)?;
+        let base = match (previous.base, current.base) {
+            (Some(previous), Some(current)) => delta(base_size(counter_type), previous, current)?,
+            _ => 0,
+        };
+        let time = match current.time.checked_sub(previous.time) {
+            Some(time @ 0..) => time,
+            _ => return Err(NoValue::Reset),
+        };
+        self.value = self.value.saturating_add(value);
+        self.base = self.base.saturating_add(base);
+        self.time = self.time.saturating_add(time);
+        self.last = Some(*current);
+        Ok(())
+    }
+
+    /// Cook the counter over every interval added, as if it had only been sampled at their start and end.
+    pub fn cook(&self, counter_type: CounterType) -> Result<f64, NoValue> {
+        let last = self.last.ok_or(NoValue::FirstSample)?;
+        let start = Raw {
+            value: 0,
+            base: Some(0),
+            time: 0,
+            frequency: last.frequency,
+        };
+        let end = Raw {
+            value: self.value,
+            // A multi counter is how many things were counted at once, not something that counts up.
+            base: match counter_type.is_multi() {
+                true => last.base,
+                false => Some(self.base),
+            },
+            time: self.time,
+            frequency: last.frequency,
+        };
+        try_cook(counter_type, Some(&start), &end)
+    }
+}
+
+/// Why a counter has no value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoValue {
+    /// The counter type measures change over time, and there's no previous sample to compare with.
+    FirstSample,
+    /// A value went backwards by more than wrapping around could explain, e.g. because the provider restarted.
+    Reset,
+    /// The counter's base (or multi counter) isn't in the sample.
+    MissingBase,
+    /// The value would be divided by zero, e.g. because no time has elapsed.
+    ZeroDivisor,
+    /// The counter type has no value by itself.
+    NotDisplayable,
+}
+
+/// How far a counter of the given size counted up between two raw values.
+///
+/// A 32-bit counter which passes its maximum starts again from zero, so a smaller value is taken to have wrapped
+/// around if that means it counted up by less than half its range. Otherwise, and for 64-bit counters, which don't
+/// wrap in practice, a smaller value means the counter was reset.
+pub fn delta(size: Size, previous: u64, current: u64) -> Result<u64, NoValue> {
+    if let Some(delta) = current.checked_sub(previous) {
+        return Ok(delta);
+    }
+    const RANGE: u64 = 1 << 32;
+    match size {
+        Size::Dword if previous < RANGE && RANGE - previous + current < RANGE / 2 => {
+            Ok(RANGE - previous + current)
+        }
+        _ => Err(NoValue::Reset),
+    }
+}
+
+/// The size of the base counter that a counter type is divided by, where the base is compared between samples.
+///
+/// Averages and sample fractions use 32-bit base counters (`PERF_AVERAGE_BASE` and `PERF_SAMPLE_BASE`), whereas
+/// precision timers are divided by a 64-bit timestamp.
+fn base_size(counter_type: CounterType) -> Size {
+    match counter_type.kind() {
+        Kind::Precision => Size::Large,
+        _ => Size::Dword,
+    }
+}
+
+/// Compute the displayable value of a counter, following the formulas for each counter type.
+///
+/// Counters which measure change over time need the raw values from the previous sample too;
+/// returns None if that isn't available, or if the value can't be computed (e.g. no time has elapsed).
+pub fn cook(counter_type: CounterType, previous: Option<&Raw>, current: &Raw) -> Option<f64> {
+    try_cook(counter_type, previous, current).ok()
+}
+
+/// Like [`cook`], but says why there's no value.
+pub fn try_cook(
+    counter_type: CounterType,
+    previous: Option<&Raw>,
+    current: &Raw,
+) -> Result<f64, NoValue> {
+    let n1 = current.value as f64;
+
+    // Differences from the previous sample, allowing for 32-bit counters wrapping around.
+    let last = || previous.ok_or(NoValue::FirstSample);
+    let delta_n = || {
+        let previous = last()?;
+        delta(counter_type.size(), previous.value, current.value).map(|d| d as f64)
+    };
+    let delta_t = || match current.time.checked_sub(last()?.time) {
+        None | Some(..=-1) => Err(NoValue::Reset),
+        Some(0) => Err(NoValue::ZeroDivisor),
+        Some(delta) => Ok(delta as f64),
+    };
+    let delta_b = || {
+        let previous = last()?;
+        let (Some(current), Some(previous)) = (current.base, previous.base) else {
+            return Err(NoValue::MissingBase);
+        };
+        match delta(base_size(counter_type), previous, current)? {
+            0 => Err(NoValue::ZeroDivisor),
+            delta => Ok(delta as f64),
+        }
+    };
+    let base = || match current.base {
+        None => Err(NoValue::MissingBase),
+        Some(0) => Err(NoValue::ZeroDivisor),
+        Some(base) => Ok(base as f64),
+    };
+    let frequency = || match current.frequency {
+        ..=0 => Err(NoValue::ZeroDivisor),
+        frequency => Ok(frequency as f64),
+    };
+
+    if !is_displayable(counter_type) {
+        return Err(NoValue::NotDisplayable);
+    }
+    match counter_type.kind() {
+        Kind::Number => Ok(n1),
+        Kind::Value => match counter_type.is_delta() {
+            true => delta_n(),
+            false => Ok(n1),
+        },
+        Kind::Rate => match counter_type.display() {
+            // Busy time, as a fraction of elapsed time.
+            Display::Percent => {
+                let fraction = delta_n()? / delta_t()?;
+                let fraction = match (counter_type.is_multi(), counter_type.is_inverse()) {
+                    (false, false) => fraction,
+                    (false, true) => 1.0 - fraction,
+                    (true, false) => fraction / base()?,
+                    (true, true) => (base()? - fraction) / base()?,
+                };
+                Ok(100.0 * fraction)
+            }
+            // Events per second.
+            _ => Ok(delta_n()? / (delta_t()? / frequency()?)),
+        },
+        Kind::Fraction => match counter_type.display() {
+            Display::Percent => match counter_type.is_delta_base() {
+                true => Ok(100.0 * delta_n()? / delta_b()?),
+                false => Ok(100.0 * n1 / base()?),
+            },
+            // Average time per operation.
+            Display::Seconds => Ok(delta_n()? / frequency()? / delta_b()?),
+            // Average count per operation.
+            _ => Ok(delta_n()? / delta_b()?),
+        },
+        Kind::Elapsed => Ok((current.time as f64 - n1) / frequency()?),
+        Kind::QueueLength => Ok(delta_n()? / delta_t()?),
+        // The base counter holds the timestamp.
+        Kind::Precision => Ok(100.0 * delta_n()? / delta_b()?),
+        Kind::Base | Kind::Histogram | Kind::Text | Kind::Zero => Err(NoValue::NotDisplayable),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX32: u64 = u32::MAX as u64;
+
+    /// A counter type, its previous and current raw values, and what they cook to.
+    type Case = (CounterType, Option<Raw>, Raw, Result<f64, NoValue>);
+
+    /// A raw value with a base, at a time in milliseconds.
+    fn raw(value: u64, base: u64, time: i64) -> Raw {
+        Raw {
+            value,
+            base: Some(base),
+            time,
+            frequency: 1000,
+        }
+    }
+
+    #[test]
+    fn detects_wraparound_and_resets() {
+        assert_eq!(delta(Size::Dword, 10, 30), Ok(20));
+        assert_eq!(delta(Size::Dword, MAX32 - 9, 10), Ok(20));
+        assert_eq!(delta(Size::Dword, 1_000_000, 10), Err(NoValue::Reset));
+        assert_eq!(delta(Size::Large, MAX32 - 9, 10), Err(NoValue::Reset));
+        assert_eq!(delta(Size::Large, u64::MAX - 9, 10), Err(NoValue::Reset));
+    }
+
+    #[test]
+    fn cooks_each_counter_type() {
+        use CounterType as T;
+        #[rustfmt::skip]
+        let cases: &[Case] = &[
+            (T::PERF_COUNTER_RAWCOUNT, None, raw(7, 0, 0), Ok(7.0)),
+            (T::PERF_COUNTER_RAWCOUNT_HEX, None, raw(7, 0, 0), Ok(7.0)),
+            (T::PERF_COUNTER_LARGE_RAWCOUNT, None, raw(1 << 40, 0, 0), Ok((1u64 << 40) as f64)),
+            (T::PERF_COUNTER_LARGE_RAWCOUNT_HEX, None, raw(7, 0, 0), Ok(7.0)),
+            // Events per second.
+            (T::PERF_COUNTER_COUNTER, Some(raw(100, 0, 0)), raw(300, 0, 1000), Ok(200.0)),
+            (T::PERF_COUNTER_COUNTER, Some(raw(MAX32 - 99, 0, 0)), raw(100, 0, 1000), Ok(200.0)),
+            (T::PERF_COUNTER_COUNTER, Some(raw(1_000_000, 0, 0)), raw(100, 0, 1000), Err(NoValue::Reset)),
+            (T::PERF_COUNTER_COUNTER, None, raw(300, 0, 1000), Err(NoValue::FirstSample)),
+            (T::PERF_COUNTER_COUNTER, Some(raw(100, 0, 1000)), raw(300, 0, 1000), Err(NoValue::ZeroDivisor)),
+            (T::PERF_COUNTER_COUNTER, Some(raw(100, 0, 1000)), raw(300, 0, 0), Err(NoValue::Reset)),
+            (T::PERF_COUNTER_BULK_COUNT, Some(raw(1000, 0, 0)), raw(5000, 0, 2000), Ok(2000.0)),
+            (T::PERF_COUNTER_BULK_COUNT, Some(raw(MAX32 - 99, 0, 0)), raw(100, 0, 1000), Err(NoValue::Reset)),
+            (T::PERF_SAMPLE_COUNTER, Some(raw(100, 0, 0)), raw(300, 0, 1000), Ok(200.0)),
+            // Busy time as a percentage of elapsed time.
+            (T::PERF_COUNTER_TIMER, Some(raw(0, 0, 0)), raw(250, 0, 1000), Ok(25.0)),
+            (T::PERF_COUNTER_TIMER, Some(raw(500, 0, 0)), raw(250, 0, 1000), Err(NoValue::Reset)),
+            (T::PERF_COUNTER_TIMER_INV, Some(raw(0, 0, 0)), raw(250, 0, 1000), Ok(75.0)),
+            (T::PERF_100NSEC_TIMER, Some(raw(0, 0, 0)), raw(500, 0, 1000), Ok(50.0)),
+            (T::PERF_100NSEC_TIMER_INV, Some(raw(0, 0, 0)), raw(500, 0, 1000), Ok(50.0)),
+            (T::PERF_OBJ_TIME_TIMER, Some(raw(0, 0, 0)), raw(100, 0, 1000), Ok(10.0)),
+            (T::PERF_COUNTER_MULTI_TIMER, Some(raw(0, 2, 0)), raw(1000, 2, 1000), Ok(50.0)),
+            (T::PERF_COUNTER_MULTI_TIMER_INV, Some(raw(0, 2, 0)), raw(1000, 2, 1000), Ok(50.0)),
+            (T::PERF_100NSEC_MULTI_TIMER, Some(raw(0, 4, 0)), raw(2000, 4, 1000), Ok(50.0)),
+            (T::PERF_100NSEC_MULTI_TIMER_INV, Some(raw(0, 4, 0)), raw(2000, 4, 1000), Ok(50.0)),
+            (T::PERF_100NSEC_MULTI_TIMER, Some(raw(0, 0, 0)), raw(2000, 0, 1000), Err(NoValue::ZeroDivisor)),
+            // Average queue length.
+            (T::PERF_COUNTER_QUEUELEN_TYPE, Some(raw(0, 0, 0)), raw(3000, 0, 1000), Ok(3.0)),
+            (T::PERF_COUNTER_QUEUELEN_TYPE, Some(raw(MAX32, 0, 0)), raw(2999, 0, 1000), Ok(3.0)),
+            (T::PERF_COUNTER_LARGE_QUEUELEN_TYPE, Some(raw(0, 0, 0)), raw(3000, 0, 1000), Ok(3.0)),
+            (T::PERF_COUNTER_LARGE_QUEUELEN_TYPE, Some(raw(MAX32, 0, 0)), raw(2999, 0, 1000), Err(NoValue::Reset)),
+            (T::PERF_COUNTER_100NS_QUEUELEN_TYPE, Some(raw(0, 0, 0)), raw(3000, 0, 1000), Ok(3.0)),
+            (T::PERF_COUNTER_OBJ_TIME_QUEUELEN_TYPE, Some(raw(0, 0, 0)), raw(3000, 0, 1000), Ok(3.0)),
+            // Fractions, of the change in the base or of the base itself.
+            (T::PERF_SAMPLE_FRACTION, Some(raw(10, 100, 0)), raw(40, 200, 1000), Ok(30.0)),
+            (T::PERF_SAMPLE_FRACTION, Some(raw(10, MAX32 - 49, 0)), raw(40, 50, 1000), Ok(30.0)),
+            (T::PERF_SAMPLE_FRACTION, Some(raw(10, 100, 0)), raw(40, 100, 1000), Err(NoValue::ZeroDivisor)),
+            (T::PERF_RAW_FRACTION, None, raw(40, 200, 0), Ok(20.0)),
+            (T::PERF_RAW_FRACTION, None, raw(40, 0, 0), Err(NoValue::ZeroDivisor)),
+            (T::PERF_LARGE_RAW_FRACTION, None, raw(40, 200, 0), Ok(20.0)),
+            (T::PERF_RAW_FRACTION, None, Raw { base: None, ..raw(40, 0, 0) }, Err(NoValue::MissingBase)),
+            // Averages per operation.
+            (T::PERF_AVERAGE_TIMER, Some(raw(0, 0, 0)), raw(2000, 4, 1000), Ok(0.5)),
+            (T::PERF_AVERAGE_TIMER, Some(raw(0, MAX32 - 1, 0)), raw(2000, 2, 1000), Ok(0.5)),
+            (T::PERF_AVERAGE_BULK, Some(raw(0, 0, 0)), raw(2000, 4, 1000), Ok(500.0)),
+            (T::PERF_AVERAGE_BULK, Some(raw(3000, 0, 0)), raw(2000, 4, 1000), Err(NoValue::Reset)),
+            // Differences.
+            (T::PERF_COUNTER_DELTA, Some(raw(5, 0, 0)), raw(8, 0, 1000), Ok(3.0)),
+            (T::PERF_COUNTER_DELTA, Some(raw(MAX32 - 1, 0, 0)), raw(1, 0, 1000), Ok(3.0)),
+            (T::PERF_COUNTER_LARGE_DELTA, Some(raw(5, 0, 0)), raw(8, 0, 1000), Ok(3.0)),
+            (T::PERF_COUNTER_LARGE_DELTA, Some(raw(MAX32 - 1, 0, 0)), raw(1, 0, 1000), Err(NoValue::Reset)),
+            // Seconds since a start time.
+            (T::PERF_ELAPSED_TIME, None, raw(2000, 0, 5000), Ok(3.0)),
+            // Busy time as a percentage of a 64-bit timestamp base.
+            (T::PERF_PRECISION_SYSTEM_TIMER, Some(raw(0, 0, 0)), raw(250, 1000, 1000), Ok(25.0)),
+            (T::PERF_PRECISION_100NS_TIMER, Some(raw(0, 0, 0)), raw(250, 1000, 1000), Ok(25.0)),
+            (T::PERF_PRECISION_OBJECT_TIMER, Some(raw(0, MAX32 - 9, 0)), raw(250, 990, 1000), Err(NoValue::Reset)),
+            // Nothing to show by themselves.
+            (T::PERF_SAMPLE_BASE, None, raw(7, 0, 0), Err(NoValue::NotDisplayable)),
+            (T::PERF_AVERAGE_BASE, None, raw(7, 0, 0), Err(NoValue::NotDisplayable)),
+            (T::PERF_RAW_BASE, None, raw(7, 0, 0), Err(NoValue::NotDisplayable)),
+            (T::PERF_LARGE_RAW_BASE, None, raw(7, 0, 0), Err(NoValue::NotDisplayable)),
+            (T::PERF_COUNTER_MULTI_BASE, None, raw(7, 0, 0), Err(NoValue::NotDisplayable)),
+            (T::PERF_COUNTER_TEXT, None, raw(7, 0, 0), Err(NoValue::NotDisplayable)),
+            (T::PERF_COUNTER_HISTOGRAM_TYPE, None, raw(7, 0, 0), Err(NoValue::NotDisplayable)),
+            (T::PERF_COUNTER_NODATA, None, raw(7, 0, 0), Err(NoValue::NotDisplayable)),
+        ];
+
+        for (counter_type, previous, current, expected) in cases {
+            let cooked = try_cook(*counter_type, previous.as_ref(), current);
+            let matches = match (cooked, expected) {
+                (Ok(cooked), Ok(expected)) => (cooked - expected).abs() < 1e-9,
+                (cooked, expected) => cooked == *expected,
+            };
+            assert!(
+                matches,
+                "{:?} from {:?} to {:?}: expected {:?}, got {:?}",
+                counter_type, previous, current, expected, cooked
+            );
+            assert_eq!(cook(*counter_type, previous.as_ref(), current), cooked.ok());
+            assert_eq!(
+                is_cumulative(*counter_type),
+                try_cook(*counter_type, None, current) == Err(NoValue::FirstSample),
+                "{:?}",
+                counter_type
+            );
+        }
+    }
+
+    #[test]
+    fn cooks_over_several_intervals() {
+        use CounterType as T;
+        let over = |counter_type, raws: &[Raw]| {
+            let mut deltas = Deltas::default();
+            for pair in raws.windows(2) {
+                let _ = deltas.add(counter_type, &pair[0], &pair[1]);
+            }
+            deltas.cook(counter_type)
+        };
+
+        // The same as cooking the first and last values, however they got there.
+        let counts = [
+            raw(100, 0, 0),
+            raw(150, 0, 1000),
+            raw(400, 0, 2000),
+            raw(700, 0, 3000),
+        ];
+        assert_eq!(over(T::PERF_COUNTER_COUNTER, &counts), Ok(200.0));
+        assert_eq!(over(T::PERF_COUNTER_DELTA, &counts), Ok(600.0));
+        // Averages per operation are weighted by the operations in each interval, including those with none.
+        let timers = [
+            raw(0, 0, 0),
+            raw(3000, 1, 1000),
+            raw(3000, 1, 2000),
+            raw(4000, 3, 3000),
+        ];
+        assert_eq!(over(T::PERF_AVERAGE_TIMER, &timers), Ok(4.0 / 3.0));
+        let multi = [raw(0, 2, 0), raw(1000, 2, 1000), raw(3000, 2, 2000)];
+        assert_eq!(over(T::PERF_COUNTER_MULTI_TIMER, &multi), Ok(75.0));
+
+        // Wrapping around is a change like any other, but an interval over which the counter was reset is left out.
+        let wrapped = [
+            raw(MAX32 - 99, 0, 0),
+            raw(100, 0, 1000),
+            raw(50, 0, 2000),
+            raw(250, 0, 3000),
+        ];
+        assert_eq!(over(T::PERF_COUNTER_COUNTER, &wrapped), Ok(200.0));
+        let reset = [raw(1 << 40, 0, 0), raw(100, 0, 1000)];
+        assert_eq!(
+            over(T::PERF_COUNTER_BULK_COUNT, &reset),
+            Err(NoValue::FirstSample)
+        );
+        assert_eq!(
+            over(T::PERF_COUNTER_COUNTER, &counts[..1]),
+            Err(NoValue::FirstSample)
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Reference documentation for countersets, generated from what their providers registered.
+
+use crate::catalog::CounterSetRef;
+use crate::error::Result;
+use crate::types::{AggregateFunc, CounterSet, InstanceType, Provider};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// A page per counterset, with a table of its counters
+    Markdown,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+        }
+    }
+}
+
+/// A page of documentation, to be written to a file.
+#[derive(Debug)]
+pub struct Page {
+    pub file_name: String,
+    pub content: String,
+}
+
+/// Document every counterset of a provider, or a single counterset, by GUID or name.
+///
+/// A provider is looked for first, so a counterset with the same name as its provider is documented along with the
+/// rest of the provider's countersets.
+pub fn generate(format: Format, all: &[Provider], target: &CounterSetRef) -> Result<Vec<Page>> {
+    let provider = all.iter().find(|p| match target {
+        CounterSetRef::Id(id) => p.id == *id,
+        CounterSetRef::Name(name) => p.name.eq_ignore_ascii_case(name),
+    });
+    let countersets = match provider {
+        Some(provider) => provider
+            .countersets
+            .iter()
+            .map(|cs| (provider, cs))
+            .collect(),
+        None => vec![target
+            .find_with_provider(all)
+            .map_err(|_| format!("No provider or counterset {} found", target))?],
+    };
+
+    let mut names = HashSet::new();
+    Ok(countersets
+        .into_iter()
+        .map(|(provider, counterset)| {
+            // Names aren't unique across providers, but GUIDs are.
+            let mut name = slug(&counterset.name);
+            if !names.insert(name.clone()) {
+                name = format!("{}-{:?}", name, counterset.id).to_lowercase();
+            }
+            Page {
+                file_name: format!("{}.{}", name, format.extension()),
+                content: match format {
+                    Format::Markdown => markdown(provider, counterset),
+                },
+            }
+        })
+        .collect())
+}
+
+/// A file name made of the lowercase letters and digits of a name, with dashes between words.
+fn slug(name: &str) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    match slug.is_empty() {
+        true => "counterset".to_string(),
+        false => slug,
+    }
+}
+
+/// Escape text so that it renders as itself, on one line, in a table cell or heading.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for word in s.split_whitespace() {
+        if !escaped.is_empty() {
+            escaped.push(' ');
+        }
+        for c in word.chars() {
+            if matches!(
+                c,
+                '\\' | '|' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#'
+            ) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn describe(instance_type: InstanceType) -> &'static str {
+    match instance_type {
+        InstanceType::SingleInstance => "Single instance",
+        InstanceType::MultiInstances => "Multiple instances",
+        InstanceType::SingleAggregate => "Single instance, aggregated across providers",
+        InstanceType::MultiAggregate => "Multiple instances, aggregated across providers",
+    }
+}
+
+fn markdown(provider: &Provider, counterset: &CounterSet) -> String {
+    // A counter's name and id, or a note that no counter has the id.
+    let counter = |id: u32| match counterset.counters.iter().find(|c| c.id == id) {
+        Some(counter) => format!("{} ({})", escape(&counter.name), id),
+        None => format!("{} (missing)", id),
+    };
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<!-- Generated by {} {} from the registered counterset, don't edit by hand. -->",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "# {}", escape(&counterset.name)).unwrap();
+    writeln!(out).unwrap();
+    if !counterset.help.trim().is_empty() {
+        writeln!(out, "{}", escape(&counterset.help)).unwrap();
+        writeln!(out).unwrap();
+    }
+    writeln!(out, "| | |").unwrap();
+    writeln!(out, "|---|---|").unwrap();
+    writeln!(
+        out,
+        "| Provider | {} (`{:?}`) |",
+        escape(&provider.name),
+        provider.id
+    )
+    .unwrap();
+    writeln!(out, "| GUID | `{:?}` |", counterset.id).unwrap();
+    writeln!(
+        out,
+        "| Instances | {} (`{:?}`) |",
+        describe(counterset.instance_type),
+        counterset.instance_type
+    )
+    .unwrap();
+    if counterset.legacy {
+        writeln!(
+            out,
+            "| Legacy | A V1 performance object, from the registry |"
+        )
+        .unwrap();
+    }
+    writeln!(out).unwrap();
+
+    writeln!(out, "## Counters").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "| Id | Name | Type | Base | Aggregate | Help |").unwrap();
+    writeln!(out, "|---:|---|---|---|---|---|").unwrap();
+    for c in &counterset.counters {
+        let mut base = c.base_counter_id.map(|id| counter(id.get()));
+        if let Some(multi) = c.multi_counter_id {
+            let multi = format!("multi: {}", counter(multi.get()));
+            base = Some(match base {
+                Some(base) => format!("{}, {}", base, multi),
+                None => multi,
+            });
+        }
+        let aggregate = match c.aggregate_func {
+            AggregateFunc::Undefined => String::new(),
+            func => format!("{:?}", func),
+        };
+        writeln!(
+            out,
+            "| {} | {} | `{:?}` | {} | {} | {} |",
+            c.id,
+            escape(&c.name),
+            c.counter_type,
+            base.unwrap_or_default(),
+            aggregate,
+            escape(&c.help)
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::catalog;
+
+    #[test]
+    fn documents_a_counterset() {
+        let mut all = catalog();
+        all[0].countersets[1].counters[5].help = "Requests | waiting,\nor *in flight*".to_string();
+        let target = "contoso disk".parse().unwrap();
+        let pages = generate(Format::Markdown, &all, &target).unwrap();
+
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].file_name, "contoso-disk.md");
+        let expected = format!(
+            "\
+<!-- Generated by perflib-explorer {} from the registered counterset, don't edit by hand. -->
+
+# Contoso Disk
+
+{}
+
+| | |
+|---|---|
+| Provider | Contoso-Storage (`3D1A2C55-6B8E-4F10-9A3B-0C5E7D9F1A24`) |
+| GUID | `8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0` |
+| Instances | Multiple instances, aggregated across providers (`MultiAggregate`) |
+
+## Counters
+
+| Id | Name | Type | Base | Aggregate | Help |
+|---:|---|---|---|---|---|
+| 0 | Bytes Read | `PERF_COUNTER_LARGE_RAWCOUNT` |  | Total | {} |
+| 1 | Read Latency | `PERF_AVERAGE_TIMER` | Read Latency Base (2) | Avg | {} |
+| 2 | Read Latency Base | `PERF_AVERAGE_BASE` |  |  | {} |
+| 3 | % Idle Time | `PERF_SAMPLE_FRACTION` | % Idle Time Base (4) | Avg | {} |
+| 4 | % Idle Time Base | `PERF_SAMPLE_BASE` |  |  | {} |
+| 5 | Queue Length | `PERF_COUNTER_RAWCOUNT` |  | Max | Requests \\| waiting, or \\*in flight\\* |
+",
+            env!("CARGO_PKG_VERSION"),
+            all[0].countersets[1].help,
+            all[0].countersets[1].counters[0].help,
+            all[0].countersets[1].counters[1].help,
+            all[0].countersets[1].counters[2].help,
+            all[0].countersets[1].counters[3].help,
+            all[0].countersets[1].counters[4].help,
+        );
+        assert_eq!(pages[0].content, expected);
+    }
+
+    #[test]
+    fn documents_every_counterset_of_a_provider() {
+        let all = catalog();
+
+        let target = "Contoso-Storage".parse().unwrap();
+        let pages = generate(Format::Markdown, &all, &target).unwrap();
+        let names = pages
+            .iter()
+            .map(|p| p.file_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["contoso-cache.md", "contoso-disk.md"]);
+        assert!(pages[0]
+            .content
+            .contains("| Instances | Single instance (`SingleInstance`) |"));
+        assert!(pages[0]
+            .content
+            .contains("| Cache Hit Ratio Base (3) | Avg |"));
+
+        let target = "7C9E1A3B-5D7F-4912-A4B6-C8DAEC0F2143".parse().unwrap();
+        let pages = generate(Format::Markdown, &all, &target).unwrap();
+        assert_eq!(pages[0].file_name, "contoso-network-adapter.md");
+
+        let target = "Contoso Toaster".parse().unwrap();
+        let error = generate(Format::Markdown, &all, &target).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No provider or counterset \"Contoso Toaster\" found"
+        );
+    }
+}
@@ -0,0 +1,7 @@
+use std::error::Error as StdError;
+
+/// Error type for operations that aren't a single Windows API call,
+/// e.g. loading snapshots or writing output files.
+pub type Error = Box<dyn StdError>;
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,268 @@
+//! Periodically send the values of a counterset's counters to metrics backends.
+
+use crate::clock::Clock;
+use crate::cook;
+use crate::error::Result;
+use crate::identity::{self, SeriesKey};
+use crate::source::Source;
+use crate::types::counter_type::{Display, Kind};
+use crate::types::{Counter, CounterSet, CounterType, Sample};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime};
+
+mod graphite;
+mod otlp;
+mod path;
+mod statsd;
+
+pub use graphite::Graphite;
+pub use otlp::{Encoding, Otlp};
+pub use path::{Template, DEFAULT_TEMPLATE};
+pub use statsd::Statsd;
+
+/// The value of one counter of one instance, as of a sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    /// The cooked value.
+    Gauge(f64),
+    /// How far the counter has counted since `start`, for consumers to compute rates from themselves.
+    Sum { total: u64, start: SystemTime },
+}
+
+#[derive(Debug, Clone)]
+pub struct Point<'a> {
+    pub counter: &'a Counter,
+    /// None for single-instance countersets.
+    pub instance: Option<String>,
+    pub value: Value,
+}
+
+/// Every value of a sample of a counterset which is worth exporting.
+#[derive(Debug, Clone)]
+pub struct Metrics<'a> {
+    pub provider: &'a str,
+    pub counterset: &'a CounterSet,
+    pub time: SystemTime,
+    /// Names and values which the exporter attaches to every point, e.g. to tell hosts or environments apart.
+    pub labels: &'a BTreeMap<String, String>,
+    pub points: Vec<Point<'a>>,
+}
+
+static NO_LABELS: BTreeMap<String, String> = BTreeMap::new();
+
+/// Whether a counter counts events, and so is exported as a monotonic sum rather than a gauge.
+///
+/// Percentages of time are computed from counters too, but they're only meaningful cooked.
+pub fn is_sum(counter_type: CounterType) -> bool {
+    matches!(counter_type.kind(), Kind::Rate | Kind::Value)
+        && counter_type.is_delta()
+        && counter_type.display() != Display::Percent
+}
+
+/// Turns successive samples of a counterset into metrics, keeping running totals of the counters exported as sums.
+pub struct Collector<'a> {
+    provider: &'a str,
+    counterset: &'a CounterSet,
+    labels: &'a BTreeMap<String, String>,
+    series: identity::Series<'a>,
+    /// By instance and counter id.
+    totals: HashMap<(SeriesKey, u32), (u64, SystemTime)>,
+    last: Option<SystemTime>,
+}
+
+impl<'a> Collector<'a> {
+    pub fn new(provider: &'a str, counterset: &'a CounterSet) -> Self {
+        Self {
+            provider,
+            counterset,
+            labels: &NO_LABELS,
+            series: identity::Series::new(counterset),
+            totals: HashMap::new(),
+            last: None,
+        }
+    }
+
+    pub fn with_labels(self, labels: &'a BTreeMap<String, String>) -> Self {
+        Self { labels, ..self }
+    }
+
+    pub fn update(&mut self, sample: Sample, time: SystemTime) -> Metrics<'a> {
+        self.series.update(sample, time);
+        let last = self.last.replace(time);
+
+        let sample = self.series.current().unwrap();
+        let previous = self.series.previous();
+        let mut totals = HashMap::new();
+        let mut points = Vec::new();
+        for (index, identity) in self.series.identities().iter().enumerate() {
+            let instance = &sample.instances[index];
+            for counter in &self.counterset.counters {
+                if !cook::is_displayable(counter.counter_type) {
+                    continue;
+                }
+                let value = match is_sum(counter.counter_type) {
+                    false => match self.series.cook(counter, index) {
+                        Some(value) => Value::Gauge(value),
+                        None => continue,
+                    },
+                    true => {
+                        let Some(now) = cook::Raw::from_sample(counter, sample, instance) else {
+                            continue;
+                        };
+                        let before = identity.previous.zip(previous).and_then(|(i, previous)| {
+                            cook::Raw::from_sample(counter, previous, &previous.instances[i])
+                        });
+                        let key = (identity.key.clone(), counter.id);
+                        let total = match (before, self.totals.get(&key)) {
+                            (Some(before), Some(&(total, start))) => {
+                                // Going backwards would have made it a different instance.
+                                let size = counter.counter_type.size();
+                                let delta = cook::delta(size, before.value, now.value).unwrap_or(0);
+                                (total.saturating_add(delta), start)
+                            }
+                            // It started again from zero at some point since the last sample.
+                            _ if identity.restarted => (now.value, last.unwrap_or(time)),
+                            _ => (0, time),
+                        };
+                        totals.insert(key, total);
+                        Value::Sum {
+                            total: total.0,
+                            start: total.1,
+                        }
+                    }
+                };
+                points.push(Point {
+                    counter,
+                    instance: instance.instance.as_ref().map(|i| i.name.clone()),
+                    value,
+                });
+            }
+        }
+        // Forget instances which have gone.
+        self.totals = totals;
+
+        Metrics {
+            provider: self.provider,
+            counterset: self.counterset,
+            time,
+            labels: self.labels,
+            points,
+        }
+    }
+}
+
+/// Somewhere metrics are sent.
+pub trait Exporter {
+    fn export(&mut self, metrics: &Metrics) -> Result<()>;
+
+    /// Send metrics now, failing if they couldn't be sent rather than buffering or dropping them, for the caller
+    /// to send again later.
+    ///
+    /// Exporters which can't tell whether anything received their metrics just export them.
+    fn deliver(&mut self, metrics: &Metrics) -> Result<()> {
+        self.export(metrics)
+    }
+
+    /// Make a last attempt to send anything which is still buffered, before shutting down.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Periodically sample a counterset, and send its metrics to every exporter.
+pub fn run(
+    collector: &mut Collector,
+    interval: Duration,
+    count: Option<u64>,
+    source: &mut dyn Source,
+    clock: &mut dyn Clock,
+    exporters: &mut [Box<dyn Exporter>],
+) -> Result<()> {
+    let mut samples = 0;
+    loop {
+        let sample = source.sample(&collector.counterset.id)?;
+        let metrics = collector.update(sample, clock.now());
+        for exporter in exporters.iter_mut() {
+            exporter.export(&metrics)?;
+        }
+
+        samples += 1;
+        log::debug!("Exported {} samples", samples);
+        if count.is_some_and(|count| samples >= count) {
+            return Ok(());
+        }
+
+        clock.sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, at, contoso_disk, named, CONTOSO_DISK};
+
+    /// Contoso Disk, where Bytes Read counts up in 32 bits.
+    pub fn counterset() -> CounterSet {
+        let mut counterset = contoso_disk();
+        counterset.counters[0].counter_type = CounterType::PERF_COUNTER_COUNTER;
+        counterset
+    }
+
+    /// A sample at a second of bytes read and queue length for each (instance name, bytes, length).
+    pub fn sample(second: i64, instances: &[(&str, u64, u64)]) -> Sample {
+        let instances = named(
+            instances
+                .iter()
+                .map(|&(name, bytes, length)| (name, [bytes, length])),
+        );
+        testing::sample(CONTOSO_DISK, second, &[0, 5], instances)
+    }
+
+    fn values(metrics: &Metrics) -> Vec<(String, String, Value)> {
+        metrics
+            .points
+            .iter()
+            .map(|p| (p.counter.name.clone(), p.instance.clone().unwrap(), p.value))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_running_totals() {
+        let counterset = counterset();
+        let mut collector = Collector::new("Contoso", &counterset);
+        let sum = |total, start| Value::Sum { total, start };
+
+        let first = collector.update(sample(0, &[("C:", u32::MAX as u64 - 9, 2)]), at(0));
+        assert_eq!(
+            values(&first),
+            [
+                ("Bytes Read".to_string(), "C:".to_string(), sum(0, at(0))),
+                (
+                    "Queue Length".to_string(),
+                    "C:".to_string(),
+                    Value::Gauge(2.0)
+                ),
+            ]
+        );
+
+        // Wrapped around, and a new instance appeared.
+        let second = collector.update(sample(1, &[("C:", 10, 3), ("D:", 5, 0)]), at(1));
+        assert_eq!(second.points[0].value, sum(20, at(0)));
+        assert_eq!(second.points[2].value, sum(0, at(1)));
+
+        // Reset, and D: went away.
+        let third = collector.update(sample(2, &[("C:", 4, 3)]), at(2));
+        assert_eq!(
+            values(&third),
+            [
+                ("Bytes Read".to_string(), "C:".to_string(), sum(4, at(1))),
+                (
+                    "Queue Length".to_string(),
+                    "C:".to_string(),
+                    Value::Gauge(3.0)
+                ),
+            ]
+        );
+        assert_eq!(collector.totals.len(), 1);
+    }
+}
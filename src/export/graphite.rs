@@ -0,0 +1,192 @@
+//! Export to Graphite with its plaintext protocol over TCP.
+
+use super::path::Template;
+use super::{Exporter, Metrics, Value};
+use crate::error::Result;
+use crate::http;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends one `path value timestamp` line per value, keeping the connection open between samples.
+///
+/// Counters which count events are sent as running totals, for Graphite's derivative functions to turn into rates.
+/// Labels are sent as tags, e.g. `path;env=prod`, which need Graphite 1.1 or later.
+/// A server which can't be reached is logged rather than failing the export, and the sample is dropped.
+pub struct Graphite {
+    /// host:port, e.g. localhost:2003
+    address: String,
+    template: Template,
+    stream: Option<TcpStream>,
+}
+
+impl Graphite {
+    pub fn new(address: &str, template: Template) -> Self {
+        Self {
+            address: address.to_string(),
+            template,
+            stream: None,
+        }
+    }
+
+    fn lines(&self, metrics: &Metrics) -> String {
+        let timestamp = metrics
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let tags = metrics
+            .labels
+            .iter()
+            .map(|(name, value)| {
+                let name = name.replace([';', '!', '^', '=', ' '], "_");
+                let value = value.replace([';', '~', ' '], "_");
+                format!(";{}={}", name, value)
+            })
+            .collect::<String>();
+        let mut lines = String::new();
+        for point in &metrics.points {
+            let path = self.template.render(
+                metrics.provider,
+                &metrics.counterset.name,
+                point.instance.as_deref(),
+                &point.counter.name,
+            ) + &tags;
+            match point.value {
+                Value::Gauge(value) => writeln!(lines, "{} {} {}", path, value, timestamp),
+                Value::Sum { total, .. } => writeln!(lines, "{} {} {}", path, total, timestamp),
+            }
+            .unwrap();
+        }
+        lines
+    }
+
+    /// Send the lines from `sent` onwards, counting the bytes written.
+    fn send(&mut self, lines: &[u8], sent: &mut usize) -> Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                let stream = http::connect(&self.address, TIMEOUT)?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                self.stream.insert(stream)
+            }
+        };
+        while *sent < lines.len() {
+            match stream.write(&lines[*sent..]) {
+                Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(written) => *sent += written,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Exporter for Graphite {
+    fn export(&mut self, metrics: &Metrics) -> Result<()> {
+        if let Err(e) = self.deliver(metrics) {
+            log::warn!("Failed to send to Graphite at {}: {}", self.address, e);
+        }
+        Ok(())
+    }
+
+    fn deliver(&mut self, metrics: &Metrics) -> Result<()> {
+        let lines = self.lines(metrics);
+        let mut sent = 0;
+        // A connection the server has since closed only fails once it's written to, so try a new one once.
+        let mut result = self.send(lines.as_bytes(), &mut sent);
+        if result.is_err() && self.stream.take().is_some() {
+            // Lines written in full to the old connection aren't sent again, only the rest, from the start of a line.
+            sent = lines[..sent].rfind('\n').map_or(0, |newline| newline + 1);
+            result = self.send(lines.as_bytes(), &mut sent);
+        }
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{counterset, sample};
+    use crate::export::Collector;
+    use crate::testing::at;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    #[test]
+    fn sends_lines_and_reconnects() {
+        let counterset = counterset();
+        let labels = [("env".to_string(), "prod east".to_string())].into();
+        let mut collector = Collector::new("Contoso", &counterset).with_labels(&labels);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let template = "{provider}.{counterset}.{counter}.{instance}"
+            .parse()
+            .unwrap();
+        let mut graphite = Graphite::new(&address, template);
+
+        graphite
+            .export(&collector.update(sample(0, &[("C:", 100, 2)]), at(0)))
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let read_lines = |reader: &mut BufReader<TcpStream>, count| {
+            (0..count)
+                .map(|_| {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    line
+                })
+                .collect::<String>()
+        };
+        assert_eq!(
+            read_lines(&mut reader, 2),
+            "\
+Contoso.Contoso_Disk.Bytes_Read.C;env=prod_east 0 1700000000
+Contoso.Contoso_Disk.Queue_Length.C;env=prod_east 2 1700000000
+"
+        );
+
+        graphite
+            .export(&collector.update(sample(1, &[("C:", 350, 3)]), at(1)))
+            .unwrap();
+        assert_eq!(
+            read_lines(&mut reader, 2),
+            "\
+Contoso.Contoso_Disk.Bytes_Read.C;env=prod_east 250 1700000001
+Contoso.Contoso_Disk.Queue_Length.C;env=prod_east 3 1700000001
+"
+        );
+
+        // The server went away and isn't back yet, so the sample is dropped.
+        drop(reader);
+        drop(listener);
+        graphite.stream = None;
+        graphite.address = "127.0.0.1:1".to_string();
+        graphite
+            .export(&collector.update(sample(2, &[("C:", 400, 3)]), at(2)))
+            .unwrap();
+        assert!(graphite.stream.is_none());
+        assert!(graphite
+            .deliver(&collector.update(sample(2, &[("C:", 400, 3)]), at(2)))
+            .is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        graphite.address = listener.local_addr().unwrap().to_string();
+        graphite
+            .export(&collector.update(sample(3, &[("C:", 450, 1)]), at(3)))
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        assert_eq!(
+            read_lines(&mut BufReader::new(stream), 1),
+            "Contoso.Contoso_Disk.Bytes_Read.C;env=prod_east 350 1700000003\n"
+        );
+    }
+}
@@ -0,0 +1,819 @@
+//! Export to an OpenTelemetry collector over OTLP/HTTP, encoded as protobuf or JSON.
+//!
+//! Only the part of the metrics data model that's needed is implemented, following
+//! https://github.com/open-telemetry/opentelemetry-proto/blob/main/opentelemetry/proto/metrics/v1/metrics.proto
+
+use super::{Exporter, Metrics, Value};
+use crate::error::Result;
+use crate::http::Endpoint;
+use crate::types::counter_type::{Display, Kind};
+use crate::types::Counter;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::VecDeque;
+use std::fs;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Protobuf,
+    Json,
+}
+
+impl Encoding {
+    fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Protobuf => "application/x-protobuf",
+            Encoding::Json => "application/json",
+        }
+    }
+}
+
+/// Samples which haven't been sent are kept until there are this many, then the oldest are dropped.
+const MAX_PENDING: usize = 60;
+
+/// At most this many samples are sent in one request.
+const MAX_BATCH: usize = 10;
+
+/// Sends each sample's metrics to a collector, keeping those which couldn't be sent to retry with the next sample.
+///
+/// A collector which can't be reached is logged rather than failing the export, so that metrics keep going to
+/// the other exporters.
+pub struct Otlp {
+    endpoint: Endpoint,
+    encoding: Encoding,
+    resource: Resource,
+    /// The metrics of each sample which hasn't been sent yet, oldest first.
+    pending: VecDeque<Vec<Metric>>,
+    max_pending: usize,
+    max_batch: usize,
+}
+
+impl Otlp {
+    pub fn new(endpoint: Endpoint, encoding: Encoding) -> Self {
+        Self {
+            endpoint,
+            encoding,
+            resource: resource(),
+            pending: VecDeque::new(),
+            max_pending: MAX_PENDING,
+            max_batch: MAX_BATCH,
+        }
+    }
+
+    fn encode(&self, batch: usize) -> Result<Vec<u8>> {
+        let request = Request {
+            resource_metrics: vec![ResourceMetrics {
+                resource: self.resource.clone(),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Scope {
+                        name: env!("CARGO_PKG_NAME").to_string(),
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                    },
+                    metrics: self.pending.range(..batch).flatten().cloned().collect(),
+                }],
+            }],
+        };
+        Ok(match self.encoding {
+            Encoding::Protobuf => {
+                let mut buf = Vec::new();
+                request.encode(&mut buf);
+                buf
+            }
+            Encoding::Json => serde_json::to_vec(&request)?,
+        })
+    }
+
+    /// Send as many pending samples as the collector will take.
+    fn send(&mut self) -> Result<()> {
+        while !self.pending.is_empty() {
+            let batch = self.pending.len().min(self.max_batch);
+            let body = self.encode(batch)?;
+            self.endpoint.post(self.encoding.content_type(), &body)?;
+            self.pending.drain(..batch);
+        }
+        Ok(())
+    }
+}
+
+impl Exporter for Otlp {
+    fn export(&mut self, metrics: &Metrics) -> Result<()> {
+        self.pending.push_back(convert(metrics));
+        if self.pending.len() > self.max_pending {
+            self.pending.pop_front();
+            log::warn!(
+                "OTLP collector {} is behind, dropped the oldest sample",
+                self.endpoint
+            );
+        }
+        if let Err(e) = self.send() {
+            log::warn!(
+                "Failed to export to OTLP collector {}, {} samples pending: {}",
+                self.endpoint,
+                self.pending.len(),
+                e
+            );
+        }
+        Ok(())
+    }
+
+    fn deliver(&mut self, metrics: &Metrics) -> Result<()> {
+        self.pending.push_back(convert(metrics));
+        let result = self.send();
+        // Only this sample is taken back, anything exported before it is still retried.
+        if result.is_err() {
+            self.pending.pop_back();
+        }
+        result
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self.send() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!(
+                "{} samples couldn't be sent to OTLP collector {}: {}",
+                self.pending.len(),
+                self.endpoint,
+                e
+            )
+            .into()),
+        }
+    }
+}
+
+/// Describes this process and the machine it's running on.
+fn resource() -> Resource {
+    let host = std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty());
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+
+    let mut attributes = vec![
+        KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        KeyValue::new("os.type", os),
+    ];
+    if let Some(host) = host {
+        attributes.push(KeyValue::new("host.name", &host));
+    }
+    Resource { attributes }
+}
+
+/// One metric per counter, with a data point per instance.
+fn convert(metrics: &Metrics) -> Vec<Metric> {
+    let time = unix_nanos(metrics.time);
+    let mut converted: Vec<(u32, Metric)> = Vec::new();
+    for point in &metrics.points {
+        let mut attributes = vec![
+            KeyValue::new("perflib.provider", metrics.provider),
+            KeyValue::new("perflib.counterset", &metrics.counterset.name),
+        ];
+        if let Some(instance) = &point.instance {
+            attributes.push(KeyValue::new("perflib.instance", instance));
+        }
+        for (key, value) in metrics.labels {
+            attributes.push(KeyValue::new(key, value));
+        }
+        let data_point = match point.value {
+            Value::Gauge(value) => DataPoint {
+                attributes,
+                start_time_unix_nano: None,
+                time_unix_nano: time,
+                as_double: Some(value),
+                as_int: None,
+            },
+            Value::Sum { total, start } => DataPoint {
+                attributes,
+                start_time_unix_nano: Some(unix_nanos(start)),
+                time_unix_nano: time,
+                as_double: None,
+                as_int: Some(total.min(i64::MAX as u64) as i64),
+            },
+        };
+
+        let index = match converted.iter().position(|(id, _)| *id == point.counter.id) {
+            Some(index) => index,
+            None => {
+                let (gauge, sum) = match point.value {
+                    Value::Gauge(_) => (Some(Gauge::default()), None),
+                    Value::Sum { .. } => (None, Some(Sum::default())),
+                };
+                converted.push((
+                    point.counter.id,
+                    Metric {
+                        name: metric_name(&metrics.counterset.name, &point.counter.name),
+                        description: point.counter.help.clone(),
+                        unit: unit(point.counter).to_string(),
+                        gauge,
+                        sum,
+                    },
+                ));
+                converted.len() - 1
+            }
+        };
+        let metric = &mut converted[index].1;
+        match (&mut metric.gauge, &mut metric.sum) {
+            (Some(gauge), _) => gauge.data_points.push(data_point),
+            (_, Some(sum)) => sum.data_points.push(data_point),
+            _ => unreachable!(),
+        }
+    }
+    converted.into_iter().map(|(_, metric)| metric).collect()
+}
+
+/// e.g. `perflib.processor_information.processor_time` for `Processor Information\% Processor Time`.
+fn metric_name(counterset: &str, counter: &str) -> String {
+    let part = |name: &str| {
+        name.to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join("_")
+    };
+    format!("perflib.{}.{}", part(counterset), part(counter))
+}
+
+/// The UCUM unit of a counter's exported value, from its type, or failing that, the words in its name.
+fn unit(counter: &Counter) -> &'static str {
+    match (counter.counter_type.kind(), counter.counter_type.display()) {
+        (_, Display::Percent) => return "%",
+        (Kind::Elapsed, _) | (_, Display::Seconds) => return "s",
+        _ => {}
+    }
+    let name = counter.name.to_lowercase();
+    // Rates are exported as sums of whatever they count.
+    let name = name.strip_suffix("/sec").unwrap_or(&name);
+    let words = name
+        .split(|c: char| !c.is_alphanumeric())
+        .collect::<Vec<_>>();
+    let has = |candidates: &[&str]| words.iter().any(|word| candidates.contains(word));
+    if has(&["bytes", "byte"]) {
+        "By"
+    } else if has(&["bits"]) {
+        "bit"
+    } else if has(&["ms", "msec", "milliseconds"]) {
+        "ms"
+    } else if has(&["sec", "secs", "seconds"]) {
+        "s"
+    } else {
+        "1"
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// The JSON encoding represents 64-bit integers as strings.
+fn int_string<T: ToString, S: Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.serialize_str(&value.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn u64_string<S: Serializer>(value: &u64, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_string())
+}
+
+/// `ExportMetricsServiceRequest`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+    resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceMetrics {
+    resource: Resource,
+    scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Resource {
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ScopeMetrics {
+    scope: Scope,
+    metrics: Vec<Metric>,
+}
+
+/// `InstrumentationScope`
+#[derive(Debug, Clone, Serialize)]
+struct Scope {
+    name: String,
+    version: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Metric {
+    name: String,
+    description: String,
+    unit: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gauge: Option<Gauge>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sum: Option<Sum>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Gauge {
+    data_points: Vec<DataPoint>,
+}
+
+/// Always cumulative and monotonic.
+#[derive(Debug, Clone, Default)]
+struct Sum {
+    data_points: Vec<DataPoint>,
+}
+
+/// `AGGREGATION_TEMPORALITY_CUMULATIVE`
+const CUMULATIVE: u64 = 2;
+
+impl Serialize for Sum {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Json<'a> {
+            data_points: &'a [DataPoint],
+            aggregation_temporality: u64,
+            is_monotonic: bool,
+        }
+        Json {
+            data_points: &self.data_points,
+            aggregation_temporality: CUMULATIVE,
+            is_monotonic: true,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// `NumberDataPoint`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DataPoint {
+    attributes: Vec<KeyValue>,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "int_string")]
+    start_time_unix_nano: Option<u64>,
+    #[serde(serialize_with = "u64_string")]
+    time_unix_nano: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    as_double: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "int_string")]
+    as_int: Option<i64>,
+}
+
+/// Only string values are needed.
+#[derive(Debug, Clone, Serialize)]
+struct KeyValue {
+    key: String,
+    value: AnyValue,
+}
+
+impl KeyValue {
+    fn new(key: &str, value: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            value: AnyValue {
+                string_value: value.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnyValue {
+    string_value: String,
+}
+
+/// Protobuf wire format, for just the field types used here.
+mod wire {
+    const VARINT: u64 = 0;
+    const I64: u64 = 1;
+    const LEN: u64 = 2;
+
+    pub fn varint(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    fn tag(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+        varint(buf, field << 3 | wire_type);
+    }
+
+    pub fn uint(buf: &mut Vec<u8>, field: u64, value: u64) {
+        tag(buf, field, VARINT);
+        varint(buf, value);
+    }
+
+    pub fn fixed64(buf: &mut Vec<u8>, field: u64, value: u64) {
+        tag(buf, field, I64);
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+        tag(buf, field, LEN);
+        varint(buf, value.len() as u64);
+        buf.extend_from_slice(value);
+    }
+
+    /// An embedded message, encoded by `encode`.
+    pub fn message(buf: &mut Vec<u8>, field: u64, encode: impl FnOnce(&mut Vec<u8>)) {
+        let mut message = Vec::new();
+        encode(&mut message);
+        bytes(buf, field, &message);
+    }
+}
+
+/// Encode a message's fields, by their numbers in the OTLP protos.
+trait Encode {
+    fn encode(&self, buf: &mut Vec<u8>);
+}
+
+fn repeated<T: Encode>(buf: &mut Vec<u8>, field: u64, messages: &[T]) {
+    for message in messages {
+        wire::message(buf, field, |buf| message.encode(buf));
+    }
+}
+
+impl Encode for Request {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        repeated(buf, 1, &self.resource_metrics);
+    }
+}
+
+impl Encode for ResourceMetrics {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        wire::message(buf, 1, |buf| self.resource.encode(buf));
+        repeated(buf, 2, &self.scope_metrics);
+    }
+}
+
+impl Encode for Resource {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        repeated(buf, 1, &self.attributes);
+    }
+}
+
+impl Encode for ScopeMetrics {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        wire::message(buf, 1, |buf| self.scope.encode(buf));
+        repeated(buf, 2, &self.metrics);
+    }
+}
+
+impl Encode for Scope {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        wire::bytes(buf, 1, self.name.as_bytes());
+        wire::bytes(buf, 2, self.version.as_bytes());
+    }
+}
+
+impl Encode for Metric {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        wire::bytes(buf, 1, self.name.as_bytes());
+        wire::bytes(buf, 2, self.description.as_bytes());
+        wire::bytes(buf, 3, self.unit.as_bytes());
+        if let Some(gauge) = &self.gauge {
+            wire::message(buf, 5, |buf| repeated(buf, 1, &gauge.data_points));
+        }
+        if let Some(sum) = &self.sum {
+            wire::message(buf, 7, |buf| {
+                repeated(buf, 1, &sum.data_points);
+                wire::uint(buf, 2, CUMULATIVE);
+                wire::uint(buf, 3, 1);
+            });
+        }
+    }
+}
+
+impl Encode for DataPoint {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        if let Some(start) = self.start_time_unix_nano {
+            wire::fixed64(buf, 2, start);
+        }
+        wire::fixed64(buf, 3, self.time_unix_nano);
+        if let Some(value) = self.as_double {
+            wire::fixed64(buf, 4, value.to_bits());
+        }
+        if let Some(value) = self.as_int {
+            wire::fixed64(buf, 6, value as u64);
+        }
+        repeated(buf, 7, &self.attributes);
+    }
+}
+
+impl Encode for KeyValue {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        wire::bytes(buf, 1, self.key.as_bytes());
+        wire::message(buf, 2, |buf| {
+            wire::bytes(buf, 1, self.value.string_value.as_bytes())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{counterset, sample};
+    use crate::export::Collector;
+    use crate::testing::at;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    /// Accept requests like a collector would, answering each with a status, and return their bodies.
+    fn stand_in(statuses: &[u16]) -> (Endpoint, JoinHandle<Vec<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/metrics", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let statuses = statuses.to_vec();
+        let server = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(reader.get_mut(), "HTTP/1.1 {} Whatever\r\n\r\n", status).unwrap();
+                bodies.push(body);
+            }
+            bodies
+        });
+        (endpoint, server)
+    }
+
+    /// The fields of a protobuf message, as (field number, value) where length-delimited values are their bytes
+    /// and others are the raw little-endian or varint value.
+    fn fields(mut buf: &[u8]) -> Vec<(u64, std::result::Result<u64, &[u8]>)> {
+        let varint = |buf: &mut &[u8]| {
+            let (mut value, mut shift) = (0, 0);
+            loop {
+                let byte = buf[0];
+                *buf = &buf[1..];
+                value |= u64::from(byte & 0x7F) << shift;
+                shift += 7;
+                if byte < 0x80 {
+                    return value;
+                }
+            }
+        };
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let tag = varint(&mut buf);
+            let value = match tag & 7 {
+                0 => Ok(varint(&mut buf)),
+                1 => {
+                    let (value, rest) = buf.split_at(8);
+                    buf = rest;
+                    Ok(u64::from_le_bytes(value.try_into().unwrap()))
+                }
+                2 => {
+                    let length = varint(&mut buf) as usize;
+                    let (value, rest) = buf.split_at(length);
+                    buf = rest;
+                    Err(value)
+                }
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            };
+            fields.push((tag >> 3, value));
+        }
+        fields
+    }
+
+    fn field(buf: &[u8], number: u64) -> Vec<std::result::Result<u64, &[u8]>> {
+        fields(buf)
+            .into_iter()
+            .filter(|(n, _)| *n == number)
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    fn message(buf: &[u8], number: u64) -> &[u8] {
+        field(buf, number)[0].unwrap_err()
+    }
+
+    #[test]
+    fn names_and_units() {
+        assert_eq!(
+            metric_name("Processor Information", "% Processor Time"),
+            "perflib.processor_information.processor_time"
+        );
+        assert_eq!(
+            metric_name("LogicalDisk", "Avg. Disk Bytes/Read"),
+            "perflib.logicaldisk.avg_disk_bytes_read"
+        );
+
+        let mut counterset = counterset();
+        let units = counterset
+            .counters
+            .iter()
+            .map(|c| unit(c))
+            .collect::<Vec<_>>();
+        // Bytes Read, Read Latency, its base, % Idle Time, its base, Queue Length.
+        assert_eq!(units, ["By", "s", "1", "%", "1", "1"]);
+
+        let counter = &mut counterset.counters[5];
+        for (name, expected) in [
+            ("Response Time (ms)", "ms"),
+            ("Uptime Seconds", "s"),
+            ("Packets/sec", "1"),
+            ("Bits Sent/sec", "bit"),
+        ] {
+            counter.name = name.to_string();
+            assert_eq!(unit(counter), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn exports_protobuf() {
+        let counterset = counterset();
+        let mut collector = Collector::new("Contoso", &counterset);
+        let (endpoint, server) = stand_in(&[200, 200]);
+        let mut otlp = Otlp::new(endpoint, Encoding::Protobuf);
+
+        otlp.export(&collector.update(sample(0, &[("C:", 100, 2)]), at(0)))
+            .unwrap();
+        otlp.export(&collector.update(sample(1, &[("C:", 350, 4)]), at(1)))
+            .unwrap();
+        let bodies = server.join().unwrap();
+
+        let request = &bodies[1];
+        let resource_metrics = message(request, 1);
+        let resource = message(resource_metrics, 1);
+        assert!(field(resource, 1)
+            .iter()
+            .any(|kv| field(kv.unwrap_err(), 1)[0] == Err(b"service.name".as_slice())));
+
+        let scope_metrics = message(resource_metrics, 2);
+        let metrics = field(scope_metrics, 2);
+        assert_eq!(metrics.len(), 2);
+
+        let bytes_read = metrics[0].unwrap_err();
+        assert_eq!(
+            message(bytes_read, 1),
+            b"perflib.contoso_disk.bytes_read".as_slice()
+        );
+        assert_eq!(message(bytes_read, 3), b"By".as_slice());
+        let sum = message(bytes_read, 7);
+        assert_eq!(field(sum, 2), [Ok(CUMULATIVE)]);
+        assert_eq!(field(sum, 3), [Ok(1)]);
+        let point = message(sum, 1);
+        assert_eq!(field(point, 2), [Ok(unix_nanos(at(0)))]);
+        assert_eq!(field(point, 3), [Ok(unix_nanos(at(1)))]);
+        assert_eq!(field(point, 6), [Ok(250)]);
+        let attributes = field(point, 7)
+            .into_iter()
+            .map(|kv| {
+                let kv = kv.unwrap_err();
+                let text = |buf: &[u8]| String::from_utf8(buf.to_vec()).unwrap();
+                (text(message(kv, 1)), text(message(message(kv, 2), 1)))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attributes,
+            [
+                ("perflib.provider".to_string(), "Contoso".to_string()),
+                ("perflib.counterset".to_string(), "Contoso Disk".to_string()),
+                ("perflib.instance".to_string(), "C:".to_string()),
+            ]
+        );
+
+        let queue_length = metrics[1].unwrap_err();
+        let gauge = message(queue_length, 5);
+        let point = message(gauge, 1);
+        assert!(field(point, 2).is_empty());
+        assert_eq!(field(point, 4), [Ok(4.0f64.to_bits())]);
+    }
+
+    #[test]
+    fn exports_json() {
+        let counterset = counterset();
+        let labels = [("env".to_string(), "prod".to_string())].into();
+        let mut collector = Collector::new("Contoso", &counterset).with_labels(&labels);
+        let (endpoint, server) = stand_in(&[200]);
+        let mut otlp = Otlp::new(endpoint, Encoding::Json);
+
+        otlp.export(&collector.update(sample(0, &[("C:", 100, 2), ("D:", 5, 1)]), at(0)))
+            .unwrap();
+        let body = &server.join().unwrap()[0];
+        let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+
+        let metrics = &request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(
+            metrics[0]["sum"],
+            serde_json::json!({
+                "dataPoints": [
+                    {
+                        "attributes": [
+                            {"key": "perflib.provider", "value": {"stringValue": "Contoso"}},
+                            {"key": "perflib.counterset", "value": {"stringValue": "Contoso Disk"}},
+                            {"key": "perflib.instance", "value": {"stringValue": "C:"}},
+                            {"key": "env", "value": {"stringValue": "prod"}},
+                        ],
+                        "startTimeUnixNano": "1700000000000000000",
+                        "timeUnixNano": "1700000000000000000",
+                        "asInt": "0",
+                    },
+                    {
+                        "attributes": [
+                            {"key": "perflib.provider", "value": {"stringValue": "Contoso"}},
+                            {"key": "perflib.counterset", "value": {"stringValue": "Contoso Disk"}},
+                            {"key": "perflib.instance", "value": {"stringValue": "D:"}},
+                            {"key": "env", "value": {"stringValue": "prod"}},
+                        ],
+                        "startTimeUnixNano": "1700000000000000000",
+                        "timeUnixNano": "1700000000000000000",
+                        "asInt": "0",
+                    },
+                ],
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+            })
+        );
+        assert_eq!(metrics[1]["name"], "perflib.contoso_disk.queue_length");
+        assert_eq!(metrics[1]["unit"], "1");
+        assert_eq!(metrics[1]["gauge"]["dataPoints"][1]["asDouble"], 1.0);
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_samples_to_retry() {
+        let counterset = counterset();
+        let mut collector = Collector::new("Contoso", &counterset);
+        let (endpoint, server) = stand_in(&[503, 200, 200]);
+        let mut otlp = Otlp::new(endpoint.clone(), Encoding::Json);
+        otlp.max_pending = 3;
+        otlp.max_batch = 2;
+
+        // Rejected by the collector, then unreachable.
+        otlp.export(&collector.update(sample(0, &[("C:", 0, 0)]), at(0)))
+            .unwrap();
+        otlp.endpoint = "http://127.0.0.1:1/v1/metrics".parse().unwrap();
+        for second in 1..5 {
+            otlp.export(&collector.update(
+                sample(second, &[("C:", 0, second as u64)]),
+                at(second as u64),
+            ))
+            .unwrap();
+        }
+        assert_eq!(otlp.pending.len(), 3);
+        assert!(otlp.flush().is_err());
+        // A sample which is delivered rather than exported is left to the caller to send again.
+        let metrics = collector.update(sample(5, &[("C:", 0, 5)]), at(5));
+        assert!(otlp.deliver(&metrics).is_err());
+        assert_eq!(otlp.pending.len(), 3);
+
+        // Everything that's left is sent once it's back, in batches.
+        otlp.endpoint = endpoint;
+        otlp.export(&metrics).unwrap();
+        assert!(otlp.pending.is_empty());
+
+        let lengths = server.join().unwrap()[1..]
+            .iter()
+            .map(|body| {
+                let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+                request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .filter(|m| m["name"] == "perflib.contoso_disk.queue_length")
+                    .map(|m| m["gauge"]["dataPoints"][0]["asDouble"].as_f64().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // The two oldest were dropped.
+        assert_eq!(lengths, [vec![3.0, 4.0], vec![5.0]]);
+    }
+}
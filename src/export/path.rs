@@ -0,0 +1,194 @@
+//! Dotted metric paths for backends like StatsD and Graphite, built from perflib names.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Provider,
+    Counterset,
+    Instance,
+    Counter,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+/// A metric path with `{provider}`, `{counterset}`, `{instance}` and `{counter}` placeholders, e.g.
+/// `perflib.{counterset}.{instance}.{counter}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    text: String,
+    parts: Vec<Part>,
+}
+
+pub const DEFAULT_TEMPLATE: &str = "perflib.{counterset}.{instance}.{counter}";
+
+impl Template {
+    /// Fill in the placeholders with sanitized names.
+    ///
+    /// Segments which end up empty, e.g. `{instance}` for single-instance countersets, are left out.
+    pub fn render(
+        &self,
+        provider: &str,
+        counterset: &str,
+        instance: Option<&str>,
+        counter: &str,
+    ) -> String {
+        let mut path = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => path += text,
+                Part::Field(field) => {
+                    path += &sanitize(match field {
+                        Field::Provider => provider,
+                        Field::Counterset => counterset,
+                        Field::Instance => instance.unwrap_or_default(),
+                        Field::Counter => counter,
+                    })
+                }
+            }
+        }
+        path.split('.')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE.parse().unwrap()
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(Part::Literal(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed `{{` in template `{}`", s))?;
+            let field = match &rest[open + 1..open + close] {
+                "provider" => Field::Provider,
+                "counterset" => Field::Counterset,
+                "instance" => Field::Instance,
+                "counter" => Field::Counter,
+                other => {
+                    return Err(format!(
+                        "unknown placeholder `{{{}}}` in template `{}`, expected one of {{provider}}, {{counterset}}, {{instance}} or {{counter}}",
+                        other, s
+                    ))
+                }
+            };
+            parts.push(Part::Field(field));
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(Self {
+            text: s.to_string(),
+            parts,
+        })
+    }
+}
+
+impl Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Make a perflib name safe to use as one segment of a metric path.
+///
+/// `%` and `/` become words, since they're meaningful in names like `% Processor Time` and `Bytes/sec`. Anything
+/// else which isn't a letter, digit, `-` or `_`, notably `.`, `:`, `|` and spaces, separates words with `_`.
+pub fn sanitize(name: &str) -> String {
+    let mut sanitized = String::new();
+    // Whether the next word needs separating from the last one.
+    let mut separate = false;
+    for c in name.chars() {
+        let word = match c {
+            '%' => "pct",
+            '/' => "per",
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => {
+                if separate && !sanitized.is_empty() {
+                    sanitized.push('_');
+                }
+                separate = false;
+                sanitized.push(c);
+                continue;
+            }
+            _ => {
+                separate = true;
+                continue;
+            }
+        };
+        if !sanitized.is_empty() {
+            sanitized.push('_');
+        }
+        sanitized += word;
+        separate = true;
+    }
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizes_names() {
+        for (name, expected) in [
+            ("% Processor Time", "pct_Processor_Time"),
+            ("Disk Bytes/sec", "Disk_Bytes_per_sec"),
+            ("Avg. Disk sec/Read", "Avg_Disk_sec_per_Read"),
+            ("Processor Information", "Processor_Information"),
+            ("chrome#1", "chrome_1"),
+            ("0,_Total", "0__Total"),
+            ("C:", "C"),
+            ("Intel(R) Ethernet (2)", "Intel_R_Ethernet_2"),
+            ("a|b:c", "a_b_c"),
+        ] {
+            assert_eq!(sanitize(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn renders_templates() {
+        let template = Template::default();
+        assert_eq!(
+            template.render("Contoso", "Contoso Disk", Some("C:"), "% Idle Time"),
+            "perflib.Contoso_Disk.C.pct_Idle_Time"
+        );
+        assert_eq!(
+            template.render("Contoso", "Memory", None, "Available Bytes"),
+            "perflib.Memory.Available_Bytes"
+        );
+
+        let template = "hosts.web1.{provider}.{counter}-{instance}"
+            .parse::<Template>()
+            .unwrap();
+        assert_eq!(
+            template.render("Contoso", "Contoso Disk", Some("D:"), "Queue Length"),
+            "hosts.web1.Contoso.Queue_Length-D"
+        );
+        assert_eq!(
+            template.to_string(),
+            "hosts.web1.{provider}.{counter}-{instance}"
+        );
+
+        assert!("perflib.{host}".parse::<Template>().is_err());
+        assert!("perflib.{counter".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Export to StatsD over UDP.
+
+use super::path::Template;
+use super::{Exporter, Metrics, Value};
+use crate::error::Result;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::net::{Ipv4Addr, Ipv6Addr, ToSocketAddrs, UdpSocket};
+use std::time::SystemTime;
+
+/// Lines are packed into datagrams of at most this many bytes, to stay within a typical MTU.
+const MAX_DATAGRAM: usize = 1432;
+
+/// Sends cooked values as gauges, and counters which count events as StatsD counters of how far they counted since
+/// the last sample.
+///
+/// Labels are sent as DogStatsD-style tags, e.g. `|#env:prod`, which most StatsD servers accept.
+///
+/// Datagrams which can't be sent are logged rather than failing the export, as StatsD clients usually do.
+pub struct Statsd {
+    socket: UdpSocket,
+    template: Template,
+    /// The last total sent for each counter, by path, and when it started.
+    totals: HashMap<String, (u64, SystemTime)>,
+}
+
+impl Statsd {
+    /// Send to a host:port, e.g. localhost:8125.
+    pub fn new(address: &str, template: Template) -> Result<Self> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("StatsD address {} didn't resolve", address))?;
+        let socket = match address.is_ipv4() {
+            true => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+            false => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+        };
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            template,
+            totals: HashMap::new(),
+        })
+    }
+
+    fn lines(&mut self, metrics: &Metrics) -> Vec<String> {
+        let tags = tags(metrics);
+        let mut totals = HashMap::new();
+        let mut lines = Vec::new();
+        for point in &metrics.points {
+            let path = self.template.render(
+                metrics.provider,
+                &metrics.counterset.name,
+                point.instance.as_deref(),
+                &point.counter.name,
+            );
+            match point.value {
+                Value::Gauge(value) => {
+                    // A signed gauge is an adjustment, so a negative value has to be set from zero.
+                    if value < 0.0 {
+                        lines.push(format!("{}:0|g{}", path, tags));
+                    }
+                    lines.push(format!("{}:{}|g{}", path, value, tags));
+                }
+                Value::Sum { total, start } => {
+                    let increment = match self.totals.get(&path) {
+                        Some(&(last, last_start)) if last_start == start => {
+                            total.saturating_sub(last)
+                        }
+                        _ => total,
+                    };
+                    lines.push(format!("{}:{}|c{}", path, increment, tags));
+                    totals.insert(path, (total, start));
+                }
+            }
+        }
+        // Forget instances which have gone.
+        self.totals = totals;
+        lines
+    }
+}
+
+/// The `|#name:value,...` suffix for a sample's labels, if it has any.
+fn tags(metrics: &Metrics) -> String {
+    let clean = |s: &str| s.replace([',', '|', '#', '\n'], "_");
+    let tags = metrics
+        .labels
+        .iter()
+        .map(|(name, value)| format!("{}:{}", clean(name).replace(':', "_"), clean(value)))
+        .collect::<Vec<_>>();
+    match tags.is_empty() {
+        true => String::new(),
+        false => format!("|#{}", tags.join(",")),
+    }
+}
+
+impl Exporter for Statsd {
+    fn export(&mut self, metrics: &Metrics) -> Result<()> {
+        let mut datagram = String::new();
+        let mut datagrams = Vec::new();
+        for line in self.lines(metrics) {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                datagrams.push(std::mem::take(&mut datagram));
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            write!(datagram, "{}", line).unwrap();
+        }
+        if !datagram.is_empty() {
+            datagrams.push(datagram);
+        }
+
+        for datagram in datagrams {
+            if let Err(e) = self.socket.send(datagram.as_bytes()) {
+                log::warn!("Failed to send to StatsD: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{counterset, sample};
+    use crate::export::Collector;
+    use crate::testing::at;
+    use std::time::Duration;
+
+    #[test]
+    fn sends_gauges_and_counters() {
+        let counterset = counterset();
+        let mut collector = Collector::new("Contoso", &counterset);
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut statsd = Statsd::new(&address, Template::default()).unwrap();
+
+        let receive = || {
+            let mut buf = [0; MAX_DATAGRAM];
+            let len = server.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        };
+
+        statsd
+            .export(&collector.update(sample(0, &[("C:", 100, 2)]), at(0)))
+            .unwrap();
+        assert_eq!(
+            receive(),
+            "perflib.Contoso_Disk.C.Bytes_Read:0|c\nperflib.Contoso_Disk.C.Queue_Length:2|g"
+        );
+
+        statsd
+            .export(&collector.update(sample(1, &[("C:", 350, 3), ("D:", 7, 1)]), at(1)))
+            .unwrap();
+        assert_eq!(
+            receive(),
+            "perflib.Contoso_Disk.C.Bytes_Read:250|c\nperflib.Contoso_Disk.C.Queue_Length:3|g\nperflib.Contoso_Disk.D.Bytes_Read:0|c\nperflib.Contoso_Disk.D.Queue_Length:1|g"
+        );
+
+        statsd
+            .export(&collector.update(sample(2, &[("C:", 400, 3)]), at(2)))
+            .unwrap();
+        assert_eq!(
+            receive(),
+            "perflib.Contoso_Disk.C.Bytes_Read:50|c\nperflib.Contoso_Disk.C.Queue_Length:3|g"
+        );
+        assert_eq!(statsd.totals.len(), 1);
+    }
+
+    #[test]
+    fn sets_negative_gauges_from_zero_with_tags() {
+        let counterset = counterset();
+        let labels = [
+            ("env".to_string(), "prod".to_string()),
+            ("role:tier".to_string(), "web|db".to_string()),
+        ]
+        .into();
+        let mut collector = Collector::new("Contoso", &counterset).with_labels(&labels);
+        let mut metrics = collector.update(sample(0, &[("C:", 0, 0)]), at(0));
+        metrics
+            .points
+            .retain(|p| matches!(p.value, Value::Gauge(_)));
+        metrics.points[0].value = Value::Gauge(-1.5);
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut statsd = Statsd::new(
+            &server.local_addr().unwrap().to_string(),
+            Template::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            statsd.lines(&metrics),
+            [
+                "perflib.Contoso_Disk.C.Queue_Length:0|g|#env:prod,role_tier:web_db",
+                "perflib.Contoso_Disk.C.Queue_Length:-1.5|g|#env:prod,role_tier:web_db"
+            ]
+        );
+    }
+}
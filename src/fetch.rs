@@ -5,7 +5,8 @@ use windows::core::{Result, GUID};
 
 mod counters;
 mod countersets;
-mod instances;
+pub mod instances;
+pub mod legacy;
 mod providers;
 
 pub fn all_providers(buf: &mut Vec<u8>) -> Result<Vec<Provider>> {
@@ -27,6 +28,7 @@ pub fn all_providers(buf: &mut Vec<u8>) -> Result<Vec<Provider>> {
             instance_type,
             counters,
             instances,
+            legacy: false,
         };
 
         match providers.entry(provider_id) {
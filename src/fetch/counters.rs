@@ -1,4 +1,6 @@
-use crate::types::{AggregateFunc, Counter, NonMaxU32};
+use crate::types::{
+    AggregateFunc, Counter, CounterAttributes, CounterType, DetailLevel, NonMaxU32,
+};
 use crate::winapi::{decode_utf16_until_null, invoke_with_buf};
 use std::collections::HashMap;
 use windows::core::{Result, GUID, HRESULT};
@@ -17,23 +19,43 @@ pub fn of_counterset(buf: &mut Vec<u8>, counterset_id: &GUID) -> Result<Vec<Coun
     let mut counters = Vec::with_capacity(names.len());
 
     // Since there can be duplicate ids (and technically duplicate names, although I don't see that),
-    // we can't just iterate over reg_info ids here--we need to iterate over names.
+    // we can't just iterate over reg_info ids here--we need to iterate over names. Each of a duplicated id's
+    // names goes with the same occurrence of the id in the help and registration info, so that lint can report them.
+    let mut seen = HashMap::<u32, usize>::new();
     for (id, name) in names {
+        let occurrence = seen.entry(id).or_default();
         // Help strings may not exist for all counters.
-        let help = help.get(&id).cloned().unwrap_or_default();
-
-        let reg_info = reg_info[&id];
+        let help = help
+            .get(&id)
+            .and_then(|help| nth(help, *occurrence))
+            .cloned()
+            .unwrap_or_default();
+
+        let reg_info = *nth(&reg_info[&id], *occurrence).unwrap();
+        *occurrence += 1;
+        let counter_type = CounterType(reg_info.Type);
         let base_counter_id = NonMaxU32::new(reg_info.BaseCounterId);
         let multi_counter_id = NonMaxU32::new(reg_info.MultiId);
+        let perf_time_id = NonMaxU32::new(reg_info.PerfTimeId);
+        let perf_freq_id = NonMaxU32::new(reg_info.PerfFreqId);
         let aggregate_func = AggregateFunc::from_bits(reg_info.AggregateFunc)?;
+        let detail_level = DetailLevel::from_bits(reg_info.DetailLevel)?;
+        let attributes = CounterAttributes(reg_info.Attrib);
+        let default_scale = reg_info.DefaultScale;
 
         counters.push(Counter {
             id,
             name,
             help,
+            counter_type,
             base_counter_id,
             multi_counter_id,
+            perf_time_id,
+            perf_freq_id,
             aggregate_func,
+            detail_level,
+            attributes,
+            default_scale,
         });
     }
 
@@ -42,10 +64,15 @@ pub fn of_counterset(buf: &mut Vec<u8>, counterset_id: &GUID) -> Result<Vec<Coun
     Ok(counters)
 }
 
+/// The nth of the entries for an id, or the last if there are fewer.
+fn nth<T>(entries: &[T], n: usize) -> Option<&T> {
+    entries.get(n).or(entries.last())
+}
+
 fn names_of_all_in_counterset(
     buf: &mut Vec<u8>,
     counterset_id: &GUID,
-) -> Result<HashMap<u32, String>> {
+) -> Result<Vec<(u32, String)>> {
     let buf = invoke_with_buf(buf, |buf, len| unsafe {
         PerfQueryCounterSetRegistrationInfo(
             None,
@@ -66,7 +93,7 @@ fn names_of_all_in_counterset(
 fn help_strings_of_all_in_counterset(
     buf: &mut Vec<u8>,
     counterset_id: &GUID,
-) -> Result<HashMap<u32, String>> {
+) -> Result<HashMap<u32, Vec<String>>> {
     let res = invoke_with_buf(buf, |buf, len| unsafe {
         PerfQueryCounterSetRegistrationInfo(
             None,
@@ -87,12 +114,15 @@ fn help_strings_of_all_in_counterset(
     };
 
     // SAFETY: buf has the required layout, as documented for PERF_REG_COUNTER_HELP_STRINGS.
-    let names = unsafe { parse_counter_strings(buf) };
+    let mut help = HashMap::<u32, Vec<String>>::new();
+    for (id, string) in unsafe { parse_counter_strings(buf) } {
+        help.entry(id).or_default().push(string);
+    }
 
-    Ok(names)
+    Ok(help)
 }
 
-/// Parse a buffer of counter strings into a map by counter id.
+/// Parse a buffer of counter strings into (counter id, string) pairs, in order, keeping any duplicate ids.
 ///
 /// # SAFETY
 ///
@@ -100,7 +130,7 @@ fn help_strings_of_all_in_counterset(
 /// followed by one or more `PERF_STRING_COUNTER_HEADER` structures,
 /// followed by string data that indicates the counter names.
 /// As documented on https://learn.microsoft.com/en-us/windows/win32/api/perflib/ne-perflib-perfreginfotype.
-unsafe fn parse_counter_strings(buf: &[u8]) -> HashMap<u32, String> {
+unsafe fn parse_counter_strings(buf: &[u8]) -> Vec<(u32, String)> {
     // SAFETY: Everything here and below depends on the Windows API being implemented as documented.
     // https://learn.microsoft.com/en-us/windows/win32/api/perflib/ne-perflib-perfreginfotype
 
@@ -109,7 +139,7 @@ unsafe fn parse_counter_strings(buf: &[u8]) -> HashMap<u32, String> {
     let header = unsafe { header_ptr.read_unaligned() };
 
     let num_counters = header.dwCounters.try_into().unwrap();
-    let mut strings = HashMap::with_capacity(num_counters);
+    let mut strings = Vec::with_capacity(num_counters);
 
     // "...followed by one or more PERF_STRING_COUNTER_HEADER structures..."
     let first_string_ptr = unsafe { header_ptr.add(1).cast::<PERF_STRING_COUNTER_HEADER>() };
@@ -127,7 +157,7 @@ unsafe fn parse_counter_strings(buf: &[u8]) -> HashMap<u32, String> {
             }
         };
 
-        strings.insert(string.dwCounterId, name);
+        strings.push((string.dwCounterId, name));
     }
 
     strings
@@ -136,7 +166,7 @@ unsafe fn parse_counter_strings(buf: &[u8]) -> HashMap<u32, String> {
 fn reg_info_of_all_in_counterset(
     buf: &mut Vec<u8>,
     counterset_id: &GUID,
-) -> Result<HashMap<u32, PERF_COUNTER_REG_INFO>> {
+) -> Result<HashMap<u32, Vec<PERF_COUNTER_REG_INFO>>> {
     let buf = invoke_with_buf(buf, |buf, len| unsafe {
         PerfQueryCounterSetRegistrationInfo(
             None,
@@ -156,7 +186,7 @@ fn reg_info_of_all_in_counterset(
     let header = unsafe { header_ptr.read_unaligned() };
 
     let num_counters = header.NumCounters.try_into().unwrap();
-    let mut reg_info = HashMap::with_capacity(num_counters);
+    let mut reg_info = HashMap::<u32, Vec<_>>::with_capacity(num_counters);
 
     // "...followed by one or more PERF_COUNTER_REG_INFO structures."
     let first_info_ptr = unsafe { header_ptr.add(1).cast::<PERF_COUNTER_REG_INFO>() };
@@ -165,7 +195,7 @@ fn reg_info_of_all_in_counterset(
         let info_ptr = unsafe { first_info_ptr.add(i) };
         let info = unsafe { info_ptr.read_unaligned() };
 
-        reg_info.insert(info.CounterId, info);
+        reg_info.entry(info.CounterId).or_default().push(info);
     }
 
     Ok(reg_info)
@@ -0,0 +1,90 @@
+use crate::error::Result;
+use crate::legacy::{self, Names};
+use crate::types::{Provider, Sample};
+use std::collections::HashSet;
+use windows::core::{GUID, HSTRING};
+use windows::Win32::Foundation::ERROR_MORE_DATA;
+use windows::Win32::System::Registry::{RegCloseKey, RegQueryValueExW, HKEY_PERFORMANCE_DATA};
+
+/// Every legacy object, except those which are just perflib's V1 view of a counterset in `countersets`.
+pub fn provider(buf: &mut Vec<u8>, countersets: &[Provider]) -> Result<Provider> {
+    let opened = Opened;
+    let names = Names::parse(query(buf, "Counter 009")?);
+    let help = Names::parse(query(buf, "Help 009")?);
+    let mut provider = legacy::parse(query(buf, "Global")?, &names, &help)?;
+    drop(opened);
+
+    let v2_names = countersets
+        .iter()
+        .flat_map(|p| &p.countersets)
+        .map(|cs| cs.name.as_str())
+        .collect::<HashSet<_>>();
+    let before = provider.countersets.len();
+    provider
+        .countersets
+        .retain(|cs| !v2_names.contains(cs.name.as_str()));
+    log::debug!(
+        "Skipped {} legacy objects which are also countersets",
+        before - provider.countersets.len()
+    );
+
+    Ok(provider)
+}
+
+/// Collect the current values of a legacy object.
+pub fn sample(buf: &mut Vec<u8>, counterset_id: &GUID) -> Result<Sample> {
+    let index = legacy::object_index(counterset_id)
+        .ok_or_else(|| format!("{:?} isn't a legacy object", counterset_id))?;
+
+    // Asking for an object's index only collects that object (and sometimes a few related ones).
+    let _opened = Opened;
+    legacy::sample(query(buf, &index.to_string())?, counterset_id)
+}
+
+/// Query a value of `HKEY_PERFORMANCE_DATA`, growing the buffer until the data fits.
+///
+/// Unlike most registry values, the required size isn't reported, since it can change between calls.
+fn query<'a>(buf: &'a mut Vec<u8>, value: &str) -> windows::core::Result<&'a [u8]> {
+    const INITIAL_SIZE: usize = 64 * 1024;
+
+    let value = HSTRING::from(value);
+    if buf.len() < INITIAL_SIZE {
+        buf.resize(INITIAL_SIZE, 0);
+    }
+
+    loop {
+        let mut len = u32::try_from(buf.len()).unwrap();
+        let res = unsafe {
+            RegQueryValueExW(
+                HKEY_PERFORMANCE_DATA,
+                &value,
+                None,
+                None,
+                Some(buf.as_mut_ptr()),
+                Some(&mut len),
+            )
+        };
+        match res {
+            Ok(()) => return Ok(&buf[..len.try_into().unwrap()]),
+            Err(e) if e.code() == ERROR_MORE_DATA.to_hresult() => {
+                let size = buf.len() * 2;
+                buf.resize(size, 0);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Closes `HKEY_PERFORMANCE_DATA` when dropped, which querying it opens, including when a query fails.
+///
+/// "When you're done collecting performance data, close the HKEY_PERFORMANCE_DATA key."
+/// https://learn.microsoft.com/en-us/windows/win32/perfctrs/using-the-registry-functions-to-consume-counter-data
+struct Opened;
+
+impl Drop for Opened {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { RegCloseKey(HKEY_PERFORMANCE_DATA) } {
+            log::warn!("Failed to close HKEY_PERFORMANCE_DATA: {}", e);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Deserializer};
+use std::convert::Infallible;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// A case-insensitive glob pattern, where `*` matches any run of characters and `?` matches any single character.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: Vec<char>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_lowercase().chars().collect(),
+        }
+    }
+
+    pub fn matches(&self, s: &str) -> bool {
+        let s = s.to_lowercase().chars().collect::<Vec<_>>();
+
+        let (mut p, mut i) = (0, 0);
+        // Where to resume after the most recent `*`, if the rest of the pattern fails to match.
+        let mut backtrack = None;
+
+        while i < s.len() {
+            match self.pattern.get(p) {
+                Some('*') => {
+                    backtrack = Some((p, i));
+                    p += 1;
+                }
+                Some('?') => {
+                    p += 1;
+                    i += 1;
+                }
+                Some(&c) if c == s[i] => {
+                    p += 1;
+                    i += 1;
+                }
+                _ => match backtrack {
+                    // Let the `*` consume one more character.
+                    Some((star, consumed)) => {
+                        backtrack = Some((star, consumed + 1));
+                        p = star + 1;
+                        i = consumed + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        self.pattern[p..].iter().all(|&c| c == '*')
+    }
+}
+
+impl FromStr for Glob {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::new(s))
+    }
+}
+
+impl<'de> Deserialize<'de> for Glob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|s| Self::new(&s))
+    }
+}
+
+impl Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern.iter().collect::<String>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches() {
+        let cases = [
+            // Literal patterns match the whole name, in any case.
+            ("Disk", "disk", true),
+            ("disk", "DISK", true),
+            ("disk", "disks", false),
+            ("disk", "my disk", false),
+            ("", "", true),
+            ("", "a", false),
+            // `*` matches any run of characters, including none.
+            ("*", "", true),
+            ("*", "anything", true),
+            ("disk*", "disk", true),
+            ("disk*", "Disk 0", true),
+            ("disk*", "my disk", false),
+            ("*disk", "My Disk", true),
+            ("*disk", "disks", false),
+            ("*time*", "% Idle Time Base", true),
+            ("a*b*c", "aXbYc", true),
+            ("a*b*c", "aXbYcZ", false),
+            // Needs backtracking, as the first `b` isn't the one to match.
+            ("a*bc", "abxbc", true),
+            ("**", "x", true),
+            // `?` matches exactly one character.
+            ("?", "", false),
+            ("?", "x", true),
+            ("?", "xy", false),
+            ("c?", "C:", true),
+            ("disk ?", "Disk 10", false),
+            ("disk ??", "Disk 10", true),
+            ("*?", "", false),
+            ("*?", "x", true),
+        ];
+        for (pattern, name, expected) in cases {
+            assert_eq!(
+                Glob::new(pattern).matches(name),
+                expected,
+                "{:?} matching {:?}",
+                pattern,
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn displays_the_lowercase_pattern() {
+        let glob = "Contoso *".parse::<Glob>().unwrap();
+        assert_eq!(glob.to_string(), "contoso *");
+    }
+}
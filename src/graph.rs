@@ -0,0 +1,237 @@
+//! Render the relationships between a counterset's counters as a graph.
+
+use crate::types::{Counter, CounterSet};
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Graphviz
+    Dot,
+    Mermaid,
+}
+
+/// An edge from a counter to a counter it depends on.
+struct Edge {
+    from: usize,
+    /// Index of the target counter, or None if no counter has the id.
+    to: Option<usize>,
+    to_id: u32,
+    label: &'static str,
+}
+
+pub fn generate(format: Format, counterset: &CounterSet) -> String {
+    let edges = edges(&counterset.counters);
+    match format {
+        Format::Dot => dot(counterset, &edges),
+        Format::Mermaid => mermaid(counterset, &edges),
+    }
+}
+
+/// Edges to each counter's base and multi counters. Counter ids aren't always unique, so a reference to a
+/// duplicated id gets an edge to every counter with that id.
+fn edges(counters: &[Counter]) -> Vec<Edge> {
+    let mut edges = Vec::new();
+    for (from, counter) in counters.iter().enumerate() {
+        let related = [
+            ("base", counter.base_counter_id),
+            ("multi", counter.multi_counter_id),
+        ];
+        for (label, id) in related {
+            let Some(id) = id.map(|id| id.get()) else {
+                continue;
+            };
+            let targets = counters
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.id == id)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            if targets.is_empty() {
+                edges.push(Edge {
+                    from,
+                    to: None,
+                    to_id: id,
+                    label,
+                });
+            }
+            for to in targets {
+                edges.push(Edge {
+                    from,
+                    to: Some(to),
+                    to_id: id,
+                    label,
+                });
+            }
+        }
+    }
+    edges
+}
+
+/// Ids of counters referenced by an edge which don't exist, without duplicates.
+fn dangling(edges: &[Edge]) -> Vec<u32> {
+    let mut ids = edges
+        .iter()
+        .filter(|e| e.to.is_none())
+        .map(|e| e.to_id)
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+fn node_name(edge_target: Option<usize>, id: u32) -> String {
+    match edge_target {
+        Some(index) => format!("c{}", index),
+        None => format!("missing{}", id),
+    }
+}
+
+fn dot(counterset: &CounterSet, edges: &[Edge]) -> String {
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+
+    let mut out = String::new();
+    writeln!(out, "digraph \"{}\" {{", escape(&counterset.name)).unwrap();
+    writeln!(out, "  rankdir=LR;").unwrap();
+    writeln!(out, "  node [shape=box];").unwrap();
+    for (i, counter) in counterset.counters.iter().enumerate() {
+        writeln!(
+            out,
+            "  c{} [label=\"{} ({})\\n{:?}\"];",
+            i,
+            escape(&counter.name),
+            counter.id,
+            counter.counter_type
+        )
+        .unwrap();
+    }
+    for id in dangling(edges) {
+        writeln!(
+            out,
+            "  missing{} [label=\"missing counter {}\", style=dashed, color=red];",
+            id, id
+        )
+        .unwrap();
+    }
+    for edge in edges {
+        let color = match edge.to {
+            Some(_) => "",
+            None => ", color=red",
+        };
+        writeln!(
+            out,
+            "  c{} -> {} [label=\"{}\"{}];",
+            edge.from,
+            node_name(edge.to, edge.to_id),
+            edge.label,
+            color
+        )
+        .unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+fn mermaid(counterset: &CounterSet, edges: &[Edge]) -> String {
+    let escape = |s: &str| s.replace('"', "#quot;");
+
+    let mut out = String::new();
+    writeln!(out, "---").unwrap();
+    // The title is in YAML front matter, which is escaped differently from labels.
+    let yaml_escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+    writeln!(out, "title: \"{}\"", yaml_escape(&counterset.name)).unwrap();
+    writeln!(out, "---").unwrap();
+    writeln!(out, "flowchart LR").unwrap();
+    for (i, counter) in counterset.counters.iter().enumerate() {
+        writeln!(
+            out,
+            "  c{}[\"{} ({})<br/>{:?}\"]",
+            i,
+            escape(&counter.name),
+            counter.id,
+            counter.counter_type
+        )
+        .unwrap();
+    }
+    let dangling = dangling(edges);
+    for id in &dangling {
+        writeln!(
+            out,
+            "  missing{}[\"missing counter {}\"]:::dangling",
+            id, id
+        )
+        .unwrap();
+    }
+    for edge in edges {
+        let arrow = match edge.to {
+            Some(_) => "-->",
+            None => "-.->",
+        };
+        writeln!(
+            out,
+            "  c{} {}|{}| {}",
+            edge.from,
+            arrow,
+            edge.label,
+            node_name(edge.to, edge.to_id)
+        )
+        .unwrap();
+    }
+    if !dangling.is_empty() {
+        writeln!(out, "  classDef dangling stroke:#f00,stroke-dasharray:5 5").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::catalog;
+    use crate::types::NonMaxU32;
+
+    #[test]
+    fn dot() {
+        assert_eq!(
+            generate(Format::Dot, &catalog()[0].countersets[1]),
+            r#"digraph "Contoso Disk" {
+  rankdir=LR;
+  node [shape=box];
+  c0 [label="Bytes Read (0)\nPERF_COUNTER_LARGE_RAWCOUNT"];
+  c1 [label="Read Latency (1)\nPERF_AVERAGE_TIMER"];
+  c2 [label="Read Latency Base (2)\nPERF_AVERAGE_BASE"];
+  c3 [label="% Idle Time (3)\nPERF_SAMPLE_FRACTION"];
+  c4 [label="% Idle Time Base (4)\nPERF_SAMPLE_BASE"];
+  c5 [label="Queue Length (5)\nPERF_COUNTER_RAWCOUNT"];
+  c1 -> c2 [label="base"];
+  c3 -> c4 [label="base"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn mermaid_with_dangling_reference() {
+        let mut all = catalog();
+        let counterset = &mut all[0].countersets[1];
+        counterset.name = "Contoso \"Disk\"".to_string();
+        counterset.counters.truncate(3);
+        counterset.counters[0].base_counter_id = NonMaxU32::new(9);
+        counterset.counters[0].multi_counter_id = NonMaxU32::new(1);
+
+        assert_eq!(
+            generate(Format::Mermaid, counterset),
+            r#"---
+title: "Contoso \"Disk\""
+---
+flowchart LR
+  c0["Bytes Read (0)<br/>PERF_COUNTER_LARGE_RAWCOUNT"]
+  c1["Read Latency (1)<br/>PERF_AVERAGE_TIMER"]
+  c2["Read Latency Base (2)<br/>PERF_AVERAGE_BASE"]
+  missing9["missing counter 9"]:::dangling
+  c0 -.->|base| missing9
+  c0 -->|multi| c1
+  c1 -->|base| c2
+  classDef dangling stroke:#f00,stroke-dasharray:5 5
+"#
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Just enough HTTP to POST to local services, e.g. alert relays and metrics collectors.
+
+use crate::error::Result;
+use std::fmt::{self, Display};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A plain HTTP URL to POST to.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    /// host:port
+    authority: String,
+    path: String,
+}
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Endpoint {
+    /// POST a body, failing unless the response is a success.
+    pub fn post(&self, content_type: &str, body: &[u8]) -> Result<()> {
+        let mut stream = connect(&self.authority, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.authority,
+            content_type,
+            body.len()
+        )?;
+        stream.write_all(body)?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(format!("unexpected response `{}`", status.trim_end()).into()),
+        }
+    }
+}
+
+/// Connect to `host:port`, trying each of its addresses in turn for up to `timeout`, as a collector which doesn't
+/// answer can otherwise take minutes to fail.
+pub fn connect(address: &str, timeout: Duration) -> Result<TcpStream> {
+    let mut error = None;
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(match error {
+        Some(e) => e.into(),
+        None => format!("`{}` has no addresses", address).into(),
+    })
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("only http:// URLs are supported, not `{}`", s))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("URL `{}` has no host", s));
+        }
+        let authority = match authority.rsplit_once(':') {
+            Some(_) => authority.to_string(),
+            None => format!("{}:80", authority),
+        };
+        Ok(Self {
+            authority,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority, self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_endpoints() {
+        let endpoint = "http://localhost:8080/alerts".parse::<Endpoint>().unwrap();
+        assert_eq!(endpoint.authority, "localhost:8080");
+        assert_eq!(endpoint.path, "/alerts");
+        assert_eq!(
+            "http://localhost".parse::<Endpoint>().unwrap().to_string(),
+            "http://localhost:80/"
+        );
+        assert!("https://localhost/".parse::<Endpoint>().is_err());
+        assert!("http:///alerts".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn connects_to_any_address() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // localhost may resolve to ::1 first, which refuses.
+        connect(&format!("localhost:{}", port), TIMEOUT).unwrap();
+        drop(listener);
+        assert!(connect(&format!("127.0.0.1:{}", port), TIMEOUT).is_err());
+    }
+}
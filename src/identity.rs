@@ -0,0 +1,273 @@
+//! Stable identities for the instances of successive samples of a counterset.
+//!
+//! Instance names aren't unique (e.g. Process has several `svchost`), ids get reused, and instances aren't always in
+//! the same order from one sample to the next. An instance is the same as one in the previous sample only if both its
+//! id and name match and nothing suggests that the underlying object was replaced in between.
+
+use crate::cook;
+use crate::types::counter_type::Kind;
+use crate::types::{Counter, CounterSet, Instance, InstanceSample, Sample};
+use std::collections::{HashMap, VecDeque};
+use std::time::SystemTime;
+
+/// Identifies one underlying instance for as long as it exists.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SeriesKey {
+    /// None for single-instance countersets.
+    pub instance: Option<Instance>,
+    /// When the instance was first seen with this id and name, so that a later instance with both is distinct.
+    pub first_seen: SystemTime,
+}
+
+/// What's known about one instance of a sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub key: SeriesKey,
+    /// Index of the same instance in the previous sample, if it was in it.
+    pub previous: Option<usize>,
+    /// The previous sample had an instance with the same id and name, but it was a different object, e.g. a
+    /// process which exited and was replaced by one with the same name and id.
+    pub restarted: bool,
+}
+
+/// Keeps the latest two samples of a counterset, with the identity of every instance of the latest one.
+pub struct Series<'a> {
+    counterset: &'a CounterSet,
+    previous: Option<Sample>,
+    current: Option<Sample>,
+    /// Parallel to `current.instances`.
+    identities: Vec<Identity>,
+}
+
+impl<'a> Series<'a> {
+    pub fn new(counterset: &'a CounterSet) -> Self {
+        Self {
+            counterset,
+            previous: None,
+            current: None,
+            identities: Vec::new(),
+        }
+    }
+
+    /// Make a sample the latest one, matching its instances up with those of the one before.
+    pub fn update(&mut self, sample: Sample, time: SystemTime) {
+        let before = self.current.take();
+        let previous_identities = std::mem::take(&mut self.identities);
+
+        // Instances with the same id and name are paired up in order, each previous one matching at most once.
+        let mut by_instance: HashMap<_, VecDeque<usize>> = HashMap::new();
+        if let Some(before) = &before {
+            for (index, instance) in before.instances.iter().enumerate() {
+                by_instance
+                    .entry(&instance.instance)
+                    .or_default()
+                    .push_back(index);
+            }
+        }
+
+        for instance in &sample.instances {
+            let matched = by_instance
+                .get_mut(&instance.instance)
+                .and_then(VecDeque::pop_front);
+            let restarted = match (matched, &before) {
+                (Some(index), Some(before)) => restarted(
+                    self.counterset,
+                    before,
+                    &before.instances[index],
+                    &sample,
+                    instance,
+                ),
+                _ => false,
+            };
+            let (previous, first_seen) = match matched {
+                Some(index) if !restarted => {
+                    (Some(index), previous_identities[index].key.first_seen)
+                }
+                _ => (None, time),
+            };
+            self.identities.push(Identity {
+                key: SeriesKey {
+                    instance: instance.instance.clone(),
+                    first_seen,
+                },
+                previous,
+                restarted,
+            });
+        }
+
+        self.previous = before;
+        self.current = Some(sample);
+    }
+
+    pub fn current(&self) -> Option<&Sample> {
+        self.current.as_ref()
+    }
+
+    pub fn previous(&self) -> Option<&Sample> {
+        self.previous.as_ref()
+    }
+
+    /// Parallel to the instances of the current sample.
+    pub fn identities(&self) -> &[Identity] {
+        &self.identities
+    }
+
+    /// Instances of the previous sample which aren't in the current one, including those which restarted.
+    pub fn disappeared(&self) -> Vec<&InstanceSample> {
+        let Some(previous) = &self.previous else {
+            return Vec::new();
+        };
+        previous
+            .instances
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.identities.iter().any(|i| i.previous == Some(*index)))
+            .map(|(_, instance)| instance)
+            .filter(|instance| {
+                // A restarted instance is still there, as far as anyone displaying it is concerned.
+                !self
+                    .identities
+                    .iter()
+                    .any(|i| i.restarted && i.key.instance == instance.instance)
+            })
+            .collect()
+    }
+
+    /// Cook a counter's value for an instance of the current sample, using the same instance from the previous
+    /// sample if the counter type needs it, but never a different object with the same id and name.
+    pub fn cook(&self, counter: &Counter, index: usize) -> Option<f64> {
+        let sample = self.current.as_ref()?;
+        let instance = &sample.instances[index];
+        let previous = self.identities[index]
+            .previous
+            .zip(self.previous.as_ref())
+            .map(|(i, previous)| (previous, &previous.instances[i]));
+        cook::cook_instance(counter, previous, sample, instance)
+    }
+}
+
+/// Whether an instance was replaced by a different object with the same id and name between two samples.
+///
+/// That's the case if a counter holding a start time changed, or a counter which only ever counts up went down by
+/// more than wrapping around could explain.
+pub fn restarted(
+    counterset: &CounterSet,
+    previous: &Sample,
+    previous_instance: &InstanceSample,
+    sample: &Sample,
+    instance: &InstanceSample,
+) -> bool {
+    counterset.counters.iter().any(|counter| {
+        let raw = |sample: &Sample, instance: &InstanceSample| {
+            cook::Raw::from_sample(counter, sample, instance).map(|raw| raw.value)
+        };
+        let (Some(before), Some(now)) = (raw(previous, previous_instance), raw(sample, instance))
+        else {
+            return false;
+        };
+        match counter.counter_type.kind() {
+            Kind::Elapsed => before != now,
+            _ if counter.counter_type.is_delta() => {
+                cook::delta(counter.counter_type.size(), before, now).is_err()
+            }
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{at, contoso_disk, instance, sample, CONTOSO_DISK};
+
+    /// Contoso Disk, where Bytes Read is counted up between samples.
+    fn counterset() -> CounterSet {
+        let mut counterset = contoso_disk();
+        counterset.counters[0].counter_type = crate::types::CounterType::PERF_COUNTER_BULK_COUNT;
+        counterset
+    }
+
+    /// A sample at a second, of bytes read for each (instance id, name, bytes).
+    fn bytes_read(second: i64, instances: &[(u32, &str, u64)]) -> Sample {
+        let instances = instances
+            .iter()
+            .map(|&(id, name, bytes)| (instance(id, name), [bytes]));
+        sample(CONTOSO_DISK, second, &[0], instances)
+    }
+
+    #[test]
+    fn follows_reordered_instances() {
+        let counterset = counterset();
+        let mut series = Series::new(&counterset);
+        series.update(
+            bytes_read(0, &[(1, "svchost", 100), (2, "svchost", 50)]),
+            at(0),
+        );
+        series.update(
+            bytes_read(1, &[(2, "svchost", 60), (1, "svchost", 300)]),
+            at(1),
+        );
+
+        let identities = series.identities();
+        assert_eq!(identities[0].previous, Some(1));
+        assert_eq!(identities[1].previous, Some(0));
+        assert!(identities
+            .iter()
+            .all(|i| !i.restarted && i.key.first_seen == at(0)));
+        assert_eq!(series.cook(&counterset.counters[0], 0), Some(10.0));
+        assert_eq!(series.cook(&counterset.counters[0], 1), Some(200.0));
+        assert!(series.disappeared().is_empty());
+    }
+
+    #[test]
+    fn pairs_up_duplicate_instances_in_order() {
+        let counterset = counterset();
+        let mut series = Series::new(&counterset);
+        series.update(
+            bytes_read(0, &[(0, "svchost", 100), (0, "svchost", 50)]),
+            at(0),
+        );
+        series.update(
+            bytes_read(1, &[(0, "svchost", 110), (0, "svchost", 70)]),
+            at(1),
+        );
+
+        let identities = series.identities();
+        assert_eq!(identities[0].previous, Some(0));
+        assert_eq!(identities[1].previous, Some(1));
+        assert_eq!(series.cook(&counterset.counters[0], 0), Some(10.0));
+        assert_eq!(series.cook(&counterset.counters[0], 1), Some(20.0));
+        assert!(series.disappeared().is_empty());
+
+        // One of them went away, so the other is matched with the first of the previous sample's.
+        series.update(bytes_read(2, &[(0, "svchost", 130)]), at(2));
+        assert_eq!(series.identities()[0].previous, Some(0));
+        assert_eq!(series.disappeared().len(), 1);
+    }
+
+    #[test]
+    fn detects_restarts() {
+        let counterset = counterset();
+        let mut series = Series::new(&counterset);
+        series.update(bytes_read(0, &[(1, "app", 1000), (2, "gone", 0)]), at(0));
+        // The counter went backwards, so this is a different app with the same id and name.
+        series.update(bytes_read(1, &[(1, "app", 10)]), at(1));
+
+        let identity = &series.identities()[0];
+        assert!(identity.restarted);
+        assert_eq!(identity.previous, None);
+        assert_eq!(identity.key.first_seen, at(1));
+        assert_eq!(series.cook(&counterset.counters[0], 0), None);
+
+        let disappeared = series.disappeared();
+        assert_eq!(disappeared.len(), 1);
+        assert_eq!(disappeared[0].instance.as_ref().unwrap().name, "gone");
+
+        // From then on it's followed as usual.
+        series.update(bytes_read(2, &[(1, "app", 30)]), at(2));
+        let identity = &series.identities()[0];
+        assert!(!identity.restarted);
+        assert_eq!(identity.key.first_seen, at(1));
+        assert_eq!(series.cook(&counterset.counters[0], 0), Some(20.0));
+    }
+}
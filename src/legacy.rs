@@ -0,0 +1,754 @@
+//! Legacy (V1) performance objects, which predate countersets and are read from the registry through
+//! `HKEY_PERFORMANCE_DATA`, e.g. Process, Memory, and the .NET CLR objects.
+//!
+//! The data is a `PERF_DATA_BLOCK` followed by a `PERF_OBJECT_TYPE` for each object, each followed by its
+//! `PERF_COUNTER_DEFINITION`s and then either a single `PERF_COUNTER_BLOCK` or a `PERF_INSTANCE_DEFINITION`
+//! and `PERF_COUNTER_BLOCK` for each instance. Objects and counters are named by indexes into the
+//! `Counter 009` and `Help 009` tables.
+//!
+//! https://learn.microsoft.com/en-us/windows/win32/perfctrs/performance-data-format
+//!
+//! Objects don't have GUIDs, so they're given synthetic ones derived from their name index, and all belong to
+//! a single synthetic provider. Counters are identified by their position in the object, since their name
+//! indexes aren't always unique. Objects with counters timed by the object's own clock get two more, hidden
+//! counters after the others, for its time and frequency.
+//!
+//! Instances without a unique id, which is most of them, are identified by name, qualified by their parent
+//! instance's like `parent/name` if they have one, e.g. the threads of a process.
+
+use crate::error::Result;
+use crate::types::counter_type::{Kind, Timer};
+use crate::types::{
+    AggregateFunc, Counter, CounterAttributes, CounterSet, CounterType, DetailLevel, Instance,
+    InstanceSample, InstanceType, NonMaxU32, Provider, Sample,
+};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use windows::core::GUID;
+
+/// Shared by the synthetic provider and counterset GUIDs, to tell them apart from real ones.
+const GUID_SUFFIX: [u8; 8] = *b"PerfV1\0\0";
+
+/// The synthetic provider which all legacy objects belong to.
+pub const PROVIDER_ID: GUID = GUID::from_values(0, 0, 0, GUID_SUFFIX);
+
+pub const PROVIDER_NAME: &str = "Legacy (V1)";
+
+/// The synthetic GUID of an object, e.g. 000000EE-0001-0000-5065-726656310000 for Processor, whose name index is 238.
+pub fn counterset_id(object_index: u32) -> GUID {
+    GUID::from_values(object_index, 1, 0, GUID_SUFFIX)
+}
+
+/// The object's name index, if the GUID is one made by `counterset_id`.
+pub fn object_index(counterset_id: &GUID) -> Option<u32> {
+    (counterset_id.data2 == 1 && counterset_id.data3 == 0 && counterset_id.data4 == GUID_SUFFIX)
+        .then_some(counterset_id.data1)
+}
+
+/// Indexes of names or help text, from the `Counter 009` or `Help 009` registry values.
+#[derive(Debug, Default)]
+pub struct Names(HashMap<u32, String>);
+
+impl Names {
+    /// Parse a `REG_MULTI_SZ` of alternating indexes and strings, e.g. "2\0System\04\0Memory\0\0".
+    pub fn parse(data: &[u8]) -> Self {
+        let text = utf16(data);
+        let mut strings = text.split('\0');
+
+        let mut names = HashMap::new();
+        while let (Some(index), Some(name)) = (strings.next(), strings.next()) {
+            if let Ok(index) = index.trim().parse() {
+                names.insert(index, name.to_string());
+            }
+        }
+        Self(names)
+    }
+
+    pub fn get(&self, index: u32) -> Option<&str> {
+        self.0.get(&index).map(|s| s.as_str())
+    }
+}
+
+/// Parse the definitions and instances of every object in a `PERF_DATA_BLOCK`.
+pub fn parse(data: &[u8], names: &Names, help: &Names) -> Result<Provider> {
+    let block = DataBlock::parse(data)?;
+
+    let mut countersets = Vec::new();
+    for object in block.objects() {
+        let object = object?;
+        let name = match names.get(object.name_index) {
+            Some(name) => name.to_string(),
+            None => format!("Object {}", object.name_index),
+        };
+        let object_help = help.get(object.help_index).unwrap_or_default().to_string();
+
+        let definitions = object.counters()?;
+        let timer = object_timer_ids(&definitions);
+        let mut counters: Vec<Counter> = definitions
+            .iter()
+            .enumerate()
+            .map(|(i, definition)| {
+                let name = match names.get(definition.name_index) {
+                    Some(name) => name.to_string(),
+                    None => format!("Counter {}", definition.name_index),
+                };
+                let help = help
+                    .get(definition.help_index)
+                    .unwrap_or_default()
+                    .to_string();
+                counter(i, definition, &definitions, timer, name, help)
+            })
+            .collect();
+        if let Some((time_id, frequency_id)) = timer {
+            counters.push(object_timer_counter(
+                time_id,
+                "Object Time",
+                "The time of the object's sample, which its counters timed by the object are measured against.",
+            ));
+            counters.push(object_timer_counter(
+                frequency_id,
+                "Object Frequency",
+                "The ticks per second of Object Time.",
+            ));
+        }
+
+        let instances = object
+            .instances(&block)?
+            .map(|instances| instances.into_iter().map(|(i, _)| i).collect());
+
+        countersets.push(CounterSet {
+            id: counterset_id(object.name_index),
+            name,
+            help: object_help,
+            instance_type: match instances {
+                None => InstanceType::SingleInstance,
+                Some(_) => InstanceType::MultiInstances,
+            },
+            counters,
+            instances,
+            legacy: true,
+        });
+    }
+    countersets.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Provider {
+        id: PROVIDER_ID,
+        name: PROVIDER_NAME.to_string(),
+        countersets,
+    })
+}
+
+/// The ids of the counters holding an object's time and frequency, after its own counters, if any of them are
+/// timed by the object.
+fn object_timer_ids(definitions: &[CounterDefinition]) -> Option<(u32, u32)> {
+    let timed = definitions
+        .iter()
+        .any(|d| CounterType(d.counter_type).timer() == Timer::Object);
+    let next = definitions.len() as u32;
+    timed.then_some((next, next + 1))
+}
+
+fn counter(
+    i: usize,
+    definition: &CounterDefinition,
+    definitions: &[CounterDefinition],
+    timer: Option<(u32, u32)>,
+    name: String,
+    help: String,
+) -> Counter {
+    let counter_type = CounterType(definition.counter_type);
+
+    // A counter's base is always the counter immediately after it.
+    let base_counter_id = match definitions.get(i + 1) {
+        Some(next)
+            if counter_type.kind() != Kind::Base
+                && CounterType(next.counter_type).kind() == Kind::Base =>
+        {
+            NonMaxU32::new(i as u32 + 1)
+        }
+        _ => None,
+    };
+
+    let (perf_time_id, perf_freq_id) = match timer {
+        Some((time_id, frequency_id)) if counter_type.timer() == Timer::Object => {
+            (NonMaxU32::new(time_id), NonMaxU32::new(frequency_id))
+        }
+        _ => (None, None),
+    };
+
+    Counter {
+        id: i as u32,
+        name,
+        help,
+        counter_type,
+        base_counter_id,
+        multi_counter_id: None,
+        perf_time_id,
+        perf_freq_id,
+        aggregate_func: AggregateFunc::Undefined,
+        detail_level: detail_level(definition.detail_level),
+        attributes: CounterAttributes::default(),
+        default_scale: definition.default_scale,
+    }
+}
+
+/// A hidden counter holding the object's time or frequency.
+fn object_timer_counter(id: u32, name: &str, help: &str) -> Counter {
+    Counter {
+        id,
+        name: name.to_string(),
+        help: help.to_string(),
+        counter_type: CounterType::PERF_COUNTER_LARGE_RAWCOUNT,
+        base_counter_id: None,
+        multi_counter_id: None,
+        perf_time_id: None,
+        perf_freq_id: None,
+        aggregate_func: AggregateFunc::Undefined,
+        detail_level: DetailLevel::Wizard,
+        attributes: CounterAttributes::NO_DISPLAYABLE,
+        default_scale: 0,
+    }
+}
+
+fn detail_level(bits: u32) -> DetailLevel {
+    match bits {
+        200 => DetailLevel::Advanced,
+        300 => DetailLevel::Expert,
+        400 => DetailLevel::Wizard,
+        _ => DetailLevel::Novice,
+    }
+}
+
+/// Extract the raw values of one object's counters from a `PERF_DATA_BLOCK`.
+pub fn sample(data: &[u8], counterset_id: &GUID) -> Result<Sample> {
+    let index = object_index(counterset_id)
+        .ok_or_else(|| format!("{:?} isn't a legacy object", counterset_id))?;
+
+    let block = DataBlock::parse(data)?;
+    let mut found = None;
+    for object in block.objects() {
+        let object = object?;
+        if object.name_index == index {
+            found = Some(object);
+            break;
+        }
+    }
+    let object = found.ok_or_else(|| format!("legacy object {} not found", index))?;
+
+    let definitions = object.counters()?;
+    let timer = object_timer_ids(&definitions);
+    let values = |counter_block: usize| -> Result<Vec<u64>> {
+        let mut values = definitions
+            .iter()
+            .map(|d| {
+                let offset = counter_block + d.offset as usize;
+                Ok(match d.size {
+                    4 => u64::from(read_u32(object.data, offset)?),
+                    8 => read_u64(object.data, offset)?,
+                    // Text and other variable length values aren't numbers.
+                    _ => 0,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if timer.is_some() {
+            values.extend([object.perf_time as u64, object.perf_freq as u64]);
+        }
+        Ok(values)
+    };
+    let mut counter_ids = (0..definitions.len() as u32).collect::<Vec<_>>();
+    if let Some((time_id, frequency_id)) = timer {
+        counter_ids.extend([time_id, frequency_id]);
+    }
+
+    let instances = match object.instances(&block)? {
+        None => vec![InstanceSample {
+            instance: None,
+            values: values(object.definition_length)?,
+        }],
+        Some(instances) => instances
+            .into_iter()
+            .map(|(instance, counter_block)| {
+                Ok(InstanceSample {
+                    instance: Some(instance),
+                    values: values(counter_block)?,
+                })
+            })
+            .collect::<Result<_>>()?,
+    };
+
+    Ok(Sample {
+        counterset_id: *counterset_id,
+        timestamp: block.perf_time,
+        time_100ns: block.perf_time_100ns,
+        frequency: block.perf_freq,
+        counter_ids,
+        instances,
+    })
+}
+
+/// `PERF_DATA_BLOCK`
+struct DataBlock<'a> {
+    data: &'a [u8],
+    header_length: usize,
+    num_objects: u32,
+    perf_time: i64,
+    perf_freq: i64,
+    perf_time_100ns: i64,
+}
+
+impl<'a> DataBlock<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.get(..8) != Some(&utf16_bytes("PERF")[..]) {
+            return Err("not a PERF_DATA_BLOCK".into());
+        }
+        if read_u32(data, 8)? != 1 {
+            return Err("big-endian performance data isn't supported".into());
+        }
+        let total_length = read_u32(data, 20)? as usize;
+        Ok(Self {
+            data: data
+                .get(..total_length)
+                .ok_or("PERF_DATA_BLOCK is truncated")?,
+            header_length: read_u32(data, 24)? as usize,
+            num_objects: read_u32(data, 28)?,
+            perf_time: read_u64(data, 56)? as i64,
+            perf_freq: read_u64(data, 64)? as i64,
+            perf_time_100ns: read_u64(data, 72)? as i64,
+        })
+    }
+
+    /// The names of an object's instances, without their parents', for naming the instances of its children.
+    fn instance_names(&self, object_index: u32) -> Result<Vec<String>> {
+        for object in self.objects() {
+            let object = object?;
+            if object.name_index == object_index {
+                let definitions = object.instance_definitions()?.unwrap_or_default();
+                return Ok(definitions.into_iter().map(|d| d.name).collect());
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    fn objects(&self) -> impl Iterator<Item = Result<Object<'a>>> + '_ {
+        let mut offset = self.header_length;
+        (0..self.num_objects).map(move |_| {
+            let object = Object::parse(self.data, offset)?;
+            offset += object.data.len();
+            Ok(object)
+        })
+    }
+}
+
+/// `PERF_OBJECT_TYPE`, with `data` spanning the whole object.
+struct Object<'a> {
+    data: &'a [u8],
+    definition_length: usize,
+    header_length: usize,
+    name_index: u32,
+    help_index: u32,
+    num_counters: u32,
+    num_instances: i32,
+    /// The object's own clock, for counters timed by the object.
+    perf_time: i64,
+    perf_freq: i64,
+}
+
+/// `NumInstances` of objects which have no instances, just a single set of values.
+const PERF_NO_INSTANCES: i32 = -1;
+
+/// `UniqueID` of instances which are identified by name only.
+const PERF_NO_UNIQUE_ID: i32 = -1;
+
+/// The id given to instances identified by name only. It's the same for all of them, rather than e.g. their
+/// position, which changes whenever an instance before them goes away, so that they're matched by name.
+const NAME_ONLY_ID: u32 = 0;
+
+impl<'a> Object<'a> {
+    fn parse(block: &'a [u8], offset: usize) -> Result<Self> {
+        let total_length = read_u32(block, offset)? as usize;
+        let data = block
+            .get(offset..offset + total_length)
+            .ok_or("PERF_OBJECT_TYPE is truncated")?;
+        Ok(Self {
+            data,
+            definition_length: read_u32(data, 4)? as usize,
+            header_length: read_u32(data, 8)? as usize,
+            name_index: read_u32(data, 12)?,
+            help_index: read_u32(data, 20)?,
+            num_counters: read_u32(data, 32)?,
+            num_instances: read_u32(data, 40)? as i32,
+            perf_time: read_u64(data, 48)? as i64,
+            perf_freq: read_u64(data, 56)? as i64,
+        })
+    }
+
+    fn counters(&self) -> Result<Vec<CounterDefinition>> {
+        let mut offset = self.header_length;
+        (0..self.num_counters)
+            .map(|_| {
+                let d = &self.data;
+                let definition = CounterDefinition {
+                    name_index: read_u32(d, offset + 4)?,
+                    help_index: read_u32(d, offset + 12)?,
+                    default_scale: read_u32(d, offset + 20)? as i32,
+                    detail_level: read_u32(d, offset + 24)?,
+                    counter_type: read_u32(d, offset + 28)?,
+                    size: read_u32(d, offset + 32)?,
+                    offset: read_u32(d, offset + 36)?,
+                };
+                offset += read_u32(d, offset)? as usize;
+                Ok(definition)
+            })
+            .collect()
+    }
+
+    /// Each instance and the offset of its `PERF_COUNTER_BLOCK`, or None if the object has no instances.
+    fn instances(&self, block: &DataBlock) -> Result<Option<Vec<(Instance, usize)>>> {
+        let Some(definitions) = self.instance_definitions()? else {
+            return Ok(None);
+        };
+
+        // The names of the instances of each parent object, by its name index.
+        let mut parents: HashMap<u32, Vec<String>> = HashMap::new();
+        let mut instances = Vec::new();
+        for definition in definitions {
+            let parent = match definition.parent_object {
+                0 => None,
+                object_index => {
+                    let names = match parents.entry(object_index) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => entry.insert(block.instance_names(object_index)?),
+                    };
+                    names.get(definition.parent_instance as usize)
+                }
+            };
+            let name = match parent {
+                Some(parent) => format!("{}/{}", parent, definition.name),
+                None => definition.name,
+            };
+            let id = match definition.unique_id {
+                PERF_NO_UNIQUE_ID => NAME_ONLY_ID,
+                id => id as u32,
+            };
+            instances.push((Instance { id, name }, definition.counter_block));
+        }
+        Ok(Some(instances))
+    }
+
+    /// Each `PERF_INSTANCE_DEFINITION`, or None if the object has no instances.
+    fn instance_definitions(&self) -> Result<Option<Vec<InstanceDefinition>>> {
+        if self.num_instances == PERF_NO_INSTANCES {
+            return Ok(None);
+        }
+
+        let mut definitions = Vec::new();
+        let mut offset = self.definition_length;
+        for _ in 0..self.num_instances.max(0) {
+            let d = &self.data;
+            let length = read_u32(d, offset)? as usize;
+            let name_offset = offset + read_u32(d, offset + 16)? as usize;
+            let name_length = read_u32(d, offset + 20)? as usize;
+            let name = d
+                .get(name_offset..name_offset + name_length)
+                .ok_or("PERF_INSTANCE_DEFINITION name is truncated")?;
+
+            let counter_block = offset + length;
+            definitions.push(InstanceDefinition {
+                parent_object: read_u32(d, offset + 4)?,
+                parent_instance: read_u32(d, offset + 8)?,
+                unique_id: read_u32(d, offset + 12)? as i32,
+                name: utf16(name).trim_end_matches('\0').to_string(),
+                counter_block,
+            });
+
+            // Skip the PERF_COUNTER_BLOCK to get to the next instance.
+            offset = counter_block + read_u32(d, counter_block)? as usize;
+        }
+        Ok(Some(definitions))
+    }
+}
+
+/// `PERF_INSTANCE_DEFINITION`
+struct InstanceDefinition {
+    /// The name index of the parent object, or 0 if the instance has no parent.
+    parent_object: u32,
+    /// The position of the parent instance in the parent object.
+    parent_instance: u32,
+    unique_id: i32,
+    name: String,
+    /// The offset of the instance's `PERF_COUNTER_BLOCK`.
+    counter_block: usize,
+}
+
+/// `PERF_COUNTER_DEFINITION`
+struct CounterDefinition {
+    name_index: u32,
+    help_index: u32,
+    default_scale: i32,
+    detail_level: u32,
+    counter_type: u32,
+    size: u32,
+    /// From the start of the `PERF_COUNTER_BLOCK`.
+    offset: u32,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes(bytes.try_into().unwrap())),
+        None => Err(format!("performance data is truncated at offset {}", offset).into()),
+    }
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    match data.get(offset..offset + 8) {
+        Some(bytes) => Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+        None => Err(format!("performance data is truncated at offset {}", offset).into()),
+    }
+}
+
+fn utf16(data: &[u8]) -> String {
+    let units = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect::<Vec<_>>();
+    String::from_utf16_lossy(&units)
+}
+
+fn utf16_bytes(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cook::Raw;
+    use std::fs;
+
+    fn blob(name: &str) -> Vec<u8> {
+        fs::read(format!("testdata/legacy/{}", name)).unwrap()
+    }
+
+    fn provider() -> Provider {
+        let names = Names::parse(&blob("counter_009.bin"));
+        let help = Names::parse(&blob("help_009.bin"));
+        parse(&blob("perf_data.bin"), &names, &help).unwrap()
+    }
+
+    #[test]
+    fn synthetic_ids() {
+        assert_eq!(
+            format!("{:?}", counterset_id(238)),
+            "000000EE-0001-0000-5065-726656310000"
+        );
+        assert_eq!(object_index(&counterset_id(238)), Some(238));
+        assert_eq!(object_index(&PROVIDER_ID), None);
+        assert_eq!(
+            object_index(&GUID::from_u128(0x8F1E2D3C_4B5A_4968_8776_A5B4C3D2E1F0)),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_objects() {
+        let provider = provider();
+        assert_eq!(provider.id, PROVIDER_ID);
+        assert_eq!(provider.name, "Legacy (V1)");
+
+        let [memory, process] = provider.countersets.as_slice() else {
+            panic!("expected two objects");
+        };
+
+        assert_eq!(memory.id, counterset_id(4));
+        assert_eq!(memory.name, "Memory");
+        assert!(memory.help.starts_with("The Memory performance object"));
+        assert_eq!(memory.instance_type, InstanceType::SingleInstance);
+        assert!(memory.instances.is_none());
+        assert!(memory.legacy);
+        let counters = memory
+            .counters
+            .iter()
+            .map(|c| (c.id, c.name.as_str(), c.counter_type, c.default_scale))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counters,
+            [
+                (
+                    0,
+                    "Available Bytes",
+                    CounterType::PERF_COUNTER_LARGE_RAWCOUNT,
+                    -6
+                ),
+                (1, "Page Faults/sec", CounterType::PERF_COUNTER_COUNTER, -1),
+            ]
+        );
+
+        assert_eq!(process.name, "Process");
+        assert_eq!(process.instance_type, InstanceType::MultiInstances);
+        let instances = process
+            .instances
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|i| (i.id, i.name.as_str()))
+            .collect::<Vec<_>>();
+        // None of them have a unique id, so they're told apart by name.
+        assert_eq!(instances, [(0, "Idle"), (0, "svchost"), (0, "svchost#1")]);
+
+        // The base counter has the same name index as the counter it's the base of.
+        let counters = process
+            .counters
+            .iter()
+            .map(|c| {
+                (
+                    c.name.as_str(),
+                    c.base_counter_id.map(|id| id.get()),
+                    c.detail_level,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counters,
+            [
+                ("% Processor Time", None, DetailLevel::Novice),
+                ("Handle Count", None, DetailLevel::Novice),
+                ("% Working Set Hit Ratio", Some(3), DetailLevel::Advanced),
+                ("% Working Set Hit Ratio", None, DetailLevel::Advanced),
+            ]
+        );
+        assert_eq!(
+            process.counters[1].help,
+            "The total number of handles currently open by this process."
+        );
+        assert_eq!(process.counters[2].help, "");
+    }
+
+    #[test]
+    fn samples_values() {
+        let data = blob("perf_data.bin");
+
+        let memory = sample(&data, &counterset_id(4)).unwrap();
+        assert_eq!(memory.timestamp, 1_234_567_890);
+        assert_eq!(memory.frequency, 10_000_000);
+        assert_eq!(memory.time_100ns, 133_541_025_700_000_000);
+        assert_eq!(memory.counter_ids, [0, 1]);
+        assert_eq!(memory.instances.len(), 1);
+        assert_eq!(memory.instances[0].instance, None);
+        assert_eq!(memory.instances[0].values, [3_221_225_472, 123_456]);
+
+        let process = sample(&data, &counterset_id(230)).unwrap();
+        let values = process
+            .instances
+            .iter()
+            .map(|i| (i.instance.as_ref().unwrap().name.as_str(), i.values.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                ("Idle", vec![50_000_000, 0, 9, 10]),
+                ("svchost", vec![7_000_000, 412, 1, 2]),
+                ("svchost#1", vec![1_000, 88, 0, 0]),
+            ]
+        );
+
+        assert_eq!(
+            sample(&data, &counterset_id(2)).err().unwrap().to_string(),
+            "legacy object 2 not found"
+        );
+    }
+
+    /// The offset of the Process object in `perf_data.bin`, after Memory.
+    fn process_offset(data: &[u8]) -> usize {
+        let memory = read_u32(data, 24).unwrap() as usize;
+        memory + read_u32(data, memory).unwrap() as usize
+    }
+
+    #[test]
+    fn names_instances_after_their_parents() {
+        let mut data = blob("perf_data.bin");
+        let process = process_offset(&data);
+        // Make svchost#1 a child of the instance of Process (230) at position 1, svchost.
+        let instances = process + read_u32(&data, process + 4).unwrap() as usize;
+        let idle = read_u32(&data, instances).unwrap() as usize;
+        let svchost = instances + idle + read_u32(&data, instances + idle).unwrap() as usize;
+        let svchost = svchost + read_u32(&data, svchost).unwrap() as usize;
+        let svchost_1 = svchost + read_u32(&data, svchost).unwrap() as usize;
+        data[svchost_1 + 4..svchost_1 + 8].copy_from_slice(&230u32.to_le_bytes());
+        data[svchost_1 + 8..svchost_1 + 12].copy_from_slice(&1u32.to_le_bytes());
+
+        let process = sample(&data, &counterset_id(230)).unwrap();
+        let names = process
+            .instances
+            .iter()
+            .map(|i| i.instance.as_ref().unwrap().name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Idle", "svchost", "svchost/svchost#1"]);
+    }
+
+    #[test]
+    fn times_counters_by_their_object() {
+        let mut data = blob("perf_data.bin");
+        let process = process_offset(&data);
+        // Make Handle Count, the second counter, an elapsed time measured by the object's clock.
+        let counters = process + read_u32(&data, process + 8).unwrap() as usize;
+        let handle_count = counters + read_u32(&data, counters).unwrap() as usize;
+        let elapsed = CounterType::PERF_ELAPSED_TIME;
+        data[handle_count + 28..handle_count + 32].copy_from_slice(&elapsed.0.to_le_bytes());
+
+        let names = Names::parse(&blob("counter_009.bin"));
+        let provider = parse(&data, &names, &Names::default()).unwrap();
+        let counterset = &provider.countersets[1];
+        let counters = counterset
+            .counters
+            .iter()
+            .map(|c| {
+                (
+                    c.id,
+                    c.name.as_str(),
+                    c.perf_time_id.map(|id| id.get()),
+                    c.perf_freq_id.map(|id| id.get()),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counters,
+            [
+                (0, "% Processor Time", None, None),
+                (1, "Handle Count", Some(4), Some(5)),
+                (2, "% Working Set Hit Ratio", None, None),
+                (3, "% Working Set Hit Ratio", None, None),
+                (4, "Object Time", None, None),
+                (5, "Object Frequency", None, None),
+            ]
+        );
+
+        let sample = sample(&data, &counterset.id).unwrap();
+        assert_eq!(sample.counter_ids, [0, 1, 2, 3, 4, 5]);
+        let svchost = &sample.instances[1];
+        assert_eq!(svchost.values, [7_000_000, 412, 1, 2, 1000, 10_000_000]);
+        let raw = Raw::from_sample(&counterset.counters[1], &sample, svchost).unwrap();
+        assert_eq!((raw.time, raw.frequency), (1000, 10_000_000));
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let data = blob("perf_data.bin");
+        let parse_err = |data: &[u8]| {
+            parse(data, &Names::default(), &Names::default())
+                .err()
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(parse_err(&data[8..]), "not a PERF_DATA_BLOCK");
+        assert_eq!(
+            parse_err(&data[..data.len() - 1]),
+            "PERF_DATA_BLOCK is truncated"
+        );
+
+        // Claim the first object is longer than the data block.
+        let mut data = data.clone();
+        let first_object = read_u32(&data, 24).unwrap() as usize;
+        data[first_object..first_object + 4].copy_from_slice(&10_000u32.to_le_bytes());
+        assert_eq!(parse_err(&data), "PERF_OBJECT_TYPE is truncated");
+    }
+}
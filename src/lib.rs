@@ -0,0 +1,11 @@
+//! The parts of perflib-explorer which other programs can use, e.g. to publish their own counters.
+
+pub mod clock;
+pub mod error;
+pub mod provider;
+pub mod snapshot;
+pub mod source;
+#[cfg(test)]
+#[allow(dead_code)] // The library's tests only load the catalog; the binary's tests use the rest.
+mod testing;
+pub mod types;
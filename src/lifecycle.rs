@@ -0,0 +1,290 @@
+//! Follow the instances of a counterset as they come and go.
+
+use crate::clock::Clock;
+use crate::error::Result;
+use crate::source::Source;
+use crate::types::{CounterSet, Instance};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{self, Display};
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+/// A difference between two enumerations of a counterset's instances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(Instance),
+    Removed(Instance),
+    /// An instance id which now belongs to an instance with a different name, none of the ones which had it being left.
+    Reused {
+        id: u32,
+        from: String,
+        to: String,
+    },
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(instance) => write!(f, "+ {} ({})", instance.name, instance.id),
+            Change::Removed(instance) => write!(f, "- {} ({})", instance.name, instance.id),
+            Change::Reused { id, from, to } => {
+                write!(f, "~ {} ({}), was {}", to, id, from)
+            }
+        }
+    }
+}
+
+/// Compares successive enumerations of a counterset's instances, counting the changes.
+#[derive(Default)]
+pub struct Tracker {
+    /// Ids and names, as of the last enumeration. Instances can share an id, e.g. when they're identified by name.
+    current: Option<BTreeSet<(u32, String)>>,
+    started: Option<SystemTime>,
+    last: Option<SystemTime>,
+    added: u64,
+    removed: u64,
+    reused: u64,
+}
+
+impl Tracker {
+    /// Compare an enumeration of instances to the previous one.
+    ///
+    /// The first enumeration has no changes, since there's nothing to compare it with.
+    pub fn update(&mut self, instances: &[Instance], time: SystemTime) -> Vec<Change> {
+        let next = instances
+            .iter()
+            .map(|i| (i.id, i.name.clone()))
+            .collect::<BTreeSet<_>>();
+        self.started.get_or_insert(time);
+        self.last = Some(time);
+
+        let Some(previous) = self.current.replace(next) else {
+            return Vec::new();
+        };
+        let next = self.current.as_ref().unwrap();
+
+        let mut added = next.difference(&previous).collect::<BTreeSet<_>>();
+        let mut removed: BTreeMap<u32, VecDeque<String>> = BTreeMap::new();
+        for (id, name) in previous.difference(next) {
+            removed.entry(*id).or_default().push_back(name.clone());
+        }
+
+        let mut changes = Vec::new();
+        // In the order they were enumerated in.
+        for instance in instances {
+            if !added.remove(&(instance.id, instance.name.clone())) {
+                continue;
+            }
+            // An id is only reused once none of the instances which had it are left.
+            let kept = previous
+                .range((instance.id, String::new())..)
+                .take_while(|(id, _)| *id == instance.id)
+                .any(|previous| next.contains(previous));
+            let from = match kept {
+                false => removed.get_mut(&instance.id).and_then(VecDeque::pop_front),
+                true => None,
+            };
+            changes.push(match from {
+                Some(from) => Change::Reused {
+                    id: instance.id,
+                    from,
+                    to: instance.name.clone(),
+                },
+                None => Change::Added(instance.clone()),
+            });
+        }
+        for (id, names) in removed {
+            changes.extend(
+                names
+                    .into_iter()
+                    .map(|name| Change::Removed(Instance { id, name })),
+            );
+        }
+
+        for change in &changes {
+            match change {
+                Change::Added(_) => self.added += 1,
+                Change::Removed(_) => self.removed += 1,
+                Change::Reused { .. } => self.reused += 1,
+            }
+        }
+        changes
+    }
+
+    /// How long instances have been followed for.
+    pub fn elapsed(&self) -> Duration {
+        match (self.started, self.last) {
+            (Some(started), Some(last)) => last.duration_since(started).unwrap_or_default(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Summarize the changes seen so far, with how often they happened.
+    pub fn summary(&self) -> String {
+        let changes = self.added + self.removed + self.reused;
+        let elapsed = self.elapsed();
+        let mut summary = format!(
+            "{} added, {} removed, {} ids reused over {}",
+            self.added,
+            self.removed,
+            self.reused,
+            humantime::format_duration(elapsed)
+        );
+        if !elapsed.is_zero() {
+            let per_minute = changes as f64 * 60.0 / elapsed.as_secs_f64();
+            summary += &format!(" ({:.2} changes/min)", per_minute);
+        }
+        summary
+    }
+}
+
+/// Periodically enumerate a counterset's instances, printing each change as it's seen, and a summary at the end.
+pub fn run(
+    counterset: &CounterSet,
+    interval: Duration,
+    count: Option<u64>,
+    source: &mut dyn Source,
+    clock: &mut dyn Clock,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut tracker = Tracker::default();
+    let mut enumerations = 0;
+    loop {
+        let instances = source.instances(&counterset.id)?;
+        let now = clock.now();
+        let time = humantime::format_rfc3339_seconds(now);
+
+        let changes = tracker.update(&instances, now);
+        if enumerations == 0 {
+            writeln!(
+                out,
+                "{} {} instances of {}",
+                time,
+                instances.len(),
+                counterset.name
+            )?;
+            for instance in &instances {
+                writeln!(out, "{}   {} ({})", time, instance.name, instance.id)?;
+            }
+        }
+        for change in changes {
+            writeln!(out, "{} {}", time, change)?;
+        }
+        out.flush()?;
+
+        enumerations += 1;
+        if count.is_some_and(|count| enumerations >= count) {
+            writeln!(out, "{}", tracker.summary())?;
+            return Ok(());
+        }
+
+        clock.sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeClock};
+    use crate::types::Sample;
+    use std::collections::VecDeque;
+    use windows::core::GUID;
+
+    fn instances(list: &[(u32, &str)]) -> Vec<Instance> {
+        list.iter()
+            .map(|&(id, name)| Instance {
+                id,
+                name: name.to_string(),
+            })
+            .collect()
+    }
+
+    struct FakeSource(VecDeque<Vec<Instance>>);
+
+    impl Source for FakeSource {
+        fn sample(&mut self, _: &GUID) -> Result<Sample> {
+            Err("Following instances only enumerates them, it never samples".into())
+        }
+
+        fn instances(&mut self, _: &GUID) -> Result<Vec<Instance>> {
+            Ok(self.0.pop_front().unwrap())
+        }
+    }
+
+    #[test]
+    fn reports_changes_and_churn() {
+        let counterset = testing::counterset(GUID::from_u128(1), "Adapters", Vec::new());
+        let mut source = FakeSource(VecDeque::from([
+            instances(&[(1, "eth0"), (2, "eth1")]),
+            instances(&[(1, "eth0"), (2, "eth1"), (3, "wlan0")]),
+            instances(&[(1, "eth0"), (3, "wlan0")]),
+            // The driver was reinstalled, and the adapter got a new name.
+            instances(&[(1, "Ethernet 2"), (3, "wlan0")]),
+        ]));
+        let mut clock = FakeClock::new();
+        let mut out = Vec::new();
+
+        run(
+            &counterset,
+            Duration::from_secs(10),
+            Some(4),
+            &mut source,
+            &mut clock,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+2023-11-14T22:13:20Z 2 instances of Adapters
+2023-11-14T22:13:20Z   eth0 (1)
+2023-11-14T22:13:20Z   eth1 (2)
+2023-11-14T22:13:30Z + wlan0 (3)
+2023-11-14T22:13:40Z - eth1 (2)
+2023-11-14T22:13:50Z ~ Ethernet 2 (1), was eth0
+1 added, 1 removed, 1 ids reused over 30s (6.00 changes/min)
+"
+        );
+    }
+
+    #[test]
+    fn tells_instances_sharing_an_id_apart_by_name() {
+        let mut tracker = Tracker::default();
+        let time = testing::at(0);
+        let changes = |tracker: &mut Tracker, list| {
+            tracker
+                .update(&instances(list), time)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        // Identified by name only, so they all have the same id.
+        changes(&mut tracker, &[(0, "svchost"), (0, "svchost#1")]);
+        assert_eq!(
+            changes(
+                &mut tracker,
+                &[(0, "svchost"), (0, "svchost#1"), (0, "explorer")]
+            ),
+            ["+ explorer (0)"]
+        );
+        assert_eq!(
+            changes(&mut tracker, &[(0, "svchost#1"), (0, "notepad")]),
+            ["+ notepad (0)", "- explorer (0)", "- svchost (0)"]
+        );
+        assert_eq!(
+            changes(&mut tracker, &[(0, "notepad"), (0, "notepad"), (7, "cmd")]),
+            ["+ cmd (7)", "- svchost#1 (0)"]
+        );
+        // Only once nothing is left of the instances with an id, is it reused.
+        assert_eq!(
+            changes(&mut tracker, &[(7, "conhost")]),
+            ["~ conhost (7), was cmd", "- notepad (0)"]
+        );
+        assert_eq!(
+            tracker.summary(),
+            "3 added, 4 removed, 1 ids reused over 0s"
+        );
+    }
+}
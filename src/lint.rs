@@ -0,0 +1,374 @@
+//! Check registered countersets for problems which make their counters hard or impossible to consume.
+
+use crate::cook;
+use crate::types::counter_type::Kind;
+use crate::types::{guid, CounterAttributes, CounterSet, CounterType, InstanceType, Provider};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use windows::core::GUID;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// One finding per line
+    Text,
+    /// A JSON array of findings
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    /// More than one counter in a counterset has the same id.
+    DuplicateCounterId,
+    /// A base or multi counter id doesn't match any counter.
+    DanglingBase,
+    /// A base counter id refers to a counter which isn't a base counter type.
+    BaseNotBaseType,
+    /// A counter whose value is divided by a base has no base.
+    MissingBase,
+    /// A counterset, or a displayable counter, has no help string.
+    MissingHelp,
+    /// A counterset or counter has no name, i.e. its name's offset was 0xFFFFFFFF.
+    EmptyName,
+    /// The instances which were enumerated don't fit the instance type.
+    InstanceType,
+}
+
+impl Rule {
+    pub fn severity(self) -> Severity {
+        match self {
+            Rule::MissingHelp => Severity::Warning,
+            Rule::DuplicateCounterId
+            | Rule::DanglingBase
+            | Rule::BaseNotBaseType
+            | Rule::MissingBase
+            | Rule::EmptyName
+            | Rule::InstanceType => Severity::Error,
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The same ids as in JSON.
+        let id = serde_json::to_value(self).unwrap();
+        f.write_str(id.as_str().unwrap())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub rule: Rule,
+    pub severity: Severity,
+    #[serde(with = "guid")]
+    pub counterset_id: GUID,
+    pub counterset_name: String,
+    /// The counter the finding is about, if it isn't about the counterset itself.
+    pub counter_id: Option<u32>,
+    pub message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{}[{}] {} ({:?})",
+            severity, self.rule, self.counterset_name, self.counterset_id
+        )?;
+        if let Some(id) = self.counter_id {
+            write!(f, ", counter {}", id)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Lint every counterset in the catalog.
+pub fn all(providers: &[Provider]) -> Vec<Finding> {
+    providers
+        .iter()
+        .flat_map(|p| &p.countersets)
+        .flat_map(counterset)
+        .collect()
+}
+
+/// Lint one counterset.
+pub fn counterset(counterset: &CounterSet) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut finding = |rule: Rule, counter_id, message: String| {
+        findings.push(Finding {
+            rule,
+            severity: rule.severity(),
+            counterset_id: counterset.id,
+            counterset_name: counterset.name.clone(),
+            counter_id,
+            message,
+        })
+    };
+
+    if counterset.name.is_empty() {
+        finding(Rule::EmptyName, None, "counterset has no name".to_string());
+    }
+    if counterset.help.is_empty() {
+        finding(
+            Rule::MissingHelp,
+            None,
+            "counterset has no help string".to_string(),
+        );
+    }
+
+    let instances = counterset.instances.as_deref().unwrap_or_default();
+    match counterset.instance_type {
+        InstanceType::SingleInstance | InstanceType::SingleAggregate if instances.len() > 1 => {
+            finding(
+                Rule::InstanceType,
+                None,
+                format!(
+                    "{:?} counterset has {} instances",
+                    counterset.instance_type,
+                    instances.len()
+                ),
+            )
+        }
+        InstanceType::MultiInstances | InstanceType::MultiAggregate => {
+            for instance in instances.iter().filter(|i| i.name.is_empty()) {
+                finding(
+                    Rule::InstanceType,
+                    None,
+                    format!(
+                        "{:?} counterset has an unnamed instance (id {})",
+                        counterset.instance_type, instance.id
+                    ),
+                )
+            }
+        }
+        _ => {}
+    }
+
+    let mut by_id = HashMap::<u32, Vec<_>>::new();
+    for counter in &counterset.counters {
+        by_id.entry(counter.id).or_default().push(counter);
+    }
+    let mut duplicates = by_id
+        .iter()
+        .filter(|(_, c)| c.len() > 1)
+        .collect::<Vec<_>>();
+    duplicates.sort_by_key(|(id, _)| **id);
+    for (id, counters) in duplicates {
+        let names = counters
+            .iter()
+            .map(|c| format!("\"{}\"", c.name))
+            .collect::<Vec<_>>();
+        finding(
+            Rule::DuplicateCounterId,
+            Some(*id),
+            format!("id is shared by {}", names.join(", ")),
+        );
+    }
+
+    for counter in &counterset.counters {
+        let id = Some(counter.id);
+        let counter_type = counter.counter_type;
+
+        if counter.name.is_empty() {
+            finding(Rule::EmptyName, id, "counter has no name".to_string());
+        }
+
+        let related = [
+            ("base", counter.base_counter_id),
+            ("multi", counter.multi_counter_id),
+        ];
+        for (relation, related_id) in related {
+            let Some(related_id) = related_id.map(|r| r.get()) else {
+                continue;
+            };
+            match by_id.get(&related_id) {
+                None => finding(
+                    Rule::DanglingBase,
+                    id,
+                    format!("{} counter {} doesn't exist", relation, related_id),
+                ),
+                Some(related) if relation == "base" => {
+                    for base in related
+                        .iter()
+                        .filter(|b| b.counter_type.kind() != Kind::Base)
+                    {
+                        finding(
+                            Rule::BaseNotBaseType,
+                            id,
+                            format!(
+                                "base counter {} (\"{}\") is {:?}, which isn't a base type",
+                                related_id, base.name, base.counter_type
+                            ),
+                        );
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        // Multi counters are divided by how many things they time at once, which a multi base holds. The multi base
+        // has the multi bit set too.
+        let multi = counter_type.is_multi() && counter_type.kind() != Kind::Base;
+        let needs_base = counter_type.kind() == Kind::Fraction || multi;
+        let base_id = match counter_type.is_multi() {
+            true => counter.multi_counter_id.or(counter.base_counter_id),
+            false => counter.base_counter_id,
+        };
+        if needs_base && base_id.is_none() {
+            finding(
+                Rule::MissingBase,
+                id,
+                format!("{:?} is divided by a base, but has none", counter_type),
+            );
+        }
+        if let (true, Some(base_id)) = (multi, base_id) {
+            let base_id = base_id.get();
+            // A base counter which isn't a base type at all has been reported already.
+            let reported = counter.multi_counter_id.is_none();
+            for base in by_id.get(&base_id).into_iter().flatten().filter(|b| {
+                b.counter_type != CounterType::PERF_COUNTER_MULTI_BASE
+                    && !(reported && b.counter_type.kind() != Kind::Base)
+            }) {
+                finding(
+                    Rule::BaseNotBaseType,
+                    id,
+                    format!(
+                        "base counter {} (\"{}\") is {:?}, but {:?} needs PERF_COUNTER_MULTI_BASE",
+                        base_id, base.name, base.counter_type, counter_type
+                    ),
+                );
+            }
+        }
+
+        // Base counters and hidden counters are never shown, so they don't need help.
+        let shown = cook::is_displayable(counter_type)
+            && !counter
+                .attributes
+                .contains(CounterAttributes::NO_DISPLAYABLE);
+        if shown && counter.help.is_empty() {
+            finding(
+                Rule::MissingHelp,
+                id,
+                "counter has no help string".to_string(),
+            );
+        }
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::catalog;
+    use crate::types::{Instance, NonMaxU32};
+
+    #[test]
+    fn clean_catalog() {
+        assert_eq!(all(&catalog()), []);
+    }
+
+    #[test]
+    fn finds_problems() {
+        let mut all = catalog();
+        let counterset = &mut all[0].countersets[1];
+        counterset.help.clear();
+        counterset.instances = Some(vec![Instance {
+            id: 7,
+            name: String::new(),
+        }]);
+        let counters = &mut counterset.counters;
+        // Duplicate id, with an empty name.
+        counters[0].id = 5;
+        counters[0].name.clear();
+        // Base counter that doesn't exist.
+        counters[1].base_counter_id = NonMaxU32::new(9);
+        // Base counter that isn't a base.
+        counters[3].base_counter_id = NonMaxU32::new(1);
+        // Fraction without a base, and no help.
+        counters[4].counter_type = CounterType::PERF_RAW_FRACTION;
+        counters[4].base_counter_id = None;
+        counters[4].attributes = CounterAttributes::default();
+
+        let findings = self::counterset(&all[0].countersets[1])
+            .iter()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            findings,
+            [
+                "warning[missing-help] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0): counterset has no help string",
+                "error[instance-type] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0): MultiAggregate counterset has an unnamed instance (id 7)",
+                "error[duplicate-counter-id] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 5: id is shared by \"\", \"Queue Length\"",
+                "error[empty-name] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 5: counter has no name",
+                "error[dangling-base] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 1: base counter 9 doesn't exist",
+                "error[base-not-base-type] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 3: base counter 1 (\"Read Latency\") is PERF_AVERAGE_TIMER, which isn't a base type",
+                "error[missing-base] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 4: PERF_RAW_FRACTION is divided by a base, but has none",
+                "warning[missing-help] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 4: counter has no help string",
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_counters_need_a_multi_base() {
+        let mut all = catalog();
+        let counters = &mut all[0].countersets[1].counters;
+        // % Idle Time, still with its sample base.
+        counters[3].counter_type = CounterType::PERF_COUNTER_MULTI_TIMER;
+        // Queue Length, with no base at all.
+        counters[5].counter_type = CounterType::PERF_100NSEC_MULTI_TIMER_INV;
+
+        let findings = self::counterset(&all[0].countersets[1])
+            .iter()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            findings,
+            [
+                "error[base-not-base-type] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 3: base counter 4 (\"% Idle Time Base\") is PERF_SAMPLE_BASE, but PERF_COUNTER_MULTI_TIMER needs PERF_COUNTER_MULTI_BASE",
+                "error[missing-base] Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 5: PERF_100NSEC_MULTI_TIMER_INV is divided by a base, but has none",
+            ]
+        );
+
+        let counters = &mut all[0].countersets[1].counters;
+        counters[4].counter_type = CounterType::PERF_COUNTER_MULTI_BASE;
+        counters[5].multi_counter_id = NonMaxU32::new(4);
+        assert_eq!(self::counterset(&all[0].countersets[1]), []);
+    }
+
+    #[test]
+    fn single_instance_with_several_instances() {
+        let mut all = catalog();
+        let counterset = &mut all[0].countersets[0];
+        counterset.instances = Some(vec![
+            Instance {
+                id: 0,
+                name: "a".to_string(),
+            },
+            Instance {
+                id: 1,
+                name: "b".to_string(),
+            },
+        ]);
+
+        let findings = self::counterset(counterset);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, Rule::InstanceType);
+        assert_eq!(
+            serde_json::to_string(&findings[0]).unwrap(),
+            r#"{"rule":"instance-type","severity":"error","counterset_id":"2B4D6F81-93A5-4C7E-8F01-23456789ABCD","counterset_name":"Contoso Cache","counter_id":null,"message":"SingleInstance counterset has 2 instances"}"#
+        );
+    }
+}
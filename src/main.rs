@@ -1,15 +1,57 @@
-use std::time::Instant;
-
-use windows::core::Result;
+use error::Result;
+use perflib_explorer::{clock, error, snapshot, source, types};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, IsTerminal};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use types::Provider;
 
+mod agent;
+mod aggregate;
+mod catalog;
+mod codegen;
+mod config;
+mod cook;
+mod docs;
+mod export;
+#[cfg(windows)]
 mod fetch;
+mod glob;
+mod graph;
+mod http;
+mod identity;
+// Only read from the registry on Windows, but parsed (and tested) everywhere.
+#[cfg_attr(not(windows), allow(dead_code))]
+mod legacy;
+mod lifecycle;
+mod lint;
+mod manifest;
+mod monitor;
 mod opt;
+mod plog;
 mod print;
-mod types;
+#[cfg(windows)]
+mod query;
+mod record;
+mod replay;
+mod report;
+mod rollup;
+mod stats;
+mod store;
+#[cfg(test)]
+mod testing;
+mod tui;
+mod watch;
+#[cfg(windows)]
 mod winapi;
 
 fn main() -> Result<()> {
-    let opt::Options { verbose, command } = clap::Parser::parse();
+    let opt::Options {
+        verbose,
+        snapshot,
+        legacy,
+        command,
+    } = clap::Parser::parse();
 
     env_logger::Builder::new()
         .filter_level(match verbose {
@@ -21,18 +63,429 @@ fn main() -> Result<()> {
 
     let start = Instant::now();
 
-    let mut buf = Vec::new();
+    // Not every command needs the catalog, e.g. replaying a log on another machine.
+    let load = || -> Result<Vec<Provider>> {
+        let all = match &snapshot {
+            Some(path) => snapshot::load(path)?,
+            None => load_live(legacy)?,
+        };
+        log::info!("Load completed at T + {}ms", start.elapsed().as_millis());
+        Ok(all)
+    };
 
-    let all = fetch::all_providers(&mut buf)?;
+    match command {
+        opt::Command::Summary(opt::Summary {
+            provider,
+            counterset,
+            counter,
+            instance_type,
+            has_instances,
+            min_counters,
+            depth,
+        }) => {
+            let filter = print::Filter {
+                provider,
+                counterset,
+                counter,
+                instance_type,
+                has_instances,
+                min_counters,
+            };
+            print::summary(&mut io::stdout().lock(), &filter.apply(load()?), depth)?;
+        }
+        opt::Command::Counterset(opt::Counterset { guid }) => print::counterset(&load()?, &guid),
+        opt::Command::Snapshot(opt::Snapshot { output }) => snapshot::save(&output, &load()?)?,
+        opt::Command::Tui => {
+            let all = load()?;
+            let mut source = live_source(snapshot.as_deref());
+            tui::run(&all, source.as_mut().map(|s| s.as_mut() as _))?;
+        }
+        opt::Command::Watch(opt::Watch {
+            counterset,
+            instance,
+            counter,
+            interval,
+            count,
+            aggregate,
+        }) => {
+            let all = load()?;
+            let counterset = counterset.find(&all)?;
+            let source = live_source(snapshot.as_deref()).ok_or(NO_LIVE_SOURCE)?;
+            let mut source = aggregate::Aggregating::wrap(source, counterset, aggregate);
+            let stdout = io::stdout();
+            let options = watch::Options {
+                instance,
+                counter,
+                interval,
+                count,
+                ansi: stdout.is_terminal(),
+            };
+            watch::run(
+                counterset,
+                &options,
+                source.as_mut(),
+                &mut clock::SystemClock,
+                &mut stdout.lock(),
+            )?;
+        }
+        opt::Command::Sample(opt::Sample {
+            counterset,
+            output,
+            store,
+            store_size,
+            retention,
+            store_tiers,
+            interval,
+            count,
+            stats,
+            window,
+            aggregate,
+        }) => {
+            let all = load()?;
+            let counterset = counterset.find(&all)?;
+            let source = live_source(snapshot.as_deref()).ok_or(NO_LIVE_SOURCE)?;
+            let mut source = aggregate::Aggregating::wrap(source, counterset, aggregate);
+            let mut log = match output {
+                Some(output) => {
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(false)
+                        .open(&output)?;
+                    Some(plog::Writer::open(file, counterset, interval)?)
+                }
+                None => None,
+            };
+            let mut ring = match store {
+                Some(path) => {
+                    store::check_tiers(&store_tiers)?;
+                    let store = store::Store {
+                        path,
+                        size: store_size,
+                        retention,
+                        tiers: store_tiers,
+                    };
+                    Some(store.ring(counterset, interval)?)
+                }
+                None => None,
+            };
+            let mut stats = stats.then(|| stats::Stats::new(counterset, window));
+            record::run(
+                counterset,
+                &record::Options { interval, count },
+                source.as_mut(),
+                &mut clock::SystemClock,
+                record::Logs {
+                    log: log.as_mut(),
+                    ring: ring.as_mut(),
+                },
+                stats.as_mut(),
+                &mut io::stdout().lock(),
+            )?;
+        }
+        opt::Command::Replay(opt::Replay {
+            log,
+            instance,
+            counter,
+            from,
+            skip,
+            format,
+            rollup,
+        }) => {
+            let options = replay::Options {
+                filter: watch::Options {
+                    instance,
+                    counter,
+                    interval: Duration::ZERO,
+                    count: None,
+                    ansi: false,
+                },
+                from,
+                skip,
+                format,
+                rollup,
+            };
+            let log = BufReader::new(File::open(log)?);
+            replay::run(log, &options, &mut io::stdout().lock())?;
+        }
+        opt::Command::Manifest(opt::Manifest { counterset }) => {
+            let all = load()?;
+            let (provider, counterset) = counterset.find_with_provider(&all)?;
+            print!("{}", manifest::generate(provider, counterset));
+        }
+        opt::Command::Codegen(opt::Codegen {
+            language,
+            counterset,
+        }) => {
+            let all = load()?;
+            print!("{}", codegen::generate(language, counterset.find(&all)?));
+        }
+        opt::Command::ValidateManifest(opt::ValidateManifest { manifest }) => {
+            let declared = manifest::parse(&fs::read_to_string(&manifest)?)?;
+            let problems = manifest::validate(&declared, &load()?);
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                return Err(format!(
+                    "{} problems found in {}",
+                    problems.len(),
+                    manifest.display()
+                )
+                .into());
+            }
+        }
+        opt::Command::Graph(opt::Graph { counterset, format }) => {
+            let all = load()?;
+            print!("{}", graph::generate(format, counterset.find(&all)?));
+        }
+        opt::Command::Lint(opt::Lint { counterset, format }) => {
+            let all = load()?;
+            let findings = match counterset {
+                Some(counterset) => lint::counterset(counterset.find(&all)?),
+                None => lint::all(&all),
+            };
+            match format {
+                lint::Format::Text => {
+                    for finding in &findings {
+                        println!("{}", finding);
+                    }
+                }
+                lint::Format::Json => println!("{}", serde_json::to_string_pretty(&findings)?),
+            }
+            let errors = findings
+                .iter()
+                .filter(|f| f.severity == lint::Severity::Error)
+                .count();
+            if errors > 0 {
+                return Err(format!("{} errors found", errors).into());
+            }
+        }
+        opt::Command::Monitor(opt::Monitor {
+            rules,
+            jsonl,
+            webhook,
+            interval,
+            count,
+        }) => {
+            let rules = monitor::parse(&fs::read_to_string(&rules)?)?;
+            let all = load()?;
+            let rules = rules
+                .into_iter()
+                .map(|rule| rule.bind(&all))
+                .collect::<Result<Vec<_>>>()?;
+            let mut evaluator = monitor::Evaluator::new(rules);
+            let mut source = live_source(snapshot.as_deref()).ok_or(NO_LIVE_SOURCE)?;
 
-    log::info!("Load completed at T + {}ms", start.elapsed().as_millis());
+            let mut outputs: Vec<Box<dyn monitor::Output>> =
+                vec![Box::new(monitor::Text(io::stdout()))];
+            if let Some(path) = jsonl {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                outputs.push(Box::new(monitor::Jsonl(file)));
+            }
+            if let Some(webhook) = webhook {
+                outputs.push(Box::new(webhook));
+            }
+            monitor::run(
+                &mut evaluator,
+                interval,
+                count,
+                source.as_mut(),
+                &mut clock::SystemClock,
+                &mut outputs,
+            )?;
+        }
+        opt::Command::Instances(opt::Instances {
+            counterset,
+            follow,
+            interval,
+            count,
+        }) => {
+            let all = load()?;
+            let counterset = counterset.find(&all)?;
+            if follow {
+                let mut source = live_source(snapshot.as_deref()).ok_or(NO_LIVE_SOURCE)?;
+                lifecycle::run(
+                    counterset,
+                    interval,
+                    count,
+                    source.as_mut(),
+                    &mut clock::SystemClock,
+                    &mut io::stdout().lock(),
+                )?;
+            } else {
+                for instance in counterset.instances.as_deref().unwrap_or_default() {
+                    println!("{} ({})", instance.name, instance.id);
+                }
+            }
+        }
+        opt::Command::Export(opt::Export {
+            counterset,
+            otlp,
+            otlp_encoding,
+            statsd,
+            graphite,
+            template,
+            interval,
+            count,
+            aggregate,
+        }) => {
+            let all = load()?;
+            let (provider, counterset) = counterset.find_with_provider(&all)?;
+            let source = live_source(snapshot.as_deref()).ok_or(NO_LIVE_SOURCE)?;
+            let mut source = aggregate::Aggregating::wrap(source, counterset, aggregate);
 
-    match command {
-        opt::Command::Summary => print::summary(&all),
-        opt::Command::Counterset(opt::Counterset { guid }) => print::counterset(&all, &guid),
+            let mut exporters: Vec<Box<dyn export::Exporter>> = Vec::new();
+            if let Some(otlp) = otlp {
+                exporters.push(Box::new(export::Otlp::new(otlp, otlp_encoding)));
+            }
+            if let Some(statsd) = statsd {
+                exporters.push(Box::new(export::Statsd::new(&statsd, template.clone())?));
+            }
+            if let Some(graphite) = graphite {
+                exporters.push(Box::new(export::Graphite::new(&graphite, template)));
+            }
+            export::run(
+                &mut export::Collector::new(&provider.name, counterset),
+                interval,
+                count,
+                source.as_mut(),
+                &mut clock::SystemClock,
+                &mut exporters,
+            )?;
+        }
+        opt::Command::CheckConfig(opt::CheckConfig { path }) => {
+            let config = config::load(&path)?;
+            let all = load()?;
+            let resolved = config.resolve(&all)?;
+            for (job, selections) in config.jobs.iter().zip(resolved) {
+                println!(
+                    "{} every {}",
+                    job.name,
+                    humantime::format_duration(job.interval)
+                );
+                for selection in selections {
+                    let instances = selection
+                        .instance
+                        .map_or("*".to_string(), |glob| glob.to_string());
+                    println!(
+                        "  {}\\{}({})",
+                        selection.provider.name, selection.counterset.name, instances
+                    );
+                    for counter in selection.counters {
+                        println!("    {}", counter.name);
+                    }
+                }
+                for include in &job.instances.include {
+                    println!("  include instances {}", include);
+                }
+                for exclude in &job.instances.exclude {
+                    println!("  exclude instances {}", exclude);
+                }
+                for (key, value) in &job.labels {
+                    println!("  label {}={}", key, value);
+                }
+                for sink in &job.sinks {
+                    println!("  send to {}", sink);
+                }
+            }
+        }
+        opt::Command::Agent(opt::Agent { config, refresh }) => {
+            let mut source = live_source(snapshot.as_deref()).ok_or(NO_LIVE_SOURCE)?;
+            let mut machine = agent::Machine::new(&config, load)?;
+            agent::run(
+                &mut machine,
+                source.as_mut(),
+                &mut clock::SystemClock,
+                refresh,
+            )?;
+        }
+        opt::Command::Query(opt::Query {
+            counterset,
+            store,
+            from,
+            to,
+            format,
+            rollup,
+            tier,
+            instance,
+            counter,
+        }) => {
+            let store = store::Store {
+                path: store,
+                size: store::parse_size(store::DEFAULT_SIZE)?,
+                retention: None,
+                tiers: Vec::new(),
+            };
+            let counterset = store.counterset(&counterset)?;
+            let options = store::query::Options {
+                filter: watch::Options {
+                    instance,
+                    counter,
+                    interval: Duration::ZERO,
+                    count: None,
+                    ansi: false,
+                },
+                from,
+                to,
+                format,
+                rollup,
+                tier,
+            };
+            store::query::run(&store, &counterset, &options, &mut io::stdout().lock())?;
+        }
+        opt::Command::Report(opt::Report { html }) => {
+            fs::write(&html, report::html(&load()?))?;
+        }
+        opt::Command::Docs(opt::Docs {
+            format,
+            target,
+            output,
+        }) => {
+            fs::create_dir_all(&output)?;
+            for page in docs::generate(format, &load()?, &target)? {
+                let path = output.join(&page.file_name);
+                fs::write(&path, page.content)?;
+                println!("{}", path.display());
+            }
+        }
     }
 
     log::info!("Print completed at T + {}ms", start.elapsed().as_millis());
 
     Ok(())
 }
+
+#[cfg(windows)]
+fn load_live(legacy: bool) -> Result<Vec<Provider>> {
+    let mut buf = Vec::new();
+    let mut all = fetch::all_providers(&mut buf)?;
+    if legacy {
+        let provider = fetch::legacy::provider(&mut buf, &all)?;
+        all.push(provider);
+    }
+    Ok(all)
+}
+
+#[cfg(not(windows))]
+fn load_live(_legacy: bool) -> Result<Vec<Provider>> {
+    Err("perflib is only available on Windows; use --snapshot to load a saved catalog".into())
+}
+
+const NO_LIVE_SOURCE: &str =
+    "counter values can only be collected on Windows, and not when using --snapshot";
+
+/// Live values are only available when exploring the current machine, not a snapshot.
+#[cfg(windows)]
+fn live_source(snapshot: Option<&Path>) -> Option<Box<dyn source::Source>> {
+    match snapshot {
+        Some(_) => None,
+        None => Some(Box::<query::Live>::default()),
+    }
+}
+
+#[cfg(not(windows))]
+fn live_source(_snapshot: Option<&Path>) -> Option<Box<dyn source::Source>> {
+    None
+}
@@ -0,0 +1,271 @@
+//! Instrumentation manifests, which providers use to declare their countersets to perflib.
+//!
+//! https://learn.microsoft.com/en-us/windows/win32/perfctrs/performance-counters-schema
+
+use crate::types::{
+    AggregateFunc, Counter, CounterAttributes, CounterSet, CounterType, DetailLevel, InstanceType,
+    Provider,
+};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+
+pub use parse::parse;
+pub use validate::validate;
+
+mod parse;
+mod validate;
+
+/// Reconstruct the `<counters>` section of a manifest declaring a single counterset.
+///
+/// Some information isn't available from perflib (e.g. the provider's binary or symbol names),
+/// so the result is a starting point rather than the original manifest.
+pub fn generate(provider: &Provider, counterset: &CounterSet) -> String {
+    let mut out = String::new();
+
+    let counterset_uri = format!(
+        "{}.{}",
+        uri_part(&provider.name),
+        uri_part(&counterset.name)
+    );
+
+    writeln!(out, "<counters schemaVersion=\"1.1\">").unwrap();
+    writeln!(
+        out,
+        "  <!-- applicationIdentity isn't recorded by perflib; set it to the binary which provides these counters. -->"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "  <provider providerName=\"{}\" providerGuid=\"{{{:?}}}\" applicationIdentity=\"\" providerType=\"userMode\">",
+        escape(&provider.name),
+        provider.id
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "    <counterSet guid=\"{{{:?}}}\" uri=\"{}\" name=\"{}\" description=\"{}\" instances=\"{}\">",
+        counterset.id,
+        escape(&counterset_uri),
+        escape(&counterset.name),
+        escape(&counterset.help),
+        instances_name(counterset.instance_type)
+    )
+    .unwrap();
+
+    let mut uris = HashSet::new();
+    for counter in &counterset.counters {
+        // Counter names should be unique, but uris must be, so fall back to the id to disambiguate.
+        let mut uri = format!("{}.{}", counterset_uri, uri_part(&counter.name));
+        if !uris.insert(uri.clone()) {
+            uri = format!("{}.{}", uri, counter.id);
+            uris.insert(uri.clone());
+        }
+        write_counter(&mut out, counter, &uri);
+    }
+
+    writeln!(out, "    </counterSet>").unwrap();
+    writeln!(out, "  </provider>").unwrap();
+    writeln!(out, "</counters>").unwrap();
+
+    out
+}
+
+fn write_counter(out: &mut String, counter: &Counter, uri: &str) {
+    write!(
+        out,
+        "      <counter id=\"{}\" uri=\"{}\" name=\"{}\" description=\"{}\" type=\"{}\" detailLevel=\"{}\" aggregate=\"{}\"",
+        counter.id,
+        escape(uri),
+        escape(&counter.name),
+        escape(&counter.help),
+        type_name(counter.counter_type),
+        detail_level_name(counter.detail_level),
+        aggregate_name(counter.aggregate_func),
+    )
+    .unwrap();
+
+    let related = [
+        ("baseID", counter.base_counter_id),
+        ("multiCounterID", counter.multi_counter_id),
+        ("perfTimeID", counter.perf_time_id),
+        ("perfFreqID", counter.perf_freq_id),
+    ];
+    for (name, id) in related {
+        if let Some(id) = id {
+            write!(out, " {}=\"{}\"", name, id.get()).unwrap();
+        }
+    }
+    if counter.default_scale != 0 {
+        write!(out, " defaultScale=\"{}\"", counter.default_scale).unwrap();
+    }
+
+    let attributes = ATTRIBUTE_NAMES
+        .iter()
+        .filter(|(flag, _)| counter.attributes.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+
+    if attributes.is_empty() {
+        writeln!(out, "/>").unwrap();
+    } else {
+        writeln!(out, ">").unwrap();
+        writeln!(out, "        <counterAttributes>").unwrap();
+        for name in attributes {
+            writeln!(out, "          <counterAttribute name=\"{}\"/>", name).unwrap();
+        }
+        writeln!(out, "        </counterAttributes>").unwrap();
+        writeln!(out, "      </counter>").unwrap();
+    }
+}
+
+/// Names used in `<counterAttribute>` elements.
+const ATTRIBUTE_NAMES: &[(CounterAttributes, &str)] = &[
+    (CounterAttributes::BY_REFERENCE, "reference"),
+    (CounterAttributes::NO_DISPLAYABLE, "noDisplay"),
+    (CounterAttributes::NO_GROUP_SEPARATOR, "noDigitGrouping"),
+    (CounterAttributes::DISPLAY_AS_REAL, "displayAsReal"),
+    (CounterAttributes::DISPLAY_AS_HEX, "displayAsHex"),
+];
+
+/// Values of the `instances` attribute of `<counterSet>` elements.
+const INSTANCE_TYPES: &[(InstanceType, &str)] = &[
+    (InstanceType::SingleInstance, "single"),
+    (InstanceType::MultiInstances, "multiple"),
+    (InstanceType::SingleAggregate, "globalAggregate"),
+    (InstanceType::MultiAggregate, "multipleAggregate"),
+];
+
+/// Values of the `aggregate` attribute of `<counter>` elements.
+const AGGREGATES: &[(AggregateFunc, &str)] = &[
+    (AggregateFunc::Undefined, "undefined"),
+    (AggregateFunc::Total, "sum"),
+    (AggregateFunc::Avg, "avg"),
+    (AggregateFunc::Min, "min"),
+    (AggregateFunc::Max, "max"),
+];
+
+fn name_of<T: PartialEq>(table: &[(T, &'static str)], value: T) -> &'static str {
+    table.iter().find(|(v, _)| *v == value).unwrap().1
+}
+
+fn instances_name(instance_type: InstanceType) -> &'static str {
+    name_of(INSTANCE_TYPES, instance_type)
+}
+
+fn type_name(counter_type: CounterType) -> String {
+    match counter_type.name() {
+        Some(name) => name.to_lowercase(),
+        None => {
+            log::warn!(
+                "Counter type {:#010X} has no name; the manifest will need to be fixed by hand",
+                counter_type.0
+            );
+            format!("{:#010X}", counter_type.0)
+        }
+    }
+}
+
+fn detail_level_name(detail_level: DetailLevel) -> &'static str {
+    match detail_level {
+        DetailLevel::Novice => "standard",
+        // Manifests only distinguish standard and advanced; the other levels are from V1 providers.
+        DetailLevel::Advanced | DetailLevel::Expert | DetailLevel::Wizard => "advanced",
+    }
+}
+
+fn aggregate_name(aggregate_func: AggregateFunc) -> &'static str {
+    name_of(AGGREGATES, aggregate_func)
+}
+
+/// Turn a display name into something suitable for a uri, e.g. "% Idle Time" -> "IdleTime".
+fn uri_part(name: &str) -> String {
+    let part = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect::<String>();
+    match part.is_empty() {
+        true => "_".to_string(),
+        false => part,
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::CounterSetRef;
+    use crate::testing::catalog;
+
+    fn generate_from_catalog(counterset: &str) -> String {
+        let all = catalog();
+        let counterset = counterset.parse::<CounterSetRef>().unwrap();
+        let (provider, counterset) = counterset.find_with_provider(&all).unwrap();
+        generate(provider, counterset)
+    }
+
+    #[test]
+    fn multi_instance_counterset() {
+        assert_eq!(
+            generate_from_catalog("Contoso Disk"),
+            r#"<counters schemaVersion="1.1">
+  <!-- applicationIdentity isn't recorded by perflib; set it to the binary which provides these counters. -->
+  <provider providerName="Contoso-Storage" providerGuid="{3D1A2C55-6B8E-4F10-9A3B-0C5E7D9F1A24}" applicationIdentity="" providerType="userMode">
+    <counterSet guid="{8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0}" uri="Contoso-Storage.ContosoDisk" name="Contoso Disk" description="Disk activity of Contoso storage volumes." instances="multipleAggregate">
+      <counter id="0" uri="Contoso-Storage.ContosoDisk.BytesRead" name="Bytes Read" description="Total number of bytes read from the volume." type="perf_counter_large_rawcount" detailLevel="standard" aggregate="sum" defaultScale="-6"/>
+      <counter id="1" uri="Contoso-Storage.ContosoDisk.ReadLatency" name="Read Latency" description="Average time per read, in seconds." type="perf_average_timer" detailLevel="standard" aggregate="avg" baseID="2"/>
+      <counter id="2" uri="Contoso-Storage.ContosoDisk.ReadLatencyBase" name="Read Latency Base" description="" type="perf_average_base" detailLevel="standard" aggregate="undefined">
+        <counterAttributes>
+          <counterAttribute name="noDisplay"/>
+        </counterAttributes>
+      </counter>
+      <counter id="3" uri="Contoso-Storage.ContosoDisk.IdleTime" name="% Idle Time" description="Percentage of time the volume was idle." type="perf_sample_fraction" detailLevel="standard" aggregate="avg" baseID="4"/>
+      <counter id="4" uri="Contoso-Storage.ContosoDisk.IdleTimeBase" name="% Idle Time Base" description="" type="perf_sample_base" detailLevel="standard" aggregate="undefined">
+        <counterAttributes>
+          <counterAttribute name="noDisplay"/>
+        </counterAttributes>
+      </counter>
+      <counter id="5" uri="Contoso-Storage.ContosoDisk.QueueLength" name="Queue Length" description="Number of requests waiting for the volume." type="perf_counter_rawcount" detailLevel="advanced" aggregate="max">
+        <counterAttributes>
+          <counterAttribute name="noDigitGrouping"/>
+        </counterAttributes>
+      </counter>
+    </counterSet>
+  </provider>
+</counters>
+"#
+        );
+    }
+
+    #[test]
+    fn escapes_and_disambiguates() {
+        let mut all = catalog();
+        let counterset = &mut all[0].countersets[0];
+        counterset.help = "Reads & writes <cached> \"here\"".to_string();
+        counterset.counters[0].name = "Cache Hits".to_string();
+        counterset.counters[1].name = "Cache/Hits".to_string();
+
+        let manifest = generate(&all[0], &all[0].countersets[0]);
+        assert!(manifest
+            .contains(r#"description="Reads &amp; writes &lt;cached&gt; &quot;here&quot;""#));
+        assert!(
+            manifest.contains(r#"uri="Contoso-Storage.ContosoCache.CacheHits" name="Cache Hits""#)
+        );
+        assert!(manifest
+            .contains(r#"uri="Contoso-Storage.ContosoCache.CacheHits.1" name="Cache/Hits""#));
+    }
+}
@@ -0,0 +1,312 @@
+use super::{AGGREGATES, ATTRIBUTE_NAMES, INSTANCE_TYPES};
+use crate::error::Result;
+use crate::types::{
+    parse_guid, AggregateFunc, Counter, CounterAttributes, CounterSet, CounterType, DetailLevel,
+    NonMaxU32, Provider,
+};
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+
+/// Parse the counter declarations in an instrumentation manifest.
+///
+/// Names and descriptions which refer to the manifest's string table (e.g. `$(string.Disk.Name)`) are resolved.
+/// Manifests don't list instances, so every counterset's `instances` is None.
+pub fn parse(xml: &str) -> Result<Vec<Provider>> {
+    let doc = Document::parse(xml)?;
+    let strings = string_table(&doc);
+
+    let mut providers = Vec::new();
+    for counters in doc.descendants().filter(|n| n.has_tag_name("counters")) {
+        for provider in children(counters, "provider") {
+            let parser = Parser {
+                doc: &doc,
+                strings: &strings,
+            };
+            providers.push(parser.provider(provider)?);
+        }
+    }
+
+    if providers.is_empty() {
+        return Err("manifest doesn't declare any counter providers".into());
+    }
+
+    Ok(providers)
+}
+
+/// The first string table in the manifest, by string id.
+fn string_table<'a>(doc: &'a Document) -> HashMap<&'a str, &'a str> {
+    let Some(table) = doc.descendants().find(|n| n.has_tag_name("stringTable")) else {
+        return HashMap::new();
+    };
+    children(table, "string")
+        .filter_map(|s| Some((s.attribute("id")?, s.attribute("value")?)))
+        .collect()
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |n| n.has_tag_name(name))
+}
+
+struct Parser<'a> {
+    doc: &'a Document<'a>,
+    strings: &'a HashMap<&'a str, &'a str>,
+}
+
+impl Parser<'_> {
+    fn provider(&self, node: Node) -> Result<Provider> {
+        let countersets = children(node, "counterSet")
+            .map(|cs| self.counterset(cs))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Provider {
+            id: self.guid(node, "providerGuid")?,
+            name: self.string(node, "providerName")?.unwrap_or_default(),
+            countersets,
+        })
+    }
+
+    fn counterset(&self, node: Node) -> Result<CounterSet> {
+        let instances = self.required(node, "instances")?;
+        let instance_type = match INSTANCE_TYPES.iter().find(|(_, name)| *name == instances) {
+            Some(&(instance_type, _)) => instance_type,
+            None => {
+                return Err(self.error(node, format!("unsupported instances \"{}\"", instances)))
+            }
+        };
+
+        let mut counters = children(node, "counter")
+            .map(|c| self.counter(c))
+            .collect::<Result<Vec<_>>>()?;
+        counters.sort_by_key(|c| c.id);
+
+        Ok(CounterSet {
+            id: self.guid(node, "guid")?,
+            name: self.string(node, "name")?.unwrap_or_default(),
+            help: self.string(node, "description")?.unwrap_or_default(),
+            instance_type,
+            counters,
+            instances: None,
+            legacy: false,
+        })
+    }
+
+    fn counter(&self, node: Node) -> Result<Counter> {
+        let type_name = self.required(node, "type")?;
+        let counter_type = CounterType::from_name(type_name)
+            .ok_or_else(|| self.error(node, format!("unknown counter type \"{}\"", type_name)))?;
+
+        let aggregate_func = match node.attribute("aggregate") {
+            None => AggregateFunc::Undefined,
+            Some(aggregate) => match AGGREGATES.iter().find(|(_, name)| *name == aggregate) {
+                Some(&(aggregate_func, _)) => aggregate_func,
+                None => {
+                    return Err(self.error(node, format!("unknown aggregate \"{}\"", aggregate)))
+                }
+            },
+        };
+
+        let detail_level = match self.required(node, "detailLevel")? {
+            "standard" => DetailLevel::Novice,
+            "advanced" => DetailLevel::Advanced,
+            other => return Err(self.error(node, format!("unknown detailLevel \"{}\"", other))),
+        };
+
+        let mut attributes = CounterAttributes::default();
+        for list in children(node, "counterAttributes") {
+            for attribute in children(list, "counterAttribute") {
+                let name = self.required(attribute, "name")?;
+                match ATTRIBUTE_NAMES.iter().find(|(_, n)| *n == name) {
+                    Some((flag, _)) => attributes.0 |= flag.0,
+                    None => {
+                        return Err(
+                            self.error(attribute, format!("unknown counterAttribute \"{}\"", name))
+                        )
+                    }
+                }
+            }
+        }
+
+        Ok(Counter {
+            id: self
+                .number(node, "id")?
+                .ok_or_else(|| self.missing(node, "id"))?,
+            name: self.string(node, "name")?.unwrap_or_default(),
+            help: self.string(node, "description")?.unwrap_or_default(),
+            counter_type,
+            base_counter_id: self.counter_id(node, "baseID")?,
+            multi_counter_id: self.counter_id(node, "multiCounterID")?,
+            perf_time_id: self.counter_id(node, "perfTimeID")?,
+            perf_freq_id: self.counter_id(node, "perfFreqID")?,
+            aggregate_func,
+            detail_level,
+            attributes,
+            default_scale: self.number(node, "defaultScale")?.unwrap_or(0),
+        })
+    }
+
+    fn required<'n>(&self, node: Node<'n, '_>, name: &str) -> Result<&'n str> {
+        node.attribute(name).ok_or_else(|| self.missing(node, name))
+    }
+
+    fn guid(&self, node: Node, name: &str) -> Result<windows::core::GUID> {
+        let value = self.required(node, name)?;
+        parse_guid(value).ok_or_else(|| self.error(node, format!("invalid {} \"{}\"", name, value)))
+    }
+
+    fn number<T: std::str::FromStr>(&self, node: Node, name: &str) -> Result<Option<T>> {
+        match node.attribute(name) {
+            None => Ok(None),
+            Some(value) => match value.parse() {
+                Ok(number) => Ok(Some(number)),
+                Err(_) => Err(self.error(node, format!("invalid {} \"{}\"", name, value))),
+            },
+        }
+    }
+
+    fn counter_id(&self, node: Node, name: &str) -> Result<Option<NonMaxU32>> {
+        Ok(self.number::<u32>(node, name)?.and_then(NonMaxU32::new))
+    }
+
+    /// An attribute which may be a literal string, or a reference to the string table.
+    fn string(&self, node: Node, name: &str) -> Result<Option<String>> {
+        let Some(value) = node.attribute(name) else {
+            return Ok(None);
+        };
+        match value
+            .strip_prefix("$(string.")
+            .and_then(|id| id.strip_suffix(')'))
+        {
+            None => Ok(Some(value.to_string())),
+            Some(id) => match self.strings.get(id) {
+                Some(s) => Ok(Some(s.to_string())),
+                None => Err(self.error(node, format!("string \"{}\" is not defined", id))),
+            },
+        }
+    }
+
+    fn missing(&self, node: Node, name: &str) -> crate::error::Error {
+        self.error(
+            node,
+            format!("<{}> is missing {}", node.tag_name().name(), name),
+        )
+    }
+
+    fn error(&self, node: Node, message: String) -> crate::error::Error {
+        let pos = self.doc.text_pos_at(node.range().start);
+        format!("line {}: {}", pos.row, message).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::generate;
+    use crate::testing::catalog;
+
+    #[test]
+    fn round_trips_generated_manifest() {
+        let all = catalog();
+        let provider = &all[0];
+
+        for counterset in &provider.countersets {
+            let parsed = parse(&generate(provider, counterset)).unwrap();
+
+            assert_eq!(parsed.len(), 1);
+            assert_eq!(parsed[0].id, provider.id);
+            assert_eq!(parsed[0].name, provider.name);
+            let [parsed] = parsed[0].countersets.as_slice() else {
+                panic!("expected one counterset");
+            };
+            assert_eq!(parsed.id, counterset.id);
+            assert_eq!(parsed.name, counterset.name);
+            assert_eq!(parsed.help, counterset.help);
+            assert_eq!(parsed.instance_type, counterset.instance_type);
+            assert_eq!(
+                format!("{:?}", parsed.counters),
+                format!("{:?}", counterset.counters)
+            );
+        }
+    }
+
+    #[test]
+    fn resolves_string_table() {
+        let providers = parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<instrumentationManifest xmlns="http://schemas.microsoft.com/win/2004/08/events">
+  <instrumentation>
+    <counters xmlns="http://schemas.microsoft.com/win/2005/12/counters" schemaVersion="1.1">
+      <provider providerGuid="{3D1A2C55-6B8E-4F10-9A3B-0C5E7D9F1A24}" applicationIdentity="contoso.exe" providerType="userMode">
+        <counterSet guid="{2B4D6F81-93A5-4C7E-8F01-23456789ABCD}" uri="Contoso.Cache" name="$(string.Cache.Name)" description="$(string.Cache.Help)" instances="single">
+          <counter id="1" uri="Contoso.Cache.Misses" name="Misses" type="perf_counter_counter" detailLevel="advanced"/>
+          <counter id="0" uri="Contoso.Cache.Hits" name="$(string.Hits.Name)" type="perf_counter_counter" detailLevel="standard" aggregate="sum"/>
+        </counterSet>
+      </provider>
+    </counters>
+  </instrumentation>
+  <localization>
+    <resources culture="en-US">
+      <stringTable>
+        <string id="Cache.Name" value="Contoso Cache"/>
+        <string id="Cache.Help" value="Block cache."/>
+        <string id="Hits.Name" value="Cache Hits"/>
+      </stringTable>
+    </resources>
+  </localization>
+</instrumentationManifest>
+"#,
+        )
+        .unwrap();
+
+        let counterset = &providers[0].countersets[0];
+        assert_eq!(providers[0].name, "");
+        assert_eq!(counterset.name, "Contoso Cache");
+        assert_eq!(counterset.help, "Block cache.");
+        let counters = counterset
+            .counters
+            .iter()
+            .map(|c| (c.id, c.name.as_str(), c.aggregate_func, c.detail_level))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counters,
+            [
+                (0, "Cache Hits", AggregateFunc::Total, DetailLevel::Novice),
+                (1, "Misses", AggregateFunc::Undefined, DetailLevel::Advanced),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let parse_err = |xml: &str| parse(xml).err().unwrap().to_string();
+
+        assert_eq!(
+            parse_err(
+                r#"<counters>
+  <provider providerGuid="{3D1A2C55-6B8E-4F10-9A3B-0C5E7D9F1A24}">
+    <counterSet guid="{2B4D6F81-93A5-4C7E-8F01-23456789ABCD}" instances="single">
+      <counter id="0" type="perf_counter_bogus" detailLevel="standard"/>
+    </counterSet>
+  </provider>
+</counters>"#
+            ),
+            "line 4: unknown counter type \"perf_counter_bogus\""
+        );
+        assert_eq!(
+            parse_err(
+                r#"<counters>
+  <provider providerGuid="{3D1A2C55-6B8E-4F10-9A3B-0C5E7D9F1A24}">
+    <counterSet guid="{2B4D6F81-93A5-4C7E-8F01-23456789ABCD}" name="$(string.Missing)" instances="single"/>
+  </provider>
+</counters>"#
+            ),
+            "line 3: string \"Missing\" is not defined"
+        );
+        assert_eq!(
+            parse_err("<instrumentationManifest/>"),
+            "manifest doesn't declare any counter providers"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use crate::cook;
+use crate::types::{CounterAttributes, CounterSet, CounterType, InstanceType, Provider};
+use std::fmt::{self, Display};
+use windows::core::GUID;
+
+/// A difference between what a manifest declares and what perflib reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub counterset_id: GUID,
+    pub counterset_name: String,
+    /// The counter the problem is with, if it isn't with the counterset itself.
+    pub counter_id: Option<u32>,
+    pub kind: ProblemKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    CounterSetNotRegistered,
+    WrongProvider {
+        manifest: GUID,
+        registered: GUID,
+    },
+    InstanceType {
+        manifest: InstanceType,
+        registered: InstanceType,
+    },
+    CounterNotRegistered {
+        name: String,
+    },
+    CounterNotDeclared {
+        name: String,
+    },
+    CounterName {
+        manifest: String,
+        registered: String,
+    },
+    CounterType {
+        manifest: CounterType,
+        registered: CounterType,
+    },
+    BaseCounter {
+        manifest: Option<u32>,
+        registered: Option<u32>,
+    },
+    MissingHelp {
+        /// Whether the help is missing from the manifest, or from what's registered.
+        in_manifest: bool,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?})", self.counterset_name, self.counterset_id)?;
+        if let Some(id) = self.counter_id {
+            write!(f, ", counter {}", id)?;
+        }
+        write!(f, ": ")?;
+
+        let id_or_none = |id: Option<u32>| match id {
+            Some(id) => id.to_string(),
+            None => "none".to_string(),
+        };
+
+        match &self.kind {
+            ProblemKind::CounterSetNotRegistered => write!(f, "declared but not registered"),
+            ProblemKind::WrongProvider {
+                manifest,
+                registered,
+            } => write!(
+                f,
+                "declared by provider {:?} but registered by provider {:?}",
+                manifest, registered
+            ),
+            ProblemKind::InstanceType {
+                manifest,
+                registered,
+            } => write!(
+                f,
+                "instance type is {:?} in the manifest but {:?} in perflib",
+                manifest, registered
+            ),
+            ProblemKind::CounterNotRegistered { name } => {
+                write!(f, "\"{}\" is declared but not registered", name)
+            }
+            ProblemKind::CounterNotDeclared { name } => {
+                write!(f, "\"{}\" is registered but not declared", name)
+            }
+            ProblemKind::CounterName {
+                manifest,
+                registered,
+            } => write!(
+                f,
+                "name is \"{}\" in the manifest but \"{}\" in perflib",
+                manifest, registered
+            ),
+            ProblemKind::CounterType {
+                manifest,
+                registered,
+            } => write!(
+                f,
+                "type is {:?} in the manifest but {:?} in perflib",
+                manifest, registered
+            ),
+            ProblemKind::BaseCounter {
+                manifest,
+                registered,
+            } => write!(
+                f,
+                "base counter is {} in the manifest but {} in perflib",
+                id_or_none(*manifest),
+                id_or_none(*registered)
+            ),
+            ProblemKind::MissingHelp { in_manifest: true } => {
+                write!(f, "has no description in the manifest")
+            }
+            ProblemKind::MissingHelp { in_manifest: false } => {
+                write!(f, "has no help string registered")
+            }
+        }
+    }
+}
+
+/// Compare the countersets declared in a manifest with the catalog reported by perflib.
+///
+/// Only countersets declared in the manifest are checked; the catalog normally contains many other providers.
+pub fn validate(manifest: &[Provider], catalog: &[Provider]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    for provider in manifest {
+        for declared in &provider.countersets {
+            let mut problem = |counter_id, kind| {
+                problems.push(Problem {
+                    counterset_id: declared.id,
+                    counterset_name: declared.name.clone(),
+                    counter_id,
+                    kind,
+                })
+            };
+
+            let registered = catalog.iter().find_map(|p| {
+                let cs = p.countersets.iter().find(|cs| cs.id == declared.id)?;
+                Some((p, cs))
+            });
+            let Some((registered_provider, registered)) = registered else {
+                problem(None, ProblemKind::CounterSetNotRegistered);
+                continue;
+            };
+
+            if registered_provider.id != provider.id {
+                problem(
+                    None,
+                    ProblemKind::WrongProvider {
+                        manifest: provider.id,
+                        registered: registered_provider.id,
+                    },
+                );
+            }
+            if registered.instance_type != declared.instance_type {
+                problem(
+                    None,
+                    ProblemKind::InstanceType {
+                        manifest: declared.instance_type,
+                        registered: registered.instance_type,
+                    },
+                );
+            }
+            if declared.help.is_empty() {
+                problem(None, ProblemKind::MissingHelp { in_manifest: true });
+            }
+            if registered.help.is_empty() {
+                problem(None, ProblemKind::MissingHelp { in_manifest: false });
+            }
+
+            compare_counters(declared, registered, &mut problem);
+        }
+    }
+
+    problems
+}
+
+fn compare_counters(
+    declared: &CounterSet,
+    registered: &CounterSet,
+    problem: &mut impl FnMut(Option<u32>, ProblemKind),
+) {
+    for counter in &declared.counters {
+        let Some(actual) = registered.counters.iter().find(|c| c.id == counter.id) else {
+            problem(
+                Some(counter.id),
+                ProblemKind::CounterNotRegistered {
+                    name: counter.name.clone(),
+                },
+            );
+            continue;
+        };
+
+        if actual.name != counter.name {
+            problem(
+                Some(counter.id),
+                ProblemKind::CounterName {
+                    manifest: counter.name.clone(),
+                    registered: actual.name.clone(),
+                },
+            );
+        }
+        if actual.counter_type != counter.counter_type {
+            problem(
+                Some(counter.id),
+                ProblemKind::CounterType {
+                    manifest: counter.counter_type,
+                    registered: actual.counter_type,
+                },
+            );
+        }
+        if actual.base_counter_id != counter.base_counter_id {
+            problem(
+                Some(counter.id),
+                ProblemKind::BaseCounter {
+                    manifest: counter.base_counter_id.map(|id| id.get()),
+                    registered: actual.base_counter_id.map(|id| id.get()),
+                },
+            );
+        }
+
+        // Base counters and hidden counters are never shown, so they don't need help.
+        let shown = cook::is_displayable(counter.counter_type)
+            && !counter
+                .attributes
+                .contains(CounterAttributes::NO_DISPLAYABLE);
+        if shown && counter.help.is_empty() {
+            problem(
+                Some(counter.id),
+                ProblemKind::MissingHelp { in_manifest: true },
+            );
+        }
+        if shown && actual.help.is_empty() {
+            problem(
+                Some(counter.id),
+                ProblemKind::MissingHelp { in_manifest: false },
+            );
+        }
+    }
+
+    for actual in &registered.counters {
+        if !declared.counters.iter().any(|c| c.id == actual.id) {
+            problem(
+                Some(actual.id),
+                ProblemKind::CounterNotDeclared {
+                    name: actual.name.clone(),
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{generate, parse};
+    use crate::testing::catalog;
+    use crate::types::NonMaxU32;
+
+    /// The manifest for the Contoso Disk counterset, as parsed from XML generated from the catalog.
+    fn manifest() -> Vec<Provider> {
+        let all = catalog();
+        parse(&generate(&all[0], &all[0].countersets[1])).unwrap()
+    }
+
+    fn check(manifest: &[Provider], catalog: &[Provider]) -> Vec<String> {
+        validate(manifest, catalog)
+            .iter()
+            .map(|p| p.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn matching_manifest() {
+        assert_eq!(check(&manifest(), &catalog()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn counterset_differences() {
+        let mut manifest = manifest();
+        manifest[0].id = GUID::from_u128(1);
+        manifest[0].countersets[0].instance_type = InstanceType::MultiInstances;
+        manifest[0].countersets[0].help.clear();
+
+        let all = catalog();
+        let mut unregistered = parse(&generate(&all[0], &all[0].countersets[0])).unwrap();
+        let mut unregistered = unregistered[0].countersets.remove(0);
+        unregistered.id = GUID::from_u128(2);
+        unregistered.name = "Contoso Unregistered".to_string();
+        manifest[0].countersets.push(unregistered);
+
+        assert_eq!(
+            check(&manifest, &catalog()),
+            [
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0): declared by provider 00000000-0000-0000-0000-000000000001 but registered by provider 3D1A2C55-6B8E-4F10-9A3B-0C5E7D9F1A24",
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0): instance type is MultiInstances in the manifest but MultiAggregate in perflib",
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0): has no description in the manifest",
+                "Contoso Unregistered (00000000-0000-0000-0000-000000000002): declared but not registered",
+            ]
+        );
+    }
+
+    #[test]
+    fn counter_differences() {
+        let mut manifest = manifest();
+        let counters = &mut manifest[0].countersets[0].counters;
+        // Renamed and retyped.
+        counters[0].name = "Bytes Written".to_string();
+        counters[0].counter_type = CounterType::PERF_COUNTER_BULK_COUNT;
+        // Wrong base.
+        counters[1].base_counter_id = NonMaxU32::new(4);
+        // Missing description, on a counter that's displayed.
+        counters[3].help.clear();
+        // Missing description, on a counter that's not displayed.
+        counters[4].help.clear();
+        // Declared, but not registered.
+        counters[5].id = 9;
+
+        let mut catalog = catalog();
+        catalog[0].countersets[1].counters[1].help.clear();
+
+        assert_eq!(
+            check(&manifest, &catalog),
+            [
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 0: name is \"Bytes Written\" in the manifest but \"Bytes Read\" in perflib",
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 0: type is PERF_COUNTER_BULK_COUNT in the manifest but PERF_COUNTER_LARGE_RAWCOUNT in perflib",
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 1: base counter is 4 in the manifest but 2 in perflib",
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 1: has no help string registered",
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 3: has no description in the manifest",
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 9: \"Queue Length\" is declared but not registered",
+                "Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), counter 5: \"Queue Length\" is registered but not declared",
+            ]
+        );
+    }
+}
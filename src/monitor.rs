@@ -0,0 +1,176 @@
+//! Evaluate alerting rules against periodically sampled counters, reporting when they fire and resolve.
+
+use crate::clock::Clock;
+use crate::error::Result;
+use crate::http::Endpoint;
+use crate::source::Source;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+mod eval;
+mod rule;
+
+pub use eval::{Evaluator, Event};
+pub use rule::parse;
+
+/// Somewhere events are sent.
+pub trait Output {
+    fn send(&mut self, event: &Event) -> Result<()>;
+}
+
+/// Periodically sample every counterset the rules refer to, and send the resulting events to every output.
+pub fn run(
+    evaluator: &mut Evaluator,
+    interval: Duration,
+    count: Option<u64>,
+    source: &mut dyn Source,
+    clock: &mut dyn Clock,
+    outputs: &mut [Box<dyn Output>],
+) -> Result<()> {
+    let countersets = evaluator.countersets();
+    let mut evaluations = 0;
+    loop {
+        for counterset in &countersets {
+            let sample = source.sample(&counterset.id)?;
+            for event in evaluator.update(sample, clock.now()) {
+                for output in outputs.iter_mut() {
+                    output.send(&event)?;
+                }
+            }
+        }
+
+        evaluations += 1;
+        log::debug!("Evaluated rules {} times", evaluations);
+        if count.is_some_and(|count| evaluations >= count) {
+            return Ok(());
+        }
+
+        clock.sleep(interval);
+    }
+}
+
+/// Writes one line of text per event.
+pub struct Text<W>(pub W);
+
+impl<W: Write> Output for Text<W> {
+    fn send(&mut self, event: &Event) -> Result<()> {
+        writeln!(self.0, "{}", event)?;
+        Ok(self.0.flush()?)
+    }
+}
+
+/// Writes one JSON object per line per event.
+pub struct Jsonl<W>(pub W);
+
+impl<W: Write> Output for Jsonl<W> {
+    fn send(&mut self, event: &Event) -> Result<()> {
+        serde_json::to_writer(&mut self.0, event)?;
+        writeln!(self.0)?;
+        Ok(self.0.flush()?)
+    }
+}
+
+/// POSTs each event as JSON to a plain HTTP URL, e.g. a local alert relay.
+///
+/// A webhook which can't be reached is logged rather than failing the monitor, so that alerts keep going to the
+/// other outputs.
+#[derive(Debug, Clone)]
+pub struct Webhook(pub Endpoint);
+
+impl Output for Webhook {
+    fn send(&mut self, event: &Event) -> Result<()> {
+        if let Err(e) = self.0.post("application/json", &serde_json::to_vec(event)?) {
+            log::warn!("Failed to send event to webhook {}: {}", self.0, e);
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Webhook {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::SystemTime;
+
+    fn event() -> Event {
+        Event {
+            time: SystemTime::UNIX_EPOCH,
+            status: eval::Status::Firing,
+            rule: "Queue".to_string(),
+            condition: r"\Contoso Disk\Queue Length > 4".to_string(),
+            counterset: "Contoso Disk".to_string(),
+            counter: "Queue Length".to_string(),
+            instance: None,
+            value: Some(5.0),
+        }
+    }
+
+    #[test]
+    fn parses_webhooks() {
+        let webhook = "http://localhost:8080/alerts".parse::<Webhook>().unwrap();
+        assert_eq!(webhook.0.to_string(), "http://localhost:8080/alerts");
+        assert!("https://localhost/".parse::<Webhook>().is_err());
+    }
+
+    #[test]
+    fn posts_to_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut webhook = format!("http://{}/alerts", listener.local_addr().unwrap())
+            .parse::<Webhook>()
+            .unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut request = String::new();
+            stream.read_to_string(&mut request).unwrap();
+            request
+        });
+
+        webhook
+            .0
+            .post("application/json", &serde_json::to_vec(&event()).unwrap())
+            .unwrap();
+        let request = server.join().unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("POST /alerts HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/json"));
+        assert_eq!(
+            body,
+            r#"{"time":"1970-01-01T00:00:00.000Z","status":"firing","rule":"Queue","condition":"\\Contoso Disk\\Queue Length > 4","counterset":"Contoso Disk","counter":"Queue Length","instance":null,"value":5.0}"#
+        );
+
+        // Failures are only logged.
+        webhook.0 = "http://127.0.0.1:1/alerts".parse().unwrap();
+        webhook.send(&event()).unwrap();
+    }
+
+    #[test]
+    fn writes_text_and_jsonl() {
+        let mut text = Text(Vec::new());
+        let mut jsonl = Jsonl(Vec::new());
+        text.send(&event()).unwrap();
+        jsonl.send(&event()).unwrap();
+        assert_eq!(
+            String::from_utf8(text.0).unwrap(),
+            "1970-01-01T00:00:00.000Z FIRING Queue: Contoso Disk\\Queue Length = 5 (\\Contoso Disk\\Queue Length > 4)\n"
+        );
+        assert!(String::from_utf8(jsonl.0)
+            .unwrap()
+            .ends_with("\"value\":5.0}\n"));
+    }
+}
@@ -0,0 +1,278 @@
+use super::rule::Bound;
+use crate::identity::{Series, SeriesKey};
+use crate::types::{CounterSet, Instance, Sample};
+use serde::{Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::time::SystemTime;
+use windows::core::GUID;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Firing,
+    Resolved,
+}
+
+/// A rule starting or stopping firing for one instance.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    #[serde(serialize_with = "rfc3339")]
+    pub time: SystemTime,
+    pub status: Status,
+    pub rule: String,
+    pub condition: String,
+    pub counterset: String,
+    pub counter: String,
+    /// None for single-instance countersets.
+    pub instance: Option<String>,
+    /// The value which triggered the event, or None if the instance disappeared.
+    pub value: Option<f64>,
+}
+
+fn rfc3339<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_rfc3339_millis(*time))
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            Status::Firing => "FIRING",
+            Status::Resolved => "RESOLVED",
+        };
+        write!(
+            f,
+            "{} {} {}: {}",
+            humantime::format_rfc3339_millis(self.time),
+            status,
+            self.rule,
+            self.counterset
+        )?;
+        if let Some(instance) = &self.instance {
+            write!(f, "({})", instance)?;
+        }
+        write!(f, "\\{}", self.counter)?;
+        match self.value {
+            Some(value) => write!(f, " = {} ({})", value, self.condition),
+            None => write!(f, " disappeared ({})", self.condition),
+        }
+    }
+}
+
+/// How long an instance has met a rule's condition, and whether the rule has fired for it yet.
+struct State {
+    since: SystemTime,
+    firing: bool,
+}
+
+/// Evaluates rules against successive samples, keeping track of each rule's state for each instance.
+///
+/// Nothing here depends on where the samples come from, so a series of samples can be evaluated at any times.
+pub struct Evaluator<'a> {
+    rules: Vec<Bound<'a>>,
+    series: HashMap<GUID, Series<'a>>,
+    /// Instances which currently meet a rule's condition, by rule index.
+    states: HashMap<(usize, SeriesKey), State>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(rules: Vec<Bound<'a>>) -> Self {
+        Self {
+            rules,
+            series: HashMap::new(),
+            states: HashMap::new(),
+        }
+    }
+
+    /// The countersets which need to be sampled, without duplicates.
+    pub fn countersets(&self) -> Vec<&'a CounterSet> {
+        let mut countersets: Vec<&CounterSet> = Vec::new();
+        for bound in &self.rules {
+            if !countersets.iter().any(|cs| cs.id == bound.counterset.id) {
+                countersets.push(bound.counterset);
+            }
+        }
+        countersets
+    }
+
+    /// Evaluate every rule on a sample's counterset, returning the rules which fired or resolved.
+    pub fn update(&mut self, sample: Sample, time: SystemTime) -> Vec<Event> {
+        let mut events = Vec::new();
+        let Some(counterset) = self
+            .rules
+            .iter()
+            .find(|b| b.counterset.id == sample.counterset_id)
+            .map(|b| b.counterset)
+        else {
+            return events;
+        };
+        let series = self
+            .series
+            .entry(counterset.id)
+            .or_insert_with(|| Series::new(counterset));
+        series.update(sample, time);
+        let sample = series.current().unwrap();
+
+        for (rule_index, bound) in self.rules.iter().enumerate() {
+            if bound.counterset.id != sample.counterset_id {
+                continue;
+            }
+            let rule = &bound.rule;
+            let event = |status, instance: &Option<Instance>, value| Event {
+                time,
+                status,
+                rule: rule.name.clone(),
+                condition: rule.to_string(),
+                counterset: bound.counterset.name.clone(),
+                counter: bound.counter.name.clone(),
+                instance: instance.as_ref().map(|i| i.name.clone()),
+                value,
+            };
+
+            let mut present = HashSet::new();
+            for (index, (instance, identity)) in
+                sample.instances.iter().zip(series.identities()).enumerate()
+            {
+                let matches = match (&rule.path.instance, &instance.instance) {
+                    (Some(glob), Some(instance)) => glob.matches(&instance.name),
+                    _ => true,
+                };
+                if !matches {
+                    continue;
+                }
+                present.insert(identity.key.clone());
+
+                // Values which can't be cooked yet (e.g. rates on the first sample) leave the state as it was.
+                let Some(value) = series.cook(bound.counter, index) else {
+                    continue;
+                };
+
+                let key = (rule_index, identity.key.clone());
+                if rule.op.apply(value, rule.threshold) {
+                    let state = self.states.entry(key).or_insert(State {
+                        since: time,
+                        firing: false,
+                    });
+                    let held = time.duration_since(state.since).unwrap_or_default();
+                    if !state.firing && held >= rule.duration {
+                        state.firing = true;
+                        events.push(event(Status::Firing, &instance.instance, Some(value)));
+                    }
+                } else if let Some(state) = self.states.remove(&key) {
+                    if state.firing {
+                        events.push(event(Status::Resolved, &instance.instance, Some(value)));
+                    }
+                }
+            }
+
+            // Rules stop firing for instances which go away.
+            let gone = self
+                .states
+                .keys()
+                .filter(|(i, key)| *i == rule_index && !present.contains(key))
+                .cloned()
+                .collect::<Vec<_>>();
+            for key in gone {
+                let state = self.states.remove(&key).unwrap();
+                if state.firing {
+                    events.push(event(Status::Resolved, &key.1.instance, None));
+                }
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::rule::Rule;
+    use crate::testing::{self, catalog, named, CONTOSO_DISK};
+    use std::time::Duration;
+
+    /// A sample of Contoso Disk's queue length for each (instance name, length).
+    fn sample(instances: &[(&str, u64)]) -> Sample {
+        let instances = named(instances.iter().map(|&(name, length)| (name, [length])));
+        testing::sample(CONTOSO_DISK, 0, &[5], instances)
+    }
+
+    /// Evaluate a rule against samples taken a minute apart, returning each event as text.
+    fn evaluate(when: &str, samples: Vec<Sample>) -> Vec<String> {
+        let all = catalog();
+        let rule = Rule::parse("Queue", when).unwrap().bind(&all).unwrap();
+        let mut evaluator = Evaluator::new(vec![rule]);
+        assert_eq!(evaluator.countersets()[0].name, "Contoso Disk");
+
+        let mut events = Vec::new();
+        for (minute, sample) in samples.into_iter().enumerate() {
+            let time =
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + 60 * minute as u64);
+            events.extend(evaluator.update(sample, time).iter().map(|e| e.to_string()));
+        }
+        events
+    }
+
+    #[test]
+    fn fires_after_duration_and_resolves() {
+        let events = evaluate(
+            r"\Contoso Disk(*)\Queue Length > 4 for 2m",
+            vec![
+                sample(&[("a", 5), ("b", 0)]),
+                sample(&[("a", 6), ("b", 9)]),
+                // Fires for a, having been over the threshold for 2 minutes.
+                sample(&[("a", 7), ("b", 9)]),
+                // b dips below the threshold before it fires, so starts again.
+                sample(&[("a", 8), ("b", 1)]),
+                sample(&[("a", 1), ("b", 9)]),
+                sample(&[("a", 1), ("b", 9)]),
+                sample(&[("a", 1), ("b", 9)]),
+            ],
+        );
+        assert_eq!(
+            events,
+            [
+                r"2023-11-14T22:15:20.000Z FIRING Queue: Contoso Disk(a)\Queue Length = 7 (\Contoso Disk(*)\Queue Length > 4 for 2m)",
+                r"2023-11-14T22:17:20.000Z RESOLVED Queue: Contoso Disk(a)\Queue Length = 1 (\Contoso Disk(*)\Queue Length > 4 for 2m)",
+                r"2023-11-14T22:19:20.000Z FIRING Queue: Contoso Disk(b)\Queue Length = 9 (\Contoso Disk(*)\Queue Length > 4 for 2m)",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_instances_and_resolves_when_they_disappear() {
+        let events = evaluate(
+            r"\Contoso Disk(disk*)\Queue Length >= 3",
+            vec![
+                sample(&[("disk1", 3), ("other", 9)]),
+                sample(&[("disk1", 4), ("other", 9)]),
+                sample(&[("other", 9)]),
+            ],
+        );
+        assert_eq!(
+            events,
+            [
+                r"2023-11-14T22:13:20.000Z FIRING Queue: Contoso Disk(disk1)\Queue Length = 3 (\Contoso Disk(disk*)\Queue Length >= 3)",
+                r"2023-11-14T22:15:20.000Z RESOLVED Queue: Contoso Disk(disk1)\Queue Length disappeared (\Contoso Disk(disk*)\Queue Length >= 3)",
+            ]
+        );
+    }
+
+    #[test]
+    fn serializes_events() {
+        let event = Event {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            status: Status::Resolved,
+            rule: "Queue".to_string(),
+            condition: r"\Contoso Disk\Queue Length > 4".to_string(),
+            counterset: "Contoso Disk".to_string(),
+            counter: "Queue Length".to_string(),
+            instance: None,
+            value: Some(2.0),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"time":"2023-11-14T22:13:20.000Z","status":"resolved","rule":"Queue","condition":"\\Contoso Disk\\Queue Length > 4","counterset":"Contoso Disk","counter":"Queue Length","instance":null,"value":2.0}"#
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::catalog::{CounterPath, CounterRef};
+use crate::cook;
+use crate::error::Result;
+use crate::types::{Counter, CounterSet, Provider};
+use serde::Deserialize;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A condition on a counter's cooked value, which must hold for a while before the rule fires.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub path: CounterPath,
+    pub op: Op,
+    pub threshold: f64,
+    /// How long the condition must hold for an instance before the rule fires for it.
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+/// A rule whose counterset and counter have been found in the catalog.
+#[derive(Debug, Clone)]
+pub struct Bound<'a> {
+    pub rule: Rule,
+    pub counterset: &'a CounterSet,
+    pub counter: &'a Counter,
+}
+
+/// The rules file, e.g.
+///
+/// ```toml
+/// [[rule]]
+/// name = "Low disk space"
+/// when = '\LogicalDisk(*)\% Free Space < 10 for 5m'
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default, rename = "rule")]
+    rules: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    name: String,
+    when: String,
+}
+
+/// Parse a TOML rules file.
+pub fn parse(toml: &str) -> Result<Vec<Rule>> {
+    let file: File = toml::from_str(toml)?;
+    file.rules
+        .into_iter()
+        .map(|entry| {
+            Rule::parse(&entry.name, &entry.when)
+                .map_err(|e| format!("rule \"{}\": {}", entry.name, e).into())
+        })
+        .collect()
+}
+
+impl Rule {
+    /// Parse a condition of the form `<path> <op> <threshold> [for <duration>]`.
+    pub fn parse(name: &str, when: &str) -> Result<Self> {
+        let when = when.trim();
+        // Counter names can have " for " in them too, so it's only a duration if it parses as one.
+        let (condition, duration) = when
+            .rsplit_once(" for ")
+            .and_then(|(condition, duration)| {
+                let duration = humantime::parse_duration(duration.trim()).ok()?;
+                Some((condition, duration))
+            })
+            .unwrap_or((when, Duration::ZERO));
+
+        let malformed = || {
+            format!(
+                "expected `<path> <op> <threshold> [for <duration>]`, got `{}`",
+                when
+            )
+        };
+        let (rest, threshold) = condition
+            .trim_end()
+            .rsplit_once(' ')
+            .ok_or_else(malformed)?;
+        let (path, op) = rest.trim_end().rsplit_once(' ').ok_or_else(malformed)?;
+        let threshold = threshold
+            .parse()
+            .map_err(|_| format!("threshold `{}` is not a number", threshold))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            path: path.trim().parse()?,
+            op: op.parse()?,
+            threshold,
+            duration,
+        })
+    }
+
+    /// Find the rule's counterset and counter in the catalog.
+    pub fn bind(self, all: &[Provider]) -> Result<Bound<'_>> {
+        let counterset = self.path.counterset.find(all)?;
+        let counter = counterset
+            .counters
+            .iter()
+            .find(|c| match &self.path.counter {
+                CounterRef::Id(id) => c.id == *id,
+                CounterRef::Name(name) => c.name.eq_ignore_ascii_case(name),
+            })
+            .ok_or_else(|| {
+                format!(
+                    "rule \"{}\": counterset {} has no counter {}",
+                    self.name, counterset.name, self.path.counter
+                )
+            })?;
+        if !cook::is_displayable(counter.counter_type) {
+            return Err(format!(
+                "rule \"{}\": counter {} is {:?}, which has no value by itself",
+                self.name, counter.name, counter.counter_type
+            )
+            .into());
+        }
+
+        Ok(Bound {
+            rule: self,
+            counterset,
+            counter,
+        })
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.path, self.op, self.threshold)?;
+        if !self.duration.is_zero() {
+            write!(f, " for {}", humantime::format_duration(self.duration))?;
+        }
+        Ok(())
+    }
+}
+
+impl Op {
+    pub fn apply(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Op::Lt => value < threshold,
+            Op::Le => value <= threshold,
+            Op::Gt => value > threshold,
+            Op::Ge => value >= threshold,
+            Op::Eq => value == threshold,
+            Op::Ne => value != threshold,
+        }
+    }
+}
+
+impl FromStr for Op {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            "==" => Op::Eq,
+            "!=" => Op::Ne,
+            _ => return Err(format!("unknown operator `{}`", s)),
+        })
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::catalog;
+
+    #[test]
+    fn parses_rules() {
+        let rules = parse(
+            r#"
+[[rule]]
+name = "Idle"
+when = '\Contoso Disk(disk?)\% Idle Time < 10.5 for 5m'
+
+[[rule]]
+name = "Queue"
+when = '\8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0\5 >= 4'
+"#,
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 2);
+
+        let idle = &rules[0];
+        assert_eq!(idle.name, "Idle");
+        assert_eq!(idle.op, Op::Lt);
+        assert_eq!(idle.threshold, 10.5);
+        assert_eq!(idle.duration, Duration::from_secs(300));
+        let instance = idle.path.instance.as_ref().unwrap();
+        assert!(instance.matches("Disk1") && !instance.matches("Disk10"));
+        assert_eq!(
+            idle.path.counter,
+            CounterRef::Name("% Idle Time".to_string())
+        );
+        assert_eq!(
+            idle.to_string(),
+            r"\Contoso Disk(disk?)\% Idle Time < 10.5 for 5m"
+        );
+
+        let queue = &rules[1];
+        assert!(queue.path.instance.is_none());
+        assert_eq!(queue.path.counter, CounterRef::Id(5));
+        assert_eq!(queue.duration, Duration::ZERO);
+
+        let all = catalog();
+        let bound = rules
+            .into_iter()
+            .map(|r| r.bind(&all).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(bound[0].counter.id, 3);
+        assert_eq!(bound[1].counter.name, "Queue Length");
+    }
+
+    #[test]
+    fn parses_counter_names_with_for_in_them() {
+        let rule = Rule::parse("r", r"\Contoso Disk\Time for Reads > 1").unwrap();
+        assert_eq!(
+            rule.path.counter,
+            CounterRef::Name("Time for Reads".to_string())
+        );
+        assert_eq!(rule.duration, Duration::ZERO);
+
+        let rule = Rule::parse("r", r"\Contoso Disk\Time for Reads > 1 for 30s").unwrap();
+        assert_eq!(
+            rule.path.counter,
+            CounterRef::Name("Time for Reads".to_string())
+        );
+        assert_eq!(rule.duration, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let errors = [
+            r"Contoso Disk\Queue Length > 1",
+            r"\Contoso Disk\Queue>1",
+            r"\Contoso Disk\Queue Length ~ 1",
+            r"\Contoso Disk\Queue Length > lots",
+            r"\Contoso Disk\Queue Length > 1 for ever",
+        ]
+        .map(|when| Rule::parse("r", when).unwrap_err().to_string());
+        assert_eq!(
+            errors[..4],
+            [
+                r"expected a path like `\Counterset(instance)\Counter`, got `Contoso Disk\Queue Length`",
+                r"expected `<path> <op> <threshold> [for <duration>]`, got `\Contoso Disk\Queue>1`",
+                "unknown operator `~`",
+                "threshold `lots` is not a number",
+            ]
+        );
+
+        let all = catalog();
+        let bind = |when| {
+            Rule::parse("r", when)
+                .unwrap()
+                .bind(&all)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            bind(r"\Contoso Disk\Missing > 1"),
+            "rule \"r\": counterset Contoso Disk has no counter \"Missing\""
+        );
+        assert_eq!(
+            bind(r"\Contoso Disk\4 > 1"),
+            "rule \"r\": counter % Idle Time Base is PERF_SAMPLE_BASE, which has no value by itself"
+        );
+    }
+}
@@ -1,4 +1,20 @@
+use crate::aggregate::Grouping;
+use crate::catalog::CounterSetRef;
+use crate::codegen;
+use crate::docs;
+use crate::export;
+use crate::glob::Glob;
+use crate::graph;
+use crate::http::Endpoint;
+use crate::lint;
+use crate::monitor;
+use crate::print;
+use crate::replay;
+use crate::store;
+use crate::types::InstanceType;
 use clap::{ArgAction, Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 use windows::core::GUID;
 
 #[derive(Parser, Debug)]
@@ -8,6 +24,14 @@ pub struct Options {
     #[arg(short = 'v', long = "verbose", action = ArgAction::Count, global = true)]
     pub verbose: u8,
 
+    /// Load the catalog from a snapshot file instead of the current machine
+    #[arg(long = "snapshot", global = true)]
+    pub snapshot: Option<PathBuf>,
+
+    /// Also load legacy (V1) performance objects from the registry, e.g. Process and Memory
+    #[arg(long = "legacy", global = true)]
+    pub legacy: bool,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -15,9 +39,76 @@ pub struct Options {
 #[derive(Subcommand, Debug)]
 pub enum Command {
     /// Print a summary of all providers, countersets, counters, and instances.
-    Summary,
+    Summary(Summary),
     /// Print detailed information about a counterset and its counters and instances.
     Counterset(Counterset),
+    /// Save all providers, countersets, counters, and instances to a snapshot file.
+    Snapshot(Snapshot),
+    /// Interactively browse providers, countersets, counters, and instances.
+    Tui,
+    /// Periodically print the values of a counterset's counters for each instance.
+    Watch(Watch),
+    /// Periodically record the raw values of a counterset's counters to a log, to be replayed later.
+    Sample(Sample),
+    /// Cook and print the values recorded in a log by `sample`.
+    Replay(Replay),
+    /// Print the counters section of an instrumentation manifest which declares a counterset.
+    Manifest(Manifest),
+    /// Check that the countersets declared in an instrumentation manifest match what perflib reports.
+    ValidateManifest(ValidateManifest),
+    /// Print code which reads a counterset's values, with constants for its GUID and counter ids.
+    Codegen(Codegen),
+    /// Print a graph of a counterset's counters, with edges to their base and multi counters.
+    Graph(Graph),
+    /// Check registered countersets for problems, failing if any errors are found.
+    Lint(Lint),
+    /// Periodically evaluate alerting rules against counter values, reporting when they fire and resolve.
+    Monitor(Monitor),
+    /// Print a counterset's instances, or follow them as they come and go.
+    Instances(Instances),
+    /// Periodically send the values of a counterset's counters to OpenTelemetry, StatsD or Graphite.
+    Export(Export),
+    /// Check a collection job config file against the catalog, and print what each job would collect.
+    CheckConfig(CheckConfig),
+    /// Run the collection jobs of a config file until stopped, reloading it when it changes.
+    Agent(Agent),
+    /// Cook and print a time range of the samples kept in a store by `sample --store` or `agent`.
+    Query(Query),
+    /// Write a searchable page of all providers, countersets, counters, and instances, e.g. from a snapshot.
+    Report(Report),
+    /// Write reference documentation for a provider's countersets, or one counterset, from their registration.
+    Docs(Docs),
+}
+
+#[derive(Args, Debug)]
+pub struct Summary {
+    /// Only show providers whose name matches this glob pattern
+    #[arg(long = "provider")]
+    pub provider: Option<Glob>,
+
+    /// Only show countersets whose name matches this glob pattern
+    #[arg(long = "counterset")]
+    pub counterset: Option<Glob>,
+
+    /// Only show counters whose name matches this glob pattern, and the countersets that have one
+    #[arg(long = "counter")]
+    pub counter: Option<Glob>,
+
+    /// Only show countersets of this instance type
+    #[arg(long = "instance-type", value_enum)]
+    pub instance_type: Option<InstanceType>,
+
+    /// Only show countersets with at least one instance
+    #[arg(long = "has-instances")]
+    pub has_instances: bool,
+
+    /// Only show countersets with at least this many counters, after filtering by --counter
+    #[arg(long = "min-counters")]
+    pub min_counters: Option<usize>,
+
+    /// How far down to print, from providers to the instances of their countersets
+    #[arg(long = "depth", value_enum, default_value_t = print::Depth::Instances)]
+    pub depth: print::Depth,
 }
 
 #[derive(Args, Debug)]
@@ -25,3 +116,308 @@ pub struct Counterset {
     /// The counterset's GUID, e.g. 811BBCE5-7327-4AD9-AB62-A8B955F61EEF
     pub guid: GUID,
 }
+
+#[derive(Args, Debug)]
+pub struct Snapshot {
+    /// The file to write the snapshot to
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct Watch {
+    /// The counterset's GUID or name
+    pub counterset: CounterSetRef,
+
+    /// Only show instances whose name matches this glob pattern
+    #[arg(long = "instance")]
+    pub instance: Option<Glob>,
+
+    /// Only show counters whose name matches this glob pattern
+    #[arg(long = "counter")]
+    pub counter: Option<Glob>,
+
+    /// How often to refresh, e.g. 500ms, 1s, 1m
+    #[arg(long = "interval", default_value = "1s", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+
+    /// Stop after this many refreshes
+    #[arg(long = "count")]
+    pub count: Option<u64>,
+
+    /// Add instances which combine others by each counter's aggregate function: total, prefix:<separator> or regex:<pattern>
+    #[arg(long = "aggregate")]
+    pub aggregate: Option<Grouping>,
+}
+
+#[derive(Args, Debug)]
+pub struct Sample {
+    /// The counterset's GUID or name
+    pub counterset: CounterSetRef,
+
+    /// The log file to append samples to, e.g. run.plog
+    #[arg(long = "output", required_unless_present_any = ["stats", "store"])]
+    pub output: Option<PathBuf>,
+
+    /// Also keep samples in a bounded store, to read back with `query`
+    #[arg(long = "store")]
+    pub store: Option<PathBuf>,
+
+    /// The most bytes --store keeps for the counterset, e.g. 512KB, 16MiB
+    #[arg(long = "store-size", default_value = store::DEFAULT_SIZE, value_parser = store::parse_size)]
+    pub store_size: u64,
+
+    /// How long --store keeps samples for, e.g. 1h, 7d, instead of until newer ones push them out
+    #[arg(long = "retention", value_parser = humantime::parse_duration)]
+    pub retention: Option<Duration>,
+
+    /// The resolutions --store rolls samples up to before deleting them, each a multiple of the last, e.g. 1m,1h
+    #[arg(long = "store-tiers", value_delimiter = ',', default_value = store::DEFAULT_TIERS, value_parser = humantime::parse_duration)]
+    pub store_tiers: Vec<Duration>,
+
+    /// How often to sample, e.g. 500ms, 1s, 1m
+    #[arg(long = "interval", default_value = "1s", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+
+    /// Stop after this many samples
+    #[arg(long = "count")]
+    pub count: Option<u64>,
+
+    /// Add instances which combine others by each counter's aggregate function: total, prefix:<separator> or regex:<pattern>
+    #[arg(long = "aggregate")]
+    pub aggregate: Option<Grouping>,
+
+    /// Print statistics of each counter and instance over the last --window, every --window and when stopping
+    #[arg(long = "stats")]
+    pub stats: bool,
+
+    /// How much history --stats covers, e.g. 30s, 5m
+    #[arg(long = "window", default_value = "1m", value_parser = humantime::parse_duration)]
+    pub window: Duration,
+}
+
+#[derive(Args, Debug)]
+pub struct Replay {
+    /// The log file written by `sample`
+    pub log: PathBuf,
+
+    /// Only show instances whose name matches this glob pattern
+    #[arg(long = "instance")]
+    pub instance: Option<Glob>,
+
+    /// Only show counters whose name matches this glob pattern
+    #[arg(long = "counter")]
+    pub counter: Option<Glob>,
+
+    /// Start from the first sample at or after this time, e.g. 2023-11-14T22:13:20Z or 1h (ago)
+    #[arg(long = "from", value_parser = store::query::parse_time)]
+    pub from: Option<SystemTime>,
+
+    /// Skip this many samples from the start of the log, or from --from
+    #[arg(long = "skip", default_value_t = 0)]
+    pub skip: u64,
+
+    /// How to print the cooked values
+    #[arg(long = "format", value_enum, default_value_t = replay::Format::Table)]
+    pub format: replay::Format,
+
+    /// Roll samples up into buckets of this length, e.g. 1m or 1h, with the value, min and max of each counter
+    #[arg(long = "rollup", value_parser = humantime::parse_duration)]
+    pub rollup: Option<Duration>,
+}
+
+#[derive(Args, Debug)]
+pub struct Manifest {
+    /// The counterset's GUID or name
+    pub counterset: CounterSetRef,
+}
+
+#[derive(Args, Debug)]
+pub struct ValidateManifest {
+    /// The manifest file, e.g. provider.man
+    pub manifest: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct Codegen {
+    /// The language to generate
+    #[arg(value_enum)]
+    pub language: codegen::Language,
+
+    /// The counterset's GUID or name
+    pub counterset: CounterSetRef,
+}
+
+#[derive(Args, Debug)]
+pub struct Graph {
+    /// The counterset's GUID or name
+    pub counterset: CounterSetRef,
+
+    /// The graph language to print
+    #[arg(long = "format", value_enum, default_value_t = graph::Format::Dot)]
+    pub format: graph::Format,
+}
+
+#[derive(Args, Debug)]
+pub struct Lint {
+    /// Only check this counterset (GUID or name), instead of all of them
+    #[arg(long = "counterset")]
+    pub counterset: Option<CounterSetRef>,
+
+    /// How to print the findings
+    #[arg(long = "format", value_enum, default_value_t = lint::Format::Text)]
+    pub format: lint::Format,
+}
+
+#[derive(Args, Debug)]
+pub struct Monitor {
+    /// The TOML file of rules, e.g. rules.toml
+    #[arg(long = "rules")]
+    pub rules: PathBuf,
+
+    /// Also append events to this file, as one JSON object per line
+    #[arg(long = "jsonl")]
+    pub jsonl: Option<PathBuf>,
+
+    /// Also POST events as JSON to this URL, e.g. http://localhost:8080/alerts
+    #[arg(long = "webhook")]
+    pub webhook: Option<monitor::Webhook>,
+
+    /// How often to evaluate the rules, e.g. 500ms, 1s, 1m
+    #[arg(long = "interval", default_value = "1s", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+
+    /// Stop after evaluating the rules this many times
+    #[arg(long = "count")]
+    pub count: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+pub struct Instances {
+    /// The counterset's GUID or name
+    pub counterset: CounterSetRef,
+
+    /// Keep enumerating instances, printing additions, removals and reused ids as they happen
+    #[arg(long = "follow")]
+    pub follow: bool,
+
+    /// How often to enumerate instances with --follow, e.g. 500ms, 1s, 1m
+    #[arg(long = "interval", default_value = "1s", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+
+    /// Stop following after this many enumerations, and print a summary of the changes
+    #[arg(long = "count")]
+    pub count: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+pub struct Export {
+    /// The counterset's GUID or name
+    pub counterset: CounterSetRef,
+
+    /// Send metrics to an OpenTelemetry collector over OTLP/HTTP, e.g. http://localhost:4318/v1/metrics
+    #[arg(long = "otlp", required_unless_present_any = ["statsd", "graphite"])]
+    pub otlp: Option<Endpoint>,
+
+    /// How to encode OTLP requests
+    #[arg(long = "otlp-encoding", value_enum, default_value_t = export::Encoding::Protobuf)]
+    pub otlp_encoding: export::Encoding,
+
+    /// Send metrics to StatsD over UDP, e.g. localhost:8125
+    #[arg(long = "statsd")]
+    pub statsd: Option<String>,
+
+    /// Send metrics to Graphite's plaintext protocol over TCP, e.g. localhost:2003
+    #[arg(long = "graphite")]
+    pub graphite: Option<String>,
+
+    /// The metric path for --statsd and --graphite, from {provider}, {counterset}, {instance} and {counter}
+    #[arg(long = "template", default_value = export::DEFAULT_TEMPLATE)]
+    pub template: export::Template,
+
+    /// How often to sample, e.g. 500ms, 1s, 1m
+    #[arg(long = "interval", default_value = "10s", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+
+    /// Stop after this many samples
+    #[arg(long = "count")]
+    pub count: Option<u64>,
+
+    /// Add instances which combine others by each counter's aggregate function: total, prefix:<separator> or regex:<pattern>
+    #[arg(long = "aggregate")]
+    pub aggregate: Option<Grouping>,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckConfig {
+    /// The config file, TOML or YAML by its extension
+    pub path: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct Agent {
+    /// The config file, TOML or YAML by its extension
+    pub config: PathBuf,
+
+    /// How often to look for newly registered countersets, e.g. 1m, 5m, 1h
+    #[arg(long = "refresh", default_value = "5m", value_parser = humantime::parse_duration)]
+    pub refresh: Duration,
+}
+
+#[derive(Args, Debug)]
+pub struct Query {
+    /// The counterset's GUID or name
+    pub counterset: CounterSetRef,
+
+    /// The store directory given to `sample --store`, or in an agent's config
+    #[arg(long = "store")]
+    pub store: PathBuf,
+
+    /// Only show samples from this time, e.g. 2023-11-14T22:13:20Z, or this long ago, e.g. 1h
+    #[arg(long = "from", value_parser = store::query::parse_time)]
+    pub from: Option<SystemTime>,
+
+    /// Only show samples up to this time, e.g. 2023-11-14T23:00:00Z, or this long ago, e.g. 10m
+    #[arg(long = "to", value_parser = store::query::parse_time)]
+    pub to: Option<SystemTime>,
+
+    /// How to print the cooked values
+    #[arg(long = "format", value_enum, default_value_t = store::query::Format::Csv)]
+    pub format: store::query::Format,
+
+    /// Roll samples up into buckets of this length, e.g. 1m or 1h, with the value, min and max of each counter
+    #[arg(long = "rollup", value_parser = humantime::parse_duration)]
+    pub rollup: Option<Duration>,
+
+    /// Show the buckets of this length kept after the samples were deleted, e.g. 1m or 1h, instead of the samples
+    #[arg(long = "tier", conflicts_with = "rollup", value_parser = humantime::parse_duration)]
+    pub tier: Option<Duration>,
+
+    /// Only show instances whose name matches this glob pattern
+    #[arg(long = "instance")]
+    pub instance: Option<Glob>,
+
+    /// Only show counters whose name matches this glob pattern
+    #[arg(long = "counter")]
+    pub counter: Option<Glob>,
+}
+
+#[derive(Args, Debug)]
+pub struct Report {
+    /// The HTML file to write, a single page with no other files needed to view it
+    #[arg(long = "html")]
+    pub html: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct Docs {
+    /// The format of the pages to write
+    #[arg(long = "format", value_enum, default_value_t = docs::Format::Markdown)]
+    pub format: docs::Format,
+
+    /// The GUID or name of a provider, to document all of its countersets, or of a single counterset
+    pub target: CounterSetRef,
+
+    /// The directory to write a page per counterset to
+    #[arg(long = "output", default_value = ".")]
+    pub output: PathBuf,
+}
@@ -0,0 +1,616 @@
+//! A compact binary log of raw samples from one counterset, which can be cooked later on any machine.
+//!
+//! Layout (all integers little-endian):
+//!
+//! ```text
+//! header:  "PLOG" | version: u32 | interval (µs): u64 | len: u32 | counterset definition (JSON, len bytes)
+//! record:  len: u32 | payload (len bytes)
+//! payload: wall clock (ms since 1970): i64 | timestamp: i64 | time_100ns: i64 | frequency: i64
+//!          | counter count: u32 | counter ids: u32... | instance count: u32 | instance...
+//! instance: has instance: u8 | [id: u32 | name len: u32 | name (UTF-8)] | values: u64 (one per counter)
+//! ```
+//!
+//! Records are length-prefixed, so readers can skip over samples without decoding them, or seek to a time by reading
+//! only the wall clock at the start of each one, and a record cut short by the collector being killed can be detected
+//! and ignored.
+
+use crate::error::Result;
+use crate::types::{CounterSet, Instance, InstanceSample, Sample};
+use std::fs::File;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime};
+use windows::core::GUID;
+
+const MAGIC: [u8; 4] = *b"PLOG";
+
+/// Bumped whenever the log format changes incompatibly, including the counterset definition in the header.
+///
+/// 2: counters have a detail level, attributes, and default scale.
+const VERSION: u32 = 2;
+
+/// Everything recorded once per log, needed to interpret the samples.
+#[derive(Debug)]
+pub struct Header {
+    pub interval: Duration,
+    /// The definition of the counterset at the time the log was started, so samples can be cooked without the catalog.
+    pub counterset: CounterSet,
+}
+
+/// One sample from the log.
+#[derive(Debug)]
+pub struct Record {
+    /// Wall clock time when the sample was collected.
+    pub time: SystemTime,
+    pub sample: Sample,
+}
+
+pub struct Writer<W> {
+    inner: W,
+    counterset_id: GUID,
+}
+
+/// Storage which can be shortened, to discard an incomplete record before appending after it.
+pub trait SetLen {
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl SetLen for File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+}
+
+impl SetLen for Cursor<Vec<u8>> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().truncate(len as usize);
+        Ok(())
+    }
+}
+
+impl<T: SetLen> SetLen for &mut T {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        (**self).set_len(len)
+    }
+}
+
+impl<W: Read + Write + Seek + SetLen> Writer<W> {
+    /// Start writing to a log, or continue appending to it if it already contains samples from the same counterset.
+    pub fn open(mut inner: W, counterset: &CounterSet, interval: Duration) -> Result<Self> {
+        let end = inner.seek(SeekFrom::End(0))?;
+
+        if end == 0 {
+            write_header(&mut inner, counterset, interval)?;
+        } else {
+            let (header, mut reader) = Reader::new(&mut inner)?;
+            if header.counterset.id != counterset.id {
+                return Err(format!(
+                    "log contains samples from counterset {:?}, not {:?}",
+                    header.counterset.id, counterset.id
+                )
+                .into());
+            }
+            if header.interval != interval {
+                log::warn!(
+                    "Log was started with interval {}; appending samples taken every {}",
+                    humantime::format_duration(header.interval),
+                    humantime::format_duration(interval)
+                );
+            }
+
+            // Find the end of the last complete record, and discard any partial record after it.
+            while reader.skip()? {}
+            let end = reader.position;
+            inner.set_len(end)?;
+            inner.seek(SeekFrom::Start(end))?;
+        }
+
+        Ok(Self {
+            inner,
+            counterset_id: counterset.id,
+        })
+    }
+
+    /// Append a sample, returning how many bytes it took up.
+    pub fn append(&mut self, time: SystemTime, sample: &Sample) -> Result<u64> {
+        if sample.counterset_id != self.counterset_id {
+            return Err(format!(
+                "sample is from counterset {:?}, not {:?}",
+                sample.counterset_id, self.counterset_id
+            )
+            .into());
+        }
+
+        let mut payload = Vec::new();
+        let millis = match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(since) => since.as_millis() as i64,
+            Err(e) => -(e.duration().as_millis() as i64),
+        };
+        payload.extend(millis.to_le_bytes());
+        payload.extend(sample.timestamp.to_le_bytes());
+        payload.extend(sample.time_100ns.to_le_bytes());
+        payload.extend(sample.frequency.to_le_bytes());
+        payload.extend(len_u32(sample.counter_ids.len())?.to_le_bytes());
+        for id in &sample.counter_ids {
+            payload.extend(id.to_le_bytes());
+        }
+        payload.extend(len_u32(sample.instances.len())?.to_le_bytes());
+        for instance in &sample.instances {
+            if instance.values.len() != sample.counter_ids.len() {
+                return Err("sample has a different number of values than counters".into());
+            }
+            match &instance.instance {
+                None => payload.push(0),
+                Some(Instance { id, name }) => {
+                    payload.push(1);
+                    payload.extend(id.to_le_bytes());
+                    payload.extend(len_u32(name.len())?.to_le_bytes());
+                    payload.extend(name.as_bytes());
+                }
+            }
+            for value in &instance.values {
+                payload.extend(value.to_le_bytes());
+            }
+        }
+
+        // Written in one go and flushed, so an interrupted collection loses at most the sample in progress.
+        let mut record = Vec::with_capacity(4 + payload.len());
+        record.extend(len_u32(payload.len())?.to_le_bytes());
+        record.extend(payload);
+        self.inner.write_all(&record)?;
+        self.inner.flush()?;
+
+        Ok(record.len() as u64)
+    }
+}
+
+fn write_header(out: &mut impl Write, counterset: &CounterSet, interval: Duration) -> Result<()> {
+    let counterset = serde_json::to_vec(counterset)?;
+    let interval = u64::try_from(interval.as_micros()).map_err(|_| "interval too long")?;
+
+    out.write_all(&MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&interval.to_le_bytes())?;
+    out.write_all(&len_u32(counterset.len())?.to_le_bytes())?;
+    out.write_all(&counterset)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+fn len_u32(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| "too much data for one log record".into())
+}
+
+pub struct Reader<R> {
+    inner: R,
+    counterset_id: GUID,
+    /// Offset of the next record.
+    position: u64,
+}
+
+impl<R: Read + Seek> Reader<R> {
+    /// Start reading a log from the beginning, returning its header and a reader for the records which follow it.
+    pub fn new(mut inner: R) -> Result<(Header, Self)> {
+        inner.seek(SeekFrom::Start(0))?;
+
+        let mut magic = [0; 4];
+        inner.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err("not a sample log".into());
+        }
+
+        let mut fixed = [0; 16];
+        inner.read_exact(&mut fixed)?;
+        let mut fixed = Bytes(&fixed);
+        let version = fixed.u32()?;
+        if version != VERSION {
+            return Err(format!(
+                "unsupported sample log version {} (expected {})",
+                version, VERSION
+            )
+            .into());
+        }
+        let interval = Duration::from_micros(fixed.u64()?);
+        let len = fixed.u32()?;
+
+        let counterset = read_up_to(&mut inner, len)?;
+        if counterset.len() != len as usize {
+            return Err("incomplete sample log header".into());
+        }
+        let counterset: CounterSet = serde_json::from_slice(&counterset)?;
+
+        let position = inner.stream_position()?;
+
+        let reader = Self {
+            inner,
+            counterset_id: counterset.id,
+            position,
+        };
+        let header = Header {
+            interval,
+            counterset,
+        };
+        Ok((header, reader))
+    }
+
+    /// Read the length of the next record, or None at the end of the log (including if the last record is incomplete).
+    fn next_len(&mut self) -> Result<Option<u32>> {
+        let mut len = [0; 4];
+        match read_fully(&mut self.inner, &mut len)? {
+            0 => return Ok(None),
+            4 => {}
+            _ => {
+                log::warn!("Ignoring incomplete record at end of log");
+                return Ok(None);
+            }
+        }
+        Ok(Some(u32::from_le_bytes(len)))
+    }
+
+    /// Skip over the next record without decoding it. Returns false at the end of the log.
+    pub fn skip(&mut self) -> Result<bool> {
+        let Some(len) = self.next_len()? else {
+            return Ok(false);
+        };
+
+        let end = self.inner.seek(SeekFrom::End(0))?;
+        let next = self.position + 4 + u64::from(len);
+        if next > end {
+            log::warn!("Ignoring incomplete record at end of log");
+            self.inner.seek(SeekFrom::Start(self.position))?;
+            return Ok(false);
+        }
+
+        self.inner.seek(SeekFrom::Start(next))?;
+        self.position = next;
+        Ok(true)
+    }
+
+    /// Skip over the records from before a time, so that the next one read is the first at or after it.
+    ///
+    /// Records are appended in the order they're collected, so this assumes the wall clock didn't go back meanwhile.
+    pub fn seek(&mut self, time: SystemTime) -> Result<()> {
+        loop {
+            let start = self.position;
+            let Some(_) = self.next_len()? else {
+                return Ok(());
+            };
+            let mut millis = [0; 8];
+            let read = read_fully(&mut self.inner, &mut millis)?;
+            self.inner.seek(SeekFrom::Start(start))?;
+            if read == millis.len() && wall_clock(i64::from_le_bytes(millis)) >= time {
+                return Ok(());
+            }
+            if !self.skip()? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Read the next record, or None at the end of the log.
+    pub fn next_record(&mut self) -> Result<Option<Record>> {
+        let Some(len) = self.next_len()? else {
+            return Ok(None);
+        };
+
+        let payload = read_up_to(&mut self.inner, len)?;
+        if payload.len() != len as usize {
+            log::warn!("Ignoring incomplete record at end of log");
+            return Ok(None);
+        }
+        self.position += 4 + u64::from(len);
+
+        let record = parse_record(self.counterset_id, &payload)?;
+        Ok(Some(record))
+    }
+}
+
+fn parse_record(counterset_id: GUID, payload: &[u8]) -> Result<Record> {
+    let mut bytes = Bytes(payload);
+
+    let time = wall_clock(bytes.i64()?);
+
+    let mut sample = Sample {
+        counterset_id,
+        timestamp: bytes.i64()?,
+        time_100ns: bytes.i64()?,
+        frequency: bytes.i64()?,
+        counter_ids: Vec::new(),
+        instances: Vec::new(),
+    };
+
+    let counters = bytes.u32()?;
+    for _ in 0..counters {
+        sample.counter_ids.push(bytes.u32()?);
+    }
+
+    let instances = bytes.u32()?;
+    for _ in 0..instances {
+        let instance = match bytes.u8()? {
+            0 => None,
+            1 => {
+                let id = bytes.u32()?;
+                let len = bytes.u32()?;
+                let name = String::from_utf8(bytes.take(len as usize)?.to_vec())?;
+                Some(Instance { id, name })
+            }
+            other => return Err(format!("invalid instance marker {}", other).into()),
+        };
+        let values = (0..counters)
+            .map(|_| bytes.u64())
+            .collect::<Result<Vec<_>>>()?;
+        sample.instances.push(InstanceSample { instance, values });
+    }
+
+    if !bytes.0.is_empty() {
+        return Err("unexpected data at end of record".into());
+    }
+
+    Ok(Record { time, sample })
+}
+
+/// The time of a record, from its milliseconds since 1970, which are negative before then.
+fn wall_clock(millis: i64) -> SystemTime {
+    match u64::try_from(millis) {
+        Ok(millis) => SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+        Err(_) => SystemTime::UNIX_EPOCH - Duration::from_millis(millis.unsigned_abs()),
+    }
+}
+
+/// Read `len` bytes, or fewer at the end of the log.
+///
+/// The buffer only grows as the bytes are read, so a corrupt length can't make it allocate more than the log holds.
+fn read_up_to(inner: &mut impl Read, len: u32) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    inner.take(u64::from(len)).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Like `read_exact`, but returns how much was read instead of failing at end of file.
+fn read_fully(inner: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match inner.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Decodes little-endian values from the front of a buffer.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err("record is truncated".into());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, at};
+    use crate::types::{AggregateFunc, CounterType};
+
+    fn counterset(id: u128) -> CounterSet {
+        let mut reads = testing::counter(7, "Reads/sec", CounterType::PERF_COUNTER_COUNTER, None);
+        reads.aggregate_func = AggregateFunc::Total;
+        testing::counterset(GUID::from_u128(id), "Disk", vec![reads])
+    }
+
+    /// A sample of reads for each (instance, if there is one, and reads).
+    fn sample(second: i64, instances: &[(Option<(u32, &str)>, u64)]) -> Sample {
+        let instances = instances.iter().map(|&(instance, reads)| {
+            let instance = instance.and_then(|(id, name)| testing::instance(id, name));
+            (instance, [reads])
+        });
+        testing::sample(GUID::from_u128(1), second, &[7], instances)
+    }
+
+    fn read_all(log: &mut Cursor<Vec<u8>>) -> Vec<Record> {
+        let (_, mut reader) = Reader::new(log).unwrap();
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record().unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn assert_same(record: &Record, time: SystemTime, sample: &Sample) {
+        assert_eq!(record.time, time);
+        assert_eq!(record.sample.counterset_id, sample.counterset_id);
+        assert_eq!(record.sample.timestamp, sample.timestamp);
+        assert_eq!(record.sample.time_100ns, sample.time_100ns);
+        assert_eq!(record.sample.frequency, sample.frequency);
+        assert_eq!(record.sample.counter_ids, sample.counter_ids);
+        assert_eq!(record.sample.instances.len(), sample.instances.len());
+        for (a, b) in record.sample.instances.iter().zip(&sample.instances) {
+            assert_eq!(a.instance, b.instance);
+            assert_eq!(a.values, b.values);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let samples = [
+            sample(0, &[(None, 5)]),
+            sample(
+                1,
+                &[(Some((0, "C:")), 10), (Some((1, "Ünïcode")), u64::MAX)],
+            ),
+        ];
+
+        let mut log = Cursor::new(Vec::new());
+        let mut writer =
+            Writer::open(&mut log, &counterset(1), Duration::from_millis(500)).unwrap();
+        for (i, sample) in samples.iter().enumerate() {
+            writer.append(at(i as u64), sample).unwrap();
+        }
+
+        let (header, _) = Reader::new(&mut log).unwrap();
+        assert_eq!(header.interval, Duration::from_millis(500));
+        assert_eq!(header.counterset.name, "Disk");
+        assert_eq!(header.counterset.counters[0].id, 7);
+
+        let records = read_all(&mut log);
+        assert_eq!(records.len(), 2);
+        assert_same(&records[0], at(0), &samples[0]);
+        assert_same(&records[1], at(1), &samples[1]);
+    }
+
+    #[test]
+    fn skip_records() {
+        let mut log = Cursor::new(Vec::new());
+        let mut writer = Writer::open(&mut log, &counterset(1), Duration::from_secs(1)).unwrap();
+        for second in 0..3 {
+            let sample = sample(second, &[(None, second as u64)]);
+            writer.append(at(second as u64), &sample).unwrap();
+        }
+
+        let (_, mut reader) = Reader::new(&mut log).unwrap();
+        assert!(reader.skip().unwrap());
+        assert!(reader.skip().unwrap());
+        let record = reader.next_record().unwrap().unwrap();
+        assert_eq!(record.sample.instances[0].values, [2]);
+        assert!(!reader.skip().unwrap());
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn append_to_existing_log() {
+        let mut log = Cursor::new(Vec::new());
+        let mut writer = Writer::open(&mut log, &counterset(1), Duration::from_secs(1)).unwrap();
+        writer.append(at(0), &sample(0, &[(None, 1)])).unwrap();
+
+        let mut writer = Writer::open(&mut log, &counterset(1), Duration::from_secs(1)).unwrap();
+        writer.append(at(1), &sample(1, &[(None, 2)])).unwrap();
+
+        let records = read_all(&mut log);
+        assert_eq!(records.len(), 2);
+        assert_same(&records[1], at(1), &sample(1, &[(None, 2)]));
+    }
+
+    #[test]
+    fn append_rejects_other_counterset() {
+        let mut log = Cursor::new(Vec::new());
+        Writer::open(&mut log, &counterset(1), Duration::from_secs(1)).unwrap();
+
+        let e = Writer::open(&mut log, &counterset(2), Duration::from_secs(1))
+            .err()
+            .unwrap();
+        assert_eq!(
+            e.to_string(),
+            "log contains samples from counterset 00000000-0000-0000-0000-000000000001, \
+             not 00000000-0000-0000-0000-000000000002"
+        );
+    }
+
+    #[test]
+    fn incomplete_record_is_ignored_and_discarded() {
+        let mut log = Cursor::new(Vec::new());
+        let mut writer = Writer::open(&mut log, &counterset(1), Duration::from_secs(1)).unwrap();
+        writer.append(at(0), &sample(0, &[(None, 1)])).unwrap();
+        writer.append(at(1), &sample(1, &[(None, 2)])).unwrap();
+
+        // Simulate the collector being killed partway through writing the second record.
+        let len = log.get_ref().len();
+        log.get_mut().truncate(len - 3);
+        assert_eq!(read_all(&mut log).len(), 1);
+
+        let mut writer = Writer::open(&mut log, &counterset(1), Duration::from_secs(1)).unwrap();
+        writer.append(at(2), &sample(2, &[(None, 3)])).unwrap();
+
+        let records = read_all(&mut log);
+        assert_eq!(records.len(), 2);
+        assert_same(&records[1], at(2), &sample(2, &[(None, 3)]));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let e = Reader::new(Cursor::new(b"{\"version\": 2}".to_vec()))
+            .err()
+            .unwrap();
+        assert_eq!(e.to_string(), "not a sample log");
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut log = Vec::new();
+        log.extend_from_slice(b"PLOG");
+        log.extend_from_slice(&1u32.to_le_bytes());
+        log.extend_from_slice(&1_000_000u64.to_le_bytes());
+        log.extend_from_slice(&2u32.to_le_bytes());
+        log.extend_from_slice(b"{}");
+        let e = Reader::new(Cursor::new(log)).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "unsupported sample log version 1 (expected 2)"
+        );
+    }
+
+    #[test]
+    fn seek_to_time() {
+        let mut log = Cursor::new(Vec::new());
+        let mut writer = Writer::open(&mut log, &counterset(1), Duration::from_secs(1)).unwrap();
+        for second in [0, 2, 4] {
+            let sample = sample(second, &[(None, second as u64)]);
+            writer.append(at(second as u64), &sample).unwrap();
+        }
+
+        let (_, mut reader) = Reader::new(&mut log).unwrap();
+        reader.seek(at(1)).unwrap();
+        assert_eq!(reader.next_record().unwrap().unwrap().time, at(2));
+        // From the current record onwards.
+        reader.seek(at(0)).unwrap();
+        assert_eq!(reader.next_record().unwrap().unwrap().time, at(4));
+
+        let (_, mut reader) = Reader::new(&mut log).unwrap();
+        reader.seek(at(4)).unwrap();
+        assert_eq!(reader.next_record().unwrap().unwrap().time, at(4));
+        reader.seek(at(5)).unwrap();
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn corrupt_lengths_are_not_allocated() {
+        let mut log = Cursor::new(Vec::new());
+        Writer::open(&mut log, &counterset(1), Duration::from_secs(1)).unwrap();
+        let header = log.get_ref().len();
+
+        // A record claiming to be 4 GiB.
+        log.get_mut().extend(u32::MAX.to_le_bytes());
+        log.get_mut().extend([0; 16]);
+        let (_, mut reader) = Reader::new(&mut log).unwrap();
+        assert!(reader.next_record().unwrap().is_none());
+
+        // A header claiming the same.
+        log.get_mut()[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        log.get_mut().truncate(header);
+        let e = Reader::new(&mut log).err().unwrap();
+        assert_eq!(e.to_string(), "incomplete sample log header");
+    }
+}
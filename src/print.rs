@@ -1,31 +1,93 @@
-use crate::types::Provider;
+use crate::glob::Glob;
+use crate::types::{InstanceType, Provider};
+use std::io::{self, Write};
 use windows::core::GUID;
 
-pub fn summary(all: &[Provider]) {
-    println!("Providers ({}):", all.len());
+/// How far down the catalog to print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Depth {
+    Providers,
+    Countersets,
+    Counters,
+    Instances,
+}
+
+/// Which parts of the catalog to summarize.
+///
+/// Providers and countersets left with nothing matching below them are dropped, so that e.g. filtering by counter
+/// only shows the countersets that have one.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub provider: Option<Glob>,
+    pub counterset: Option<Glob>,
+    pub counter: Option<Glob>,
+    pub instance_type: Option<InstanceType>,
+    pub has_instances: bool,
+    /// After filtering by counter.
+    pub min_counters: Option<usize>,
+}
+
+impl Filter {
+    pub fn apply(&self, mut all: Vec<Provider>) -> Vec<Provider> {
+        let filters_countersets = self.counterset.is_some()
+            || self.counter.is_some()
+            || self.instance_type.is_some()
+            || self.has_instances
+            || self.min_counters.is_some();
+
+        all.retain(|p| self.provider.as_ref().is_none_or(|g| g.matches(&p.name)));
+        for p in &mut all {
+            p.countersets.retain_mut(|cs| {
+                if let Some(glob) = &self.counter {
+                    cs.counters.retain(|c| glob.matches(&c.name));
+                }
+                self.counterset.as_ref().is_none_or(|g| g.matches(&cs.name))
+                    && self.instance_type.is_none_or(|t| cs.instance_type == t)
+                    && (!self.has_instances || cs.instances.as_ref().is_some_and(|i| !i.is_empty()))
+                    && !(self.counter.is_some() && cs.counters.is_empty())
+                    && self.min_counters.is_none_or(|min| cs.counters.len() >= min)
+            });
+        }
+        all.retain(|p| !(filters_countersets && p.countersets.is_empty()));
+        all
+    }
+}
+
+pub fn summary(out: &mut impl Write, all: &[Provider], depth: Depth) -> io::Result<()> {
+    writeln!(out, "Providers ({}):", all.len())?;
     for p in all {
-        println!("# {:?}: {}", p.id, p.name);
-        println!("  Countersets ({}):", p.countersets.len());
+        writeln!(out, "# {:?}: {}", p.id, p.name)?;
+        if depth < Depth::Countersets {
+            continue;
+        }
+        writeln!(out, "  Countersets ({}):", p.countersets.len())?;
         for cs in &p.countersets {
-            println!("  @ {:?}: {}; {}", cs.id, cs.name, cs.help);
-            println!("    Counters ({}):", cs.counters.len());
+            writeln!(out, "  @ {:?}: {}; {}", cs.id, cs.name, cs.help)?;
+            if depth < Depth::Counters {
+                continue;
+            }
+            writeln!(out, "    Counters ({}):", cs.counters.len())?;
             for c in &cs.counters {
-                println!("    - {:?}: {}; {}", c.id, c.name, c.help);
+                writeln!(out, "    - {:?}: {}; {}", c.id, c.name, c.help)?;
+            }
+            if depth < Depth::Instances {
+                continue;
             }
             match &cs.instances {
                 Some(instances) => {
-                    println!("    Instances ({}):", instances.len());
+                    writeln!(out, "    Instances ({}):", instances.len())?;
                     for i in instances {
-                        println!("    > {:?}: {}", i.id, i.name);
+                        writeln!(out, "    > {:?}: {}", i.id, i.name)?;
                     }
                 }
                 None => {
-                    println!("    Instances: none");
+                    writeln!(out, "    Instances: none")?;
                 }
             }
         }
-        println!();
+        writeln!(out)?;
     }
+    Ok(())
 }
 
 pub fn counterset(all: &[Provider], counterset_id: &GUID) {
@@ -43,3 +105,92 @@ pub fn counterset(all: &[Provider], counterset_id: &GUID) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::catalog;
+
+    fn summarize(filter: &Filter, depth: Depth) -> String {
+        let all = catalog();
+        let mut out = Vec::new();
+        summary(&mut out, &filter.apply(all), depth).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn filters_and_prunes_empty_parents() {
+        let names = |filter: Filter| {
+            let all = catalog();
+            filter
+                .apply(all)
+                .iter()
+                .flat_map(|p| &p.countersets)
+                .map(|cs| format!("{} {}", cs.name, cs.counters.len()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(Filter::default()).len(), 3);
+        assert_eq!(
+            names(Filter {
+                provider: Some(Glob::new("*storage")),
+                ..Filter::default()
+            }),
+            ["Contoso Cache 4", "Contoso Disk 6"]
+        );
+        assert_eq!(
+            names(Filter {
+                counter: Some(Glob::new("*base")),
+                ..Filter::default()
+            }),
+            ["Contoso Cache 1", "Contoso Disk 2"]
+        );
+        assert_eq!(
+            names(Filter {
+                counter: Some(Glob::new("*base")),
+                min_counters: Some(2),
+                ..Filter::default()
+            }),
+            ["Contoso Disk 2"]
+        );
+        assert_eq!(
+            names(Filter {
+                has_instances: true,
+                instance_type: Some(InstanceType::MultiInstances),
+                ..Filter::default()
+            }),
+            ["Contoso Network Adapter 3"]
+        );
+
+        // Only the providers with a counterset left are shown.
+        let out = summarize(
+            &Filter {
+                counterset: Some(Glob::new("contoso network*")),
+                ..Filter::default()
+            },
+            Depth::Providers,
+        );
+        assert_eq!(
+            out,
+            "Providers (1):\n# 7C9E1A3B-5D7F-4912-A4B6-C8DAEC0F2143: Contoso-Network\n"
+        );
+    }
+
+    #[test]
+    fn prints_down_to_a_depth() {
+        let filter = Filter {
+            counterset: Some(Glob::new("contoso disk")),
+            ..Filter::default()
+        };
+        let countersets = summarize(&filter, Depth::Countersets);
+        assert!(countersets.contains("  @ 8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0: Contoso Disk;"));
+        assert!(!countersets.contains("Counters ("));
+
+        let counters = summarize(&filter, Depth::Counters);
+        assert!(counters.contains("    Counters (6):\n    - 0: Bytes Read;"));
+        assert!(!counters.contains("Instances"));
+
+        let instances = summarize(&filter, Depth::Instances);
+        assert!(instances.contains("    Instances (2):\n    > 0: C:\n    > 1: D:\n"));
+    }
+}
@@ -0,0 +1,453 @@
+//! Publishing our own counters, without an instrumentation manifest.
+//!
+//! perflib learns about a V2 provider's counterset from a template: a `PERF_COUNTERSET_INFO` followed by a
+//! `PERF_COUNTER_INFO` for each counter, which also lays out where each counter's value lives in an
+//! instance's data block. Building the template is plain byte-shuffling, so it works (and is tested) anywhere;
+//! only registering it and setting values needs Windows.
+//!
+//! https://learn.microsoft.com/en-us/windows/win32/perfctrs/using-the-perflib-functions-to-provide-counter-data
+
+use crate::error::Result;
+use crate::types::counter_type::{Kind, Size};
+use crate::types::{
+    AggregateFunc, Counter, CounterAttributes, CounterSet, CounterType, DetailLevel, InstanceType,
+    NonMaxU32,
+};
+use std::collections::HashSet;
+use windows::core::GUID;
+
+#[cfg(windows)]
+pub use register::{Instance, Provider};
+
+#[cfg(windows)]
+mod register;
+
+/// Size of `PERF_COUNTERSET_INFO`.
+pub const COUNTERSET_INFO_SIZE: usize = 40;
+/// Size of `PERF_COUNTER_INFO`.
+pub const COUNTER_INFO_SIZE: usize = 32;
+
+/// Describes a counterset in code, and builds the template which registers it with perflib.
+///
+/// Counters are laid out in the order they're added. Methods which describe a counter (`help`, `base`, ...)
+/// apply to the most recently added one, and panic if there isn't one.
+#[derive(Debug)]
+pub struct CounterSetBuilder {
+    provider_id: GUID,
+    counterset: CounterSet,
+}
+
+impl CounterSetBuilder {
+    pub fn new(provider_id: GUID, id: GUID, name: &str, instance_type: InstanceType) -> Self {
+        Self {
+            provider_id,
+            counterset: CounterSet {
+                id,
+                name: name.to_string(),
+                help: String::new(),
+                instance_type,
+                counters: Vec::new(),
+                instances: None,
+                legacy: false,
+            },
+        }
+    }
+
+    /// Describe an existing counterset, e.g. one from the catalog or a sample log, to publish it ourselves.
+    pub fn from_counterset(provider_id: GUID, counterset: &CounterSet) -> Self {
+        let mut builder = Self::new(
+            provider_id,
+            counterset.id,
+            &counterset.name,
+            counterset.instance_type,
+        );
+        builder.counterset.help = counterset.help.clone();
+        for counter in &counterset.counters {
+            builder.counterset.counters.push(Counter {
+                id: counter.id,
+                name: counter.name.clone(),
+                help: counter.help.clone(),
+                counter_type: counter.counter_type,
+                base_counter_id: counter.base_counter_id,
+                multi_counter_id: counter.multi_counter_id,
+                perf_time_id: counter.perf_time_id,
+                perf_freq_id: counter.perf_freq_id,
+                aggregate_func: counter.aggregate_func,
+                detail_level: counter.detail_level,
+                attributes: counter.attributes,
+                default_scale: counter.default_scale,
+            });
+        }
+        builder
+    }
+
+    /// Set the help text of the counterset, or of the last counter if any have been added.
+    pub fn help(mut self, help: &str) -> Self {
+        match self.counterset.counters.last_mut() {
+            Some(counter) => counter.help = help.to_string(),
+            None => self.counterset.help = help.to_string(),
+        }
+        self
+    }
+
+    /// Add a counter with no attributes, standard detail level, no scale, and no aggregation.
+    pub fn counter(mut self, id: u32, name: &str, counter_type: CounterType) -> Self {
+        self.counterset.counters.push(Counter {
+            id,
+            name: name.to_string(),
+            help: String::new(),
+            counter_type,
+            base_counter_id: None,
+            multi_counter_id: None,
+            perf_time_id: None,
+            perf_freq_id: None,
+            aggregate_func: AggregateFunc::Undefined,
+            detail_level: DetailLevel::Novice,
+            attributes: CounterAttributes::default(),
+            default_scale: 0,
+        });
+        self
+    }
+
+    /// The counter whose value is the denominator of the last counter's, which must be added next.
+    pub fn base(mut self, base_counter_id: u32) -> Self {
+        self.last().base_counter_id = NonMaxU32::new(base_counter_id);
+        self
+    }
+
+    pub fn attributes(mut self, attributes: CounterAttributes) -> Self {
+        self.last().attributes = attributes;
+        self
+    }
+
+    pub fn detail_level(mut self, detail_level: DetailLevel) -> Self {
+        self.last().detail_level = detail_level;
+        self
+    }
+
+    pub fn default_scale(mut self, default_scale: i32) -> Self {
+        self.last().default_scale = default_scale;
+        self
+    }
+
+    /// How the last counter is combined across instances, for aggregate countersets.
+    ///
+    /// Templates have nowhere to put this, so it's only kept in the description, for `counterset` to be turned into
+    /// a manifest with it.
+    pub fn aggregate(mut self, aggregate_func: AggregateFunc) -> Self {
+        self.last().aggregate_func = aggregate_func;
+        self
+    }
+
+    fn last(&mut self) -> &mut Counter {
+        self.counterset
+            .counters
+            .last_mut()
+            .expect("add a counter before describing it")
+    }
+
+    /// The counterset as described so far, e.g. to pass to `manifest::generate` so that names and help are
+    /// registered too.
+    pub fn counterset(&self) -> &CounterSet {
+        &self.counterset
+    }
+
+    /// Check the description and lay out the template.
+    pub fn build(&self) -> Result<Template> {
+        let cs = &self.counterset;
+        let error = |counter: &Counter, message: &str| -> crate::error::Error {
+            format!(
+                "{}, counter {} ({}): {}",
+                cs.name, counter.id, counter.name, message
+            )
+            .into()
+        };
+
+        if cs.counters.is_empty() {
+            return Err(format!("{} has no counters", cs.name).into());
+        }
+
+        let mut ids = HashSet::new();
+        let mut slots = Vec::with_capacity(cs.counters.len());
+        let mut offset = 0u32;
+        for (i, counter) in cs.counters.iter().enumerate() {
+            if !ids.insert(counter.id) {
+                return Err(error(counter, "duplicate id"));
+            }
+
+            let size = match counter.counter_type.size() {
+                Size::Dword => 4,
+                Size::Large => 8,
+                Size::Zero | Size::Variable => {
+                    return Err(error(
+                        counter,
+                        "only 32 and 64 bit counters can be published",
+                    ))
+                }
+            };
+
+            // perflib finds the base from the template's order rather than by id.
+            if let Some(base) = counter.base_counter_id {
+                match cs.counters.get(i + 1) {
+                    Some(next)
+                        if next.id == base.get() && next.counter_type.kind() == Kind::Base => {}
+                    _ => {
+                        return Err(error(
+                            counter,
+                            &format!(
+                                "base counter {} must be a base counter added immediately after it",
+                                base.get()
+                            ),
+                        ))
+                    }
+                }
+            }
+
+            // Keep 64 bit values naturally aligned.
+            offset = offset.next_multiple_of(size);
+            slots.push(Slot {
+                counter_id: counter.id,
+                offset,
+                size,
+            });
+            offset += size;
+        }
+        let data_size = offset.next_multiple_of(8);
+
+        let mut bytes =
+            Vec::with_capacity(COUNTERSET_INFO_SIZE + COUNTER_INFO_SIZE * cs.counters.len());
+        bytes.extend_from_slice(&guid_bytes(&cs.id));
+        bytes.extend_from_slice(&guid_bytes(&self.provider_id));
+        bytes.extend_from_slice(&(cs.counters.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(cs.instance_type as u32).to_le_bytes());
+        for (counter, slot) in cs.counters.iter().zip(&slots) {
+            bytes.extend_from_slice(&counter.id.to_le_bytes());
+            bytes.extend_from_slice(&counter.counter_type.0.to_le_bytes());
+            bytes.extend_from_slice(&counter.attributes.0.to_le_bytes());
+            bytes.extend_from_slice(&slot.size.to_le_bytes());
+            bytes.extend_from_slice(&(counter.detail_level as u32).to_le_bytes());
+            bytes.extend_from_slice(&counter.default_scale.to_le_bytes());
+            bytes.extend_from_slice(&slot.offset.to_le_bytes());
+        }
+
+        Ok(Template {
+            counterset_id: cs.id,
+            provider_id: self.provider_id,
+            bytes,
+            slots,
+            data_size,
+        })
+    }
+}
+
+/// A GUID in its in-memory layout: three little-endian fields followed by eight bytes.
+fn guid_bytes(guid: &GUID) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[0..4].copy_from_slice(&guid.data1.to_le_bytes());
+    bytes[4..6].copy_from_slice(&guid.data2.to_le_bytes());
+    bytes[6..8].copy_from_slice(&guid.data3.to_le_bytes());
+    bytes[8..16].copy_from_slice(&guid.data4);
+    bytes
+}
+
+/// A counterset template, ready to be registered with perflib.
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub counterset_id: GUID,
+    pub provider_id: GUID,
+    bytes: Vec<u8>,
+    slots: Vec<Slot>,
+    data_size: u32,
+}
+
+/// Where a counter's value lives in an instance's data block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub counter_id: u32,
+    /// From the start of the data block.
+    pub offset: u32,
+    /// 4 or 8 bytes.
+    pub size: u32,
+}
+
+impl Template {
+    /// The `PERF_COUNTERSET_INFO` and `PERF_COUNTER_INFO` structures, as perflib expects them.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The layout of the counters' values, in template order.
+    pub fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+
+    pub fn slot(&self, counter_id: u32) -> Option<&Slot> {
+        self.slots.iter().find(|s| s.counter_id == counter_id)
+    }
+
+    /// Size of the values in each instance's data block.
+    pub fn data_size(&self) -> u32 {
+        self.data_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use windows::Win32::System::Performance::{PERF_COUNTERSET_INFO, PERF_COUNTER_INFO};
+
+    const PROVIDER: GUID = GUID::from_u128(0x00112233_4455_6677_8899_aabbccddeeff);
+    const COUNTERSET: GUID = GUID::from_u128(0x10203040_5060_7080_90a0_b0c0d0e0f000);
+
+    #[test]
+    fn template_bytes() {
+        let template = CounterSetBuilder::new(
+            PROVIDER,
+            COUNTERSET,
+            "Requests",
+            InstanceType::MultiAggregate,
+        )
+        .counter(1, "Queued", CounterType::PERF_COUNTER_RAWCOUNT)
+        .aggregate(AggregateFunc::Total)
+        .counter(2, "Bytes/sec", CounterType::PERF_COUNTER_BULK_COUNT)
+        .attributes(CounterAttributes::NO_GROUP_SEPARATOR)
+        .detail_level(DetailLevel::Advanced)
+        .default_scale(-3)
+        .build()
+        .unwrap();
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            // PERF_COUNTERSET_INFO
+            0x40, 0x30, 0x20, 0x10, 0x60, 0x50, 0x80, 0x70,
+            0x90, 0xa0, 0xb0, 0xc0, 0xd0, 0xe0, 0xf0, 0x00,
+            0x33, 0x22, 0x11, 0x00, 0x55, 0x44, 0x77, 0x66,
+            0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            2, 0, 0, 0,
+            6, 0, 0, 0,
+            // PERF_COUNTER_INFO for Queued
+            1, 0, 0, 0,
+            0x00, 0x00, 0x01, 0x00,
+            0, 0, 0, 0, 0, 0, 0, 0,
+            4, 0, 0, 0,
+            100, 0, 0, 0,
+            0, 0, 0, 0,
+            0, 0, 0, 0,
+            // PERF_COUNTER_INFO for Bytes/sec, aligned to 8 bytes
+            2, 0, 0, 0,
+            0x00, 0x05, 0x41, 0x10,
+            0x04, 0, 0, 0, 0, 0, 0, 0,
+            8, 0, 0, 0,
+            200, 0, 0, 0,
+            0xfd, 0xff, 0xff, 0xff,
+            8, 0, 0, 0,
+        ];
+        assert_eq!(template.as_bytes(), expected);
+        assert_eq!(
+            template.slots(),
+            [
+                Slot {
+                    counter_id: 1,
+                    offset: 0,
+                    size: 4
+                },
+                Slot {
+                    counter_id: 2,
+                    offset: 8,
+                    size: 8
+                },
+            ]
+        );
+        assert_eq!(template.data_size(), 16);
+    }
+
+    #[test]
+    fn matches_windows_structs() {
+        assert_eq!(
+            std::mem::size_of::<PERF_COUNTERSET_INFO>(),
+            COUNTERSET_INFO_SIZE
+        );
+        assert_eq!(std::mem::size_of::<PERF_COUNTER_INFO>(), COUNTER_INFO_SIZE);
+
+        let all = testing::catalog();
+        let counterset = &all[0].countersets[1];
+        let template = CounterSetBuilder::from_counterset(all[0].id, counterset)
+            .build()
+            .unwrap();
+        let bytes = template.as_bytes();
+        assert_eq!(
+            bytes.len(),
+            COUNTERSET_INFO_SIZE + COUNTER_INFO_SIZE * counterset.counters.len()
+        );
+
+        // SAFETY: the lengths were checked above, and read_unaligned copes with the Vec's alignment.
+        let info = unsafe { (bytes.as_ptr() as *const PERF_COUNTERSET_INFO).read_unaligned() };
+        assert_eq!(info.CounterSetGuid, counterset.id);
+        assert_eq!(info.ProviderGuid, all[0].id);
+        assert_eq!(info.NumCounters, 6);
+        assert_eq!(info.InstanceType, InstanceType::MultiAggregate as u32);
+
+        let offsets = counterset
+            .counters
+            .iter()
+            .enumerate()
+            .map(|(i, counter)| {
+                let info = unsafe {
+                    (bytes[COUNTERSET_INFO_SIZE + COUNTER_INFO_SIZE * i..].as_ptr()
+                        as *const PERF_COUNTER_INFO)
+                        .read_unaligned()
+                };
+                assert_eq!(info.CounterId, counter.id);
+                assert_eq!(info.Type, counter.counter_type.0);
+                assert_eq!(info.Attrib, counter.attributes.0);
+                assert_eq!(info.DetailLevel, counter.detail_level as u32);
+                assert_eq!(info.Scale, counter.default_scale);
+                (info.Offset, info.Size)
+            })
+            .collect::<Vec<_>>();
+        // Bytes Read is 64 bit; the rest are 32 bit.
+        assert_eq!(
+            offsets,
+            [(0, 8), (8, 4), (12, 4), (16, 4), (20, 4), (24, 4)]
+        );
+        assert_eq!(template.data_size(), 32);
+    }
+
+    #[test]
+    fn rejects_invalid_countersets() {
+        let build_err = |builder: CounterSetBuilder| builder.build().err().unwrap().to_string();
+        let builder = || {
+            CounterSetBuilder::new(
+                PROVIDER,
+                COUNTERSET,
+                "Requests",
+                InstanceType::SingleInstance,
+            )
+        };
+
+        assert_eq!(build_err(builder()), "Requests has no counters");
+        assert_eq!(
+            build_err(
+                builder()
+                    .counter(1, "A", CounterType::PERF_COUNTER_RAWCOUNT)
+                    .counter(1, "B", CounterType::PERF_COUNTER_RAWCOUNT)
+            ),
+            "Requests, counter 1 (B): duplicate id"
+        );
+        assert_eq!(
+            build_err(builder().counter(1, "Name", CounterType::PERF_COUNTER_TEXT)),
+            "Requests, counter 1 (Name): only 32 and 64 bit counters can be published"
+        );
+        assert_eq!(
+            build_err(
+                builder()
+                    .counter(1, "Hit Ratio", CounterType::PERF_RAW_FRACTION)
+                    .base(3)
+                    .counter(2, "Misses", CounterType::PERF_COUNTER_RAWCOUNT)
+                    .counter(3, "Lookups", CounterType::PERF_RAW_BASE)
+            ),
+            "Requests, counter 1 (Hit Ratio): base counter 3 must be a base counter added immediately after it"
+        );
+    }
+}
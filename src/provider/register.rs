@@ -0,0 +1,126 @@
+use super::Template;
+use windows::core::{Error, Result, GUID, HSTRING};
+use windows::Win32::Foundation::{
+    ERROR_ARITHMETIC_OVERFLOW, ERROR_NOT_FOUND, ERROR_SUCCESS, HANDLE, WIN32_ERROR,
+};
+use windows::Win32::System::Performance::{
+    PerfCreateInstance, PerfDeleteInstance, PerfSetCounterSetInfo, PerfSetULongCounterValue,
+    PerfSetULongLongCounterValue, PerfStartProvider, PerfStopProvider, PERF_COUNTERSET_INFO,
+    PERF_COUNTERSET_INSTANCE,
+};
+
+/// A registered V2 provider, which stops providing when dropped.
+pub struct Provider {
+    handle: HANDLE,
+    templates: Vec<Template>,
+}
+
+impl Provider {
+    pub fn start(provider_id: &GUID) -> Result<Self> {
+        let mut handle = HANDLE::default();
+        check(unsafe { PerfStartProvider(provider_id, None, &mut handle) })?;
+        Ok(Self {
+            handle,
+            templates: Vec::new(),
+        })
+    }
+
+    /// Register a counterset, so that instances of it can be created.
+    pub fn add_counterset(&mut self, template: Template) -> Result<()> {
+        // Copy the template into a u64 buffer, since perflib expects the structures to be aligned.
+        let bytes = template.as_bytes();
+        let mut block = vec![0u64; bytes.len().div_ceil(8)];
+        // SAFETY: block is at least as long as bytes.
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), block.as_mut_ptr().cast(), bytes.len())
+        };
+
+        check(unsafe {
+            PerfSetCounterSetInfo(
+                self.handle,
+                block.as_mut_ptr().cast::<PERF_COUNTERSET_INFO>(),
+                bytes.len().try_into().unwrap(),
+            )
+        })?;
+        self.templates.push(template);
+        Ok(())
+    }
+
+    /// Create an instance of a registered counterset, with all of its values zero.
+    ///
+    /// Single-instance countersets have exactly one instance, which is normally unnamed with id 0.
+    pub fn create_instance(
+        &self,
+        counterset_id: &GUID,
+        name: &str,
+        id: u32,
+    ) -> Result<Instance<'_>> {
+        let template = self
+            .templates
+            .iter()
+            .find(|t| t.counterset_id == *counterset_id)
+            .ok_or_else(|| Error::from(ERROR_NOT_FOUND))?;
+
+        let name = HSTRING::from(name);
+        let block = unsafe { PerfCreateInstance(self.handle, counterset_id, &name, id) };
+        if block.is_null() {
+            return Err(Error::from_win32());
+        }
+
+        Ok(Instance {
+            provider: self,
+            template,
+            block,
+        })
+    }
+}
+
+impl Drop for Provider {
+    fn drop(&mut self) {
+        if let Err(e) = check(unsafe { PerfStopProvider(self.handle) }) {
+            log::warn!("Failed to stop providing counters: {}", e);
+        }
+    }
+}
+
+/// An instance of a counterset published by a `Provider`, which is removed when dropped.
+pub struct Instance<'a> {
+    provider: &'a Provider,
+    template: &'a Template,
+    block: *mut PERF_COUNTERSET_INSTANCE,
+}
+
+impl Instance<'_> {
+    /// Set a counter's raw value, failing if the counter is 32 bit and the value doesn't fit.
+    pub fn set(&self, counter_id: u32, value: u64) -> Result<()> {
+        let slot = self
+            .template
+            .slot(counter_id)
+            .ok_or_else(|| Error::from(ERROR_NOT_FOUND))?;
+
+        let handle = self.provider.handle;
+        check(match slot.size {
+            4 => {
+                let value =
+                    u32::try_from(value).map_err(|_| Error::from(ERROR_ARITHMETIC_OVERFLOW))?;
+                unsafe { PerfSetULongCounterValue(handle, self.block, counter_id, value) }
+            }
+            _ => unsafe { PerfSetULongLongCounterValue(handle, self.block, counter_id, value) },
+        })
+    }
+}
+
+impl Drop for Instance<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = check(unsafe { PerfDeleteInstance(self.provider.handle, self.block) }) {
+            log::warn!("Failed to delete an instance: {}", e);
+        }
+    }
+}
+
+fn check(res: u32) -> Result<()> {
+    match WIN32_ERROR(res) {
+        ERROR_SUCCESS => Ok(()),
+        e => Err(Error::from(e)),
+    }
+}
@@ -0,0 +1,260 @@
+use crate::legacy;
+use crate::source::Source;
+use crate::types::{Instance, InstanceSample, Sample};
+use crate::winapi::{decode_utf16_until_null, invoke_with_buf};
+use std::collections::HashMap;
+use std::mem;
+use windows::core::{Error, Result, GUID, PCWSTR};
+use windows::Win32::Foundation::{ERROR_INVALID_DATA, ERROR_SUCCESS, HANDLE, WIN32_ERROR};
+use windows::Win32::System::Performance::{
+    PerfAddCounters, PerfCloseQueryHandle, PerfOpenQueryHandle, PerfQueryCounterData,
+    PERF_COUNTERSET, PERF_COUNTER_DATA, PERF_COUNTER_HEADER, PERF_COUNTER_IDENTIFIER,
+    PERF_DATA_HEADER, PERF_ERROR_RETURN, PERF_INSTANCE_HEADER, PERF_MULTIPLE_COUNTERS,
+    PERF_MULTI_COUNTERS, PERF_MULTI_INSTANCES, PERF_WILDCARD_COUNTER,
+};
+
+/// A perflib query handle which collects all counters of all instances of a single counterset.
+pub struct Query {
+    handle: HANDLE,
+    counterset_id: GUID,
+}
+
+impl Query {
+    pub fn open(counterset_id: &GUID) -> Result<Self> {
+        let mut handle = HANDLE::default();
+        check(unsafe { PerfOpenQueryHandle(PCWSTR::null(), &mut handle) })?;
+
+        // Construct this immediately, so the handle gets closed if adding counters fails.
+        let query = Self {
+            handle,
+            counterset_id: *counterset_id,
+        };
+
+        // "Each PERF_COUNTER_IDENTIFIER block consists of a PERF_COUNTER_IDENTIFIER structure,
+        // optionally followed by a null-terminated UTF-16LE instance name, followed by padding to a multiple of 8 bytes."
+        // https://learn.microsoft.com/en-us/windows/win32/api/perflib/ns-perflib-perf_counter_identifier
+        let wildcard_instance = ['*' as u16, 0];
+        let size = (mem::size_of::<PERF_COUNTER_IDENTIFIER>()
+            + mem::size_of_val(&wildcard_instance))
+        .next_multiple_of(8);
+
+        // Use u64 so the block is 8-byte aligned.
+        let mut block = vec![0u64; size / 8];
+        let block_ptr = block.as_mut_ptr().cast::<PERF_COUNTER_IDENTIFIER>();
+
+        // SAFETY: block is large enough to hold the identifier followed by the instance name.
+        unsafe {
+            block_ptr.write(PERF_COUNTER_IDENTIFIER {
+                CounterSetGuid: *counterset_id,
+                Status: 0,
+                Size: size.try_into().unwrap(),
+                CounterId: PERF_WILDCARD_COUNTER,
+                InstanceId: PERF_WILDCARD_COUNTER,
+                Index: 0,
+                Reserved: 0,
+            });
+            block_ptr
+                .add(1)
+                .cast::<[u16; 2]>()
+                .write_unaligned(wildcard_instance);
+        }
+
+        check(unsafe { PerfAddCounters(query.handle, block_ptr, size.try_into().unwrap()) })?;
+
+        // "Status: Receives the error code for the add operation."
+        let identifier = unsafe { block_ptr.read() };
+        check(identifier.Status)?;
+
+        Ok(query)
+    }
+
+    pub fn sample(&self, buf: &mut Vec<u8>) -> Result<Sample> {
+        let data = invoke_with_buf(buf, |buf, len| {
+            // Note: as with instance enumeration, this may result in windows writing to an unaligned buffer.
+            let buf_len = buf.len().try_into().unwrap();
+            let buf = buf.as_mut_ptr().cast::<PERF_DATA_HEADER>();
+            unsafe { PerfQueryCounterData(self.handle, Some(buf), buf_len, len) }
+        })?;
+
+        parse_counter_data(&self.counterset_id, data)
+    }
+}
+
+impl Drop for Query {
+    fn drop(&mut self) {
+        let res = unsafe { PerfCloseQueryHandle(self.handle) };
+        if let Err(e) = check(res) {
+            log::warn!("Failed to close query handle: {}", e);
+        }
+    }
+}
+
+/// Live perflib data, opening one query per counterset as needed.
+#[derive(Default)]
+pub struct Live {
+    buf: Vec<u8>,
+    queries: HashMap<GUID, Query>,
+}
+
+impl Source for Live {
+    fn sample(&mut self, counterset_id: &GUID) -> crate::error::Result<Sample> {
+        if legacy::object_index(counterset_id).is_some() {
+            return crate::fetch::legacy::sample(&mut self.buf, counterset_id);
+        }
+
+        let query = match self.queries.get(counterset_id) {
+            Some(query) => query,
+            None => {
+                let query = Query::open(counterset_id)?;
+                self.queries.entry(*counterset_id).or_insert(query)
+            }
+        };
+
+        Ok(query.sample(&mut self.buf)?)
+    }
+
+    fn instances(&mut self, counterset_id: &GUID) -> crate::error::Result<Vec<Instance>> {
+        if legacy::object_index(counterset_id).is_some() {
+            let sample = crate::fetch::legacy::sample(&mut self.buf, counterset_id)?;
+            return Ok(sample
+                .instances
+                .into_iter()
+                .filter_map(|i| i.instance)
+                .collect());
+        }
+
+        let instances = crate::fetch::instances::of_counterset(&mut self.buf, counterset_id)?;
+        Ok(instances.unwrap_or_default())
+    }
+
+    fn reopen(&mut self, counterset_id: &GUID) {
+        self.queries.remove(counterset_id);
+    }
+}
+
+fn check(res: u32) -> Result<()> {
+    match WIN32_ERROR(res) {
+        ERROR_SUCCESS => Ok(()),
+        e => Err(Error::from(e)),
+    }
+}
+
+/// Read a plain-old-data structure from the buffer at the given offset.
+///
+/// Only used with perflib structures, which are valid for all bit patterns.
+fn read_at<T: Copy>(buf: &[u8], offset: usize) -> T {
+    assert!(offset + mem::size_of::<T>() <= buf.len());
+    // SAFETY: bounds checked above, and T is valid for all bit patterns.
+    unsafe { buf.as_ptr().add(offset).cast::<T>().read_unaligned() }
+}
+
+/// Parse the result of `PerfQueryCounterData` for a query containing a single wildcard counter identifier.
+fn parse_counter_data(counterset_id: &GUID, buf: &[u8]) -> Result<Sample> {
+    // Everything here and below depends on the Windows API being implemented as documented.
+    // https://learn.microsoft.com/en-us/windows/win32/api/perflib/nf-perflib-perfquerycounterdata
+
+    // "The block includes a PERF_DATA_HEADER structure..."
+    let header = read_at::<PERF_DATA_HEADER>(buf, 0);
+    let mut offset = mem::size_of::<PERF_DATA_HEADER>();
+
+    // "...followed by a sequence of PERF_COUNTER_HEADER blocks."
+    // We only ever add one identifier, so there is only one of them.
+    if header.dwNumCounters != 1 {
+        return Err(Error::from(ERROR_INVALID_DATA));
+    }
+
+    let counter_header = read_at::<PERF_COUNTER_HEADER>(buf, offset);
+    offset += mem::size_of::<PERF_COUNTER_HEADER>();
+
+    let mut sample = Sample {
+        counterset_id: *counterset_id,
+        timestamp: header.PerfTimeStamp,
+        time_100ns: header.PerfTime100NSec,
+        frequency: header.PerfFreq,
+        counter_ids: Vec::new(),
+        instances: Vec::new(),
+    };
+
+    match counter_header.dwType {
+        PERF_ERROR_RETURN => return Err(Error::from(WIN32_ERROR(counter_header.dwStatus))),
+        // Single-instance countersets:
+        // "PERF_COUNTER_HEADER block followed by a PERF_MULTI_COUNTERS block and a sequence of PERF_COUNTER_DATA blocks."
+        PERF_MULTIPLE_COUNTERS => {
+            sample.counter_ids = parse_multi_counters(buf, &mut offset);
+            let values = parse_counter_values(buf, &mut offset, sample.counter_ids.len());
+            sample.instances.push(InstanceSample {
+                instance: None,
+                values,
+            });
+        }
+        // Multi-instance countersets:
+        // "PERF_COUNTER_HEADER block followed by a PERF_MULTI_COUNTERS block and a PERF_MULTI_INSTANCES block."
+        PERF_COUNTERSET => {
+            sample.counter_ids = parse_multi_counters(buf, &mut offset);
+
+            let multi_instances = read_at::<PERF_MULTI_INSTANCES>(buf, offset);
+            offset += mem::size_of::<PERF_MULTI_INSTANCES>();
+
+            for _ in 0..multi_instances.dwInstances {
+                // "Each instance is a PERF_INSTANCE_HEADER block followed by a sequence of PERF_COUNTER_DATA blocks."
+                let instance = read_at::<PERF_INSTANCE_HEADER>(buf, offset);
+                let name = decode_utf16_until_null(
+                    &buf[offset + mem::size_of::<PERF_INSTANCE_HEADER>()..],
+                );
+                offset += usize::try_from(instance.Size).unwrap();
+
+                let values = parse_counter_values(buf, &mut offset, sample.counter_ids.len());
+                sample.instances.push(InstanceSample {
+                    instance: Some(Instance {
+                        id: instance.InstanceId,
+                        name,
+                    }),
+                    values,
+                });
+            }
+        }
+        _ => return Err(Error::from(ERROR_INVALID_DATA)),
+    }
+
+    Ok(sample)
+}
+
+fn parse_multi_counters(buf: &[u8], offset: &mut usize) -> Vec<u32> {
+    // "PERF_MULTI_COUNTERS structure followed by a sequence of DWORD counter IDs."
+    let multi_counters = read_at::<PERF_MULTI_COUNTERS>(buf, *offset);
+
+    let first_id = *offset + mem::size_of::<PERF_MULTI_COUNTERS>();
+    let ids = (0..multi_counters.dwCounters as usize)
+        .map(|i| read_at::<u32>(buf, first_id + i * mem::size_of::<u32>()))
+        .collect();
+
+    // dwSize includes the ids and any padding.
+    *offset += usize::try_from(multi_counters.dwSize).unwrap();
+
+    ids
+}
+
+fn parse_counter_values(buf: &[u8], offset: &mut usize, count: usize) -> Vec<u64> {
+    (0..count)
+        .map(|_| {
+            // "PERF_COUNTER_DATA structure followed by the counter value."
+            let data = read_at::<PERF_COUNTER_DATA>(buf, *offset);
+            let value_offset = *offset + mem::size_of::<PERF_COUNTER_DATA>();
+
+            let value = match data.dwDataSize {
+                4 => read_at::<u32>(buf, value_offset).into(),
+                8 => read_at::<u64>(buf, value_offset),
+                // Text and other variable-length data isn't a number, so there's nothing sensible to record.
+                size => {
+                    log::debug!("Ignoring counter value of size {}", size);
+                    0
+                }
+            };
+
+            // dwSize includes the header, the value, and any padding.
+            *offset += usize::try_from(data.dwSize).unwrap();
+
+            value
+        })
+        .collect()
+}
@@ -0,0 +1,123 @@
+use crate::clock::Clock;
+use crate::error::Result;
+use crate::plog;
+use crate::source::Source;
+use crate::stats::Stats;
+use crate::store::Ring;
+use crate::types::CounterSet;
+use std::io::{Read, Seek, Write};
+use std::time::Duration;
+
+pub struct Options {
+    pub interval: Duration,
+    /// Stop after this many samples, instead of running until interrupted.
+    pub count: Option<u64>,
+}
+
+/// Where the raw values of each sample are appended, if anywhere.
+pub struct Logs<'a, W> {
+    pub log: Option<&'a mut plog::Writer<W>>,
+    pub ring: Option<&'a mut Ring>,
+}
+
+/// Periodically sample a counterset, appending the raw values to a log and/or a store's ring, and/or keeping
+/// statistics of them.
+///
+/// Statistics are printed whenever a window's worth of time has passed, and when sampling stops.
+pub fn run<W: Read + Write + Seek + plog::SetLen>(
+    counterset: &CounterSet,
+    options: &Options,
+    source: &mut dyn Source,
+    clock: &mut dyn Clock,
+    mut logs: Logs<W>,
+    mut stats: Option<&mut Stats>,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut samples = 0;
+    let mut reported = clock.now();
+    loop {
+        let sample = source.sample(&counterset.id)?;
+        let now = clock.now();
+        if let Some(log) = logs.log.as_mut() {
+            log.append(now, &sample)?;
+        }
+        if let Some(ring) = logs.ring.as_mut() {
+            ring.append(counterset, now, &sample)?;
+        }
+
+        samples += 1;
+        log::debug!("Recorded sample {}", samples);
+        let done = options.count.is_some_and(|count| samples >= count);
+
+        if let Some(stats) = stats.as_mut() {
+            stats.update(sample, now);
+            if done || now.duration_since(reported).unwrap_or_default() >= stats.window() {
+                writeln!(out, "{}", stats.report())?;
+                out.flush()?;
+                reported = now;
+            }
+        }
+
+        if done {
+            return Ok(());
+        }
+
+        clock.sleep(options.interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, at, contoso_disk, named, FakeClock, FakeSource};
+    use std::io::Cursor;
+
+    #[test]
+    fn records_each_sample_to_a_log() {
+        let counterset = contoso_disk();
+        let samples = (0..3)
+            .map(|second| {
+                let instances = named([("C:", [second as u64 * 100, 1])]);
+                testing::sample(counterset.id, second, &[0, 5], instances)
+            })
+            .collect();
+        let options = Options {
+            interval: Duration::from_secs(5),
+            count: Some(3),
+        };
+        let mut log = Cursor::new(Vec::new());
+        let mut writer = plog::Writer::open(&mut log, &counterset, options.interval).unwrap();
+        let logs = Logs {
+            log: Some(&mut writer),
+            ring: None,
+        };
+
+        run(
+            &counterset,
+            &options,
+            &mut FakeSource(samples),
+            &mut FakeClock::new(),
+            logs,
+            None,
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let (header, mut reader) = plog::Reader::new(log).unwrap();
+        assert_eq!(header.interval, options.interval);
+        assert_eq!(header.counterset.id, counterset.id);
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record().unwrap() {
+            let values = record.sample.instances[0].values.clone();
+            records.push((record.time, record.sample.timestamp, values));
+        }
+        assert_eq!(
+            records,
+            [
+                (at(0), 0, vec![0, 1]),
+                (at(5), 1000, vec![100, 1]),
+                (at(10), 2000, vec![200, 1]),
+            ]
+        );
+    }
+}
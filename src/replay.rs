@@ -0,0 +1,270 @@
+use crate::error::Result;
+use crate::identity::Series;
+use crate::plog;
+use crate::rollup::{self, Rollup};
+use crate::watch::{self, Watch};
+use std::io::{Read, Seek, Write};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Tables of cooked values, as printed by `watch`
+    Table,
+    /// One row per instance per sample, for spreadsheets and other tools
+    Csv,
+}
+
+pub struct Options {
+    pub filter: watch::Options,
+    /// Start from the first sample at or after this time.
+    pub from: Option<SystemTime>,
+    /// Skip this many samples from the start of the log, or from `from`.
+    pub skip: u64,
+    pub format: Format,
+    /// Roll the samples up into buckets of this length, e.g. a minute, rather than printing each one.
+    pub rollup: Option<Duration>,
+}
+
+/// Cook the samples in a log and print them.
+pub fn run<R: Read + Seek>(log: R, options: &Options, out: &mut dyn Write) -> Result<()> {
+    let (header, mut reader) = plog::Reader::new(log)?;
+    let counterset = &header.counterset;
+
+    if let Some(from) = options.from {
+        reader.seek(from)?;
+    }
+    for _ in 0..options.skip {
+        if !reader.skip()? {
+            break;
+        }
+    }
+
+    let filter = watch::Options {
+        interval: header.interval,
+        ..options.filter.clone()
+    };
+
+    match options.format {
+        Format::Table if options.rollup.is_some() => {
+            return Err("--rollup is only supported with --format csv".into());
+        }
+        Format::Table => {
+            let mut watch = Watch::new(counterset, &filter);
+            let mut frames = 0;
+            while let Some(record) = reader.next_record()? {
+                if frames > 0 {
+                    writeln!(out)?;
+                }
+                write!(out, "{}", watch.update(record.sample, record.time))?;
+                frames += 1;
+            }
+        }
+        Format::Csv if options.rollup.is_some() => {
+            let counters = filter.shown_counters(counterset);
+            rollup::write_csv_header(out, &counters)?;
+            let mut rollup = Rollup::new(counterset, counters, options.rollup.unwrap());
+            while let Some(record) = reader.next_record()? {
+                if let Some(bucket) = rollup.update(record.sample, record.time) {
+                    rollup::write_csv_rows(out, &bucket, &filter)?;
+                }
+            }
+            if let Some(bucket) = rollup.finish() {
+                rollup::write_csv_rows(out, &bucket, &filter)?;
+            }
+        }
+        Format::Csv => {
+            let counters = filter.shown_counters(counterset);
+
+            let mut columns = vec!["time", "instance"];
+            columns.extend(counters.iter().map(|c| c.name.as_str()));
+            write_csv_row(out, columns)?;
+
+            let mut series = Series::new(counterset);
+            while let Some(record) = reader.next_record()? {
+                let time = humantime::format_rfc3339_millis(record.time).to_string();
+                series.update(record.sample, record.time);
+                let sample = series.current().unwrap();
+                for (index, instance) in sample.instances.iter().enumerate() {
+                    if !filter.is_shown(instance) {
+                        continue;
+                    }
+                    let values = counters
+                        .iter()
+                        .map(|counter| {
+                            series
+                                .cook(counter, index)
+                                .map(|value| value.to_string())
+                                .unwrap_or_default()
+                        })
+                        .collect::<Vec<_>>();
+
+                    let mut row = vec![time.as_str()];
+                    row.push(instance.instance.as_ref().map_or("", |i| &i.name));
+                    row.extend(values.iter().map(String::as_str));
+                    write_csv_row(out, row)?;
+                }
+            }
+        }
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
+
+pub fn write_csv_row<'a>(
+    out: &mut dyn Write,
+    fields: impl IntoIterator<Item = &'a str>,
+) -> Result<()> {
+    let fields = fields
+        .into_iter()
+        .map(|field| match field.contains([',', '"', '\n', '\r']) {
+            true => format!("\"{}\"", field.replace('"', "\"\"")),
+            false => field.to_string(),
+        })
+        .collect::<Vec<_>>();
+    writeln!(out, "{}", fields.join(","))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glob::Glob;
+    use crate::testing::{self, at, counter};
+    use crate::types::{CounterSet, CounterType};
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    fn counterset() -> CounterSet {
+        testing::counterset(
+            testing::CONTOSO_DISK,
+            "Disk",
+            vec![
+                counter(0, "Reads/sec", CounterType::PERF_COUNTER_COUNTER, None),
+                counter(1, "Idle, %", CounterType::PERF_SAMPLE_FRACTION, Some(2)),
+                counter(2, "Idle Base", CounterType::PERF_SAMPLE_BASE, None),
+            ],
+        )
+    }
+
+    /// A log with one sample per second, each with (instance id, name, [reads, idle, idle base]).
+    fn log(samples: &[&[(u32, &str, [u64; 3])]]) -> Cursor<Vec<u8>> {
+        let mut log = Cursor::new(Vec::new());
+        let mut writer =
+            plog::Writer::open(&mut log, &counterset(), Duration::from_secs(1)).unwrap();
+        for (second, instances) in samples.iter().enumerate() {
+            let instances = instances
+                .iter()
+                .map(|&(id, name, values)| (testing::instance(id, name), values));
+            let sample = testing::sample(counterset().id, second as i64, &[0, 1, 2], instances);
+            writer.append(at(second as u64), &sample).unwrap();
+        }
+        log
+    }
+
+    fn replay(log: Cursor<Vec<u8>>, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(log, options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn options(format: Format) -> Options {
+        Options {
+            filter: watch::Options {
+                instance: None,
+                counter: None,
+                interval: Duration::ZERO,
+                count: None,
+                ansi: false,
+            },
+            from: None,
+            skip: 0,
+            format,
+            rollup: None,
+        }
+    }
+
+    #[test]
+    fn csv() {
+        let log = log(&[
+            &[(0, "C:", [100, 0, 0]), (1, "D:", [0, 0, 0])],
+            &[(0, "C:", [150, 3, 4]), (1, "D:", [10, 1, 4])],
+        ]);
+
+        assert_eq!(
+            replay(log, &options(Format::Csv)),
+            "\
+time,instance,Reads/sec,\"Idle, %\"
+2023-11-14T22:13:20.000Z,C:,,
+2023-11-14T22:13:20.000Z,D:,,
+2023-11-14T22:13:21.000Z,C:,50,75
+2023-11-14T22:13:21.000Z,D:,10,25
+"
+        );
+    }
+
+    #[test]
+    fn csv_rollup() {
+        let log = log(&[
+            &[(0, "C:", [100, 0, 0]), (1, "D:", [0, 0, 0])],
+            &[(0, "C:", [150, 3, 4]), (1, "D:", [10, 1, 4])],
+            &[(0, "C:", [170, 6, 8]), (1, "D:", [30, 2, 8])],
+        ]);
+        let rolled_up = Options {
+            rollup: Some(Duration::from_secs(2)),
+            ..options(Format::Csv)
+        };
+
+        assert_eq!(
+            replay(log, &rolled_up),
+            "\
+time,instance,Reads/sec,Reads/sec (min),Reads/sec (max),\"Idle, %\",\"Idle, % (min)\",\"Idle, % (max)\"
+2023-11-14T22:13:20.000Z,C:,50,50,50,75,75,75
+2023-11-14T22:13:20.000Z,D:,10,10,10,25,25,25
+2023-11-14T22:13:22.000Z,C:,20,20,20,75,75,75
+2023-11-14T22:13:22.000Z,D:,20,20,20,25,25,25
+"
+        );
+
+        let table = Options {
+            rollup: Some(Duration::from_secs(2)),
+            ..options(Format::Table)
+        };
+        assert!(run(self::log(&[]), &table, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn table_with_filter_and_skip() {
+        let log = log(&[
+            &[(0, "C:", [100, 0, 0]), (1, "D:", [0, 0, 0])],
+            &[(0, "C:", [150, 3, 4]), (1, "D:", [10, 1, 4])],
+            &[(0, "C:", [170, 6, 8]), (1, "D:", [30, 2, 8])],
+        ]);
+
+        let options = Options {
+            filter: watch::Options {
+                instance: Some(Glob::new("d*")),
+                counter: Some(Glob::new("reads*")),
+                ..options(Format::Table).filter
+            },
+            skip: 1,
+            ..options(Format::Table)
+        };
+
+        assert_eq!(
+            replay(log, &options),
+            "\
+Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0) at 2023-11-14T22:13:21Z, every 1s
+
+Instance  Reads/sec
+  D:            -
+
+Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0) at 2023-11-14T22:13:22Z, every 1s
+
+Instance  Reads/sec
+  D:           20
+"
+        );
+    }
+}
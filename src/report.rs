@@ -0,0 +1,268 @@
+//! Render the whole catalog as a single self-contained HTML page, for people without the tool or a Windows machine.
+
+use crate::types::{CounterSet, Provider};
+use std::fmt::Write as _;
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 0; display: flex; }
+nav { width: 20em; flex-shrink: 0; height: 100vh; overflow-y: auto; position: sticky; top: 0; padding: 1em;
+  box-sizing: border-box; background: #f4f4f4; border-right: 1px solid #ddd; }
+nav ul { list-style: none; padding-left: 1em; margin: 0.2em 0; }
+nav > ul { padding-left: 0; }
+main { padding: 1em 2em; min-width: 0; }
+input[type=search] { width: 100%; padding: 0.4em; box-sizing: border-box; margin-bottom: 1em; }
+section.counterset { border-top: 1px solid #ddd; padding-top: 0.5em; }
+table { border-collapse: collapse; margin: 0.5em 0; }
+th, td { text-align: left; vertical-align: top; padding: 0.2em 0.6em; border-bottom: 1px solid #eee; }
+code { font-size: 0.9em; }
+.muted { color: #777; }
+.missing { color: #b00; }
+:target { background: #fff3c4; }
+";
+
+/// Hides the countersets, and providers, which don't contain the search text.
+const SCRIPT: &str = "
+document.getElementById('search').addEventListener('input', event => {
+  const query = event.target.value.trim().toLowerCase();
+  for (const provider of document.querySelectorAll('section.provider')) {
+    let shown = 0;
+    for (const counterset of provider.querySelectorAll('section.counterset')) {
+      const hidden = query !== '' && !counterset.textContent.toLowerCase().includes(query)
+        && !provider.dataset.name.includes(query);
+      counterset.hidden = hidden;
+      document.getElementById('nav-' + counterset.id).hidden = hidden;
+      shown += hidden ? 0 : 1;
+    }
+    provider.hidden = query !== '' && shown === 0 && !provider.dataset.name.includes(query);
+    document.getElementById('nav-' + provider.id).hidden = provider.hidden;
+  }
+});
+";
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The anchor of a counter, by its position, as ids aren't always unique.
+fn counter_anchor(counterset: &CounterSet, index: usize) -> String {
+    format!("cs-{:?}-{}", counterset.id, index)
+}
+
+/// A link to the counter with an id, or a note that there isn't one.
+fn counter_link(counterset: &CounterSet, id: Option<u32>) -> String {
+    let Some(id) = id else {
+        return String::new();
+    };
+    match counterset.counters.iter().position(|c| c.id == id) {
+        Some(index) => format!(
+            "<a href=\"#{}\">{}</a>",
+            counter_anchor(counterset, index),
+            escape(&counterset.counters[index].name)
+        ),
+        None => format!("<span class=\"missing\">{} (missing)</span>", id),
+    }
+}
+
+pub fn html(all: &[Provider]) -> String {
+    let countersets = all.iter().map(|p| p.countersets.len()).sum::<usize>();
+    let counters = all
+        .iter()
+        .flat_map(|p| &p.countersets)
+        .map(|cs| cs.counters.len())
+        .sum::<usize>();
+
+    let mut out = String::new();
+    writeln!(out, "<!DOCTYPE html>").unwrap();
+    writeln!(out, "<html lang=\"en\">").unwrap();
+    writeln!(out, "<head>").unwrap();
+    writeln!(out, "<meta charset=\"utf-8\">").unwrap();
+    writeln!(out, "<title>Performance counters</title>").unwrap();
+    writeln!(out, "<style>{}</style>", STYLE).unwrap();
+    writeln!(out, "</head>").unwrap();
+    writeln!(out, "<body>").unwrap();
+
+    writeln!(out, "<nav>").unwrap();
+    writeln!(
+        out,
+        "<input type=\"search\" id=\"search\" placeholder=\"Search\" autofocus>"
+    )
+    .unwrap();
+    writeln!(out, "<ul>").unwrap();
+    for provider in all {
+        writeln!(
+            out,
+            "<li id=\"nav-p-{:?}\"><a href=\"#p-{:?}\">{}</a><ul>",
+            provider.id,
+            provider.id,
+            escape(&provider.name)
+        )
+        .unwrap();
+        for counterset in &provider.countersets {
+            writeln!(
+                out,
+                "<li id=\"nav-cs-{:?}\"><a href=\"#cs-{:?}\">{}</a></li>",
+                counterset.id,
+                counterset.id,
+                escape(&counterset.name)
+            )
+            .unwrap();
+        }
+        writeln!(out, "</ul></li>").unwrap();
+    }
+    writeln!(out, "</ul>").unwrap();
+    writeln!(out, "</nav>").unwrap();
+
+    writeln!(out, "<main>").unwrap();
+    writeln!(out, "<h1>Performance counters</h1>").unwrap();
+    writeln!(
+        out,
+        "<p class=\"muted\">{} providers, {} countersets, {} counters, generated by {} {}.</p>",
+        all.len(),
+        countersets,
+        counters,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )
+    .unwrap();
+    for provider in all {
+        writeln!(
+            out,
+            "<section class=\"provider\" id=\"p-{:?}\" data-name=\"{}\">",
+            provider.id,
+            escape(&provider.name.to_lowercase())
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "<h2>{} <code class=\"muted\">{:?}</code></h2>",
+            escape(&provider.name),
+            provider.id
+        )
+        .unwrap();
+        for counterset in &provider.countersets {
+            write_counterset(&mut out, counterset);
+        }
+        writeln!(out, "</section>").unwrap();
+    }
+    writeln!(out, "</main>").unwrap();
+
+    writeln!(out, "<script>{}</script>", SCRIPT).unwrap();
+    writeln!(out, "</body>").unwrap();
+    writeln!(out, "</html>").unwrap();
+    out
+}
+
+fn write_counterset(out: &mut String, counterset: &CounterSet) {
+    writeln!(
+        out,
+        "<section class=\"counterset\" id=\"cs-{:?}\">",
+        counterset.id
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "<h3>{} <code class=\"muted\">{:?}</code></h3>",
+        escape(&counterset.name),
+        counterset.id
+    )
+    .unwrap();
+    if !counterset.help.is_empty() {
+        writeln!(out, "<p>{}</p>", escape(&counterset.help)).unwrap();
+    }
+    let legacy = match counterset.legacy {
+        true => ", a legacy performance object",
+        false => "",
+    };
+    writeln!(
+        out,
+        "<p class=\"muted\">{:?}{}</p>",
+        counterset.instance_type, legacy
+    )
+    .unwrap();
+
+    writeln!(out, "<table>").unwrap();
+    writeln!(
+        out,
+        "<tr><th>Id</th><th>Name</th><th>Type</th><th>Base</th><th>Multi</th><th>Aggregate</th><th>Detail</th><th>Help</th></tr>"
+    )
+    .unwrap();
+    for (index, counter) in counterset.counters.iter().enumerate() {
+        writeln!(
+            out,
+            "<tr id=\"{}\"><td>{}</td><td>{}</td><td><code>{:?}</code></td><td>{}</td><td>{}</td><td>{:?}</td><td>{:?}</td><td>{}</td></tr>",
+            counter_anchor(counterset, index),
+            counter.id,
+            escape(&counter.name),
+            counter.counter_type,
+            counter_link(counterset, counter.base_counter_id.map(|id| id.get())),
+            counter_link(counterset, counter.multi_counter_id.map(|id| id.get())),
+            counter.aggregate_func,
+            counter.detail_level,
+            escape(&counter.help)
+        )
+        .unwrap();
+    }
+    writeln!(out, "</table>").unwrap();
+
+    match &counterset.instances {
+        Some(instances) if !instances.is_empty() => {
+            writeln!(out, "<p>Instances ({}):</p>", instances.len()).unwrap();
+            writeln!(out, "<ul>").unwrap();
+            for instance in instances {
+                writeln!(
+                    out,
+                    "<li>{} <span class=\"muted\">({})</span></li>",
+                    escape(&instance.name),
+                    instance.id
+                )
+                .unwrap();
+            }
+            writeln!(out, "</ul>").unwrap();
+        }
+        _ => writeln!(out, "<p class=\"muted\">No instances</p>").unwrap(),
+    }
+    writeln!(out, "</section>").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::catalog;
+
+    #[test]
+    fn renders_every_counterset_with_links_between_counters() {
+        let mut all = catalog();
+        all[0].countersets[1].counters[5].help = "Requests <waiting> & \"in flight\"".to_string();
+        all[0].countersets[1].counters[5].base_counter_id = crate::types::NonMaxU32::new(9);
+        let html = html(&all);
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("3 countersets, 13 counters"));
+        for counterset in all.iter().flat_map(|p| &p.countersets) {
+            assert!(html.contains(&format!("id=\"cs-{:?}\"", counterset.id)));
+            assert!(html.contains(&format!("href=\"#cs-{:?}\"", counterset.id)));
+        }
+        // Read Latency's base.
+        assert!(html.contains(
+            "<td><a href=\"#cs-8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0-2\">Read Latency Base</a></td>"
+        ));
+        assert!(html.contains("id=\"cs-8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0-2\""));
+        assert!(html.contains("<span class=\"missing\">9 (missing)</span>"));
+        assert!(html.contains("Requests &lt;waiting&gt; &amp; &quot;in flight&quot;"));
+        assert!(html.contains("<li>Wi-Fi <span class=\"muted\">(1)</span></li>"));
+        assert!(html.contains("<p class=\"muted\">No instances</p>"));
+        // Nothing to fetch from elsewhere.
+        assert!(!html.contains("src=") && !html.contains("http"));
+    }
+}
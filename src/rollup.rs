@@ -0,0 +1,377 @@
+//! Downsampling of a counterset's samples into buckets of a coarser resolution, e.g. a minute of samples a second.
+//!
+//! Each counter is rolled up the way it's cooked. Counters cooked from one sample, such as gauges and raw
+//! fractions, are averaged over their values in the bucket. Cumulative counters, such as rates and averages per
+//! operation, are cooked again from the changes in their raw values over the whole bucket, so that a bucket's rate
+//! is the same as if the counter had only been sampled at its start and end. Either way, the least and greatest of
+//! the values in the bucket are kept too.
+
+use crate::cook::{self, Deltas, Raw};
+use crate::error::Result;
+use crate::identity::{self, SeriesKey};
+use crate::replay::write_csv_row;
+use crate::types::{Counter, CounterSet, Instance, Sample};
+use crate::watch;
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+/// A counter's value over a bucket, for one instance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub value: f64,
+    /// The least and greatest of the values cooked from each sample in the bucket.
+    pub min: f64,
+    pub max: f64,
+}
+
+/// The rolled up values of every instance seen in a bucket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    pub start: SystemTime,
+    /// In the order they were first seen, each with a point per counter rolled up, if it had a value.
+    pub instances: Vec<(Option<Instance>, Vec<Option<Point>>)>,
+}
+
+/// What's been seen of one counter of one instance in a bucket.
+#[derive(Debug, Clone, Default)]
+struct Accumulator {
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    deltas: Deltas,
+}
+
+impl Accumulator {
+    fn add(&mut self, value: f64) {
+        if self.count == 0 {
+            (self.min, self.max) = (value, value);
+        }
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn point(&self, counter: &Counter) -> Option<Point> {
+        let value = match cook::is_cumulative(counter.counter_type) {
+            true => self.deltas.cook(counter.counter_type).ok()?,
+            false if self.count > 0 => self.sum / self.count as f64,
+            false => return None,
+        };
+        Some(match self.count {
+            0 => Point {
+                value,
+                min: value,
+                max: value,
+            },
+            _ => Point {
+                value,
+                min: self.min,
+                max: self.max,
+            },
+        })
+    }
+}
+
+/// Rolls successive samples of a counterset up into buckets of a fixed length.
+///
+/// Buckets start at whole multiples of their length since 1970, so that a minute's starts on the minute. The
+/// change between two samples goes in the bucket of the later one.
+pub struct Rollup<'a> {
+    counters: Vec<&'a Counter>,
+    resolution: Duration,
+    samples: identity::Series<'a>,
+    start: Option<SystemTime>,
+    /// In the order they were first seen in the bucket, with an accumulator per counter.
+    instances: Vec<(SeriesKey, Vec<Accumulator>)>,
+    index: HashMap<SeriesKey, usize>,
+}
+
+impl<'a> Rollup<'a> {
+    pub fn new(
+        counterset: &'a CounterSet,
+        counters: Vec<&'a Counter>,
+        resolution: Duration,
+    ) -> Self {
+        Self {
+            counters,
+            resolution,
+            samples: identity::Series::new(counterset),
+            start: None,
+            instances: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    pub fn counters(&self) -> &[&'a Counter] {
+        &self.counters
+    }
+
+    /// The start of the bucket a time falls in.
+    fn bucket(&self, time: SystemTime) -> SystemTime {
+        let since = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let resolution = self.resolution.as_nanos().max(1);
+        let start = since / resolution * resolution;
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(start as u64)
+    }
+
+    /// Remember a sample for the next one to be cooked against, without rolling it up.
+    pub fn skip(&mut self, sample: Sample, time: SystemTime) {
+        self.samples.update(sample, time);
+    }
+
+    /// Roll up a sample, returning the previous bucket if the sample is the first of a new one.
+    pub fn update(&mut self, sample: Sample, time: SystemTime) -> Option<Bucket> {
+        let start = self.bucket(time);
+        let finished = match self.start {
+            Some(current) if current != start => self.finish(),
+            _ => None,
+        };
+        self.start = Some(start);
+
+        self.samples.update(sample, time);
+        let sample = self.samples.current().unwrap();
+        let previous = self.samples.previous();
+        for (index, identity) in self.samples.identities().iter().enumerate() {
+            let next = self.instances.len();
+            let slot = *self.index.entry(identity.key.clone()).or_insert(next);
+            if slot == next {
+                let accumulators = vec![Accumulator::default(); self.counters.len()];
+                self.instances.push((identity.key.clone(), accumulators));
+            }
+            let instance = &sample.instances[index];
+            let before = identity
+                .previous
+                .zip(previous)
+                .map(|(i, previous)| (previous, &previous.instances[i]));
+
+            for (counter, accumulator) in self.counters.iter().zip(&mut self.instances[slot].1) {
+                if let Some(value) = self.samples.cook(counter, index) {
+                    accumulator.add(value);
+                }
+                if !cook::is_cumulative(counter.counter_type) {
+                    continue;
+                }
+                let current = Raw::from_sample(counter, sample, instance);
+                let before = before
+                    .and_then(|(sample, instance)| Raw::from_sample(counter, sample, instance));
+                if let (Some(before), Some(current)) = (before, current) {
+                    // A reset was already noticed by cooking, and leaves the interval out.
+                    let _ = accumulator
+                        .deltas
+                        .add(counter.counter_type, &before, &current);
+                }
+            }
+        }
+
+        finished
+    }
+
+    /// Finish the current bucket, e.g. once there are no more samples, even though it may not be full.
+    pub fn finish(&mut self) -> Option<Bucket> {
+        let start = self.start.take()?;
+        self.index.clear();
+        let instances = std::mem::take(&mut self.instances)
+            .into_iter()
+            .map(|(key, accumulators)| {
+                let points = self
+                    .counters
+                    .iter()
+                    .zip(&accumulators)
+                    .map(|(counter, accumulator)| accumulator.point(counter))
+                    .collect();
+                (key.instance, points)
+            })
+            .collect();
+        Some(Bucket { start, instances })
+    }
+}
+
+/// Write the header of a CSV file of buckets, with the value, least and greatest of each counter.
+pub fn write_csv_header(out: &mut dyn Write, counters: &[&Counter]) -> Result<()> {
+    let mut columns = vec!["time".to_string(), "instance".to_string()];
+    for counter in counters {
+        columns.push(counter.name.clone());
+        columns.push(format!("{} (min)", counter.name));
+        columns.push(format!("{} (max)", counter.name));
+    }
+    write_csv_row(out, columns.iter().map(String::as_str))
+}
+
+/// Write a row for each instance of a bucket which the filter shows.
+pub fn write_csv_rows(out: &mut dyn Write, bucket: &Bucket, filter: &watch::Options) -> Result<()> {
+    let time = humantime::format_rfc3339_millis(bucket.start).to_string();
+    for (instance, points) in &bucket.instances {
+        if !filter.shows(instance.as_ref()) {
+            continue;
+        }
+        let mut row = vec![
+            time.clone(),
+            instance
+                .as_ref()
+                .map(|i| i.name.clone())
+                .unwrap_or_default(),
+        ];
+        for point in points {
+            match point {
+                Some(point) => {
+                    row.extend([point.value, point.min, point.max].map(|v| v.to_string()))
+                }
+                None => row.extend([String::new(), String::new(), String::new()]),
+            }
+        }
+        write_csv_row(out, row.iter().map(String::as_str))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, contoso_disk, instance, CONTOSO_DISK};
+    use crate::types::CounterType;
+
+    /// On the hour.
+    fn at(second: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_699_999_200 + second)
+    }
+
+    /// Contoso Disk, with Bytes Read counting up and Read Latency as an average time per read.
+    fn counterset() -> CounterSet {
+        let mut counterset = contoso_disk();
+        counterset.counters[0].counter_type = CounterType::PERF_COUNTER_BULK_COUNT;
+        counterset
+    }
+
+    /// A sample of C: a second, with bytes read, total read time in ms, reads and queue length.
+    fn sample(second: u64, bytes: u64, latency: u64, reads: u64, length: u64) -> Sample {
+        let instances = [(instance(0, "C:"), [bytes, latency, reads, length])];
+        testing::sample(CONTOSO_DISK, second as i64, &[0, 1, 2, 5], instances)
+    }
+
+    /// Roll up a synthetic series with a sample every second, returning the points of each instance in each bucket.
+    fn roll_up(
+        resolution: u64,
+        samples: impl IntoIterator<Item = Sample>,
+    ) -> Vec<(u64, Vec<Vec<Option<Point>>>)> {
+        let counterset = counterset();
+        let counters = counterset
+            .counters
+            .iter()
+            .filter(|c| cook::is_displayable(c.counter_type))
+            .collect();
+        let mut rollup = Rollup::new(&counterset, counters, Duration::from_secs(resolution));
+        let mut buckets = Vec::new();
+        for (second, sample) in samples.into_iter().enumerate() {
+            buckets.extend(rollup.update(sample, at(second as u64)));
+        }
+        buckets.extend(rollup.finish());
+        buckets
+            .into_iter()
+            .map(|bucket| {
+                let start = bucket.start.duration_since(at(0)).unwrap().as_secs();
+                (
+                    start,
+                    bucket
+                        .instances
+                        .into_iter()
+                        .map(|(_, points)| points)
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn point(value: f64, min: f64, max: f64) -> Option<Point> {
+        Some(Point { value, min, max })
+    }
+
+    #[test]
+    fn averages_gauges_and_recomputes_rates() {
+        // Reading 1000 bytes/s for a minute, then 4000 bytes/s for a minute, with a read every other second taking
+        // 2ms and 4ms respectively, and a queue which grows by one a second and empties every ten.
+        let mut bytes = 0;
+        let mut latency = 0;
+        let mut reads = 0;
+        let samples = (0..120).map(|second| {
+            if second > 0 {
+                bytes += if second <= 60 { 1000 } else { 4000 };
+                if second % 2 == 0 {
+                    latency += if second <= 60 { 2 } else { 4 };
+                    reads += 1;
+                }
+            }
+            sample(second, bytes, latency, reads, second % 10)
+        });
+
+        // Bytes Read, Read Latency, % Idle Time (not sampled), Queue Length.
+        assert_eq!(
+            roll_up(60, samples),
+            [
+                (
+                    0,
+                    vec![vec![
+                        point(1000.0, 1000.0, 1000.0),
+                        point(58.0 / 1000.0 / 29.0, 0.002, 0.002),
+                        None,
+                        point(4.5, 0.0, 9.0),
+                    ]]
+                ),
+                (
+                    60,
+                    vec![vec![
+                        // Including the change from the last sample of the bucket before.
+                        point(3950.0, 1000.0, 4000.0),
+                        // Weighted by the reads, rather than averaging the average of each second.
+                        point(118.0 / 1000.0 / 30.0, 0.002, 0.004),
+                        None,
+                        point(4.5, 0.0, 9.0),
+                    ]]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_out_resets_and_keeps_tiers_consistent() {
+        // Counting up 100 bytes/s, except that the provider restarts after 30s and starts counting from zero.
+        let samples = || {
+            (0..7200).map(|second| {
+                let bytes = if second < 30 {
+                    second * 100
+                } else {
+                    (second - 30) * 100
+                };
+                sample(second, bytes, 0, 0, 1)
+            })
+        };
+
+        // The restarted provider's instance is a different one, and the change across the restart is left out.
+        let minutes = roll_up(60, samples());
+        assert_eq!(minutes.len(), 120);
+        assert_eq!(minutes[0].1.len(), 2);
+        assert!(minutes
+            .iter()
+            .flat_map(|(_, instances)| instances)
+            .all(|points| points[0] == point(100.0, 100.0, 100.0)));
+
+        // Rolling up by the hour gives the same rate as by the minute, with the same queue length.
+        let hours = roll_up(3600, samples());
+        assert_eq!(
+            hours.iter().map(|(start, _)| *start).collect::<Vec<_>>(),
+            [0, 3600]
+        );
+        for (_, instances) in hours {
+            let points = instances.last().unwrap();
+            assert_eq!(points[0], point(100.0, 100.0, 100.0));
+            assert_eq!(points[3], point(1.0, 1.0, 1.0));
+            // No reads, so no latency.
+            assert_eq!(points[1], None);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use crate::error::Result;
+use crate::types::Provider;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Bumped whenever the snapshot format changes incompatibly.
+const VERSION: u32 = 3;
+
+/// A saved copy of the counterset catalog, so it can be explored without access to the original machine.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<P> {
+    version: u32,
+    providers: P,
+}
+
+pub fn load(path: &Path) -> Result<Vec<Provider>> {
+    let file = File::open(path)?;
+    let snapshot: Snapshot<Vec<Provider>> = serde_json::from_reader(BufReader::new(file))?;
+
+    if snapshot.version != VERSION {
+        return Err(format!(
+            "unsupported snapshot version {} (expected {})",
+            snapshot.version, VERSION
+        )
+        .into());
+    }
+
+    Ok(snapshot.providers)
+}
+
+pub fn save(path: &Path, all: &[Provider]) -> Result<()> {
+    let snapshot = Snapshot {
+        version: VERSION,
+        providers: all,
+    };
+
+    let mut file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut file, &snapshot)?;
+    writeln!(file)?;
+    file.flush()?;
+
+    Ok(())
+}
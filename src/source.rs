@@ -0,0 +1,27 @@
+use crate::error::Result;
+use crate::types::{Instance, Sample};
+use windows::core::GUID;
+
+/// A source of raw counter data.
+///
+/// On Windows this is live perflib data; elsewhere (and in tests) it can be anything that produces samples.
+pub trait Source {
+    /// Collect the current values of every counter and instance of a counterset.
+    fn sample(&mut self, counterset_id: &GUID) -> Result<Sample>;
+
+    /// List the current instances of a counterset, which is empty for single-instance countersets.
+    ///
+    /// By default this takes a whole sample, but sources can usually enumerate instances more cheaply.
+    fn instances(&mut self, counterset_id: &GUID) -> Result<Vec<Instance>> {
+        let sample = self.sample(counterset_id)?;
+        Ok(sample
+            .instances
+            .into_iter()
+            .filter_map(|i| i.instance)
+            .collect())
+    }
+
+    /// Let go of anything held open for a counterset, e.g. a query handle whose provider has gone away, so that the
+    /// next sample starts afresh.
+    fn reopen(&mut self, _counterset_id: &GUID) {}
+}
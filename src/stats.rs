@@ -0,0 +1,367 @@
+//! Rolling statistics over the cooked values of a counterset's counters, e.g. to summarize a load test.
+
+use crate::cook;
+use crate::identity::{self, SeriesKey};
+use crate::types::{AggregateFunc, Counter, CounterSet, Sample};
+use crate::watch::format_value;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::time::{Duration, SystemTime};
+
+/// Statistics of the values of one counter (for one instance, or rolled up) within the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Population standard deviation.
+    pub stddev: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    /// Change per second from the first value to the last, or None with fewer than two values.
+    pub rate: Option<f64>,
+}
+
+/// Summarize a series of values, in the order they were sampled. Returns None if there are none.
+pub fn summarize(points: &[(SystemTime, f64)]) -> Option<Summary> {
+    let (first, last) = (points.first()?, points.last()?);
+
+    let mut sorted = points.iter().map(|&(_, v)| v).collect::<Vec<_>>();
+    sorted.sort_by(f64::total_cmp);
+
+    let count = sorted.len();
+    let mean = sorted.iter().sum::<f64>() / count as f64;
+    let variance = sorted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count as f64;
+    let elapsed = last
+        .0
+        .duration_since(first.0)
+        .unwrap_or_default()
+        .as_secs_f64();
+
+    Some(Summary {
+        count,
+        min: sorted[0],
+        max: sorted[count - 1],
+        mean,
+        stddev: variance.sqrt(),
+        p50: percentile(&sorted, 0.50),
+        p95: percentile(&sorted, 0.95),
+        p99: percentile(&sorted, 0.99),
+        rate: (elapsed > 0.0).then(|| (last.1 - first.1) / elapsed),
+    })
+}
+
+/// The value below which a fraction `p` of the sorted values lie, interpolating between the nearest two.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p * (sorted.len() - 1) as f64;
+    let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+}
+
+/// Combine the values of every instance of a counter, the way the counter says it should be aggregated.
+///
+/// Returns None if the counter doesn't say, or if there are no values.
+pub fn rollup(func: AggregateFunc, values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let total = values.iter().sum::<f64>();
+    match func {
+        AggregateFunc::Undefined => None,
+        AggregateFunc::Total => Some(total),
+        AggregateFunc::Avg => Some(total / values.len() as f64),
+        AggregateFunc::Min => values.iter().copied().reduce(f64::min),
+        AggregateFunc::Max => values.iter().copied().reduce(f64::max),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Label {
+    Instance(SeriesKey),
+    /// Every instance, combined by the counter's aggregate function.
+    Rollup(AggregateFunc),
+}
+
+struct Series {
+    /// Index into `Stats::counters`.
+    counter: usize,
+    label: Label,
+    points: VecDeque<(SystemTime, f64)>,
+}
+
+/// Keeps the cooked values of every displayable counter of every instance within a window of time.
+pub struct Stats<'a> {
+    counterset: &'a CounterSet,
+    counters: Vec<&'a Counter>,
+    window: Duration,
+    samples: identity::Series<'a>,
+    /// In the order they first appeared.
+    series: Vec<Series>,
+    index: HashMap<(usize, Label), usize>,
+}
+
+impl<'a> Stats<'a> {
+    pub fn new(counterset: &'a CounterSet, window: Duration) -> Self {
+        Self {
+            counterset,
+            counters: counterset
+                .counters
+                .iter()
+                .filter(|c| cook::is_displayable(c.counter_type))
+                .collect(),
+            window,
+            samples: identity::Series::new(counterset),
+            series: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Add a sample's values, and forget values which are now outside the window.
+    pub fn update(&mut self, sample: Sample, time: SystemTime) {
+        // Don't roll up instances which are already totals, as legacy objects often have.
+        let rolled_up = sample
+            .instances
+            .iter()
+            .map(|i| i.instance.as_ref().is_some_and(|i| i.name != "_Total"))
+            .collect::<Vec<_>>();
+        self.samples.update(sample, time);
+        let identities = self.samples.identities().to_vec();
+
+        for counter in 0..self.counters.len() {
+            let mut values = Vec::new();
+            for (index, identity) in identities.iter().enumerate() {
+                let Some(value) = self.samples.cook(self.counters[counter], index) else {
+                    continue;
+                };
+                self.push(counter, Label::Instance(identity.key.clone()), time, value);
+                if rolled_up[index] {
+                    values.push(value);
+                }
+            }
+
+            let func = self.counters[counter].aggregate_func;
+            if let Some(value) = rollup(func, &values) {
+                self.push(counter, Label::Rollup(func), time, value);
+            }
+        }
+
+        for series in &mut self.series {
+            while series
+                .points
+                .front()
+                .is_some_and(|&(t, _)| time.duration_since(t).unwrap_or_default() > self.window)
+            {
+                series.points.pop_front();
+            }
+        }
+        // Instances which have gone away, once all of their values are outside the window.
+        if self.series.iter().any(|s| s.points.is_empty()) {
+            self.series.retain(|s| !s.points.is_empty());
+            self.index = self
+                .series
+                .iter()
+                .enumerate()
+                .map(|(index, s)| ((s.counter, s.label.clone()), index))
+                .collect();
+        }
+    }
+
+    fn push(&mut self, counter: usize, label: Label, time: SystemTime, value: f64) {
+        let next = self.series.len();
+        let index = *self.index.entry((counter, label.clone())).or_insert(next);
+        if index == next {
+            self.series.push(Series {
+                counter,
+                label,
+                points: VecDeque::new(),
+            });
+        }
+        self.series[index].points.push_back((time, value));
+    }
+
+    /// Render a table of statistics for each counter and instance with values in the window.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{} ({:?}), over the last {}",
+            self.counterset.name,
+            self.counterset.id,
+            humantime::format_duration(self.window)
+        )
+        .unwrap();
+        writeln!(out).unwrap();
+
+        let mut series = self.series.iter().collect::<Vec<_>>();
+        // Rollups after the instances they're rolled up from.
+        series.sort_by_key(|s| (s.counter, matches!(s.label, Label::Rollup(_))));
+
+        let mut table = vec![[
+            "Counter", "Instance", "Samples", "Min", "Max", "Mean", "StdDev", "P50", "P95", "P99",
+            "Rate/s",
+        ]
+        .map(String::from)
+        .to_vec()];
+        for series in series {
+            let points = series.points.iter().copied().collect::<Vec<_>>();
+            let Some(summary) = summarize(&points) else {
+                continue;
+            };
+            let instance = match &series.label {
+                Label::Instance(key) => match &key.instance {
+                    Some(instance) => instance.name.clone(),
+                    None => "-".to_string(),
+                },
+                Label::Rollup(func) => format!("({:?})", func),
+            };
+            let mut row = vec![
+                self.counters[series.counter].name.clone(),
+                instance,
+                summary.count.to_string(),
+            ];
+            row.extend(
+                [
+                    summary.min,
+                    summary.max,
+                    summary.mean,
+                    summary.stddev,
+                    summary.p50,
+                    summary.p95,
+                    summary.p99,
+                ]
+                .map(format_value),
+            );
+            row.push(summary.rate.map_or("-".to_string(), format_value));
+            table.push(row);
+        }
+
+        let widths = (0..table[0].len())
+            .map(|col| {
+                table
+                    .iter()
+                    .map(|row| row[col].chars().count())
+                    .max()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        for row in &table {
+            let mut line = String::new();
+            for (col, cell) in row.iter().enumerate() {
+                match col {
+                    0 => write!(line, "{:<width$}", cell, width = widths[col]),
+                    1 => write!(line, "  {:<width$}", cell, width = widths[col]),
+                    _ => write!(line, "  {:>width$}", cell, width = widths[col]),
+                }
+                .unwrap();
+            }
+            writeln!(out, "{}", line.trim_end()).unwrap();
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{at, contoso_disk, instance, named, sample};
+
+    #[test]
+    fn summarizes_values() {
+        let points = (1..=100).map(|v| (at(v), v as f64)).collect::<Vec<_>>();
+        let summary = summarize(&points).unwrap();
+        assert_eq!(summary.count, 100);
+        assert_eq!((summary.min, summary.max, summary.mean), (1.0, 100.0, 50.5));
+        assert!((summary.stddev - 28.866).abs() < 0.001);
+        assert_eq!(summary.p50, 50.5);
+        assert!((summary.p95 - 95.05).abs() < 1e-9);
+        assert!((summary.p99 - 99.01).abs() < 1e-9);
+        assert_eq!(summary.rate, Some(1.0));
+
+        let single = summarize(&[(at(0), 7.0)]).unwrap();
+        assert_eq!((single.p50, single.p99, single.stddev), (7.0, 7.0, 0.0));
+        assert_eq!(single.rate, None);
+        assert_eq!(summarize(&[]), None);
+    }
+
+    #[test]
+    fn rolls_up_by_aggregate_function() {
+        let values = [1.0, 4.0, 7.0];
+        assert_eq!(rollup(AggregateFunc::Total, &values), Some(12.0));
+        assert_eq!(rollup(AggregateFunc::Avg, &values), Some(4.0));
+        assert_eq!(rollup(AggregateFunc::Min, &values), Some(1.0));
+        assert_eq!(rollup(AggregateFunc::Max, &values), Some(7.0));
+        assert_eq!(rollup(AggregateFunc::Undefined, &values), None);
+        assert_eq!(rollup(AggregateFunc::Total, &[]), None);
+    }
+
+    #[test]
+    fn reports_window() {
+        let mut counterset = contoso_disk();
+        counterset.counters[0].aggregate_func = AggregateFunc::Total;
+        counterset.counters[5].aggregate_func = AggregateFunc::Max;
+
+        let mut stats = Stats::new(&counterset, Duration::from_secs(10));
+        // Bytes read and queue length of each instance, the first falling out of the window.
+        let sample = |instances: &[(&str, [u64; 2])]| {
+            sample(counterset.id, 0, &[0, 5], named(instances.iter().copied()))
+        };
+        stats.update(sample(&[("a", [1000, 100])]), at(0));
+        stats.update(sample(&[("a", [10, 1]), ("b", [20, 5])]), at(5));
+        stats.update(sample(&[("a", [30, 3]), ("_Total", [99, 99])]), at(15));
+
+        assert_eq!(
+            stats.report(),
+            "\
+Contoso Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0), over the last 10s
+
+Counter       Instance  Samples  Min  Max  Mean  StdDev  P50    P95     P99  Rate/s
+Bytes Read    a               2   10   30    20      10   20     29  29.800       2
+Bytes Read    b               1   20   20    20       0   20     20      20       -
+Bytes Read    _Total          1   99   99    99       0   99     99      99       -
+Bytes Read    (Total)         2   30   30    30       0   30     30      30       0
+Queue Length  a               2    1    3     2       1    2  2.900   2.980   0.200
+Queue Length  b               1    5    5     5       0    5      5       5       -
+Queue Length  _Total          1   99   99    99       0   99     99      99       -
+Queue Length  (Max)           2    3    5     4       1    4  4.900   4.980  -0.200
+"
+        );
+    }
+
+    #[test]
+    fn forgets_instances_outside_the_window() {
+        let counterset = contoso_disk();
+        let mut stats = Stats::new(&counterset, Duration::from_secs(10));
+        // Queue length of each instance, by id and name.
+        let sample = |instances: &[(u32, &str)]| {
+            let instances = instances
+                .iter()
+                .map(|&(id, name)| (instance(id, name), [1]));
+            sample(counterset.id, 0, &[5], instances)
+        };
+        stats.update(sample(&[(1, "a"), (2, "b")]), at(0));
+        stats.update(sample(&[(2, "b"), (3, "c")]), at(5));
+        assert_eq!(stats.series.len(), 4);
+
+        stats.update(sample(&[(3, "c"), (1, "a")]), at(15));
+        let labels = stats
+            .series
+            .iter()
+            .map(|s| match &s.label {
+                Label::Instance(key) => key.instance.as_ref().unwrap().name.as_str(),
+                Label::Rollup(_) => "(Avg)",
+            })
+            .collect::<Vec<_>>();
+        // a's first values fell out of the window, and it came back as a new series.
+        assert_eq!(labels, ["b", "(Avg)", "c", "a"]);
+        assert_eq!(stats.index.len(), 4);
+        let a = &stats.series[3];
+        assert_eq!(stats.index[&(a.counter, a.label.clone())], 3);
+    }
+}
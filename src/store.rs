@@ -0,0 +1,610 @@
+//! A bounded local store of raw samples, for reading back later and for spooling while sinks are unreachable.
+//!
+//! Each counterset's samples are kept in a ring of segments under `<store>/<counterset GUID>/`, each a sample log
+//! as written by `sample --output` and named by the time of its first sample in milliseconds since 1970. A new
+//! segment is started once the current one reaches its share of the store's size, and the oldest segments are
+//! deleted to stay within the size and retention. Segments only appear under their name once their header has been
+//! written in full, samples are appended in one write each, and a sample cut short by a crash is discarded when the
+//! ring is next opened.
+//!
+//! Before a segment is deleted, its samples are rolled up into coarser tiers, e.g. a minute then an hour, which are
+//! each given an equal share of the store's size with the samples, so a store goes back much further at a lower
+//! resolution. Retention only applies to the samples themselves.
+
+use crate::catalog::CounterSetRef;
+use crate::error::Result;
+use crate::plog::{self, Record};
+use crate::types::{parse_guid, CounterSet, Sample};
+use serde::{Deserialize, Deserializer};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use windows::core::GUID;
+
+pub mod query;
+pub mod tier;
+
+/// A segment is started once the current one reaches this share of the size, so that the oldest samples can be
+/// deleted a share at a time.
+const SEGMENTS: u64 = 8;
+
+pub const DEFAULT_SIZE: &str = "16MiB";
+
+pub const DEFAULT_TIERS: &str = "1m,1h";
+
+/// Below this, segments would hold only a few samples each.
+const MIN_SIZE: u64 = 64 * 1024;
+
+/// Where samples are stored, and how many to keep.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Store {
+    pub path: PathBuf,
+    /// The most bytes to keep for each counterset.
+    #[serde(default = "default_size", deserialize_with = "size")]
+    pub size: u64,
+    /// How long to keep samples for, if not until they're pushed out by newer ones.
+    #[serde(default, deserialize_with = "retention")]
+    pub retention: Option<Duration>,
+    /// The resolutions to keep samples at once they've been deleted, each coarser than the last.
+    #[serde(default = "default_tiers", deserialize_with = "tiers")]
+    pub tiers: Vec<Duration>,
+}
+
+fn default_size() -> u64 {
+    parse_size(DEFAULT_SIZE).unwrap()
+}
+
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<u64, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_size(&s).map_err(serde::de::Error::custom)
+}
+
+fn default_tiers() -> Vec<Duration> {
+    parse_tiers(DEFAULT_TIERS).unwrap()
+}
+
+fn tiers<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<Duration>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_tiers(&s).map_err(serde::de::Error::custom)
+}
+
+fn retention<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Duration>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Parse a size in bytes, e.g. `65536`, `512KB` or `16MiB`.
+pub fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "kib" => 1 << 10,
+        "mb" => 1000 * 1000,
+        "mib" => 1 << 20,
+        "gb" => 1000 * 1000 * 1000,
+        "gib" => 1 << 30,
+        _ => return Err(format!("expected a size like 512KB or 16MiB, got `{}`", s)),
+    };
+    let size = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("expected a size like 512KB or 16MiB, got `{}`", s))?;
+    if size < MIN_SIZE {
+        return Err(format!(
+            "a store needs at least {} bytes, got {}",
+            MIN_SIZE, s
+        ));
+    }
+    Ok(size)
+}
+
+/// Parse the resolutions of a store's tiers, e.g. `1m,1h`, or none if empty.
+pub fn parse_tiers(s: &str) -> std::result::Result<Vec<Duration>, String> {
+    let tiers = s
+        .split(',')
+        .map(str::trim)
+        .filter(|tier| !tier.is_empty())
+        .map(|tier| humantime::parse_duration(tier).map_err(|e| format!("`{}`: {}", tier, e)))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    check_tiers(&tiers)?;
+    Ok(tiers)
+}
+
+/// Check that each tier is a whole number of seconds, and a multiple of the one before, so that its buckets are
+/// made of whole ones of the one before.
+pub fn check_tiers(tiers: &[Duration]) -> std::result::Result<(), String> {
+    let mut previous: Option<Duration> = None;
+    for &tier in tiers {
+        if tier.is_zero() || tier.subsec_nanos() != 0 {
+            return Err(format!(
+                "a tier needs a whole number of seconds, got {}",
+                humantime::format_duration(tier)
+            ));
+        }
+        if let Some(previous) = previous {
+            if tier <= previous || tier.as_secs() % previous.as_secs() != 0 {
+                return Err(format!(
+                    "each tier needs to be a multiple of the one before, got {} after {}",
+                    humantime::format_duration(tier),
+                    humantime::format_duration(previous)
+                ));
+            }
+        }
+        previous = Some(tier);
+    }
+    Ok(())
+}
+
+/// A segment of a ring, by the time of its first sample.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Segment {
+    start: u64,
+    path: PathBuf,
+    len: u64,
+}
+
+/// The extension of a segment until its header has been written.
+const PARTIAL: &str = "partial";
+
+impl Store {
+    fn ring_dir(&self, counterset_id: &GUID) -> PathBuf {
+        self.path.join(format!("{:?}", counterset_id))
+    }
+
+    /// Start appending samples of a counterset to its ring.
+    pub fn ring(&self, counterset: &CounterSet, interval: Duration) -> Result<Ring> {
+        let dir = self.ring_dir(&counterset.id);
+        fs::create_dir_all(&dir)?;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == PARTIAL) {
+                log::debug!("Deleting {}, which was never finished", path.display());
+                fs::remove_file(&path)?;
+            }
+        }
+        // Discard anything cut short in the last segment by a crash, rather than warning about it on every read.
+        if let Some(last) = segments(&dir)?.pop() {
+            let file = OpenOptions::new().read(true).write(true).open(&last.path)?;
+            if let Err(e) = plog::Writer::open(file, counterset, interval) {
+                log::warn!(
+                    "Deleting {}, which can't be read: {}",
+                    last.path.display(),
+                    e
+                );
+                fs::remove_file(&last.path)?;
+            }
+        }
+        Ok(Ring {
+            store: self.clone(),
+            dir,
+            interval,
+            current: None,
+        })
+    }
+
+    /// Read the samples of a counterset from a time onwards, oldest first.
+    pub fn records(&self, counterset_id: &GUID, from: SystemTime) -> Result<Records> {
+        records(&self.ring_dir(counterset_id), from)
+    }
+
+    /// The definition of a stored counterset as of its latest segment, by GUID or name.
+    pub fn counterset(&self, counterset: &CounterSetRef) -> Result<CounterSet> {
+        let mut found = Vec::new();
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(format!("No store at {}", self.path.display()).into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let Some(id) = entry.file_name().to_str().and_then(parse_guid) else {
+                continue;
+            };
+            if matches!(counterset, CounterSetRef::Id(wanted) if *wanted != id) {
+                continue;
+            }
+            let Some(last) = segments(&entry.path())?.pop() else {
+                continue;
+            };
+            let (header, _) = plog::Reader::new(BufReader::new(File::open(&last.path)?))?;
+            let matched = match counterset {
+                CounterSetRef::Id(_) => true,
+                CounterSetRef::Name(name) => header.counterset.name.eq_ignore_ascii_case(name),
+            };
+            if matched {
+                found.push(header.counterset);
+            }
+        }
+        match found.len() {
+            0 => Err(format!(
+                "Counterset {} not found in store {}",
+                counterset,
+                self.path.display()
+            )
+            .into()),
+            1 => Ok(found.pop().unwrap()),
+            _ => Err(format!(
+                "Counterset {} is ambiguous, use one of its GUIDs instead: {}",
+                counterset,
+                found
+                    .iter()
+                    .map(|cs| format!("{:?}", cs.id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into()),
+        }
+    }
+}
+
+/// The segments in a ring's directory, oldest first.
+fn segments(dir: &Path) -> Result<Vec<Segment>> {
+    files(dir, "plog")
+}
+
+/// The files in a directory named by the time of their first entry and with an extension, oldest first.
+fn files(dir: &Path, extension: &str) -> Result<Vec<Segment>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut segments = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let start = path
+            .extension()
+            .filter(|ext| *ext == extension)
+            .and_then(|_| path.file_stem()?.to_str()?.parse().ok());
+        if let Some(start) = start {
+            let len = fs::metadata(&path)?.len();
+            segments.push(Segment { start, path, len });
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Read the samples in a ring's directory from the segment with the last one before `from` onwards, for the ones
+/// after it to be cooked against.
+fn records(dir: &Path, from: SystemTime) -> Result<Records> {
+    let from = unix_millis(from);
+    let mut segments = segments(dir)?;
+    let first = segments
+        .iter()
+        .rposition(|s| s.start < from)
+        .unwrap_or_default();
+    Ok(Records {
+        segments: segments.drain(first..).map(|s| s.path).collect(),
+        reader: None,
+    })
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Appends samples of one counterset to its ring.
+pub struct Ring {
+    store: Store,
+    dir: PathBuf,
+    interval: Duration,
+    /// The segment being appended to, and how big it is.
+    current: Option<(plog::Writer<File>, u64)>,
+}
+
+impl Ring {
+    /// Append a sample, making room for it if need be.
+    ///
+    /// Each segment is started once the last one has reached its share of the size, so the ring can go over by a
+    /// sample per segment.
+    ///
+    /// A new segment is started each time a ring is opened, so that it has the counterset's current definition.
+    pub fn append(
+        &mut self,
+        counterset: &CounterSet,
+        time: SystemTime,
+        sample: &Sample,
+    ) -> Result<()> {
+        let segment_size = self.store.size / SEGMENTS;
+        if self
+            .current
+            .as_ref()
+            .is_none_or(|(_, len)| *len >= segment_size)
+        {
+            let path = self.dir.join(format!("{:013}.plog", unix_millis(time)));
+            if !path.exists() {
+                create_segment(&path, counterset, self.interval)?;
+            }
+            let file = OpenOptions::new().read(true).write(true).open(&path)?;
+            let writer = plog::Writer::open(file, counterset, self.interval)?;
+            let len = fs::metadata(&path)?.len();
+            self.current = Some((writer, len));
+            self.expire(time)?;
+        }
+
+        let (writer, len) = self.current.as_mut().unwrap();
+        *len += writer.append(time, sample)?;
+        Ok(())
+    }
+
+    /// Read the samples appended so far from a time onwards, oldest first.
+    pub fn records(&self, from: SystemTime) -> Result<Records> {
+        records(&self.dir, from)
+    }
+
+    /// Delete the oldest segments, apart from the current one, until the ring is within its size and retention,
+    /// rolling each up into the first tier first.
+    ///
+    /// Segments are counted by their size rather than their number, as a ring which is opened often, e.g. by each
+    /// `sample --store` or agent reload, has many small ones.
+    fn expire(&self, now: SystemTime) -> Result<()> {
+        let segments = segments(&self.dir)?;
+        let mut samples = segments.iter().map(|s| s.len).sum::<u64>();
+        let expired = self
+            .store
+            .retention
+            .and_then(|retention| now.checked_sub(retention))
+            .map_or(0, unix_millis);
+        for (index, segment) in segments.iter().enumerate().take(segments.len() - 1) {
+            // Rolling up a segment can grow the tiers, or shrink them by expiring their oldest files.
+            let full = samples + tier::len(&self.store, &self.dir)? > self.store.size;
+            // Everything in a segment is older than the start of the next one.
+            let old = segments[index + 1].start <= expired;
+            if !full && !old {
+                break;
+            }
+            tier::roll_up_segment(&self.store, &self.dir, &segments[index..])?;
+            log::debug!("Deleting {}", segment.path.display());
+            fs::remove_file(&segment.path)?;
+            samples -= segment.len;
+        }
+        Ok(())
+    }
+}
+
+/// Start a segment under another name, and only give it its own once its header is written and synced, so that a
+/// crash can't leave a segment which can't be read.
+fn create_segment(path: &Path, counterset: &CounterSet, interval: Duration) -> Result<()> {
+    let partial = path.with_extension(PARTIAL);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&partial)?;
+    plog::Writer::open(&mut file, counterset, interval)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Reads samples from the segments of a ring, oldest first.
+pub struct Records {
+    segments: VecDeque<PathBuf>,
+    reader: Option<plog::Reader<BufReader<File>>>,
+}
+
+impl Records {
+    pub fn next_record(&mut self) -> Result<Option<Record>> {
+        loop {
+            if let Some(reader) = &mut self.reader {
+                if let Some(record) = reader.next_record()? {
+                    return Ok(Some(record));
+                }
+            }
+            let Some(path) = self.segments.pop_front() else {
+                return Ok(None);
+            };
+            self.reader = match File::open(&path) {
+                Ok(file) => Some(plog::Reader::new(BufReader::new(file))?.1),
+                // Expired since the ring was listed.
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::testing::{self, at, contoso_disk, instance, CONTOSO_DISK};
+
+    /// A sample of Contoso Disk with bytes read and queue length for C:.
+    pub fn sample(second: u64, bytes: u64, length: u64) -> Sample {
+        let instances = [(instance(0, "C:"), [bytes, length])];
+        testing::sample(CONTOSO_DISK, second as i64, &[0, 5], instances)
+    }
+
+    /// A store in a new temporary directory.
+    pub fn store(name: &str, size: u64, retention: Option<Duration>) -> Store {
+        let path =
+            std::env::temp_dir().join(format!("perflib-explorer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        Store {
+            path,
+            size,
+            retention,
+            tiers: Vec::new(),
+        }
+    }
+
+    fn read_all(store: &Store, from: SystemTime) -> Vec<u64> {
+        let mut records = store.records(&contoso_disk().id, from).unwrap();
+        let mut seconds = Vec::new();
+        while let Some(record) = records.next_record().unwrap() {
+            seconds.push(record.sample.timestamp as u64 / 1000);
+        }
+        seconds
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("65536"), Ok(65536));
+        assert_eq!(parse_size("512KB"), Ok(512_000));
+        assert_eq!(parse_size("16MiB"), Ok(16 << 20));
+        assert_eq!(parse_size("1 gb"), Ok(1_000_000_000));
+        assert!(parse_size("16").unwrap_err().contains("at least"));
+        assert!(parse_size("16MB/s").is_err());
+        assert!(parse_size("99999999999GB").is_err());
+    }
+
+    #[test]
+    fn parses_tiers() {
+        let minutes = |m: &[u64]| {
+            m.iter()
+                .map(|m| Duration::from_secs(m * 60))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(parse_tiers("1m,1h"), Ok(minutes(&[1, 60])));
+        assert_eq!(parse_tiers(" 5m , 1h , 1d "), Ok(minutes(&[5, 60, 1440])));
+        assert_eq!(parse_tiers(""), Ok(Vec::new()));
+        assert!(parse_tiers("1h,1m").unwrap_err().contains("multiple"));
+        assert!(parse_tiers("1m,90s").unwrap_err().contains("multiple"));
+        assert!(parse_tiers("1500ms").unwrap_err().contains("whole number"));
+        assert!(parse_tiers("hourly").is_err());
+    }
+
+    #[test]
+    fn keeps_the_newest_samples_within_its_size() {
+        let store = store("ring", MIN_SIZE, None);
+        let counterset = contoso_disk();
+        let mut ring = store.ring(&counterset, Duration::from_secs(1)).unwrap();
+        for second in 0..2000 {
+            ring.append(&counterset, at(second), &sample(second, second, 0))
+                .unwrap();
+        }
+
+        let dir = store.ring_dir(&counterset.id);
+        let segments = segments(&dir).unwrap();
+        let total = segments
+            .iter()
+            .map(|s| fs::metadata(&s.path).unwrap().len())
+            .sum::<u64>();
+        // Give or take a sample per segment.
+        assert!(total <= MIN_SIZE + SEGMENTS * 100, "{} bytes", total);
+        assert!(
+            total >= MIN_SIZE / SEGMENTS * (SEGMENTS - 1),
+            "{} bytes",
+            total
+        );
+        // The oldest full segment is deleted once the newest one is started.
+        assert!(
+            (SEGMENTS - 1..=SEGMENTS).contains(&(segments.len() as u64)),
+            "{} segments",
+            segments.len()
+        );
+
+        let seconds = read_all(&store, SystemTime::UNIX_EPOCH);
+        assert_eq!(seconds.last(), Some(&1999));
+        assert!(seconds.windows(2).all(|w| w[1] == w[0] + 1));
+
+        // From the segment with the last sample before a time.
+        let recent = read_all(&store, at(1990) + Duration::from_millis(1));
+        assert!(recent[0] <= 1990 && recent.len() < seconds.len());
+
+        fs::remove_dir_all(&store.path).unwrap();
+    }
+
+    #[test]
+    fn expires_old_samples_and_survives_crashes() {
+        let store = store("expiry", MIN_SIZE, Some(Duration::from_secs(600)));
+        let counterset = contoso_disk();
+        let mut ring = store.ring(&counterset, Duration::from_secs(1)).unwrap();
+        ring.append(&counterset, at(0), &sample(0, 0, 0)).unwrap();
+        ring.append(&counterset, at(1), &sample(1, 1, 0)).unwrap();
+        drop(ring);
+
+        // Killed while appending.
+        let dir = store.ring_dir(&counterset.id);
+        let last = segments(&dir).unwrap().pop().unwrap();
+        let len = fs::metadata(&last.path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&last.path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let mut ring = store.ring(&counterset, Duration::from_secs(1)).unwrap();
+        ring.append(&counterset, at(700), &sample(700, 2, 0))
+            .unwrap();
+        assert_eq!(read_all(&store, SystemTime::UNIX_EPOCH), [0, 700]);
+
+        // The first segment is only deleted once everything in it is too old.
+        drop(ring);
+        let mut ring = store.ring(&counterset, Duration::from_secs(1)).unwrap();
+        ring.append(&counterset, at(1400), &sample(1400, 3, 0))
+            .unwrap();
+        assert_eq!(read_all(&store, SystemTime::UNIX_EPOCH), [700, 1400]);
+
+        assert_eq!(
+            store
+                .counterset(&"contoso disk".parse().unwrap())
+                .unwrap()
+                .id,
+            counterset.id
+        );
+        assert!(store.counterset(&"Contoso Cache".parse().unwrap()).is_err());
+
+        fs::remove_dir_all(&store.path).unwrap();
+    }
+
+    #[test]
+    fn keeps_samples_across_many_reopens() {
+        let store = store("reopen", MIN_SIZE, None);
+        let counterset = contoso_disk();
+        for second in 0..3 * SEGMENTS {
+            let mut ring = store.ring(&counterset, Duration::from_secs(1)).unwrap();
+            ring.append(&counterset, at(second), &sample(second, second, 0))
+                .unwrap();
+        }
+
+        let seconds = read_all(&store, SystemTime::UNIX_EPOCH);
+        assert_eq!(seconds, (0..3 * SEGMENTS).collect::<Vec<_>>());
+
+        fs::remove_dir_all(&store.path).unwrap();
+    }
+
+    #[test]
+    fn discards_segments_cut_short_while_starting() {
+        let store = store("torn", MIN_SIZE, None);
+        let counterset = contoso_disk();
+        let mut ring = store.ring(&counterset, Duration::from_secs(1)).unwrap();
+        ring.append(&counterset, at(0), &sample(0, 0, 0)).unwrap();
+        drop(ring);
+
+        // Killed while writing the header of a segment, before and after it got its name.
+        let dir = store.ring_dir(&counterset.id);
+        let partial = dir.join(format!("{:013}.{}", unix_millis(at(1)), PARTIAL));
+        fs::write(&partial, b"PLOG").unwrap();
+        let torn = dir.join(format!("{:013}.plog", unix_millis(at(2))));
+        fs::write(&torn, b"PLOG\x02\x00").unwrap();
+
+        let mut ring = store.ring(&counterset, Duration::from_secs(1)).unwrap();
+        assert!(!partial.exists() && !torn.exists());
+        ring.append(&counterset, at(3), &sample(3, 3, 0)).unwrap();
+        assert_eq!(read_all(&store, SystemTime::UNIX_EPOCH), [0, 3]);
+
+        fs::remove_dir_all(&store.path).unwrap();
+    }
+}
@@ -0,0 +1,382 @@
+//! Read a time range of a counterset's samples back out of a store, cooked.
+
+use super::{tier, Records, Store};
+use crate::error::Result;
+use crate::identity::Series;
+use crate::replay::write_csv_row;
+use crate::rollup::{self, Bucket, Point, Rollup};
+use crate::types::{Counter, CounterSet};
+use crate::watch;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// One row per instance per sample, for spreadsheets and other tools
+    Csv,
+    /// An array with an object per instance per sample
+    Json,
+}
+
+pub struct Options {
+    pub filter: watch::Options,
+    pub from: Option<SystemTime>,
+    pub to: Option<SystemTime>,
+    pub format: Format,
+    /// Roll the samples up into buckets of this length, e.g. an hour, rather than printing each one.
+    pub rollup: Option<Duration>,
+    /// Read the buckets of this length that the store has kept, rather than its samples.
+    pub tier: Option<Duration>,
+}
+
+/// Parse a time as RFC 3339, e.g. `2023-11-14T22:13:20Z`, or as a duration before now, e.g. `1h`.
+pub fn parse_time(s: &str) -> std::result::Result<SystemTime, String> {
+    if let Ok(ago) = humantime::parse_duration(s) {
+        return SystemTime::now()
+            .checked_sub(ago)
+            .ok_or_else(|| format!("`{}` ago is too long ago", s));
+    }
+    humantime::parse_rfc3339_weak(s).map_err(|_| {
+        format!(
+            "expected a time like 2023-11-14T22:13:20Z or 1h, got `{}`",
+            s
+        )
+    })
+}
+
+/// Cook the stored samples of a counterset between two times, and print them.
+///
+/// Samples from just before the range are read too, so that the first ones in it have a value.
+pub fn run(
+    store: &Store,
+    counterset: &CounterSet,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let counters = options.filter.shown_counters(counterset);
+    let from = options.from.unwrap_or(SystemTime::UNIX_EPOCH);
+    if let Some(resolution) = options.tier {
+        let dir = tier::dir(&store.ring_dir(&counterset.id), resolution);
+        if !dir.exists() {
+            return Err(format!(
+                "Store {} has no {} tier for {}",
+                store.path.display(),
+                humantime::format_duration(resolution),
+                counterset.name
+            )
+            .into());
+        }
+        let rows = tier::read(&dir, from, options.to)?;
+        return print_buckets(tier::buckets(rows, &counters), &counters, options, out);
+    }
+    let mut records = store.records(&counterset.id, from)?;
+    if let Some(resolution) = options.rollup {
+        let rollup = Rollup::new(counterset, counters, resolution);
+        return roll_up(rollup, &mut records, from, options, out);
+    }
+    let mut series = Series::new(counterset);
+
+    match options.format {
+        Format::Csv => {
+            let mut columns = vec!["time", "instance"];
+            columns.extend(counters.iter().map(|c| c.name.as_str()));
+            write_csv_row(out, columns)?;
+        }
+        Format::Json => write!(out, "[")?,
+    }
+
+    let mut rows = 0;
+    while let Some(record) = records.next_record()? {
+        if options.to.is_some_and(|to| record.time > to) {
+            break;
+        }
+        let time = record.time;
+        series.update(record.sample, time);
+        if time < from {
+            continue;
+        }
+
+        let formatted = humantime::format_rfc3339_millis(time).to_string();
+        let sample = series.current().unwrap();
+        for (index, instance) in sample.instances.iter().enumerate() {
+            if !options.filter.is_shown(instance) {
+                continue;
+            }
+            let name = instance.instance.as_ref().map(|i| i.name.as_str());
+            let values = counters
+                .iter()
+                .map(|counter| series.cook(counter, index))
+                .collect::<Vec<_>>();
+            match options.format {
+                Format::Csv => {
+                    let values = values
+                        .iter()
+                        .map(|value| value.map(|v| v.to_string()).unwrap_or_default())
+                        .collect::<Vec<_>>();
+                    let mut row = vec![formatted.as_str(), name.unwrap_or_default()];
+                    row.extend(values.iter().map(String::as_str));
+                    write_csv_row(out, row)?;
+                }
+                Format::Json => {
+                    // Written by hand to keep the counters in order.
+                    let values = counters
+                        .iter()
+                        .zip(&values)
+                        .map(|(counter, value)| {
+                            Ok(format!(
+                                "{}: {}",
+                                serde_json::to_string(&counter.name)?,
+                                serde_json::to_string(value)?
+                            ))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    write!(
+                        out,
+                        "{}\n  {{\"time\": {}, \"instance\": {}, \"values\": {{{}}}}}",
+                        if rows == 0 { "" } else { "," },
+                        serde_json::to_string(&formatted)?,
+                        serde_json::to_string(&name)?,
+                        values.join(", ")
+                    )?;
+                }
+            }
+            rows += 1;
+        }
+    }
+
+    if options.format == Format::Json {
+        writeln!(out, "{}]", if rows == 0 { "" } else { "\n" })?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Roll up the samples which are within the range, and print the buckets.
+fn roll_up(
+    mut rollup: Rollup,
+    records: &mut Records,
+    from: SystemTime,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut buckets = Vec::new();
+    while let Some(record) = records.next_record()? {
+        if options.to.is_some_and(|to| record.time > to) {
+            break;
+        }
+        if record.time < from {
+            rollup.skip(record.sample, record.time);
+        } else if let Some(bucket) = rollup.update(record.sample, record.time) {
+            buckets.push(bucket);
+        }
+    }
+    buckets.extend(rollup.finish());
+    print_buckets(buckets, rollup.counters(), options, out)
+}
+
+fn print_buckets(
+    buckets: Vec<Bucket>,
+    counters: &[&Counter],
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<()> {
+    match options.format {
+        Format::Csv => rollup::write_csv_header(out, counters)?,
+        Format::Json => write!(out, "[")?,
+    }
+
+    let mut rows = 0;
+    for bucket in buckets {
+        if options.format == Format::Csv {
+            rollup::write_csv_rows(out, &bucket, &options.filter)?;
+            continue;
+        }
+        let time = humantime::format_rfc3339_millis(bucket.start).to_string();
+        for (instance, points) in &bucket.instances {
+            if !options.filter.shows(instance.as_ref()) {
+                continue;
+            }
+            // Written by hand to keep the counters in order.
+            let object = |value: fn(&Point) -> f64| -> Result<String> {
+                let fields = counters
+                    .iter()
+                    .zip(points)
+                    .map(|(counter, point)| {
+                        Ok(format!(
+                            "{}: {}",
+                            serde_json::to_string(&counter.name)?,
+                            serde_json::to_string(&point.as_ref().map(value))?
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(format!("{{{}}}", fields.join(", ")))
+            };
+            write!(
+                out,
+                "{}\n  {{\"time\": {}, \"instance\": {}, \"values\": {}, \"min\": {}, \"max\": {}}}",
+                if rows == 0 { "" } else { "," },
+                serde_json::to_string(&time)?,
+                serde_json::to_string(&instance.as_ref().map(|i| &i.name))?,
+                object(|p| p.value)?,
+                object(|p| p.min)?,
+                object(|p| p.max)?
+            )?;
+            rows += 1;
+        }
+    }
+
+    if options.format == Format::Json {
+        writeln!(out, "{}]", if rows == 0 { "" } else { "\n" })?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glob::Glob;
+    use crate::store::tests::{sample, store};
+    use crate::testing::{at, contoso_disk};
+    use std::fs;
+    use std::time::Duration;
+
+    fn query(store: &Store, counterset: &CounterSet, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(store, counterset, options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn reads_a_time_range() {
+        let store = store("query", 1 << 20, None);
+        let mut counterset = contoso_disk();
+        // Bytes Read counts up, so its first value in the range comes from the sample before it.
+        counterset.counters[0].counter_type = crate::types::CounterType::PERF_COUNTER_COUNTER;
+        let mut ring = store.ring(&counterset, Duration::from_secs(1)).unwrap();
+        for second in 0..5 {
+            ring.append(
+                &counterset,
+                at(second),
+                &sample(second, second * 100, second),
+            )
+            .unwrap();
+        }
+
+        let mut options = Options {
+            filter: watch::Options {
+                instance: Some(Glob::new("c*")),
+                counter: Some(Glob::new("*e*")),
+                interval: Duration::ZERO,
+                count: None,
+                ansi: false,
+            },
+            from: Some(at(2)),
+            to: Some(at(3)),
+            format: Format::Csv,
+            rollup: None,
+            tier: None,
+        };
+        assert_eq!(
+            query(&store, &counterset, &options),
+            "\
+time,instance,Bytes Read,Read Latency,% Idle Time,Queue Length
+2023-11-14T22:13:22.000Z,C:,100,,,2
+2023-11-14T22:13:23.000Z,C:,100,,,3
+"
+        );
+
+        options.format = Format::Json;
+        options.filter.counter = Some(Glob::new("queue*"));
+        options.to = None;
+        assert_eq!(
+            query(&store, &counterset, &options),
+            r#"[
+  {"time": "2023-11-14T22:13:22.000Z", "instance": "C:", "values": {"Queue Length": 2.0}},
+  {"time": "2023-11-14T22:13:23.000Z", "instance": "C:", "values": {"Queue Length": 3.0}},
+  {"time": "2023-11-14T22:13:24.000Z", "instance": "C:", "values": {"Queue Length": 4.0}}
+]
+"#
+        );
+
+        // Buckets of 2s from 22:13:22, with the rate into the first cooked from the sample before it.
+        options.rollup = Some(Duration::from_secs(2));
+        options.filter.counter = Some(Glob::new("bytes*"));
+        assert_eq!(
+            query(&store, &counterset, &options),
+            r#"[
+  {"time": "2023-11-14T22:13:22.000Z", "instance": "C:", "values": {"Bytes Read": 100.0}, "min": {"Bytes Read": 100.0}, "max": {"Bytes Read": 100.0}},
+  {"time": "2023-11-14T22:13:24.000Z", "instance": "C:", "values": {"Bytes Read": 100.0}, "min": {"Bytes Read": 100.0}, "max": {"Bytes Read": 100.0}}
+]
+"#
+        );
+        options.format = Format::Csv;
+        options.filter.counter = Some(Glob::new("queue*"));
+        assert_eq!(
+            query(&store, &counterset, &options),
+            "\
+time,instance,Queue Length,Queue Length (min),Queue Length (max)
+2023-11-14T22:13:22.000Z,C:,2.5,2,3
+2023-11-14T22:13:24.000Z,C:,4,4,4
+"
+        );
+        options.rollup = None;
+
+        options.format = Format::Json;
+        options.from = Some(at(10));
+        assert_eq!(query(&store, &counterset, &options), "[]\n");
+
+        assert_eq!(parse_time("2023-11-14T22:13:20Z"), Ok(at(0)));
+        assert!(parse_time("1h").unwrap() < SystemTime::now());
+        assert!(parse_time("yesterday").is_err());
+
+        fs::remove_dir_all(&store.path).unwrap();
+    }
+
+    #[test]
+    fn reads_a_tier() {
+        let mut store = store("query-tier", 1 << 20, None);
+        store.tiers = vec![Duration::from_secs(60)];
+        let counterset = contoso_disk();
+        let ring = store.ring_dir(&counterset.id);
+        let row = |minute: u64, bytes: f64| tier::Row {
+            start: (1_700_000_040 + minute * 60) * 1000,
+            instance: Some(crate::types::Instance {
+                id: 0,
+                name: "C:".to_string(),
+            }),
+            points: [(0, (bytes, 0.0, 2.0 * bytes))].into(),
+        };
+        tier::append(&store, &ring, 0, &[row(0, 1.0), row(1, 2.0), row(2, 3.0)]).unwrap();
+
+        let mut options = Options {
+            filter: watch::Options {
+                instance: None,
+                counter: Some(Glob::new("bytes*")),
+                interval: Duration::ZERO,
+                count: None,
+                ansi: false,
+            },
+            from: Some(at(100)),
+            to: None,
+            format: Format::Json,
+            rollup: None,
+            tier: Some(Duration::from_secs(60)),
+        };
+        assert_eq!(
+            query(&store, &counterset, &options),
+            r#"[
+  {"time": "2023-11-14T22:15:00.000Z", "instance": "C:", "values": {"Bytes Read": 2.0}, "min": {"Bytes Read": 0.0}, "max": {"Bytes Read": 4.0}},
+  {"time": "2023-11-14T22:16:00.000Z", "instance": "C:", "values": {"Bytes Read": 3.0}, "min": {"Bytes Read": 0.0}, "max": {"Bytes Read": 6.0}}
+]
+"#
+        );
+
+        options.tier = Some(Duration::from_secs(3600));
+        let mut out = Vec::new();
+        let error = run(&store, &counterset, &options, &mut out).unwrap_err();
+        assert!(error.to_string().contains("has no 1h tier"), "{}", error);
+
+        fs::remove_dir_all(&store.path).unwrap();
+    }
+}
@@ -0,0 +1,439 @@
+//! Rolled up samples, kept for longer than the samples themselves, e.g. for a minute then for an hour.
+//!
+//! Each tier of a ring is a ring of its own under `<ring>/<resolution>/`, e.g. `1m/`, of files of JSON lines named
+//! like the ring's segments, with a line per instance per bucket. Before the oldest segment of the ring is deleted,
+//! its samples are rolled up into the first tier, and before the oldest file of a tier is deleted, its rows are rolled
+//! up into the next. A coarser tier averages the values of the finer one, which for cumulative counters gives the same
+//! rate as rolling the samples up would, since the finer buckets are all the same length.
+//!
+//! Only whole buckets are rolled up, and each only once, so a tier carries on from the bucket after its last row.
+
+use super::{files, unix_millis, Records, Segment, Store, SEGMENTS};
+use crate::error::Result;
+use crate::plog;
+use crate::rollup::{Bucket, Point, Rollup};
+use crate::types::{Counter, Instance};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const EXTENSION: &str = "jsonl";
+
+/// A bucket of one instance, as a line of a tier.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Row {
+    /// The start of the bucket, in milliseconds since 1970.
+    pub start: u64,
+    pub instance: Option<Instance>,
+    /// The value, least and greatest value of each counter which had one, by id.
+    pub points: BTreeMap<u32, (f64, f64, f64)>,
+}
+
+/// The directory of one of a ring's tiers.
+pub fn dir(ring: &Path, resolution: Duration) -> PathBuf {
+    let name = humantime::format_duration(resolution).to_string();
+    ring.join(name.replace(' ', ""))
+}
+
+/// The rows of a bucket, one per instance.
+fn rows(bucket: &Bucket, counters: &[&Counter]) -> Vec<Row> {
+    bucket
+        .instances
+        .iter()
+        .map(|(instance, points)| Row {
+            start: unix_millis(bucket.start),
+            instance: instance.clone(),
+            points: counters
+                .iter()
+                .zip(points)
+                .filter_map(|(counter, point)| {
+                    let point = (*point)?;
+                    Some((counter.id, (point.value, point.min, point.max)))
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// The buckets of a tier's rows, in order, with a point for each of the counters.
+pub fn buckets(rows: Vec<Row>, counters: &[&Counter]) -> Vec<Bucket> {
+    let mut buckets: Vec<Bucket> = Vec::new();
+    for row in rows {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_millis(row.start);
+        if buckets.last().is_none_or(|b| b.start != start) {
+            buckets.push(Bucket {
+                start,
+                instances: Vec::new(),
+            });
+        }
+        let points = counters
+            .iter()
+            .map(|counter| {
+                let &(value, min, max) = row.points.get(&counter.id)?;
+                Some(Point { value, min, max })
+            })
+            .collect();
+        let bucket = buckets.last_mut().unwrap();
+        bucket.instances.push((row.instance, points));
+    }
+    buckets
+}
+
+/// Roll rows up into buckets of a coarser resolution in milliseconds, averaging their values.
+fn combine(rows: &[Row], resolution: u64) -> Vec<Row> {
+    // Each combined row, with how many values each of its counters has.
+    let mut combined: Vec<(Row, BTreeMap<u32, u32>)> = Vec::new();
+    for row in rows {
+        let start = row.start / resolution * resolution;
+        // Rows are in order, so a bucket which has been seen is near the end.
+        let index = combined
+            .iter()
+            .rposition(|(r, _)| r.start == start && r.instance == row.instance)
+            .unwrap_or_else(|| {
+                let row = Row {
+                    start,
+                    instance: row.instance.clone(),
+                    points: BTreeMap::new(),
+                };
+                combined.push((row, BTreeMap::new()));
+                combined.len() - 1
+            });
+        let (sum, counts) = &mut combined[index];
+        for (&id, &(value, min, max)) in &row.points {
+            *counts.entry(id).or_default() += 1;
+            sum.points
+                .entry(id)
+                .and_modify(|p| *p = (p.0 + value, p.1.min(min), p.2.max(max)))
+                .or_insert((value, min, max));
+        }
+    }
+    combined
+        .into_iter()
+        .map(|(mut row, counts)| {
+            for (id, point) in &mut row.points {
+                point.0 /= f64::from(counts[id]);
+            }
+            row
+        })
+        .collect()
+}
+
+/// The most bytes to keep of each tier, which is an equal share of the store's size with the samples.
+fn share(store: &Store) -> u64 {
+    store.size / (store.tiers.len() as u64 + 1)
+}
+
+/// The bytes kept in all of a ring's tiers, for the samples to be kept within what's left of the store's size.
+pub(super) fn len(store: &Store, ring: &Path) -> Result<u64> {
+    let mut len = 0;
+    for &resolution in &store.tiers {
+        len += files(&dir(ring, resolution), EXTENSION)?
+            .iter()
+            .map(|f| f.len)
+            .sum::<u64>();
+    }
+    Ok(len)
+}
+
+/// Read the rows of a tier from a time onwards, up to another.
+pub fn read(dir: &Path, from: SystemTime, to: Option<SystemTime>) -> Result<Vec<Row>> {
+    let (from, to) = (unix_millis(from), to.map(unix_millis));
+    let files = files(dir, EXTENSION)?;
+    let first = files
+        .iter()
+        .rposition(|f| f.start <= from)
+        .unwrap_or_default();
+    let mut rows = read_files(&files[first..])?;
+    rows.retain(|row| row.start >= from && to.is_none_or(|to| row.start <= to));
+    Ok(rows)
+}
+
+fn read_files(files: &[Segment]) -> Result<Vec<Row>> {
+    let mut rows = Vec::new();
+    for file in files {
+        let text = match fs::read_to_string(&file.path) {
+            Ok(text) => text,
+            // Rolled up into the next tier since the tier was listed.
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for line in text.lines() {
+            match serde_json::from_str(line) {
+                Ok(row) => rows.push(row),
+                Err(e) => log::warn!("Ignoring a row of {}: {}", file.path.display(), e),
+            }
+        }
+    }
+    Ok(rows)
+}
+
+/// The start of the last row in a tier's files, in milliseconds since 1970.
+fn last_start(files: &[Segment]) -> Result<Option<u64>> {
+    for file in files.iter().rev() {
+        if let Some(row) = read_files(std::slice::from_ref(file))?.last() {
+            return Ok(Some(row.start));
+        }
+    }
+    Ok(None)
+}
+
+/// Roll the oldest of a ring's segments up into its first tier, before the segment is deleted.
+///
+/// The newer segments are read up to the end of the bucket that the next one starts in, so that the bucket has all of
+/// its samples, and the rest is left for when the next segment is deleted in turn.
+pub(super) fn roll_up_segment(store: &Store, ring: &Path, segments: &[Segment]) -> Result<()> {
+    let Some(&resolution) = store.tiers.first() else {
+        return Ok(());
+    };
+    let dir = dir(ring, resolution);
+    let millis = resolution.as_millis() as u64;
+    let from = last_start(&files(&dir, EXTENSION)?)?.map_or(0, |start| start + millis);
+    let until = segments[1].start.next_multiple_of(millis);
+    if until <= from {
+        return Ok(());
+    }
+
+    let file = BufReader::new(File::open(&segments[0].path)?);
+    let (header, _) = plog::Reader::new(file)?;
+    let counterset = header.counterset;
+    let mut rollup = Rollup::new(
+        &counterset,
+        counterset.counters.iter().collect(),
+        resolution,
+    );
+    let mut records = Records {
+        segments: segments.iter().map(|s| s.path.clone()).collect(),
+        reader: None,
+    };
+    let mut rows = Vec::new();
+    // The bucket that the samples run out in isn't finished, so it's left out.
+    while let Some(record) = records.next_record()? {
+        let time = unix_millis(record.time);
+        if time < from {
+            rollup.skip(record.sample, record.time);
+            continue;
+        }
+        if let Some(bucket) = rollup.update(record.sample, record.time) {
+            rows.extend(self::rows(&bucket, rollup.counters()));
+        }
+        if time >= until {
+            break;
+        }
+    }
+    append(store, ring, 0, &rows)
+}
+
+/// Roll the oldest file of a tier up into the next tier, before the file is deleted.
+///
+/// Like segments, newer files are read up to the end of the bucket that the next one starts in, but a bucket is only
+/// finished once the tier has a row from after it.
+fn roll_up_file(store: &Store, ring: &Path, tier: usize, files: &[Segment]) -> Result<()> {
+    let resolution = store.tiers[tier + 1];
+    let millis = resolution.as_millis() as u64;
+    let next = super::files(&dir(ring, resolution), EXTENSION)?;
+    let from = last_start(&next)?.map_or(0, |start| start + millis);
+    let last = last_start(files)?.map_or(0, |start| start / millis * millis);
+    let until = files[1].start.next_multiple_of(millis).min(last);
+    if until <= from {
+        return Ok(());
+    }
+
+    let newer = files.iter().take_while(|f| f.start < until).count();
+    let mut rows = read_files(&files[..newer])?;
+    rows.retain(|row| row.start >= from && row.start < until);
+    append(store, ring, tier + 1, &combine(&rows, millis))
+}
+
+/// Append rows to a tier, starting a file once the last one has reached its share of the tier's size.
+pub(super) fn append(store: &Store, ring: &Path, tier: usize, rows: &[Row]) -> Result<()> {
+    let Some(first) = rows.first() else {
+        return Ok(());
+    };
+    let dir = dir(ring, store.tiers[tier]);
+    fs::create_dir_all(&dir)?;
+    let path = match files(&dir, EXTENSION)?.pop() {
+        Some(last) if last.len < share(store) / SEGMENTS => last.path,
+        _ => dir.join(format!("{:013}.{}", first.start, EXTENSION)),
+    };
+
+    let mut lines = Vec::new();
+    for row in rows {
+        serde_json::to_writer(&mut lines, row)?;
+        lines.push(b'\n');
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    discard_partial_line(&mut file)?;
+    // In one write, so that a crash can only leave part of the last line.
+    file.write_all(&lines)?;
+
+    expire(store, ring, tier)
+}
+
+/// Discard the end of a file after its last newline, left by a crash while appending to it.
+fn discard_partial_line(file: &mut File) -> Result<()> {
+    let mut text = Vec::new();
+    file.read_to_end(&mut text)?;
+    if !text.is_empty() && text.last() != Some(&b'\n') {
+        let end = text.iter().rposition(|&b| b == b'\n').map_or(0, |n| n + 1);
+        file.set_len(end as u64)?;
+        file.seek(SeekFrom::Start(end as u64))?;
+    }
+    Ok(())
+}
+
+/// Delete the oldest files of a tier, apart from the current one, until it's within its share of the store's size,
+/// rolling each up into the next tier first.
+fn expire(store: &Store, ring: &Path, tier: usize) -> Result<()> {
+    let files = files(&dir(ring, store.tiers[tier]), EXTENSION)?;
+    let mut total = files.iter().map(|f| f.len).sum::<u64>();
+    for (index, file) in files.iter().enumerate().take(files.len() - 1) {
+        if total <= share(store) {
+            break;
+        }
+        if tier + 1 < store.tiers.len() {
+            roll_up_file(store, ring, tier, &files[index..])?;
+        }
+        log::debug!("Deleting {}", file.path.display());
+        fs::remove_file(&file.path)?;
+        total -= file.len;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{sample, store};
+    use crate::store::MIN_SIZE;
+    use crate::testing::{at, contoso_disk};
+
+    // Contoso Disk's Bytes Read and Queue Length.
+    const BYTES_READ: u32 = 0;
+    const QUEUE_LENGTH: u32 = 5;
+
+    fn row(start: u64, instance: &str, points: &[(u32, (f64, f64, f64))]) -> Row {
+        Row {
+            start,
+            instance: Some(Instance {
+                id: 0,
+                name: instance.to_string(),
+            }),
+            points: points.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn combines_rows_into_coarser_buckets() {
+        let rows = [
+            row(0, "C:", &[(BYTES_READ, (10.0, 5.0, 20.0))]),
+            row(0, "D:", &[(BYTES_READ, (1.0, 1.0, 1.0))]),
+            row(
+                10_000,
+                "C:",
+                &[
+                    (BYTES_READ, (30.0, 0.0, 40.0)),
+                    (QUEUE_LENGTH, (2.0, 1.0, 3.0)),
+                ],
+            ),
+            row(60_000, "C:", &[(BYTES_READ, (7.0, 7.0, 7.0))]),
+        ];
+        assert_eq!(
+            combine(&rows, 60_000),
+            [
+                row(
+                    0,
+                    "C:",
+                    &[
+                        (BYTES_READ, (20.0, 0.0, 40.0)),
+                        (QUEUE_LENGTH, (2.0, 1.0, 3.0)),
+                    ],
+                ),
+                row(0, "D:", &[(BYTES_READ, (1.0, 1.0, 1.0))]),
+                row(60_000, "C:", &[(BYTES_READ, (7.0, 7.0, 7.0))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rolls_expired_samples_up_into_each_tier() {
+        let mut store = store("tiers", MIN_SIZE, None);
+        store.tiers = vec![Duration::from_secs(10), Duration::from_secs(60)];
+        let counterset = contoso_disk();
+        let mut ring = store.ring(&counterset, Duration::from_secs(1)).unwrap();
+        for second in 0..12_000 {
+            ring.append(&counterset, at(second), &sample(second, second, 1))
+                .unwrap();
+        }
+
+        let ring = store.ring_dir(&counterset.id);
+        let seconds = read(&dir(&ring, store.tiers[0]), SystemTime::UNIX_EPOCH, None).unwrap();
+        let minutes = read(&dir(&ring, store.tiers[1]), SystemTime::UNIX_EPOCH, None).unwrap();
+        // Every bucket is rolled up once, with no gap between one tier and the next.
+        assert!(seconds
+            .windows(2)
+            .all(|w| w[1].start == w[0].start + 10_000));
+        assert!(minutes
+            .windows(2)
+            .all(|w| w[1].start == w[0].start + 60_000));
+        assert_eq!(minutes[0].start, unix_millis(at(0)) / 60_000 * 60_000);
+        assert!(seconds[0].start > unix_millis(at(0)));
+        assert!(minutes.last().unwrap().start + 60_000 >= seconds[0].start);
+        let mut records = store
+            .records(&counterset.id, SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let first = records.next_record().unwrap().unwrap();
+        assert!(seconds.last().unwrap().start + 10_000 >= unix_millis(first.time));
+
+        // Averaged over the minute from 40s, however it was split between files.
+        let minute = minutes.iter().find(|r| r.start == unix_millis(at(40)));
+        assert_eq!(
+            minute.unwrap().points,
+            BTreeMap::from([
+                (BYTES_READ, (69.5, 40.0, 99.0)),
+                (QUEUE_LENGTH, (1.0, 1.0, 1.0)),
+            ])
+        );
+
+        // The samples and tiers share the store's size, apart from the files being appended to.
+        for &resolution in &store.tiers {
+            let files = files(&dir(&ring, resolution), EXTENSION).unwrap();
+            let total = files.iter().map(|f| f.len).sum::<u64>();
+            assert!(
+                total <= share(&store) + share(&store) / SEGMENTS,
+                "{} bytes",
+                total
+            );
+        }
+        let segments = crate::store::segments(&ring).unwrap();
+        let total = segments.iter().map(|s| s.len).sum::<u64>() + len(&store, &ring).unwrap();
+        assert!(total <= MIN_SIZE + MIN_SIZE / SEGMENTS, "{} bytes", total);
+
+        fs::remove_dir_all(&store.path).unwrap();
+    }
+
+    #[test]
+    fn discards_rows_cut_short_by_a_crash() {
+        let mut store = store("torn-tier", MIN_SIZE, None);
+        store.tiers = vec![Duration::from_secs(60)];
+        let ring = store.ring_dir(&contoso_disk().id);
+        append(&store, &ring, 0, &[row(0, "C:", &[])]).unwrap();
+        let dir = dir(&ring, store.tiers[0]);
+        let path = files(&dir, EXTENSION).unwrap().remove(0).path;
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(b"{\"start\":").unwrap();
+
+        append(&store, &ring, 0, &[row(60_000, "C:", &[])]).unwrap();
+        let rows = read(&dir, SystemTime::UNIX_EPOCH, None).unwrap();
+        assert_eq!(rows, [row(0, "C:", &[]), row(60_000, "C:", &[])]);
+
+        fs::remove_dir_all(&store.path).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+//! Fixtures shared by the tests: the catalog of made-up providers in `testdata`, countersets made up on the spot,
+//! samples of them, a source of those samples, and a clock which only moves when slept.
+
+use crate::clock::Clock;
+use crate::error::Result;
+use crate::snapshot;
+use crate::source::Source;
+use crate::types::{
+    AggregateFunc, Counter, CounterAttributes, CounterSet, CounterType, DetailLevel, Instance,
+    InstanceSample, InstanceType, NonMaxU32, Provider, Sample,
+};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+use windows::core::GUID;
+
+/// The providers in `testdata/catalog.json`.
+pub fn catalog() -> Vec<Provider> {
+    snapshot::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/catalog.json")).unwrap()
+}
+
+/// The GUID of Contoso Disk, for samples of it.
+pub const CONTOSO_DISK: GUID = GUID::from_u128(0x8F1E2D3C_4B5A_4968_8776_A5B4C3D2E1F0);
+
+/// Contoso Disk, as registered: Bytes Read (0), Read Latency (1) and its base (2), % Idle Time (3) and its base (4),
+/// and Queue Length (5).
+pub fn contoso_disk() -> CounterSet {
+    catalog().remove(0).countersets.remove(1)
+}
+
+/// A counter of a type, with the id of its base counter if it has one.
+pub fn counter(id: u32, name: &str, counter_type: CounterType, base: Option<u32>) -> Counter {
+    Counter {
+        id,
+        name: name.to_string(),
+        help: String::new(),
+        counter_type,
+        base_counter_id: base.and_then(NonMaxU32::new),
+        multi_counter_id: None,
+        perf_time_id: None,
+        perf_freq_id: None,
+        aggregate_func: AggregateFunc::Undefined,
+        detail_level: DetailLevel::Novice,
+        attributes: CounterAttributes::default(),
+        default_scale: 0,
+    }
+}
+
+/// A multi-instance counterset of some counters, with no instances listed.
+pub fn counterset(id: GUID, name: &str, counters: Vec<Counter>) -> CounterSet {
+    CounterSet {
+        id,
+        name: name.to_string(),
+        help: String::new(),
+        instance_type: InstanceType::MultiInstances,
+        counters,
+        instances: None,
+        legacy: false,
+    }
+}
+
+/// A second after 2023-11-14T22:13:20Z.
+pub fn at(second: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + second)
+}
+
+/// An instance by its id and name.
+pub fn instance(id: u32, name: &str) -> Option<Instance> {
+    Some(Instance {
+        id,
+        name: name.to_string(),
+    })
+}
+
+/// Instances by name, with their position as their id.
+pub fn named<'a, V>(
+    instances: impl IntoIterator<Item = (&'a str, V)>,
+) -> Vec<(Option<Instance>, V)> {
+    instances
+        .into_iter()
+        .enumerate()
+        .map(|(id, (name, values))| (instance(id as u32, name), values))
+        .collect()
+}
+
+/// A sample taken a number of seconds in, of some of a counterset's counters for each (instance, values).
+pub fn sample<V: Into<Vec<u64>>>(
+    counterset_id: GUID,
+    second: i64,
+    counter_ids: &[u32],
+    instances: impl IntoIterator<Item = (Option<Instance>, V)>,
+) -> Sample {
+    Sample {
+        counterset_id,
+        timestamp: second * 1000,
+        time_100ns: second * 10_000_000,
+        frequency: 1000,
+        counter_ids: counter_ids.to_vec(),
+        instances: instances
+            .into_iter()
+            .map(|(instance, values)| InstanceSample {
+                instance,
+                values: values.into(),
+            })
+            .collect(),
+    }
+}
+
+/// A source which returns its samples in turn, whichever counterset is asked for.
+pub struct FakeSource(pub VecDeque<Sample>);
+
+impl Source for FakeSource {
+    fn sample(&mut self, _: &GUID) -> Result<Sample> {
+        Ok(self.0.pop_front().unwrap())
+    }
+}
+
+/// A clock which starts at `at(0)` and only moves, by whole seconds, when slept. The seconds can be shared with fakes
+/// which need to know the time.
+pub struct FakeClock {
+    pub seconds: Rc<Cell<u64>>,
+    pub slept: Vec<Duration>,
+}
+
+impl FakeClock {
+    pub fn new() -> FakeClock {
+        FakeClock::sharing(Rc::default())
+    }
+
+    pub fn sharing(seconds: Rc<Cell<u64>>) -> FakeClock {
+        FakeClock {
+            seconds,
+            slept: Vec::new(),
+        }
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> SystemTime {
+        at(self.seconds.get())
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        assert_eq!(duration.subsec_nanos(), 0, "slept for part of a second");
+        self.seconds.set(self.seconds.get() + duration.as_secs());
+        self.slept.push(duration);
+    }
+}
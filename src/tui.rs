@@ -0,0 +1,214 @@
+use crate::error::Result;
+use crate::source::Source;
+use crate::types::Provider;
+use app::{App, Flow};
+use ratatui::backend::Backend;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use ratatui::Terminal;
+use std::time::{Duration, Instant};
+
+mod app;
+mod ui;
+
+/// How often live values are refreshed while a counterset is selected.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Run an interactive browser over all providers, countersets, counters, and instances.
+///
+/// Live values are shown for the selected counterset if `source` is provided.
+pub fn run(all: &[Provider], source: Option<&mut dyn Source>) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let res = event_loop(&mut terminal, App::new(all), source);
+    ratatui::restore();
+    res
+}
+
+fn event_loop<B: Backend>(
+    terminal: &mut Terminal<B>,
+    mut app: App,
+    mut source: Option<&mut dyn Source>,
+) -> Result<()> {
+    let mut last_refresh = Instant::now();
+
+    loop {
+        if app.live_is_stale() || last_refresh.elapsed() >= REFRESH_INTERVAL {
+            app.refresh_live(source.as_mut().map(|s| &mut **s as _));
+            last_refresh = Instant::now();
+        }
+
+        terminal.draw(|frame| ui::draw(frame, &mut app))?;
+
+        let timeout = REFRESH_INTERVAL.saturating_sub(last_refresh.elapsed());
+        if !event::poll(timeout)? {
+            continue;
+        }
+
+        if let Event::Key(key) = event::read()? {
+            // Ignore key releases, which are reported on Windows.
+            if key.kind == KeyEventKind::Press && app.handle_key(key) == Flow::Quit {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, catalog, named};
+    use crate::types::Sample;
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use windows::core::GUID;
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '→' => KeyCode::Right,
+                '←' => KeyCode::Left,
+                '↓' => KeyCode::Down,
+                '↑' => KeyCode::Up,
+                '⏎' => KeyCode::Enter,
+                c => KeyCode::Char(c),
+            };
+            assert_eq!(
+                app.handle_key(KeyEvent::new(code, KeyModifiers::NONE)),
+                Flow::Continue
+            );
+        }
+    }
+
+    fn render(app: &mut App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| ui::draw(frame, app)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        let mut screen = String::new();
+        for y in 0..buffer.area.height {
+            for x in 0..buffer.area.width {
+                screen.push_str(buffer[(x, y)].symbol());
+            }
+            screen.push('\n');
+        }
+        screen
+    }
+
+    struct FakeSource;
+
+    impl Source for FakeSource {
+        fn sample(&mut self, counterset_id: &GUID) -> Result<Sample> {
+            let instances = named([("C:", [4096, 3]), ("D:", [8192, 7])]);
+            Ok(testing::sample(*counterset_id, 0, &[0, 5], instances))
+        }
+    }
+
+    #[test]
+    fn starts_collapsed() {
+        let all = catalog();
+        let mut app = App::new(&all);
+
+        let screen = render(&mut app);
+        assert!(screen.contains("▸ Contoso-Storage"));
+        assert!(screen.contains("▸ Contoso-Network"));
+        assert!(!screen.contains("Contoso Disk"));
+        assert!(screen.contains("Provider: Contoso-Storage"));
+        assert!(screen.contains("Id: 3D1A2C55-6B8E-4F10-9A3B-0C5E7D9F1A24"));
+    }
+
+    #[test]
+    fn counter_details() {
+        let all = catalog();
+        let mut app = App::new(&all);
+
+        // Expand the provider, move to "Contoso Disk", expand it, and select "Read Latency".
+        press(&mut app, "→↓↓→↓↓");
+
+        let screen = render(&mut app);
+        assert!(screen.contains("▾ Contoso-Storage"));
+        assert!(screen.contains("▾ Contoso Disk"));
+        assert!(screen.contains("Counter: Read Latency"));
+        assert!(screen.contains("Type: PERF_AVERAGE_TIMER"));
+        assert!(screen.contains("Base counter: 2 (Read Latency Base)"));
+        assert!(screen.contains("Multi counter: none"));
+        assert!(screen.contains("Aggregate: Avg"));
+        assert!(screen.contains("Help: Average time per read, in seconds."));
+
+        // Collapsing from a counter goes to its counterset, which shows the instance type.
+        press(&mut app, "←");
+        let screen = render(&mut app);
+        assert!(screen.contains("Instance type: MultiAggregate"));
+    }
+
+    #[test]
+    fn incremental_search() {
+        let all = catalog();
+        let mut app = App::new(&all);
+
+        press(&mut app, "/wi");
+        let screen = render(&mut app);
+        assert!(screen.contains("/wi"));
+        assert!(screen.contains("Counter: Current Bandwidth"));
+
+        // Extending the query moves to the first match from where the search started, expanding its ancestors.
+        press(&mut app, "-f⏎");
+        let screen = render(&mut app);
+        assert!(screen.contains("▾ Contoso-Network"));
+        assert!(screen.contains("Instance: Wi-Fi"));
+
+        press(&mut app, "/idle⏎");
+        assert_eq!(app.name(app.selected().unwrap()), "% Idle Time");
+        press(&mut app, "n");
+        assert_eq!(app.name(app.selected().unwrap()), "% Idle Time Base");
+        press(&mut app, "n");
+        assert_eq!(app.name(app.selected().unwrap()), "% Idle Time");
+        press(&mut app, "N");
+        assert_eq!(app.name(app.selected().unwrap()), "% Idle Time Base");
+    }
+
+    #[test]
+    fn cancelled_search_restores_selection() {
+        let all = catalog();
+        let mut app = App::new(&all);
+
+        press(&mut app, "↓/cache");
+        assert!(render(&mut app).contains("Counterset: Contoso Cache"));
+
+        app.handle_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(render(&mut app).contains("Provider: Contoso-Network"));
+    }
+
+    #[test]
+    fn live_values() {
+        let all = catalog();
+        let mut app = App::new(&all);
+
+        app.refresh_live(Some(&mut FakeSource));
+        assert!(render(&mut app).contains("Select a counterset to see live values."));
+
+        press(&mut app, "/contoso disk⏎");
+        assert!(app.live_is_stale());
+        app.refresh_live(Some(&mut FakeSource));
+        let screen = render(&mut app);
+        assert!(screen.contains("> C:"));
+        assert!(screen.contains("Bytes Read: 4096"));
+        assert!(screen.contains("> D:"));
+        assert!(screen.contains("Queue Length: 7"));
+
+        // Selecting a counter restricts the values to that counter.
+        press(&mut app, "→↓");
+        assert!(!app.live_is_stale());
+        let screen = render(&mut app);
+        assert!(screen.contains("Bytes Read: 8192"));
+        assert!(!screen.contains("Queue Length: "));
+    }
+
+    #[test]
+    fn live_values_unavailable() {
+        let all = catalog();
+        let mut app = App::new(&all);
+
+        press(&mut app, "/contoso cache⏎");
+        app.refresh_live(None);
+        assert!(render(&mut app).contains("Live values are only available on Windows"));
+    }
+}
@@ -0,0 +1,345 @@
+use crate::source::Source;
+use crate::types::{CounterSet, Provider, Sample};
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::widgets::ListState;
+use std::collections::HashSet;
+use windows::core::GUID;
+
+/// A node in the tree, identified by its indices into the provider list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Node {
+    Provider(usize),
+    CounterSet(usize, usize),
+    Counter(usize, usize, usize),
+    Instance(usize, usize, usize),
+}
+
+impl Node {
+    pub fn depth(self) -> usize {
+        match self {
+            Node::Provider(..) => 0,
+            Node::CounterSet(..) => 1,
+            Node::Counter(..) | Node::Instance(..) => 2,
+        }
+    }
+
+    fn parent(self) -> Option<Node> {
+        match self {
+            Node::Provider(..) => None,
+            Node::CounterSet(p, _) => Some(Node::Provider(p)),
+            Node::Counter(p, cs, _) | Node::Instance(p, cs, _) => Some(Node::CounterSet(p, cs)),
+        }
+    }
+
+    fn counterset(self) -> Option<(usize, usize)> {
+        match self {
+            Node::Provider(..) => None,
+            Node::CounterSet(p, cs) | Node::Counter(p, cs, _) | Node::Instance(p, cs, _) => {
+                Some((p, cs))
+            }
+        }
+    }
+}
+
+/// The most recent live values for the selected counterset.
+pub enum Live {
+    /// Nothing with live values is selected.
+    None,
+    Unavailable,
+    Values(Sample),
+    Error(String),
+}
+
+pub struct Search {
+    pub query: String,
+    /// Where the selection was before searching, to restore it on cancel.
+    origin: Node,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+pub struct App<'a> {
+    pub all: &'a [Provider],
+    expanded: HashSet<Node>,
+    /// The currently visible nodes, in display order.
+    pub rows: Vec<Node>,
+    pub list_state: ListState,
+    /// Present while the user is typing a search query.
+    pub search: Option<Search>,
+    /// The last confirmed search query, for jumping between matches.
+    last_query: String,
+    pub live: Live,
+    live_counterset: Option<GUID>,
+}
+
+impl<'a> App<'a> {
+    pub fn new(all: &'a [Provider]) -> Self {
+        let mut app = Self {
+            all,
+            expanded: HashSet::new(),
+            rows: Vec::new(),
+            list_state: ListState::default(),
+            search: None,
+            last_query: String::new(),
+            live: Live::None,
+            live_counterset: None,
+        };
+        app.update_rows();
+        if !app.rows.is_empty() {
+            app.list_state.select(Some(0));
+        }
+        app
+    }
+
+    pub fn selected(&self) -> Option<Node> {
+        self.list_state.selected().map(|i| self.rows[i])
+    }
+
+    pub fn is_expanded(&self, node: Node) -> bool {
+        self.expanded.contains(&node)
+    }
+
+    pub fn has_children(&self, node: Node) -> bool {
+        !self.children(node).is_empty()
+    }
+
+    pub fn name(&self, node: Node) -> &'a str {
+        match node {
+            Node::Provider(p) => &self.all[p].name,
+            Node::CounterSet(p, cs) => &self.all[p].countersets[cs].name,
+            Node::Counter(p, cs, c) => &self.all[p].countersets[cs].counters[c].name,
+            Node::Instance(p, cs, i) => {
+                &self.all[p].countersets[cs]
+                    .instances
+                    .as_deref()
+                    .unwrap_or_default()[i]
+                    .name
+            }
+        }
+    }
+
+    /// The counterset containing the selected node, if any.
+    pub fn selected_counterset(&self) -> Option<&'a CounterSet> {
+        let (p, cs) = self.selected()?.counterset()?;
+        Some(&self.all[p].countersets[cs])
+    }
+
+    /// The counterset which the live values pane should show, if any.
+    fn selected_counterset_id(&self) -> Option<GUID> {
+        self.selected_counterset().map(|cs| cs.id)
+    }
+
+    fn children(&self, node: Node) -> Vec<Node> {
+        match node {
+            Node::Provider(p) => (0..self.all[p].countersets.len())
+                .map(|cs| Node::CounterSet(p, cs))
+                .collect(),
+            Node::CounterSet(p, cs) => {
+                let counterset = &self.all[p].countersets[cs];
+                let counters = (0..counterset.counters.len()).map(|c| Node::Counter(p, cs, c));
+                let instances = (0..counterset.instances.as_ref().map_or(0, Vec::len))
+                    .map(|i| Node::Instance(p, cs, i));
+                counters.chain(instances).collect()
+            }
+            Node::Counter(..) | Node::Instance(..) => Vec::new(),
+        }
+    }
+
+    /// All nodes in display order, regardless of whether they're expanded.
+    fn all_nodes(&self) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        for p in 0..self.all.len() {
+            let provider = Node::Provider(p);
+            nodes.push(provider);
+            for counterset in self.children(provider) {
+                nodes.push(counterset);
+                nodes.extend(self.children(counterset));
+            }
+        }
+        nodes
+    }
+
+    fn update_rows(&mut self) {
+        let selected = self.selected();
+
+        self.rows.clear();
+        for p in 0..self.all.len() {
+            let provider = Node::Provider(p);
+            self.rows.push(provider);
+            if !self.is_expanded(provider) {
+                continue;
+            }
+            for counterset in self.children(provider) {
+                self.rows.push(counterset);
+                if self.is_expanded(counterset) {
+                    self.rows.extend(self.children(counterset));
+                }
+            }
+        }
+
+        if let Some(selected) = selected {
+            self.select(selected);
+        }
+    }
+
+    /// Select a node, expanding its ancestors if necessary.
+    fn select(&mut self, node: Node) {
+        let mut ancestor = node.parent();
+        let mut changed = false;
+        while let Some(parent) = ancestor {
+            changed |= self.expanded.insert(parent);
+            ancestor = parent.parent();
+        }
+        if changed {
+            self.update_rows();
+        }
+
+        let index = self.rows.iter().position(|n| *n == node);
+        self.list_state.select(index);
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let current = self.list_state.selected().unwrap_or(0);
+        let last = self.rows.len() - 1;
+        let next = current.saturating_add_signed(delta).min(last);
+        self.list_state.select(Some(next));
+    }
+
+    fn expand(&mut self) {
+        let Some(node) = self.selected() else { return };
+        if !self.has_children(node) {
+            return;
+        }
+        if self.expanded.insert(node) {
+            self.update_rows();
+        } else {
+            self.move_by(1);
+        }
+    }
+
+    fn collapse(&mut self) {
+        let Some(node) = self.selected() else { return };
+        if self.expanded.remove(&node) {
+            self.update_rows();
+        } else if let Some(parent) = node.parent() {
+            self.select(parent);
+        }
+    }
+
+    /// Find the next node (in display order, wrapping around) whose name contains the query, case-insensitively.
+    fn find(&self, query: &str, from: Node, forwards: bool, include_from: bool) -> Option<Node> {
+        let query = query.to_lowercase();
+        let nodes = self.all_nodes();
+        let start = nodes.iter().position(|n| *n == from).unwrap_or(0);
+
+        let skip = if include_from { 0 } else { 1 };
+        let len = nodes.len();
+        (skip..len + skip)
+            .map(|i| match forwards {
+                true => nodes[(start + i) % len],
+                false => nodes[(start + len - i % len) % len],
+            })
+            .find(|n| self.name(*n).to_lowercase().contains(&query))
+    }
+
+    fn jump_to_match(&mut self, forwards: bool) {
+        if self.last_query.is_empty() {
+            return;
+        }
+        let Some(from) = self.selected() else { return };
+        if let Some(node) = self.find(&self.last_query, from, forwards, false) {
+            self.select(node);
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Flow {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Flow::Quit;
+        }
+
+        if let Some(search) = &mut self.search {
+            match key.code {
+                KeyCode::Esc => {
+                    let origin = search.origin;
+                    self.search = None;
+                    self.select(origin);
+                }
+                KeyCode::Enter => {
+                    self.last_query = search.query.clone();
+                    self.search = None;
+                }
+                KeyCode::Backspace => {
+                    search.query.pop();
+                    self.update_search();
+                }
+                KeyCode::Char(c) => {
+                    search.query.push(c);
+                    self.update_search();
+                }
+                _ => {}
+            }
+            return Flow::Continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Flow::Quit,
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::PageUp => self.move_by(-20),
+            KeyCode::PageDown => self.move_by(20),
+            KeyCode::Home | KeyCode::Char('g') => self.move_by(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.move_by(isize::MAX),
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Enter => self.expand(),
+            KeyCode::Left | KeyCode::Char('h') => self.collapse(),
+            KeyCode::Char('/') => {
+                if let Some(origin) = self.selected() {
+                    self.search = Some(Search {
+                        query: String::new(),
+                        origin,
+                    });
+                }
+            }
+            KeyCode::Char('n') => self.jump_to_match(true),
+            KeyCode::Char('N') => self.jump_to_match(false),
+            _ => {}
+        }
+        Flow::Continue
+    }
+
+    /// Jump to the first match at or after where the search started.
+    fn update_search(&mut self) {
+        let Some(search) = &self.search else { return };
+        let origin = search.origin;
+        let found = match search.query.is_empty() {
+            true => Some(origin),
+            false => self.find(&search.query, origin, true, true),
+        };
+        if let Some(node) = found {
+            self.select(node);
+        }
+    }
+
+    /// Whether the live values should be refreshed, because the selected counterset changed.
+    pub fn live_is_stale(&self) -> bool {
+        self.live_counterset != self.selected_counterset_id()
+    }
+
+    pub fn refresh_live(&mut self, source: Option<&mut dyn Source>) {
+        self.live_counterset = self.selected_counterset_id();
+        self.live = match (self.live_counterset, source) {
+            (None, _) => Live::None,
+            (Some(_), None) => Live::Unavailable,
+            (Some(id), Some(source)) => match source.sample(&id) {
+                Ok(sample) => Live::Values(sample),
+                Err(e) => Live::Error(e.to_string()),
+            },
+        };
+    }
+}
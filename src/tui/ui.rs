@@ -0,0 +1,207 @@
+use crate::tui::app::{App, Live, Node};
+use crate::types::{CounterSet, Sample};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Wrap};
+use ratatui::Frame;
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [main, footer] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let [tree, right] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(main);
+    let [details, live] =
+        Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(right);
+
+    draw_tree(frame, app, tree);
+    draw_details(frame, app, details);
+    draw_live(frame, app, live);
+    draw_footer(frame, app, footer);
+}
+
+fn draw_tree(frame: &mut Frame, app: &mut App, area: Rect) {
+    let items = app
+        .rows
+        .iter()
+        .map(|&node| {
+            let marker = match app.has_children(node) {
+                false => "  ",
+                true if app.is_expanded(node) => "▾ ",
+                true => "▸ ",
+            };
+            let indent = "  ".repeat(node.depth());
+            let style = match node {
+                Node::Provider(..) => Style::new().add_modifier(Modifier::BOLD),
+                Node::Instance(..) => Style::new().add_modifier(Modifier::ITALIC),
+                Node::CounterSet(..) | Node::Counter(..) => Style::new(),
+            };
+            ListItem::new(Line::from(vec![
+                Span::raw(indent),
+                Span::raw(marker),
+                Span::styled(app.name(node), style),
+            ]))
+        })
+        .collect::<Vec<_>>();
+
+    let list = List::new(items)
+        .block(Block::bordered().title(format!("Providers ({})", app.all.len())))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+    frame.render_stateful_widget(list, area, &mut app.list_state);
+}
+
+fn draw_details(frame: &mut Frame, app: &App, area: Rect) {
+    let field = |name: &str, value: String| {
+        Line::from(vec![
+            Span::styled(
+                format!("{}: ", name),
+                Style::new().add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(value),
+        ])
+    };
+
+    let lines = match app.selected() {
+        None => Vec::new(),
+        Some(Node::Provider(p)) => {
+            let provider = &app.all[p];
+            vec![
+                field("Provider", provider.name.clone()),
+                field("Id", format!("{:?}", provider.id)),
+                field("Countersets", provider.countersets.len().to_string()),
+            ]
+        }
+        Some(Node::CounterSet(p, cs)) => {
+            let counterset = &app.all[p].countersets[cs];
+            vec![
+                field("Counterset", counterset.name.clone()),
+                field("Id", format!("{:?}", counterset.id)),
+                field("Instance type", format!("{:?}", counterset.instance_type)),
+                field("Counters", counterset.counters.len().to_string()),
+                field(
+                    "Instances",
+                    match &counterset.instances {
+                        Some(instances) => instances.len().to_string(),
+                        None => "none".to_string(),
+                    },
+                ),
+                field("Help", counterset.help.clone()),
+            ]
+        }
+        Some(Node::Counter(p, cs, c)) => {
+            let counterset = &app.all[p].countersets[cs];
+            let counter = &counterset.counters[c];
+            let related = |id: Option<u32>| match id {
+                None => "none".to_string(),
+                Some(id) => match counter_name(counterset, id) {
+                    Some(name) => format!("{} ({})", id, name),
+                    None => format!("{} (not found)", id),
+                },
+            };
+            vec![
+                field("Counter", counter.name.clone()),
+                field("Id", counter.id.to_string()),
+                field("Type", format!("{:?}", counter.counter_type)),
+                field(
+                    "Base counter",
+                    related(counter.base_counter_id.map(|id| id.get())),
+                ),
+                field(
+                    "Multi counter",
+                    related(counter.multi_counter_id.map(|id| id.get())),
+                ),
+                field("Aggregate", format!("{:?}", counter.aggregate_func)),
+                field("Help", counter.help.clone()),
+            ]
+        }
+        Some(Node::Instance(p, cs, i)) => {
+            let counterset = &app.all[p].countersets[cs];
+            let instance = &counterset.instances.as_deref().unwrap_or_default()[i];
+            vec![
+                field("Instance", instance.name.clone()),
+                field("Id", instance.id.to_string()),
+                field("Counterset", counterset.name.clone()),
+            ]
+        }
+    };
+
+    let details = Paragraph::new(lines)
+        .block(Block::bordered().title("Details"))
+        .wrap(Wrap { trim: false });
+
+    frame.render_widget(details, area);
+}
+
+fn draw_live(frame: &mut Frame, app: &App, area: Rect) {
+    let lines = match &app.live {
+        Live::None => vec![Line::raw("Select a counterset to see live values.")],
+        Live::Unavailable => vec![Line::raw(
+            "Live values are only available on Windows, when not using a snapshot.",
+        )],
+        Live::Error(e) => vec![Line::raw(format!("Failed to collect values: {}", e))],
+        Live::Values(sample) => match (app.selected(), app.selected_counterset()) {
+            (Some(node), Some(counterset)) if counterset.id == sample.counterset_id => {
+                live_lines(sample, counterset, node)
+            }
+            _ => Vec::new(),
+        },
+    };
+
+    let live = Paragraph::new(lines).block(Block::bordered().title("Live values"));
+
+    frame.render_widget(live, area);
+}
+
+/// Values from a sample, restricted to the selected counter or instance if there is one.
+fn live_lines<'a>(sample: &'a Sample, counterset: &'a CounterSet, node: Node) -> Vec<Line<'a>> {
+    let (counter_id, instance_id) = match node {
+        Node::Counter(_, _, c) => (Some(counterset.counters[c].id), None),
+        Node::Instance(_, _, i) => (
+            None,
+            Some(counterset.instances.as_deref().unwrap_or_default()[i].id),
+        ),
+        Node::Provider(..) | Node::CounterSet(..) => (None, None),
+    };
+
+    let mut lines = Vec::new();
+    for instance in &sample.instances {
+        if instance_id.is_some() && instance.instance.as_ref().map(|i| i.id) != instance_id {
+            continue;
+        }
+        if let Some(instance) = &instance.instance {
+            lines.push(Line::styled(
+                format!("> {}", instance.name),
+                Style::new().add_modifier(Modifier::ITALIC),
+            ));
+        }
+        for (&id, value) in sample.counter_ids.iter().zip(&instance.values) {
+            if counter_id.is_some() && Some(id) != counter_id {
+                continue;
+            }
+            let name = counter_name(counterset, id).unwrap_or("?");
+            lines.push(Line::raw(format!("  {}: {}", name, value)));
+        }
+    }
+    lines
+}
+
+fn counter_name(counterset: &CounterSet, id: u32) -> Option<&str> {
+    counterset
+        .counters
+        .iter()
+        .find(|c| c.id == id)
+        .map(|c| c.name.as_str())
+}
+
+fn draw_footer(frame: &mut Frame, app: &App, area: Rect) {
+    let footer = match &app.search {
+        Some(search) => Line::raw(format!("/{}", search.query)),
+        None => Line::styled(
+            "↑↓ move  ←→ collapse/expand  / search  n/N next/prev match  q quit",
+            Style::new().add_modifier(Modifier::DIM),
+        ),
+    };
+
+    frame.render_widget(footer, area);
+}
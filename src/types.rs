@@ -1,16 +1,33 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::{self, Debug};
-use windows::core::{Error, Result, GUID};
+use windows::core::GUID;
+
+pub use counter_type::CounterType;
+
+pub mod counter_type;
+
+#[cfg(windows)]
+use windows::core::{Error, Result};
+#[cfg(windows)]
 use windows::Win32::Foundation::{RPC_X_ENUM_VALUE_OUT_OF_RANGE, WIN32_ERROR};
+#[cfg(windows)]
+use windows::Win32::System::Performance::PERF_COUNTER_AGGREGATE_FUNC;
+#[cfg(windows)]
+use windows::Win32::System::Performance::PERF_DETAIL;
 use windows::Win32::System::Performance::{
     PERF_AGGREGATE_AVG, PERF_AGGREGATE_MAX, PERF_AGGREGATE_MIN, PERF_AGGREGATE_TOTAL,
-    PERF_AGGREGATE_UNDEFINED, PERF_COUNTERSET_MULTI_INSTANCES, PERF_COUNTERSET_SINGLE_AGGREGATE,
-    PERF_COUNTERSET_SINGLE_INSTANCE, PERF_COUNTER_AGGREGATE_FUNC,
+    PERF_AGGREGATE_UNDEFINED, PERF_ATTRIB_BY_REFERENCE, PERF_ATTRIB_DISPLAY_AS_HEX,
+    PERF_ATTRIB_DISPLAY_AS_REAL, PERF_ATTRIB_NO_DISPLAYABLE, PERF_ATTRIB_NO_GROUP_SEPARATOR,
+    PERF_COUNTERSET_MULTI_INSTANCES, PERF_COUNTERSET_SINGLE_AGGREGATE,
+    PERF_COUNTERSET_SINGLE_INSTANCE, PERF_DETAIL_ADVANCED, PERF_DETAIL_EXPERT, PERF_DETAIL_NOVICE,
+    PERF_DETAIL_WIZARD,
 };
 
 /// A provider of countersets.
 /// Uniquely identified by its GUID, which appears to be fixed.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Provider {
+    #[serde(with = "guid")]
     pub id: GUID,
     pub name: String,
     pub countersets: Vec<CounterSet>,
@@ -19,17 +36,21 @@ pub struct Provider {
 /// A set of counters.
 /// Uniquely identified by its GUID, which appears to be fixed.
 /// Generally represents a category of something, like "Disk IO".
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CounterSet {
+    #[serde(with = "guid")]
     pub id: GUID,
     pub name: String,
     pub help: String,
     pub instance_type: InstanceType,
     pub counters: Vec<Counter>,
     pub instances: Option<Vec<Instance>>,
+    /// A V1 performance object read from the registry, rather than a real counterset; see `legacy`.
+    #[serde(default)]
+    pub legacy: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
 #[repr(u32)]
 pub enum InstanceType {
     SingleInstance = PERF_COUNTERSET_SINGLE_INSTANCE,
@@ -38,6 +59,7 @@ pub enum InstanceType {
     MultiAggregate = PERF_COUNTERSET_MULTI_INSTANCES | PERF_COUNTERSET_SINGLE_AGGREGATE,
 }
 
+#[cfg(windows)]
 impl InstanceType {
     pub fn from_bits(bits: u32) -> Result<Self> {
         const SINGLE_INSTANCE: u32 = InstanceType::SingleInstance as _;
@@ -58,17 +80,26 @@ impl InstanceType {
 /// A counter in a counterset.
 /// Uniquely identified by the combination of name and id. (I have seen duplicate ids in practice, but not duplicate names.)
 /// Normally represents a category of something, like "Bytes Read", and seems to generally be fixed for a given counterset.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Counter {
     pub id: u32,
     pub name: String,
     pub help: String,
+    pub counter_type: CounterType,
     pub base_counter_id: Option<NonMaxU32>,
     pub multi_counter_id: Option<NonMaxU32>,
+    /// For counters using their own time base, the counter containing the current time.
+    pub perf_time_id: Option<NonMaxU32>,
+    /// For counters using their own time base, the counter containing the frequency of `perf_time_id`.
+    pub perf_freq_id: Option<NonMaxU32>,
     pub aggregate_func: AggregateFunc,
+    pub detail_level: DetailLevel,
+    pub attributes: CounterAttributes,
+    /// Power of 10 by which to scale the value when graphing, e.g. -3 to display bytes as kilobytes.
+    pub default_scale: i32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[repr(u32)]
 pub enum AggregateFunc {
     Undefined = PERF_AGGREGATE_UNDEFINED.0,
@@ -78,6 +109,7 @@ pub enum AggregateFunc {
     Max = PERF_AGGREGATE_MAX,
 }
 
+#[cfg(windows)]
 impl AggregateFunc {
     pub fn from_bits(bits: PERF_COUNTER_AGGREGATE_FUNC) -> Result<Self> {
         const UNDEFINED: u32 = AggregateFunc::Undefined as _;
@@ -97,15 +129,92 @@ impl AggregateFunc {
     }
 }
 
+/// Which users a counter is intended for; tools may hide counters above the user's chosen level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u32)]
+pub enum DetailLevel {
+    Novice = PERF_DETAIL_NOVICE.0,
+    Advanced = PERF_DETAIL_ADVANCED.0,
+    Expert = PERF_DETAIL_EXPERT.0,
+    Wizard = PERF_DETAIL_WIZARD.0,
+}
+
+#[cfg(windows)]
+impl DetailLevel {
+    pub fn from_bits(bits: u32) -> Result<Self> {
+        const NOVICE: u32 = DetailLevel::Novice as _;
+        const ADVANCED: u32 = DetailLevel::Advanced as _;
+        const EXPERT: u32 = DetailLevel::Expert as _;
+        const WIZARD: u32 = DetailLevel::Wizard as _;
+
+        Ok(match PERF_DETAIL(bits).0 {
+            NOVICE => Self::Novice,
+            ADVANCED => Self::Advanced,
+            EXPERT => Self::Expert,
+            WIZARD => Self::Wizard,
+            _ => return Err(Error::from(WIN32_ERROR(RPC_X_ENUM_VALUE_OUT_OF_RANGE as _))),
+        })
+    }
+}
+
+/// Flags describing how a counter's value should be displayed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CounterAttributes(pub u64);
+
+impl CounterAttributes {
+    /// The value is a pointer to the data, rather than the data itself.
+    pub const BY_REFERENCE: Self = Self(PERF_ATTRIB_BY_REFERENCE);
+    /// The counter should not be shown to users.
+    pub const NO_DISPLAYABLE: Self = Self(PERF_ATTRIB_NO_DISPLAYABLE);
+    pub const NO_GROUP_SEPARATOR: Self = Self(PERF_ATTRIB_NO_GROUP_SEPARATOR);
+    pub const DISPLAY_AS_REAL: Self = Self(PERF_ATTRIB_DISPLAY_AS_REAL);
+    pub const DISPLAY_AS_HEX: Self = Self(PERF_ATTRIB_DISPLAY_AS_HEX);
+
+    pub const ALL: &'static [(Self, &'static str)] = &[
+        (Self::BY_REFERENCE, "BY_REFERENCE"),
+        (Self::NO_DISPLAYABLE, "NO_DISPLAYABLE"),
+        (Self::NO_GROUP_SEPARATOR, "NO_GROUP_SEPARATOR"),
+        (Self::DISPLAY_AS_REAL, "DISPLAY_AS_REAL"),
+        (Self::DISPLAY_AS_HEX, "DISPLAY_AS_HEX"),
+    ];
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Debug for CounterAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = Self::ALL
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| name.to_string())
+            .collect::<Vec<_>>();
+
+        let known = Self::ALL.iter().fold(0, |acc, (flag, _)| acc | flag.0);
+        if self.0 & !known != 0 {
+            names.push(format!("{:#X}", self.0 & !known));
+        }
+
+        match names.is_empty() {
+            true => f.write_str("(none)"),
+            false => f.write_str(&names.join(" | ")),
+        }
+    }
+}
+
 /// An instance of a counterset.
 /// Not all countersets have instances.
 /// Instances are generally things like "2.5GB Ethernet Adapter", and so are not fixed.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Instance {
     pub id: u32,
     pub name: String,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "u32", try_from = "u32")]
 pub struct NonMaxU32(u32);
 
 impl Debug for NonMaxU32 {
@@ -121,4 +230,78 @@ impl NonMaxU32 {
             _ => Some(Self(value)),
         }
     }
+
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for NonMaxU32 {
+    type Error = &'static str;
+
+    fn try_from(value: u32) -> std::result::Result<Self, Self::Error> {
+        Self::new(value).ok_or("value must not be u32::MAX")
+    }
+}
+
+impl From<NonMaxU32> for u32 {
+    fn from(value: NonMaxU32) -> Self {
+        value.0
+    }
+}
+
+/// A single collection of raw data for all counters and instances of a counterset.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub counterset_id: GUID,
+    /// Value of the high-resolution performance counter when the sample was taken.
+    pub timestamp: i64,
+    /// Time the sample was taken, in 100ns units since 1601-01-01.
+    pub time_100ns: i64,
+    /// Ticks per second of the high-resolution performance counter.
+    pub frequency: i64,
+    pub counter_ids: Vec<u32>,
+    pub instances: Vec<InstanceSample>,
+}
+
+/// The raw values of every counter for one instance of a counterset.
+#[derive(Debug, Clone)]
+pub struct InstanceSample {
+    /// None for single-instance countersets.
+    pub instance: Option<Instance>,
+    /// Parallel to `Sample::counter_ids`.
+    pub values: Vec<u64>,
+}
+
+/// Parse a GUID in the format produced by its `Debug` impl, e.g. 811BBCE5-7327-4AD9-AB62-A8B955F61EEF.
+///
+/// Unlike `GUID::from`, this returns None instead of panicking on malformed input.
+pub fn parse_guid(s: &str) -> Option<GUID> {
+    let s = s.trim_start_matches('{').trim_end_matches('}');
+    let well_formed = s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+    well_formed.then(|| GUID::from(s))
+}
+
+/// Serde helpers to (de)serialize GUIDs as strings.
+pub mod guid {
+    use super::*;
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(
+        id: &GUID,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:?}", id))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<GUID, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_guid(&s).ok_or_else(|| D::Error::custom(format!("invalid GUID: {}", s)))
+    }
 }
@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Debug};
+use windows::Win32::System::Performance::{
+    PERF_COUNTER_BASE, PERF_COUNTER_ELAPSED, PERF_COUNTER_FRACTION, PERF_COUNTER_HISTOGRAM,
+    PERF_COUNTER_HISTOGRAM_TYPE, PERF_COUNTER_PRECISION, PERF_COUNTER_QUEUELEN, PERF_COUNTER_RATE,
+    PERF_DELTA_BASE, PERF_DELTA_COUNTER, PERF_DISPLAY_NOSHOW, PERF_DISPLAY_PERCENT,
+    PERF_DISPLAY_PER_SEC, PERF_DISPLAY_SECONDS, PERF_INVERSE_COUNTER, PERF_MULTI_COUNTER,
+    PERF_OBJECT_TIMER, PERF_SIZE_DWORD, PERF_SIZE_LARGE, PERF_SIZE_VARIABLE_LEN, PERF_SIZE_ZERO,
+    PERF_TIMER_100NS, PERF_TYPE_COUNTER, PERF_TYPE_NUMBER, PERF_TYPE_TEXT, PERF_TYPE_ZERO,
+};
+
+/// The type of a counter, which determines how its raw values are turned into displayable values.
+///
+/// This is a bitfield (see winperf.h), but in practice only the combinations named here are used.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CounterType(pub u32);
+
+impl CounterType {
+    pub const PERF_COUNTER_COUNTER: Self = Self(0x10410400);
+    pub const PERF_COUNTER_TIMER: Self = Self(0x20410500);
+    pub const PERF_COUNTER_QUEUELEN_TYPE: Self = Self(0x00450400);
+    pub const PERF_COUNTER_LARGE_QUEUELEN_TYPE: Self = Self(0x00450500);
+    pub const PERF_COUNTER_100NS_QUEUELEN_TYPE: Self = Self(0x00550500);
+    pub const PERF_COUNTER_OBJ_TIME_QUEUELEN_TYPE: Self = Self(0x00650500);
+    pub const PERF_COUNTER_BULK_COUNT: Self = Self(0x10410500);
+    pub const PERF_COUNTER_TEXT: Self = Self(0x00000B00);
+    pub const PERF_COUNTER_RAWCOUNT: Self = Self(0x00010000);
+    pub const PERF_COUNTER_LARGE_RAWCOUNT: Self = Self(0x00010100);
+    pub const PERF_COUNTER_RAWCOUNT_HEX: Self = Self(0x00000000);
+    pub const PERF_COUNTER_LARGE_RAWCOUNT_HEX: Self = Self(0x00000100);
+    pub const PERF_SAMPLE_FRACTION: Self = Self(0x20C20400);
+    pub const PERF_SAMPLE_COUNTER: Self = Self(0x00410400);
+    pub const PERF_COUNTER_NODATA: Self = Self(0x40000200);
+    pub const PERF_COUNTER_TIMER_INV: Self = Self(0x21410500);
+    pub const PERF_SAMPLE_BASE: Self = Self(0x40030401);
+    pub const PERF_AVERAGE_TIMER: Self = Self(0x30020400);
+    pub const PERF_AVERAGE_BASE: Self = Self(0x40030402);
+    pub const PERF_AVERAGE_BULK: Self = Self(0x40020500);
+    pub const PERF_OBJ_TIME_TIMER: Self = Self(0x20610500);
+    pub const PERF_100NSEC_TIMER: Self = Self(0x20510500);
+    pub const PERF_100NSEC_TIMER_INV: Self = Self(0x21510500);
+    pub const PERF_COUNTER_MULTI_TIMER: Self = Self(0x22410500);
+    pub const PERF_COUNTER_MULTI_TIMER_INV: Self = Self(0x23410500);
+    pub const PERF_COUNTER_MULTI_BASE: Self = Self(0x42030500);
+    pub const PERF_100NSEC_MULTI_TIMER: Self = Self(0x22510500);
+    pub const PERF_100NSEC_MULTI_TIMER_INV: Self = Self(0x23510500);
+    pub const PERF_RAW_FRACTION: Self = Self(0x20020400);
+    pub const PERF_LARGE_RAW_FRACTION: Self = Self(0x20020500);
+    pub const PERF_RAW_BASE: Self = Self(0x40030403);
+    pub const PERF_LARGE_RAW_BASE: Self = Self(0x40030503);
+    pub const PERF_ELAPSED_TIME: Self = Self(0x30240500);
+    pub const PERF_COUNTER_HISTOGRAM_TYPE: Self = Self(0x80000000);
+    pub const PERF_COUNTER_DELTA: Self = Self(0x00400400);
+    pub const PERF_COUNTER_LARGE_DELTA: Self = Self(0x00400500);
+    pub const PERF_PRECISION_SYSTEM_TIMER: Self = Self(0x20470500);
+    pub const PERF_PRECISION_100NS_TIMER: Self = Self(0x20570500);
+    pub const PERF_PRECISION_OBJECT_TIMER: Self = Self(0x20670500);
+
+    const NAMES: &'static [(Self, &'static str)] = &[
+        (Self::PERF_COUNTER_COUNTER, "PERF_COUNTER_COUNTER"),
+        (Self::PERF_COUNTER_TIMER, "PERF_COUNTER_TIMER"),
+        (
+            Self::PERF_COUNTER_QUEUELEN_TYPE,
+            "PERF_COUNTER_QUEUELEN_TYPE",
+        ),
+        (
+            Self::PERF_COUNTER_LARGE_QUEUELEN_TYPE,
+            "PERF_COUNTER_LARGE_QUEUELEN_TYPE",
+        ),
+        (
+            Self::PERF_COUNTER_100NS_QUEUELEN_TYPE,
+            "PERF_COUNTER_100NS_QUEUELEN_TYPE",
+        ),
+        (
+            Self::PERF_COUNTER_OBJ_TIME_QUEUELEN_TYPE,
+            "PERF_COUNTER_OBJ_TIME_QUEUELEN_TYPE",
+        ),
+        (Self::PERF_COUNTER_BULK_COUNT, "PERF_COUNTER_BULK_COUNT"),
+        (Self::PERF_COUNTER_TEXT, "PERF_COUNTER_TEXT"),
+        (Self::PERF_COUNTER_RAWCOUNT, "PERF_COUNTER_RAWCOUNT"),
+        (
+            Self::PERF_COUNTER_LARGE_RAWCOUNT,
+            "PERF_COUNTER_LARGE_RAWCOUNT",
+        ),
+        (Self::PERF_COUNTER_RAWCOUNT_HEX, "PERF_COUNTER_RAWCOUNT_HEX"),
+        (
+            Self::PERF_COUNTER_LARGE_RAWCOUNT_HEX,
+            "PERF_COUNTER_LARGE_RAWCOUNT_HEX",
+        ),
+        (Self::PERF_SAMPLE_FRACTION, "PERF_SAMPLE_FRACTION"),
+        (Self::PERF_SAMPLE_COUNTER, "PERF_SAMPLE_COUNTER"),
+        (Self::PERF_COUNTER_NODATA, "PERF_COUNTER_NODATA"),
+        (Self::PERF_COUNTER_TIMER_INV, "PERF_COUNTER_TIMER_INV"),
+        (Self::PERF_SAMPLE_BASE, "PERF_SAMPLE_BASE"),
+        (Self::PERF_AVERAGE_TIMER, "PERF_AVERAGE_TIMER"),
+        (Self::PERF_AVERAGE_BASE, "PERF_AVERAGE_BASE"),
+        (Self::PERF_AVERAGE_BULK, "PERF_AVERAGE_BULK"),
+        (Self::PERF_OBJ_TIME_TIMER, "PERF_OBJ_TIME_TIMER"),
+        (Self::PERF_100NSEC_TIMER, "PERF_100NSEC_TIMER"),
+        (Self::PERF_100NSEC_TIMER_INV, "PERF_100NSEC_TIMER_INV"),
+        (Self::PERF_COUNTER_MULTI_TIMER, "PERF_COUNTER_MULTI_TIMER"),
+        (
+            Self::PERF_COUNTER_MULTI_TIMER_INV,
+            "PERF_COUNTER_MULTI_TIMER_INV",
+        ),
+        (Self::PERF_COUNTER_MULTI_BASE, "PERF_COUNTER_MULTI_BASE"),
+        (Self::PERF_100NSEC_MULTI_TIMER, "PERF_100NSEC_MULTI_TIMER"),
+        (
+            Self::PERF_100NSEC_MULTI_TIMER_INV,
+            "PERF_100NSEC_MULTI_TIMER_INV",
+        ),
+        (Self::PERF_RAW_FRACTION, "PERF_RAW_FRACTION"),
+        (Self::PERF_LARGE_RAW_FRACTION, "PERF_LARGE_RAW_FRACTION"),
+        (Self::PERF_RAW_BASE, "PERF_RAW_BASE"),
+        (Self::PERF_LARGE_RAW_BASE, "PERF_LARGE_RAW_BASE"),
+        (Self::PERF_ELAPSED_TIME, "PERF_ELAPSED_TIME"),
+        (
+            Self::PERF_COUNTER_HISTOGRAM_TYPE,
+            "PERF_COUNTER_HISTOGRAM_TYPE",
+        ),
+        (Self::PERF_COUNTER_DELTA, "PERF_COUNTER_DELTA"),
+        (Self::PERF_COUNTER_LARGE_DELTA, "PERF_COUNTER_LARGE_DELTA"),
+        (
+            Self::PERF_PRECISION_SYSTEM_TIMER,
+            "PERF_PRECISION_SYSTEM_TIMER",
+        ),
+        (
+            Self::PERF_PRECISION_100NS_TIMER,
+            "PERF_PRECISION_100NS_TIMER",
+        ),
+        (
+            Self::PERF_PRECISION_OBJECT_TIMER,
+            "PERF_PRECISION_OBJECT_TIMER",
+        ),
+    ];
+
+    /// The winperf.h name of this type, if it's one of the standard combinations.
+    pub fn name(self) -> Option<&'static str> {
+        Self::NAMES
+            .iter()
+            .find(|(ty, _)| *ty == self)
+            .map(|(_, name)| *name)
+    }
+
+    /// The type with this winperf.h name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, n)| n.eq_ignore_ascii_case(name))
+            .map(|(ty, _)| *ty)
+    }
+
+    pub fn kind(self) -> Kind {
+        const TYPE_MASK: u32 = 0xC00;
+        const SUBTYPE_MASK: u32 = 0xF0000;
+
+        if self.0 & PERF_COUNTER_HISTOGRAM_TYPE != 0 {
+            return Kind::Histogram;
+        }
+
+        match self.0 & TYPE_MASK {
+            PERF_TYPE_NUMBER => Kind::Number,
+            PERF_TYPE_TEXT => Kind::Text,
+            PERF_TYPE_ZERO => Kind::Zero,
+            PERF_TYPE_COUNTER => match self.0 & SUBTYPE_MASK {
+                PERF_COUNTER_RATE => Kind::Rate,
+                PERF_COUNTER_FRACTION => Kind::Fraction,
+                PERF_COUNTER_BASE => Kind::Base,
+                PERF_COUNTER_ELAPSED => Kind::Elapsed,
+                PERF_COUNTER_QUEUELEN => Kind::QueueLength,
+                PERF_COUNTER_HISTOGRAM => Kind::Histogram,
+                PERF_COUNTER_PRECISION => Kind::Precision,
+                _ => Kind::Value,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// How large the counter's raw value is.
+    pub fn size(self) -> Size {
+        match self.0 & 0x300 {
+            PERF_SIZE_DWORD => Size::Dword,
+            PERF_SIZE_LARGE => Size::Large,
+            PERF_SIZE_ZERO => Size::Zero,
+            PERF_SIZE_VARIABLE_LEN => Size::Variable,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Which clock the counter's time values are measured in.
+    pub fn timer(self) -> Timer {
+        match self.0 & (PERF_TIMER_100NS | PERF_OBJECT_TIMER) {
+            PERF_TIMER_100NS => Timer::Ns100,
+            PERF_OBJECT_TIMER => Timer::Object,
+            _ => Timer::Tick,
+        }
+    }
+
+    pub fn display(self) -> Display {
+        match self.0 & 0xF0000000 & !PERF_COUNTER_HISTOGRAM_TYPE {
+            PERF_DISPLAY_PER_SEC => Display::PerSec,
+            PERF_DISPLAY_PERCENT => Display::Percent,
+            PERF_DISPLAY_SECONDS => Display::Seconds,
+            PERF_DISPLAY_NOSHOW => Display::NoShow,
+            _ => Display::NoSuffix,
+        }
+    }
+
+    /// Whether the value is computed from the difference between two samples.
+    pub fn is_delta(self) -> bool {
+        self.0 & PERF_DELTA_COUNTER != 0
+    }
+
+    /// Whether the base value is computed from the difference between two samples.
+    pub fn is_delta_base(self) -> bool {
+        self.0 & PERF_DELTA_BASE != 0
+    }
+
+    pub fn is_inverse(self) -> bool {
+        self.0 & PERF_INVERSE_COUNTER != 0
+    }
+
+    /// Whether the counter's value is divided by the value of its multi counter.
+    pub fn is_multi(self) -> bool {
+        self.0 & PERF_MULTI_COUNTER != 0
+    }
+}
+
+impl Debug for CounterType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:#010X}", self.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Number,
+    Value,
+    Rate,
+    Fraction,
+    Base,
+    Elapsed,
+    QueueLength,
+    Histogram,
+    Precision,
+    Text,
+    Zero,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    /// 32 bits.
+    Dword,
+    /// 64 bits.
+    Large,
+    /// No value.
+    Zero,
+    /// Variable-length data, e.g. text.
+    Variable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timer {
+    /// The system performance counter, i.e. `PerfTimeStamp` and `PerfFreq`.
+    Tick,
+    /// 100ns units, i.e. `PerfTime100NSec`.
+    Ns100,
+    /// The counterset's own time and frequency counters.
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Display {
+    NoSuffix,
+    PerSec,
+    Percent,
+    Seconds,
+    NoShow,
+}
@@ -0,0 +1,417 @@
+use crate::clock::Clock;
+use crate::cook;
+use crate::error::Result;
+use crate::glob::Glob;
+use crate::identity::{Series, SeriesKey};
+use crate::source::Source;
+use crate::types::{Counter, CounterSet, Instance, InstanceSample, Sample};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone)]
+pub struct Options {
+    /// Only show instances whose name matches.
+    pub instance: Option<Glob>,
+    /// Only show counters whose name matches.
+    pub counter: Option<Glob>,
+    pub interval: Duration,
+    /// Stop after this many refreshes, instead of running until interrupted.
+    pub count: Option<u64>,
+    /// Clear the screen between refreshes and highlight changes with ANSI escapes.
+    pub ansi: bool,
+}
+
+impl Options {
+    /// The counters to display, in column order.
+    pub fn shown_counters<'a>(&self, counterset: &'a CounterSet) -> Vec<&'a Counter> {
+        counterset
+            .counters
+            .iter()
+            .filter(|c| cook::is_displayable(c.counter_type))
+            .filter(|c| self.counter.as_ref().is_none_or(|g| g.matches(&c.name)))
+            .collect()
+    }
+
+    pub fn is_shown(&self, instance: &InstanceSample) -> bool {
+        self.shows(instance.instance.as_ref())
+    }
+
+    /// Whether an instance is shown, or everything of a single-instance counterset.
+    pub fn shows(&self, instance: Option<&Instance>) -> bool {
+        match (&self.instance, instance) {
+            (Some(glob), Some(instance)) => glob.matches(&instance.name),
+            _ => true,
+        }
+    }
+}
+
+/// Periodically sample a counterset and print a table of cooked values for each instance.
+pub fn run(
+    counterset: &CounterSet,
+    options: &Options,
+    source: &mut dyn Source,
+    clock: &mut dyn Clock,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut watch = Watch::new(counterset, options);
+
+    let mut refreshes = 0;
+    loop {
+        let sample = source.sample(&counterset.id)?;
+        let frame = watch.update(sample, clock.now());
+
+        if options.ansi {
+            write!(out, "{}", CLEAR_SCREEN)?;
+        } else if refreshes > 0 {
+            writeln!(out)?;
+        }
+        write!(out, "{}", frame)?;
+        out.flush()?;
+
+        refreshes += 1;
+        if options.count.is_some_and(|count| refreshes >= count) {
+            return Ok(());
+        }
+
+        clock.sleep(options.interval);
+    }
+}
+
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+const HIGHLIGHT: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders successive samples of a counterset as tables of cooked values.
+pub struct Watch<'a> {
+    counterset: &'a CounterSet,
+    options: &'a Options,
+    /// The counters to display, in column order.
+    counters: Vec<&'a Counter>,
+    series: Series<'a>,
+    /// Values displayed in the previous frame, to highlight changes.
+    displayed: HashMap<(SeriesKey, u32), f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Present,
+    /// Wasn't in the previous sample.
+    Appeared,
+    /// Was in the previous sample, but not this one.
+    Disappeared,
+    /// Has the same id and name as an instance in the previous sample, but is a different object.
+    Restarted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Same,
+    Up,
+    Down,
+}
+
+struct Row {
+    instance: Option<Instance>,
+    status: Status,
+    cells: Vec<Option<(f64, Change)>>,
+}
+
+impl<'a> Watch<'a> {
+    pub fn new(counterset: &'a CounterSet, options: &'a Options) -> Self {
+        Self {
+            counterset,
+            options,
+            counters: options.shown_counters(counterset),
+            series: Series::new(counterset),
+            displayed: HashMap::new(),
+        }
+    }
+
+    /// Incorporate a new sample, and render the resulting table.
+    pub fn update(&mut self, sample: Sample, now: SystemTime) -> String {
+        self.series.update(sample, now);
+        let sample = self.series.current().unwrap();
+
+        let mut rows = Vec::new();
+        let mut displayed = HashMap::new();
+
+        for (index, (instance, identity)) in sample
+            .instances
+            .iter()
+            .zip(self.series.identities())
+            .enumerate()
+        {
+            if !self.options.is_shown(instance) {
+                continue;
+            }
+            let status = match identity.previous {
+                _ if identity.restarted => Status::Restarted,
+                None if self.series.previous().is_some() => Status::Appeared,
+                _ => Status::Present,
+            };
+
+            let cells = self
+                .counters
+                .iter()
+                .map(|counter| {
+                    let value = self.series.cook(counter, index)?;
+
+                    let key = (identity.key.clone(), counter.id);
+                    let change = match self.displayed.get(&key) {
+                        Some(&old) if value > old => Change::Up,
+                        Some(&old) if value < old => Change::Down,
+                        _ => Change::Same,
+                    };
+                    displayed.insert(key, value);
+
+                    Some((value, change))
+                })
+                .collect();
+
+            rows.push(Row {
+                instance: instance.instance.clone(),
+                status,
+                cells,
+            });
+        }
+
+        for instance in self.series.disappeared() {
+            if self.options.is_shown(instance) {
+                rows.push(Row {
+                    instance: instance.instance.clone(),
+                    status: Status::Disappeared,
+                    cells: vec![None; self.counters.len()],
+                });
+            }
+        }
+
+        self.displayed = displayed;
+
+        self.render(&rows, now)
+    }
+
+    fn render(&self, rows: &[Row], now: SystemTime) -> String {
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "{} ({:?}) at {}, every {}",
+            self.counterset.name,
+            self.counterset.id,
+            humantime::format_rfc3339_seconds(now),
+            humantime::format_duration(self.options.interval),
+        )
+        .unwrap();
+        writeln!(out).unwrap();
+
+        let mut table = vec![std::iter::once("Instance".to_string())
+            .chain(self.counters.iter().map(|c| c.name.clone()))
+            .collect::<Vec<_>>()];
+        let mut changes = vec![vec![Change::Same; self.counters.len() + 1]];
+
+        for row in rows {
+            let marker = match row.status {
+                Status::Present => "  ",
+                Status::Appeared => "+ ",
+                Status::Disappeared => "- ",
+                Status::Restarted => "* ",
+            };
+            let name = match &row.instance {
+                Some(instance) => &instance.name,
+                None => "-",
+            };
+
+            let mut cells = vec![format!("{}{}", marker, name)];
+            let mut row_changes = vec![Change::Same];
+            for cell in &row.cells {
+                match cell {
+                    Some((value, change)) => {
+                        let arrow = match change {
+                            Change::Same => "  ",
+                            Change::Up => " ▲",
+                            Change::Down => " ▼",
+                        };
+                        cells.push(format!("{}{}", format_value(*value), arrow));
+                        row_changes.push(*change);
+                    }
+                    None => {
+                        cells.push("-  ".to_string());
+                        row_changes.push(Change::Same);
+                    }
+                }
+            }
+            table.push(cells);
+            changes.push(row_changes);
+        }
+
+        let widths = (0..=self.counters.len())
+            .map(|col| {
+                table
+                    .iter()
+                    .map(|row| row[col].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+
+        for (row, row_changes) in table.iter().zip(&changes) {
+            let mut line = String::new();
+            for (col, (cell, change)) in row.iter().zip(row_changes).enumerate() {
+                let padded = match col {
+                    0 => format!("{:<width$}", cell, width = widths[col]),
+                    _ => format!("  {:>width$}", cell, width = widths[col]),
+                };
+                if self.options.ansi && *change != Change::Same {
+                    write!(line, "{}{}{}", HIGHLIGHT, padded, RESET).unwrap();
+                } else {
+                    line.push_str(&padded);
+                }
+            }
+            writeln!(out, "{}", line.trim_end()).unwrap();
+        }
+
+        out
+    }
+}
+
+pub fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.3}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, counter, FakeClock, FakeSource};
+    use crate::types::CounterType;
+
+    fn counterset() -> CounterSet {
+        testing::counterset(
+            testing::CONTOSO_DISK,
+            "Disk",
+            vec![
+                counter(0, "Reads/sec", CounterType::PERF_COUNTER_COUNTER, None),
+                counter(1, "% Idle", CounterType::PERF_SAMPLE_FRACTION, Some(2)),
+                counter(2, "% Idle Base", CounterType::PERF_SAMPLE_BASE, None),
+                counter(3, "Queue", CounterType::PERF_COUNTER_RAWCOUNT, None),
+            ],
+        )
+    }
+
+    /// Build a sample one second after the previous, with (instance id, name, [reads, idle, idle base, queue]).
+    fn sample(second: i64, instances: &[(u32, &str, [u64; 4])]) -> Sample {
+        let instances = instances
+            .iter()
+            .map(|&(id, name, values)| (testing::instance(id, name), values));
+        testing::sample(counterset().id, second, &[0, 1, 2, 3], instances)
+    }
+
+    fn options() -> Options {
+        Options {
+            instance: None,
+            counter: None,
+            interval: Duration::from_secs(1),
+            count: Some(3),
+            ansi: false,
+        }
+    }
+
+    fn watch(options: &Options, samples: Vec<Sample>) -> (String, Vec<Duration>) {
+        let mut source = FakeSource(samples.into());
+        let mut clock = FakeClock::new();
+        let mut out = Vec::new();
+
+        run(&counterset(), options, &mut source, &mut clock, &mut out).unwrap();
+
+        (String::from_utf8(out).unwrap(), clock.slept)
+    }
+
+    #[test]
+    fn cooks_rates_and_fractions() {
+        let (out, slept) = watch(
+            &options(),
+            vec![
+                sample(0, &[(0, "C:", [100, 0, 0, 2])]),
+                sample(1, &[(0, "C:", [150, 30, 40, 2])]),
+                sample(2, &[(0, "C:", [170, 60, 80, 1])]),
+            ],
+        );
+
+        assert_eq!(
+            out,
+            "\
+Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0) at 2023-11-14T22:13:20Z, every 1s
+
+Instance  Reads/sec  % Idle  Queue
+  C:            -       -      2
+
+Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0) at 2023-11-14T22:13:21Z, every 1s
+
+Instance  Reads/sec  % Idle  Queue
+  C:           50      75      2
+
+Disk (8F1E2D3C-4B5A-4968-8776-A5B4C3D2E1F0) at 2023-11-14T22:13:22Z, every 1s
+
+Instance  Reads/sec  % Idle  Queue
+  C:           20 ▼    75      1 ▼
+"
+        );
+        assert_eq!(slept, vec![Duration::from_secs(1); 2]);
+    }
+
+    #[test]
+    fn instances_appear_and_disappear() {
+        let options = Options {
+            counter: Some(Glob::new("reads*")),
+            ..options()
+        };
+        let (out, _) = watch(
+            &options,
+            vec![
+                sample(0, &[(0, "C:", [100, 0, 0, 0]), (1, "D:", [0, 0, 0, 0])]),
+                sample(1, &[(0, "C:", [110, 0, 0, 0]), (2, "E:", [5, 0, 0, 0])]),
+                sample(2, &[(0, "C:", [130, 0, 0, 0]), (2, "E:", [10, 0, 0, 0])]),
+            ],
+        );
+
+        let frames = out.split("\n\n").collect::<Vec<_>>();
+        assert_eq!(
+            frames[1],
+            "Instance  Reads/sec\n  C:            -\n  D:            -"
+        );
+        assert_eq!(
+            frames[3],
+            "Instance  Reads/sec\n  C:           10\n+ E:            -\n- D:            -"
+        );
+        assert_eq!(
+            frames[5],
+            "Instance  Reads/sec\n  C:           20 ▲\n  E:            5\n"
+        );
+    }
+
+    #[test]
+    fn filters_instances() {
+        let options = Options {
+            instance: Some(Glob::new("d?")),
+            count: Some(1),
+            ansi: true,
+            ..options()
+        };
+        let (out, _) = watch(
+            &options,
+            vec![sample(
+                0,
+                &[(0, "C:", [0, 0, 0, 1]), (1, "D:", [0, 0, 0, 2])],
+            )],
+        );
+
+        assert!(out.starts_with(CLEAR_SCREEN));
+        assert!(out.contains("  D:"));
+        assert!(!out.contains("C:"));
+    }
+}
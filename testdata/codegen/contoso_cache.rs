@@ -0,0 +1,245 @@
+//! Bindings for the "Contoso Cache" counterset, generated by perflib-explorer.
+//!
+//! Requires the `windows` crate with the `Win32_Foundation` and `Win32_System_Performance` features.
+
+#![allow(dead_code)]
+
+use std::mem;
+use windows::core::{Error, Result, GUID, PCWSTR};
+use windows::Win32::Foundation::{
+    ERROR_INVALID_DATA, ERROR_NOT_ENOUGH_MEMORY, ERROR_SUCCESS, HANDLE, WIN32_ERROR,
+};
+use windows::Win32::System::Performance::{
+    PerfAddCounters, PerfCloseQueryHandle, PerfOpenQueryHandle, PerfQueryCounterData,
+    PERF_COUNTERSET, PERF_COUNTER_DATA, PERF_COUNTER_HEADER, PERF_COUNTER_IDENTIFIER,
+    PERF_DATA_HEADER, PERF_ERROR_RETURN, PERF_INSTANCE_HEADER, PERF_MULTIPLE_COUNTERS,
+    PERF_MULTI_COUNTERS, PERF_MULTI_INSTANCES, PERF_WILDCARD_COUNTER,
+};
+
+/// Block cache used by Contoso storage volumes.
+pub const COUNTERSET_ID: GUID = GUID::from_u128(0x2B4D6F81_93A5_4C7E_8F01_23456789ABCD);
+
+/// Ids of the counters in the counterset.
+pub mod counter_ids {
+    /// Cache Hits
+    pub const CACHE_HITS: u32 = 0;
+    /// Cache Misses
+    pub const CACHE_MISSES: u32 = 1;
+    /// Cache Hit Ratio
+    pub const CACHE_HIT_RATIO: u32 = 2;
+    /// Cache Hit Ratio Base
+    pub const CACHE_HIT_RATIO_BASE: u32 = 3;
+}
+
+/// Raw values of the counters for one instance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContosoCache {
+    /// Number of reads satisfied from the cache.
+    ///
+    /// Cache Hits: `PERF_COUNTER_COUNTER`
+    pub cache_hits: u32,
+    /// Number of reads that went to disk.
+    ///
+    /// Cache Misses: `PERF_COUNTER_COUNTER`
+    pub cache_misses: u32,
+    /// Percentage of reads satisfied from the cache.
+    ///
+    /// Cache Hit Ratio: `PERF_RAW_FRACTION`
+    pub cache_hit_ratio: u32,
+    /// Cache Hit Ratio Base: `PERF_RAW_BASE`
+    pub cache_hit_ratio_base: u32,
+}
+
+impl ContosoCache {
+    fn set(&mut self, counter_id: u32, value: u64) {
+        match counter_id {
+            counter_ids::CACHE_HITS => self.cache_hits = value as u32,
+            counter_ids::CACHE_MISSES => self.cache_misses = value as u32,
+            counter_ids::CACHE_HIT_RATIO => self.cache_hit_ratio = value as u32,
+            counter_ids::CACHE_HIT_RATIO_BASE => self.cache_hit_ratio_base = value as u32,
+            _ => {}
+        }
+    }
+}
+
+impl Reader {
+    /// Read the current values of the counters.
+    pub fn read(&mut self) -> Result<ContosoCache> {
+        let mut values = ContosoCache::default();
+        for instance in self.query()? {
+            for (id, value) in instance.values {
+                values.set(id, value);
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// Collects the values of every counter of every instance of the counterset, using a perflib query.
+pub struct Reader {
+    handle: HANDLE,
+    buf: Vec<u8>,
+}
+
+/// Raw values of one instance, as (counter id, value) pairs.
+struct RawInstance {
+    id: u32,
+    name: String,
+    values: Vec<(u32, u64)>,
+}
+
+impl Reader {
+    pub fn open() -> Result<Self> {
+        let mut handle = HANDLE::default();
+        check(unsafe { PerfOpenQueryHandle(PCWSTR::null(), &mut handle) })?;
+
+        // Construct this immediately, so the handle gets closed if adding counters fails.
+        let reader = Self {
+            handle,
+            buf: Vec::new(),
+        };
+
+        // A single identifier for all counters of all instances,
+        // followed by the wildcard instance name and padding to a multiple of 8 bytes.
+        let wildcard_instance = ['*' as u16, 0];
+        let size = (mem::size_of::<PERF_COUNTER_IDENTIFIER>()
+            + mem::size_of_val(&wildcard_instance))
+        .next_multiple_of(8);
+
+        // Use u64 so the block is 8-byte aligned.
+        let mut block = vec![0u64; size / 8];
+        let block_ptr = block.as_mut_ptr().cast::<PERF_COUNTER_IDENTIFIER>();
+
+        // SAFETY: block is large enough to hold the identifier followed by the instance name.
+        unsafe {
+            block_ptr.write(PERF_COUNTER_IDENTIFIER {
+                CounterSetGuid: COUNTERSET_ID,
+                Status: 0,
+                Size: size.try_into().unwrap(),
+                CounterId: PERF_WILDCARD_COUNTER,
+                InstanceId: PERF_WILDCARD_COUNTER,
+                Index: 0,
+                Reserved: 0,
+            });
+            block_ptr
+                .add(1)
+                .cast::<[u16; 2]>()
+                .write_unaligned(wildcard_instance);
+        }
+
+        check(unsafe { PerfAddCounters(reader.handle, block_ptr, size.try_into().unwrap()) })?;
+        check(unsafe { block_ptr.read() }.Status)?;
+
+        Ok(reader)
+    }
+
+    fn query(&mut self) -> Result<Vec<RawInstance>> {
+        let mut len = 0;
+        loop {
+            let buf_len = self.buf.len().try_into().unwrap();
+            let buf = self.buf.as_mut_ptr().cast::<PERF_DATA_HEADER>();
+            let res = unsafe { PerfQueryCounterData(self.handle, Some(buf), buf_len, &mut len) };
+            match WIN32_ERROR(res) {
+                ERROR_SUCCESS => break,
+                ERROR_NOT_ENOUGH_MEMORY => self.buf.resize(len.try_into().unwrap(), 0),
+                e => return Err(Error::from(e)),
+            }
+        }
+
+        parse(&self.buf[..len.try_into().unwrap()])
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        unsafe { PerfCloseQueryHandle(self.handle) };
+    }
+}
+
+fn check(res: u32) -> Result<()> {
+    match WIN32_ERROR(res) {
+        ERROR_SUCCESS => Ok(()),
+        e => Err(Error::from(e)),
+    }
+}
+
+/// Read a perflib structure from the buffer at the given offset.
+fn read_at<T: Copy>(buf: &[u8], offset: usize) -> T {
+    assert!(offset + mem::size_of::<T>() <= buf.len());
+    // SAFETY: bounds checked above, and perflib structures are valid for all bit patterns.
+    unsafe { buf.as_ptr().add(offset).cast::<T>().read_unaligned() }
+}
+
+/// Parse the result of `PerfQueryCounterData`, as documented for a query with a single wildcard identifier.
+fn parse(buf: &[u8]) -> Result<Vec<RawInstance>> {
+    let header = read_at::<PERF_DATA_HEADER>(buf, 0);
+    let mut offset = mem::size_of::<PERF_DATA_HEADER>();
+    if header.dwNumCounters != 1 {
+        return Err(Error::from(ERROR_INVALID_DATA));
+    }
+
+    let counter_header = read_at::<PERF_COUNTER_HEADER>(buf, offset);
+    offset += mem::size_of::<PERF_COUNTER_HEADER>();
+
+    match counter_header.dwType {
+        PERF_ERROR_RETURN => Err(Error::from(WIN32_ERROR(counter_header.dwStatus))),
+        PERF_MULTIPLE_COUNTERS => {
+            let ids = parse_ids(buf, &mut offset);
+            let values = parse_values(buf, &mut offset, &ids);
+            Ok(vec![RawInstance {
+                id: 0,
+                name: String::new(),
+                values,
+            }])
+        }
+        PERF_COUNTERSET => {
+            let ids = parse_ids(buf, &mut offset);
+            let multi_instances = read_at::<PERF_MULTI_INSTANCES>(buf, offset);
+            offset += mem::size_of::<PERF_MULTI_INSTANCES>();
+
+            let mut instances = Vec::new();
+            for _ in 0..multi_instances.dwInstances {
+                let instance = read_at::<PERF_INSTANCE_HEADER>(buf, offset);
+                let name = buf[offset + mem::size_of::<PERF_INSTANCE_HEADER>()..]
+                    .chunks_exact(2)
+                    .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+                    .take_while(|c| *c != 0)
+                    .collect::<Vec<_>>();
+                offset += usize::try_from(instance.Size).unwrap();
+
+                instances.push(RawInstance {
+                    id: instance.InstanceId,
+                    name: String::from_utf16_lossy(&name),
+                    values: parse_values(buf, &mut offset, &ids),
+                });
+            }
+            Ok(instances)
+        }
+        _ => Err(Error::from(ERROR_INVALID_DATA)),
+    }
+}
+
+fn parse_ids(buf: &[u8], offset: &mut usize) -> Vec<u32> {
+    let multi_counters = read_at::<PERF_MULTI_COUNTERS>(buf, *offset);
+    let first_id = *offset + mem::size_of::<PERF_MULTI_COUNTERS>();
+    let ids = (0..multi_counters.dwCounters as usize)
+        .map(|i| read_at::<u32>(buf, first_id + i * mem::size_of::<u32>()))
+        .collect();
+    *offset += usize::try_from(multi_counters.dwSize).unwrap();
+    ids
+}
+
+fn parse_values(buf: &[u8], offset: &mut usize, ids: &[u32]) -> Vec<(u32, u64)> {
+    let mut values = Vec::with_capacity(ids.len());
+    for &id in ids {
+        let data = read_at::<PERF_COUNTER_DATA>(buf, *offset);
+        let value_offset = *offset + mem::size_of::<PERF_COUNTER_DATA>();
+        match data.dwDataSize {
+            4 => values.push((id, read_at::<u32>(buf, value_offset).into())),
+            8 => values.push((id, read_at::<u64>(buf, value_offset))),
+            _ => {}
+        }
+        *offset += usize::try_from(data.dwSize).unwrap();
+    }
+    values
+}